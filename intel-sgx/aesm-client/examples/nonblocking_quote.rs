@@ -0,0 +1,63 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Obtain target info and a quote from AESM on a mio event loop.
+//!
+//! Usage: `nonblocking_quote <report file> <SPID in hex>`, where the report
+//! file contains a report for the QE's target info, as printed by the first
+//! step.
+
+#[cfg(unix)]
+fn main() -> Result<(), aesm_client::failure::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    use aesm_client::{NonblockingAesmClient, QuoteType, Spid};
+    use mio::unix::EventedFd;
+    use mio::{Events, Poll, PollOpt, Ready, Token};
+
+    // Drive a pending request on `poll` until it completes. The request is
+    // polled before waiting, as the socket may already be ready.
+    fn run<T, R: AsRawFd>(
+        poll: &Poll,
+        pending: &mut R,
+        mut step: impl FnMut(&mut R) -> std::task::Poll<aesm_client::Result<T>>,
+    ) -> aesm_client::Result<T> {
+        let fd = pending.as_raw_fd();
+        poll.register(&EventedFd(&fd), Token(0), Ready::readable() | Ready::writable(), PollOpt::edge())?;
+        let mut events = Events::with_capacity(8);
+        let res = loop {
+            if let std::task::Poll::Ready(res) = step(pending) {
+                break res;
+            }
+            // A real application would handle its other sources here.
+            poll.poll(&mut events, None)?;
+        };
+        poll.deregister(&EventedFd(&fd))?;
+        res
+    }
+
+    let args: Vec<String> = std::env::args().collect();
+    let client = NonblockingAesmClient::new();
+    let poll = Poll::new()?;
+
+    let mut pending = client.start_init_quote()?;
+    let quote_info = run(&poll, &mut pending, |p| p.poll())?;
+    println!("QE target info: {:02x?}", quote_info.target_info());
+
+    if let [_, report, spid] = &args[..] {
+        let report = std::fs::read(report)?;
+        let spid: Spid = spid.parse()?;
+        let mut pending = client.start_get_quote(&report, spid.as_bytes(), &[], QuoteType::Linkable, &[0; 16])?;
+        let quote = run(&poll, &mut pending, |p| p.poll())?;
+        println!("quote: {:02x?}", quote.quote());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("NonblockingAesmClient is only available on unix");
+}
@@ -0,0 +1,160 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Print the attestation capabilities of this host, as reported by AESM.
+//!
+//! Usage: `aesm-info [--json]`
+//!
+//! The exit status tells which check failed first, see the `EXIT_*`
+//! constants, so that this can be used as a health check.
+
+#![deny(warnings)]
+
+// AesmClient::diagnose and the attestation key IDs aren't available on
+// Windows.
+#[cfg(not(windows))]
+mod info {
+    use std::fmt;
+    use std::process;
+
+    use aesm_client::failure::Fail;
+    use aesm_client::{AesmClient, AesmKeyId, Error};
+    use serde_json::{json, Value};
+
+    /// Invalid command line arguments.
+    const EXIT_USAGE: i32 = 2;
+    /// AESM isn't listening on any of the known sockets.
+    const EXIT_CONNECT: i32 = 3;
+    /// AESM accepted the connection, but didn't respond properly.
+    const EXIT_AESM: i32 = 4;
+    /// EPID attestation isn't available.
+    const EXIT_INIT_QUOTE: i32 = 5;
+    /// The attestation keys couldn't be listed.
+    const EXIT_KEY_IDS: i32 = 6;
+
+    struct PrintHex<'a>(&'a [u8]);
+
+    impl<'a> fmt::Display for PrintHex<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            for b in self.0 {
+                write!(f, "{:02x}", b)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// The results of the checks, printed as text or JSON at the end.
+    struct Report {
+        json: bool,
+        fields: serde_json::Map<String, Value>,
+        exit_code: i32,
+    }
+
+    impl Report {
+        fn set(&mut self, key: &str, label: &str, value: Value, text: &dyn fmt::Display) {
+            if !self.json {
+                println!("{}: {}", label, text);
+            }
+            self.fields.insert(key.to_owned(), value);
+        }
+
+        fn fail(&mut self, key: &str, label: &str, exit_code: i32, err: &Error) {
+            // Include the causes, e.g. why the socket couldn't be opened.
+            let err = (err as &dyn Fail).iter_chain().map(|e| e.to_string()).collect::<Vec<_>>().join(": ");
+            self.set(key, label, json!({ "error": err }), &format_args!("error: {}", err));
+            if self.exit_code == 0 {
+                self.exit_code = exit_code;
+            }
+        }
+    }
+
+    fn key_id_json(key_id: &AesmKeyId) -> Value {
+        json!({
+            "algorithm": key_id.algorithm().to_string(),
+            "algorithm_id": key_id.algorithm_id(),
+            "att_key_type": key_id.att_key_type(),
+            "mrsigner": PrintHex(key_id.mrsigner()).to_string(),
+            "prod_id": key_id.prod_id(),
+        })
+    }
+
+    fn go(report: &mut Report) {
+        let client = AesmClient::new();
+
+        let diagnostics = match client.diagnose() {
+            Ok(diagnostics) => diagnostics,
+            Err(err) => return report.fail("connection", "AESM", EXIT_CONNECT, &err),
+        };
+        if let Some(ref path) = diagnostics.socket_path {
+            report.set("socket_path", "Socket", json!(path.display().to_string()), &path.display());
+        }
+        if let Some(ref err) = diagnostics.connect_error {
+            return report.fail("connection", "AESM", EXIT_CONNECT, err);
+        }
+        if let Some(ref err) = diagnostics.request_error {
+            return report.fail("connection", "AESM", EXIT_AESM, err);
+        }
+        let latency = diagnostics.latency.unwrap_or_default();
+        report.set(
+            "connection",
+            "AESM",
+            json!({ "latency_us": latency.as_micros() as u64 }),
+            &format_args!("responding (latency {:?})", latency),
+        );
+
+        match client.init_quote() {
+            Ok(quote_info) => {
+                let gid = PrintHex(&quote_info.gid()).to_string();
+                report.set("epid_group_id", "EPID group ID", json!(gid), &gid);
+            }
+            Err(err) => report.fail("epid_group_id", "EPID group ID", EXIT_INIT_QUOTE, &err),
+        }
+
+        match client.get_supported_att_key_ids() {
+            Ok(key_ids) => {
+                let text = key_ids.iter()
+                    .map(|key_id| format!("\n  {} (type {}, product {}, MRSIGNER {})",
+                        key_id.algorithm(), key_id.att_key_type(), key_id.prod_id(), PrintHex(key_id.mrsigner())))
+                    .collect::<String>();
+                let value = key_ids.iter().map(|key_id| key_id_json(&key_id)).collect();
+                report.set("att_key_ids", "Attestation keys", Value::Array(value), &text);
+            }
+            Err(err) => report.fail("att_key_ids", "Attestation keys", EXIT_KEY_IDS, &err),
+        }
+    }
+
+    pub fn main() {
+        let mut json = false;
+        for arg in std::env::args().skip(1) {
+            match &arg[..] {
+                "--json" => json = true,
+                _ => {
+                    eprintln!("Usage: aesm-info [--json]");
+                    process::exit(EXIT_USAGE);
+                }
+            }
+        }
+
+        let mut report = Report { json, fields: Default::default(), exit_code: 0 };
+        go(&mut report);
+        if report.json {
+            report.fields.insert("exit_code".to_owned(), json!(report.exit_code));
+            println!("{}", Value::Object(report.fields));
+        }
+        process::exit(report.exit_code);
+    }
+}
+
+#[cfg(not(windows))]
+fn main() {
+    info::main()
+}
+
+#[cfg(windows)]
+fn main() {
+    eprintln!("aesm-info isn't supported on Windows");
+    std::process::exit(1);
+}
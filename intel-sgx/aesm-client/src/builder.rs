@@ -0,0 +1,152 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::metrics::MetricsHook;
+use crate::{imp, AesmClient, AesmMetric};
+
+/// Builder for `AesmClient`.
+///
+/// Not all options are supported on all targets. `build` returns an error if
+/// an option was set that is not supported on the current target.
+///
+/// | Option              | unix | sgx      | windows |
+/// |---------------------|------|----------|---------|
+/// | `socket_path`       | yes  | no       | no      |
+/// | `tcp_address`       | no   | required | no      |
+/// | `default_timeout`   | yes  | yes      | no      |
+/// | `no_timeout`        | yes  | yes      | no      |
+/// | `retries`           | yes  | yes      | no      |
+/// | `transient_retries` | yes  | yes      | no      |
+/// | `retry_backoff`     | yes  | yes      | no      |
+/// | `max_response_size` | yes  | yes      | no      |
+/// | `metrics_hook`      | yes  | yes      | no      |
+/// | `wire_dump`         | yes  | yes      | no      |
+/// | `sgx_device_paths`  | yes  | no       | no      |
+#[derive(Clone, Debug, Default)]
+pub struct AesmClientBuilder {
+    pub(crate) socket_path: Option<PathBuf>,
+    pub(crate) tcp_address: Option<String>,
+    // `Some(None)` if the client shouldn't time out.
+    pub(crate) default_timeout: Option<Option<Duration>>,
+    pub(crate) retries: Option<u32>,
+    pub(crate) transient_retries: Option<u32>,
+    pub(crate) retry_backoff: Option<Duration>,
+    pub(crate) max_response_size: Option<usize>,
+    pub(crate) metrics_hook: Option<MetricsHook>,
+    pub(crate) wire_dump: Option<bool>,
+    pub(crate) sgx_device_paths: Option<Vec<PathBuf>>,
+}
+
+impl AesmClientBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Connect to AESM at this unix socket path. By default, the client
+    /// looks for AESM at several well-known locations.
+    pub fn socket_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.socket_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Connect to AESM (or a proxy for it) at this TCP address.
+    pub fn tcp_address<A: Into<String>>(mut self, address: A) -> Self {
+        self.tcp_address = Some(address.into());
+        self
+    }
+
+    /// The timeout passed to AESM with every request. The client waits
+    /// slightly longer for a response, so that AESM can report the timeout.
+    ///
+    /// By default, the timeout depends on the request: a second for requests
+    /// AESM answers from its state, 10 seconds for launch tokens and
+    /// attestation key IDs, a minute for quotes and five minutes for
+    /// requests that may provision the platform.
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(Some(timeout));
+        self
+    }
+
+    /// Don't pass a timeout to AESM, and wait for responses as long as it
+    /// takes. This is useful for requests that may take very long, such as
+    /// getting a launch token while AESM provisions the platform. Overrides
+    /// `default_timeout`, and vice versa.
+    pub fn no_timeout(mut self) -> Self {
+        self.default_timeout = Some(None);
+        self
+    }
+
+    /// How many times to retry a request if communication with AESM fails.
+    /// Defaults to 0.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// How many times to retry a request if AESM returns an error that is
+    /// likely temporary, see `AesmError::is_transient`. Defaults to 0.
+    pub fn transient_retries(mut self, retries: u32) -> Self {
+        self.transient_retries = Some(retries);
+        self
+    }
+
+    /// How long to wait before the first retry after a transient error. The
+    /// delay doubles with each subsequent retry, up to 10 seconds. Defaults
+    /// to 100 milliseconds.
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = Some(backoff);
+        self
+    }
+
+    /// The largest response the client accepts from AESM, in bytes. Larger
+    /// responses are rejected with `Error::ResponseTooLarge` before they are
+    /// read, to protect against a broken or malicious AESM. Defaults to 4 MiB,
+    /// which is far more than any quote needs, including ECDSA quotes with
+    /// their certification data.
+    pub fn max_response_size(mut self, size: usize) -> Self {
+        self.max_response_size = Some(size);
+        self
+    }
+
+    /// Call `hook` after every request to AESM with the kind of request, how
+    /// long it took, and how it ended, e.g. to export latency metrics. The
+    /// hook is also called for requests that fail before reaching AESM.
+    ///
+    /// The hook runs on the thread that made the request, so it should return
+    /// quickly.
+    pub fn metrics_hook(mut self, hook: Arc<dyn Fn(AesmMetric) + Send + Sync>) -> Self {
+        self.metrics_hook = Some(MetricsHook(hook));
+        self
+    }
+
+    /// Log every request and response as hex at trace level, to debug a
+    /// misbehaving AESM. SPIDs are masked, but the dump contains reports and
+    /// nonces. Disabled by default.
+    pub fn wire_dump(mut self, enable: bool) -> Self {
+        self.wire_dump = Some(enable);
+        self
+    }
+
+    /// The SGX device nodes to look for when checking whether SGX is
+    /// available on this host, see `AesmClient::diagnose`. Defaults to the
+    /// device nodes of the in-kernel and out-of-tree drivers.
+    pub fn sgx_device_paths<I: IntoIterator<Item = P>, P: AsRef<Path>>(mut self, paths: I) -> Self {
+        self.sgx_device_paths = Some(paths.into_iter().map(|path| path.as_ref().to_owned()).collect());
+        self
+    }
+
+    pub fn build(self) -> Result<AesmClient> {
+        Ok(AesmClient {
+            inner: imp::AesmClient::from_builder(self)?,
+            quote_info: Default::default(),
+        })
+    }
+}
@@ -0,0 +1,146 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::time::Duration;
+
+use crate::error::{AesmError, Error, Result};
+
+/// How long AESM may take to respond to each of the requests sent by
+/// `AesmClient::capabilities`.
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What AESM can do on this host, as returned by
+/// `AesmClient::capabilities`.
+///
+/// Every capability is probed independently, so that one failed probe
+/// doesn't hide the results of the others.
+#[derive(Debug)]
+pub struct AesmCapabilities {
+    /// EPID quotes, i.e. whether `init_quote` succeeds.
+    pub epid: Capability,
+    /// ECDSA quotes with DCAP, i.e. whether AESM has an ECDSA attestation
+    /// key.
+    pub dcap: Capability,
+    /// Launch tokens, i.e. whether AESM's launch service handles
+    /// `get_launch_token` requests.
+    pub launch_token: Capability,
+}
+
+impl AesmCapabilities {
+    /// Whether AESM can generate quotes of any kind.
+    pub fn can_attest(&self) -> bool {
+        self.epid.is_available() || self.dcap.is_available()
+    }
+}
+
+/// Whether AESM supports a feature.
+#[derive(Debug)]
+pub enum Capability {
+    Available,
+    /// AESM responded that it can't provide the feature. This holds the
+    /// error code AESM returned, if any.
+    Unavailable(Option<AesmError>),
+    /// The probe failed, e.g. because it timed out or AESM is busy, so
+    /// whether the feature is available is unknown. Probing again later may
+    /// succeed.
+    Unknown(Error),
+}
+
+impl Capability {
+    pub fn is_available(&self) -> bool {
+        matches!(*self, Capability::Available)
+    }
+
+    /// Interpret the result of a probe, which is `Ok(false)` if AESM
+    /// responded but doesn't provide the feature.
+    pub(crate) fn from_probe(res: Result<bool>) -> Self {
+        match res {
+            Ok(true) => Capability::Available,
+            Ok(false) => Capability::Unavailable(None),
+            Err(Error::AesmCode(code)) if !code.is_transient() => Capability::Unavailable(Some(code)),
+            // AESM versions that don't know a request respond without the
+            // expected payload.
+            Err(Error::AesmBadResponse(_)) => Capability::Unavailable(None),
+            Err(err) => Capability::Unknown(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sgx_isa::Targetinfo;
+
+    use super::*;
+    use crate::test_support::MockAesm;
+    use crate::{
+        AesmClient, AesmKeyId, AttestationAlgorithm, Response, Response_GetLaunchTokenResponse, Response_GetSupportedAttKeyIDNumResponse,
+        Response_GetSupportedAttKeyIDsResponse, Response_InitQuoteResponse,
+    };
+
+    #[test]
+    fn capabilities() {
+        // EPID and launch tokens work, but AESM is busy when asked for the
+        // attestation keys.
+        let mock = MockAesm::start(|req| {
+            if req.has_initQuoteReq() {
+                let mut body = Response_InitQuoteResponse::new();
+                body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+                MockAesm::response(0, body)
+            } else if req.has_getSupportedAttKeyIDNumReq() {
+                MockAesm::error_code::<Response_GetSupportedAttKeyIDNumResponse>(18)
+            } else {
+                assert!(req.has_getLicTokenReq());
+                MockAesm::error_code::<Response_GetLaunchTokenResponse>(6)
+            }
+        });
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let capabilities = client.capabilities().unwrap();
+        assert!(capabilities.epid.is_available());
+        assert!(matches!(capabilities.dcap, Capability::Unknown(Error::AesmCode(AesmError::Busy_18))));
+        assert!(capabilities.launch_token.is_available());
+        assert!(capabilities.can_attest());
+
+        // A DCAP-only host, with an AESM that doesn't know launch tokens.
+        let mock = MockAesm::start(|req| {
+            if req.has_initQuoteReq() {
+                MockAesm::error_code::<Response_InitQuoteResponse>(41)
+            } else if req.has_getSupportedAttKeyIDNumReq() {
+                let mut body = Response_GetSupportedAttKeyIDNumResponse::new();
+                body.set_att_key_id_num(1);
+                MockAesm::response(0, body)
+            } else if req.has_getSupportedAttKeyIDsReq() {
+                let mut key = vec![0; AesmKeyId::SIZE];
+                key[154..158].copy_from_slice(&u32::from(AttestationAlgorithm::EcdsaP256).to_le_bytes());
+                let mut body = Response_GetSupportedAttKeyIDsResponse::new();
+                body.set_att_key_ids(key);
+                MockAesm::response(0, body)
+            } else {
+                Some(Response::new())
+            }
+        });
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let capabilities = client.capabilities().unwrap();
+        assert!(matches!(capabilities.epid, Capability::Unavailable(Some(AesmError::PlatformLibUnavailable_41))));
+        assert!(capabilities.dcap.is_available());
+        assert!(matches!(capabilities.launch_token, Capability::Unavailable(None)));
+
+        // A parameter error doesn't tell whether the launch service works.
+        let mock = MockAesm::start(|req| {
+            if req.has_getLicTokenReq() {
+                MockAesm::error_code::<Response_GetLaunchTokenResponse>(3)
+            } else {
+                Some(Response::new())
+            }
+        });
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let capabilities = client.capabilities().unwrap();
+        assert!(matches!(capabilities.launch_token, Capability::Unknown(Error::AesmCode(AesmError::ParameterError_3))));
+
+        let dir = tempfile::tempdir().unwrap();
+        let client = AesmClient::builder().socket_path(dir.path().join("missing.socket")).build().unwrap();
+        assert!(client.capabilities().is_err());
+    }
+}
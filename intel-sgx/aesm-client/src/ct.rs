@@ -0,0 +1,40 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Constant-time comparisons, see the crate documentation for where they're
+//! used.
+
+use std::ptr;
+
+/// Whether `a` and `b` are equal. All bytes are compared, without exiting
+/// early, so that the time taken doesn't reveal how much of `a` matches.
+/// Only the lengths are compared in variable time.
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    // Keep the compiler from turning the loop into one that exits once
+    // `diff` is nonzero.
+    unsafe { ptr::read_volatile(&diff) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn ct_eq() {
+        let a = (0..=255).collect::<Vec<u8>>();
+        assert!(super::ct_eq(&a, &a.clone()));
+        assert!(super::ct_eq(&[], &[]));
+        for i in 0..a.len() {
+            let mut b = a.clone();
+            b[i] ^= 0x80;
+            assert!(!super::ct_eq(&a, &b), "byte {}", i);
+        }
+        assert!(!super::ct_eq(&a, &a[..255]));
+        assert!(!super::ct_eq(&a[..1], &[]));
+    }
+}
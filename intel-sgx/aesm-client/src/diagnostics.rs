@@ -0,0 +1,43 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::{AesmError, Error};
+
+/// The state of the connection to AESM, as returned by
+/// `AesmClient::diagnose`.
+#[derive(Debug)]
+pub struct AesmDiagnostics {
+    /// The unix socket the client connected to. `None` in SGX, where the
+    /// client uses a TCP stream, and if no socket was found.
+    pub socket_path: Option<PathBuf>,
+    /// Whether the client could connect to AESM.
+    pub connected: bool,
+    /// Why the client couldn't connect to AESM.
+    pub connect_error: Option<Error>,
+    /// How long AESM took to respond to a trivial request, if it responded.
+    pub latency: Option<Duration>,
+    /// Why the trivial request failed, e.g. because AESM returned an error or
+    /// closed the connection.
+    pub request_error: Option<Error>,
+}
+
+impl AesmDiagnostics {
+    /// The error code AESM returned for the trivial request, if any.
+    pub fn aesm_error(&self) -> Option<AesmError> {
+        match self.request_error {
+            Some(Error::AesmCode(code)) => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Whether AESM is reachable and responds without error.
+    pub fn is_healthy(&self) -> bool {
+        self.connected && self.request_error.is_none()
+    }
+}
@@ -4,14 +4,20 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use std::io::Error as IoError;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::PathBuf;
 use std::result::Result as StdResult;
 
+use sgx_isa::Attributes;
+
+use crate::AesmRequestKind;
+
 pub type Result<T> = StdResult<T, Error>;
 
 // These numbers are from psw/ae/inc/internal/aesm_error.h and (surprisingly)
 // not from psw/ae/inc/aeerror.h
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum AesmError {
     UnexpectedError_1,
@@ -132,14 +138,180 @@ pub enum Error {
     AesmCode(AesmError),
     #[fail(display = "error communicating with aesm")]
     AesmCommunication(#[cause] IoError),
-    #[fail(display = "missing expected {} payload in response from aesm", _0)]
+    #[fail(display = "permission denied connecting to aesm at {:?}: the socket exists, but the user isn't allowed to access it", path)]
+    PermissionDenied { path: PathBuf },
+    /// `request` is the request AESM didn't respond to in time, `None` if
+    /// connecting timed out.
+    #[fail(display = "timed out waiting for aesm")]
+    Timeout { request: Option<AesmRequestKind> },
+    #[fail(display = "SGX is not available: {}", _0)]
+    SgxNotAvailable(SgxCheck),
+    /// AESM didn't return a field the client needs, e.g. because its
+    /// version doesn't support the request.
+    #[fail(display = "missing expected {} in response from aesm", _0)]
     AesmBadResponse(String),
+    /// `raw` is the field as AESM returned it, e.g. for logging.
+    #[fail(display = "malformed {} in response from aesm: {}", field, reason)]
+    MalformedResponse { field: &'static str, reason: &'static str, raw: Vec<u8> },
     #[fail(display = "invalid quote type {}", _0)]
     InvalidQuoteType(u32),
     #[fail(display = "invalid quote size")]
     InvalidQuoteSize,
+    #[fail(display = "invalid ECDSA quote: {}", _0)]
+    InvalidEcdsaQuote(&'static str),
     #[fail(display = "invalid token size")]
     InvalidTokenSize,
+    #[fail(display = "attributes {:?} don't match the attributes {:?} required by the sigstruct", requested, allowed)]
+    AttributesMismatch { requested: Attributes, allowed: Attributes },
+    #[fail(display = "invalid target info size")]
+    InvalidTargetInfoSize,
+    #[fail(display = "invalid attestation key ID size")]
+    InvalidKeyIdSize,
+    #[fail(display = "AESM does not support the attestation key ID")]
+    UnsupportedAttKeyId,
+    #[fail(display = "no ECDSA attestation key is available")]
+    NoEcdsaAttestationKey,
+    #[fail(display = "the platform has no default attestation key")]
+    NoDefaultAttestationKey,
+    #[fail(display = "DCAP attestation is not available: {}", _0)]
+    DcapUnavailable(DcapUnavailableReason),
+    #[fail(display = "AESM announced a response of {} bytes, exceeding the limit of {} bytes", announced, limit)]
+    ResponseTooLarge { limit: usize, announced: usize },
+    /// AESM closed the connection after sending part of its response. The
+    /// request isn't retried, as AESM may have acted on it.
+    #[fail(display = "AESM closed the connection after sending {} bytes of its response", received)]
+    PartialResponse { received: usize },
+    #[fail(display = "invalid {} size: expected {} bytes, got {}", field, expected, got)]
+    InvalidArgument { field: &'static str, expected: usize, got: usize },
+    #[fail(display = "invalid SPID: expected 32 hex digits, got {}", _0)]
+    InvalidSpidLength(usize),
+    #[fail(display = "invalid SPID: {:?} is not a hex digit", _0)]
+    InvalidSpidCharacter(char),
+    #[fail(display = "invalid signature revocation list: unsupported version {:02x?}", _0)]
+    InvalidSigRlVersion([u8; 4]),
+    #[fail(display = "signature revocation list is for EPID group {:08x}, but the platform is in group {:08x}", sig_rl, platform)]
+    SigRlGroupMismatch { sig_rl: u32, platform: u32 },
+    #[fail(display = "invalid platform info blob: {}", _0)]
+    InvalidPlatformInfo(&'static str),
+    #[fail(display = "failed to generate a random nonce")]
+    NonceGeneration(#[cause] IoError),
+    #[fail(display = "QE report does not match the nonce and quote")]
+    QeReportMismatch,
+    #[fail(display = "REPORTDATA of the quote is not bound to the attestation public key")]
+    ReportDataMismatch,
+    #[fail(display = "attestation failed while {}: {}", step, error)]
+    Attestation { step: AttestationStep, error: Box<Error> },
+    #[fail(display = "AESM did not return an extended EPID group ID")]
+    MissingExtendedEpidGroupId,
+    #[fail(display = "the launch enclave white list doesn't apply to this platform")]
+    WhiteListNotApplicable,
+    #[fail(display = "the launch enclave white list was rejected: {}", _0)]
+    WhiteListRejected(WhiteListRejection),
+    #[fail(display = "the PSE session is unusable after a failed request")]
+    PseSessionUnusable,
+    #[fail(display = "option `{}` is not supported on this target", _0)]
+    UnsupportedOption(&'static str),
+    #[fail(display = "option `{}` is required on this target", _0)]
+    MissingOption(&'static str),
+}
+
+/// Which check found that SGX isn't available on this host, see
+/// `Error::SgxNotAvailable`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SgxCheck {
+    /// There is no SGX device node, such as `/dev/sgx_enclave` or
+    /// `/dev/isgx`, so the SGX driver isn't loaded.
+    DeviceNode,
+    /// AESM's socket doesn't exist, so AESM isn't running.
+    AesmSocket,
+    /// The AESM service isn't installed (Windows).
+    AesmService,
+}
+
+impl fmt::Display for SgxCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SgxCheck::DeviceNode => f.write_str("no SGX device node found"),
+            SgxCheck::AesmSocket => f.write_str("no AESM socket found"),
+            SgxCheck::AesmService => f.write_str("the AESM service is not installed"),
+        }
+    }
+}
+
+/// The step of `AesmClient::attest_epid` that failed, see
+/// `Error::Attestation`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AttestationStep {
+    /// Getting the target info of QE with `init_quote`.
+    InitQuote,
+    /// Generating the random nonce.
+    GenerateNonce,
+    /// Getting the quote with `get_quote_req`.
+    GetQuote,
+    /// Checking that the QE report is bound to the nonce and the quote.
+    VerifyQeReport,
+}
+
+impl fmt::Display for AttestationStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AttestationStep::*;
+        match *self {
+            InitQuote => f.write_str("initializing the quote"),
+            GenerateNonce => f.write_str("generating a nonce"),
+            GetQuote => f.write_str("getting the quote"),
+            VerifyQeReport => f.write_str("verifying the QE report"),
+        }
+    }
+}
+
+/// Why a launch enclave white list wasn't installed, see
+/// `Error::WhiteListRejected`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WhiteListRejection {
+    /// The certificate chain is too short to contain a version.
+    Malformed,
+    /// AESM couldn't verify the certificate chain (`ParameterError_3`).
+    InvalidSignature,
+    /// The installed white list is newer than the certificate chain.
+    Downgrade { installed: u32, requested: u32 },
+}
+
+impl fmt::Display for WhiteListRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WhiteListRejection::Malformed => f.write_str("malformed certificate chain"),
+            WhiteListRejection::InvalidSignature => f.write_str("invalid signature"),
+            WhiteListRejection::Downgrade { installed, requested } => {
+                write!(f, "version {} is older than the installed version {}", requested, installed)
+            }
+        }
+    }
+}
+
+/// Why AESM can't produce DCAP quotes, see `Error::DcapUnavailable`.
+///
+/// Callers that can also use EPID attestation may fall back to it on any of
+/// these.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DcapUnavailableReason {
+    /// AESM doesn't have the ECDSA quoting interface (`InterfaceUnavailable_40`).
+    EcdsaUnsupported,
+    /// The quote provider library isn't installed (`PlatformLibUnavailable_41`).
+    QuoteProviderMissing,
+    /// The attestation key hasn't been generated and certified
+    /// (`AttKeyNotInitialized_42`, `NoPlatformCertData_44`).
+    AttestationKeyUnavailable,
+}
+
+impl fmt::Display for DcapUnavailableReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DcapUnavailableReason::*;
+        match *self {
+            EcdsaUnsupported => f.write_str("ECDSA attestation is not supported"),
+            QuoteProviderMissing => f.write_str("the quote provider library is missing"),
+            AttestationKeyUnavailable => f.write_str("the attestation key is not available"),
+        }
+    }
 }
 
 impl From<IoError> for Error {
@@ -148,7 +320,87 @@ impl From<IoError> for Error {
     }
 }
 
+impl AesmError {
+    /// Whether this error is likely to go away if the request is retried
+    /// later.
+    ///
+    /// The following errors are transient:
+    ///
+    /// | Error                    | Reason                                        |
+    /// |--------------------------|-----------------------------------------------|
+    /// | `NetworkError_12`        | AESM couldn't reach a backend server          |
+    /// | `NetworkBusyError_13`    | The network is busy                           |
+    /// | `ServiceStopped_17`      | AESM is shutting down or restarting           |
+    /// | `Busy_18`                | AESM is busy with another request             |
+    /// | `BackendServerBusy_19`   | A backend server is busy                      |
+    /// | `UpdateAvailable_20`     | AESM is updating the platform's EPID group    |
+    /// | `OutOfEpc_29`            | EPC is in use by other enclaves               |
+    /// | `ServiceUnavailable_30`  | A service AESM depends on isn't ready yet     |
+    ///
+    /// All other errors are permanent: they indicate an invalid request (e.g.
+    /// `ParameterError_3`, `InvalidReport_47`), a request the platform doesn't
+    /// support (e.g. `UnsupportedAttKeyId_38`, `NoDeviceError_2`), missing
+    /// permissions (e.g. `FileAccessError_15`) or a broken platform that
+    /// needs intervention (e.g. `EpidRevokedError_5`, `EnclaveLoadError_48`).
+    /// `Unknown` codes are treated as permanent.
+    pub fn is_transient(&self) -> bool {
+        use self::AesmError::*;
+        matches!(
+            *self,
+            NetworkError_12 | NetworkBusyError_13 | ServiceStopped_17 | Busy_18 | BackendServerBusy_19
+                | UpdateAvailable_20 | OutOfEpc_29 | ServiceUnavailable_30
+        )
+    }
+
+    /// If this error means that AESM can't produce DCAP quotes, the reason.
+    pub fn dcap_unavailable_reason(&self) -> Option<DcapUnavailableReason> {
+        use self::AesmError::*;
+        match *self {
+            InterfaceUnavailable_40 => Some(DcapUnavailableReason::EcdsaUnsupported),
+            PlatformLibUnavailable_41 => Some(DcapUnavailableReason::QuoteProviderMissing),
+            AttKeyNotInitialized_42 | NoPlatformCertData_44 => Some(DcapUnavailableReason::AttestationKeyUnavailable),
+            _ => None,
+        }
+    }
+}
+
 impl Error {
+    /// Whether this error is likely to go away if the request is retried
+    /// later.
+    ///
+    /// This is the case for transient AESM error codes, see
+    /// `AesmError::is_transient`, and for the connection to AESM being closed
+    /// or reset. All other errors are permanent. `Error::Attestation` is
+    /// transient if the error of the failed step is.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            Error::AesmCode(ref code) => code.is_transient(),
+            Error::Attestation { ref error, .. } => error.is_transient(),
+            Error::AesmCommunication(ref err) => matches!(
+                err.kind(),
+                ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+
+    /// Maps AESM error codes that mean DCAP attestation isn't available to
+    /// `Error::DcapUnavailable`. Other errors are returned unchanged.
+    pub(crate) fn dcap_unavailable(self) -> Error {
+        match self {
+            Error::AesmCode(code) => match code.dcap_unavailable_reason() {
+                Some(reason) => Error::DcapUnavailable(reason),
+                None => Error::AesmCode(code),
+            },
+            err => err,
+        }
+    }
+
+    #[cfg(feature = "verify")]
+    pub(crate) fn at_step(step: AttestationStep) -> impl FnOnce(Error) -> Error {
+        move |error| Error::Attestation { step, error: Box::new(error) }
+    }
+
     pub fn aesm_code(code: u32) -> Error {
         Error::AesmCode(code.into())
     }
@@ -157,3 +409,62 @@ impl Error {
         Error::AesmBadResponse(expected.to_owned())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aesm_error_is_transient() {
+        // Every code AESM may return, so that new codes are classified
+        // explicitly.
+        let transient = [12, 13, 17, 18, 19, 20, 29, 30];
+        for code in 1..=50 {
+            let error = AesmError::from(code);
+            if let AesmError::Unknown(_) = error {
+                panic!("code {} is not covered by AesmError", code);
+            }
+            assert_eq!(error.is_transient(), transient.contains(&code), "{:?}", error);
+        }
+        assert!(!AesmError::Unknown(51).is_transient());
+    }
+
+    #[test]
+    fn error_is_transient() {
+        let table = [
+            (Error::aesm_code(18), true),
+            (Error::aesm_code(3), false),
+            (IoError::from(ErrorKind::BrokenPipe).into(), true),
+            (IoError::from(ErrorKind::ConnectionReset).into(), true),
+            (IoError::from(ErrorKind::ConnectionAborted).into(), true),
+            (IoError::from(ErrorKind::UnexpectedEof).into(), true),
+            (IoError::from(ErrorKind::NotFound).into(), false),
+            (IoError::from(ErrorKind::PermissionDenied).into(), false),
+            (Error::aesm_bad_response("InitQuoteResponse"), false),
+            (Error::InvalidArgument { field: "spid", expected: 16, got: 0 }, false),
+            (Error::UnsupportedOption("socket_path"), false),
+        ];
+        for (error, transient) in &table {
+            assert_eq!(error.is_transient(), *transient, "{:?}", error);
+        }
+    }
+    #[test]
+    fn dcap_unavailable() {
+        use self::DcapUnavailableReason::*;
+        let table = [
+            (40, Some(EcdsaUnsupported)),
+            (41, Some(QuoteProviderMissing)),
+            (42, Some(AttestationKeyUnavailable)),
+            (44, Some(AttestationKeyUnavailable)),
+        ];
+        for code in 1..=51 {
+            let expected = table.iter().find(|&&(c, _)| c == code).and_then(|&(_, reason)| reason);
+            match (Error::aesm_code(code).dcap_unavailable(), expected) {
+                (Error::DcapUnavailable(reason), Some(expected)) => assert_eq!(reason, expected, "code {}", code),
+                (Error::AesmCode(err), None) => assert_eq!(err, AesmError::from(code)),
+                (err, _) => panic!("code {} mapped to {:?}", code, err),
+            }
+        }
+        assert!(matches!(Error::Timeout { request: None }.dcap_unavailable(), Error::Timeout { request: None }));
+    }
+}
@@ -0,0 +1,91 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Formatting of byte buffers in `Debug` impls, so that quotes and the like
+//! don't flood logs.
+
+use std::fmt;
+
+const PREFIX_LEN: usize = 4;
+const SUFFIX_LEN: usize = 2;
+
+/// Formats as the length and the first and last few bytes in hex, e.g.
+/// `1456 bytes [03 00 02 00 … a1 ff]`. The alternate flag (`{:#?}`) prints
+/// all bytes.
+pub(crate) struct HexBytes<'a>(pub &'a [u8]);
+
+impl<'a> fmt::Debug for HexBytes<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.0;
+        write!(f, "{} bytes [", bytes.len())?;
+        if f.alternate() || bytes.len() <= PREFIX_LEN + SUFFIX_LEN {
+            write_hex(f, bytes)?;
+        } else {
+            write_hex(f, &bytes[..PREFIX_LEN])?;
+            f.write_str(" … ")?;
+            write_hex(f, &bytes[bytes.len() - SUFFIX_LEN..])?;
+        }
+        f.write_str("]")
+    }
+}
+
+/// Formats as the length only, for fields such as SPIDs that shouldn't end
+/// up in logs, even with the alternate flag.
+pub(crate) struct Redacted<'a>(pub &'a [u8]);
+
+impl<'a> fmt::Debug for Redacted<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes [redacted]", self.0.len())
+    }
+}
+
+fn write_hex(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    for (i, b) in bytes.iter().enumerate() {
+        if i != 0 {
+            f.write_str(" ")?;
+        }
+        write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_bytes() {
+        assert_eq!(format!("{:?}", HexBytes(&[])), "0 bytes []");
+        assert_eq!(format!("{:?}", HexBytes(&[1, 2, 3, 4, 5, 0xff])), "6 bytes [01 02 03 04 05 ff]");
+        assert_eq!(format!("{:?}", HexBytes(&[1, 2, 3, 4, 5, 0xa1, 0xff])), "7 bytes [01 02 03 04 … a1 ff]");
+        assert_eq!(format!("{:#?}", HexBytes(&[1, 2, 3, 4, 5, 0xa1, 0xff])), "7 bytes [01 02 03 04 05 a1 ff]");
+    }
+
+    #[test]
+    fn quote_types() {
+        let mut quote = vec![0; 1456];
+        quote[..4].copy_from_slice(&[3, 0, 2, 0]);
+        quote[1454..].copy_from_slice(&[0xa1, 0xff]);
+        let quote = crate::QuoteResult::new(quote, vec![1; 384]);
+        assert_eq!(
+            format!("{:?}", quote),
+            "QuoteResult { quote: 1456 bytes [03 00 02 00 … a1 ff], qe_report: 384 bytes [01 01 01 01 … 01 01] }"
+        );
+        assert!(format!("{:#?}", quote).len() > 3 * 1456);
+
+        let quote_info = crate::QuoteInfoEx::new(vec![], vec![2; 512], vec![3; 4], 1000).unwrap();
+        assert_eq!(
+            format!("{:?}", quote_info),
+            "QuoteInfoEx { att_key_id: 0 bytes [], target_info: 512 bytes [02 02 02 02 … 02 02], pub_key: 4 bytes [03 03 03 03], quote_size: 1000 }"
+        );
+    }
+
+    #[test]
+    fn redacted() {
+        assert_eq!(format!("{:?}", Redacted(&[1; 16])), "16 bytes [redacted]");
+        assert_eq!(format!("{:#?}", Redacted(&[1; 16])), "16 bytes [redacted]");
+    }
+}
@@ -1,71 +1,315 @@
-use imp::AesmClient;
-pub use error::{AesmError, Error, Result};
+use crate::imp::AesmClient;
+pub use crate::error::{AesmError, Error, Result};
 use protobuf::Message;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::mem::size_of;
-use byteorder::{LittleEndian, NativeEndian, ReadBytesExt, WriteBytesExt};
-use {
-    quote_buffer_size, AesmRequest, FromResponse, QuoteInfo, QuoteResult, QuoteType,
-    Request_GetQuoteRequest, Request_InitQuoteRequest, Request_GetSupportedAttKeyIDNumRequest, Request_GetSupportedAttKeyIDsRequest, Request_InitQuoteExRequest, Request_GetQuoteSizeExRequest, Request_GetQuoteExRequest
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use crate::{
+    check_target_info, quote_buffer_size, AesmKeyId, AesmRequestKind, Request, AesmKeyIds, AesmRequest, FromResponse, QuoteInfo, QuoteInfoEx, QuoteResult, QuoteType, UpdateStatus,
+    Request_GetQuoteRequest, Request_InitQuoteRequest, Request_GetSupportedAttKeyIDNumRequest, Request_GetSupportedAttKeyIDsRequest, Request_SelectAttKeyIDRequest, Request_InitQuoteExRequest, Request_GetQuoteSizeExRequest, Request_GetQuoteExRequest, Request_SGXGetExtendedEpidGroupIdRequest,
+    Request_GetPsCapRequest, Request_GetWhiteListSizeRequest, Request_GetWhiteListRequest, Request_SGXRegisterRequest, Request_CheckUpdateStatusRequest, Request_ReportAttestationErrorRequest,
+    Response_GetQuoteResponse, Response_InitQuoteResponse, Response_InitQuoteExResponse, Response_GetQuoteSizeExResponse, Response_GetQuoteExResponse
 };
-// FIXME: remove conditional compilation after resolving https://github.com/fortanix/rust-sgx/issues/31
-#[cfg(not(target_env = "sgx"))]
+#[cfg(feature = "pse")]
+use crate::{Request_CloseSessionRequest, Request_CreateSessionRequest, Request_ExchangeReportRequest, Request_InvokeServiceRequest};
+use crate::metrics::Measurement;
+use self::wire_dump::Direction;
+use std::cmp;
+use std::thread;
 use std::time::Duration;
 
 
+pub(super) mod wire_dump;
+
+/// The size of `sgx_update_info_bit_t` in the Intel SDK.
+const UPDATE_INFO_SIZE: u32 = 12;
+
+/// The `data_type` of an `SGXRegisterRequest` with a white list certificate
+/// chain, `SGX_REGISTER_WHITE_LIST_CERT` in the Intel SDK.
+const REGISTER_WHITE_LIST_CERT: u32 = 0;
+
 /// This timeout is an argument in AESM request protobufs.
 ///
 /// This value should be used for operations that can be completed locally, i.e.
-/// without network interaction. Only the `try_connect()` operation falls into this
-/// category.
-#[allow(unused)]
+/// without network interaction, such as looking up AESM's configuration.
 pub(super) const LOCAL_AESM_TIMEOUT_US: u32 = 1_000_000;
 /// This timeout is an argument in AESM request protobufs.
 ///
 /// This value should be used for operations that might need interaction with
-/// remote servers. All AESM requests fall into this category, because they either
-/// always require interaction with a remote server or can trigger an initialization
-/// step that involves communication with a remote server.
+/// remote servers, e.g. to check for updates of the platform's TCB.
 pub(super) const REMOTE_AESM_TIMEOUT_US: u32 = 30_000_000;
 
+// Timeouts for requests that load an enclave, but don't need the network,
+// for requests that generate a quote, which may need a revocation list, and
+// for requests that may provision the platform first.
+const LAUNCH_AESM_TIMEOUT: Duration = Duration::from_secs(10);
+const QUOTE_AESM_TIMEOUT: Duration = Duration::from_secs(60);
+const PROVISIONING_AESM_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How much longer the client waits for a response than AESM is asked to
+/// take, so that AESM can report that it timed out.
+const TRANSPORT_TIMEOUT_MARGIN: Duration = Duration::from_millis(500);
+
+/// A buffer that may contain secrets, such as a serialized request with an
+/// SPID and nonce. With the `zeroize` feature, it's wiped when dropped.
+#[cfg(feature = "zeroize")]
+pub(super) type SecretBytes = zeroize::Zeroizing<Vec<u8>>;
+#[cfg(not(feature = "zeroize"))]
+pub(super) type SecretBytes = Vec<u8>;
+
+#[cfg(feature = "zeroize")]
+pub(super) fn secret_bytes(bytes: Vec<u8>) -> SecretBytes {
+    zeroize::Zeroizing::new(bytes)
+}
+
+#[cfg(not(feature = "zeroize"))]
+pub(super) fn secret_bytes(bytes: Vec<u8>) -> SecretBytes {
+    bytes
+}
+
+/// The delay before the first retry after a transient AESM error.
+pub(super) const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest delay between retries after transient AESM errors.
+pub(super) const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
+/// The delay before the next retry after a transient AESM error, if the
+/// previous retry was delayed by `backoff`.
+pub(super) fn next_retry_backoff(backoff: Duration) -> Duration {
+    cmp::min(backoff.checked_mul(2).unwrap_or(MAX_RETRY_BACKOFF), MAX_RETRY_BACKOFF)
+}
+
+/// The retries left for a request, shared by the blocking and the
+/// asynchronous client, which differ only in how they wait.
+pub(super) struct RetryState {
+    retries: u32,
+    transient_retries: u32,
+    backoff: Duration,
+}
+
+impl RetryState {
+    pub(super) fn new(client: &AesmClient) -> Self {
+        RetryState {
+            retries: client.retries,
+            transient_retries: client.transient_retries,
+            backoff: client.retry_backoff,
+        }
+    }
+
+    /// Whether to retry the request of `kind` after `res`, and if so, how
+    /// long to wait first.
+    pub(super) fn retry_action<T>(&mut self, kind: &str, res: &Result<T>) -> Option<Duration> {
+        match *res {
+            Err(ref err @ Error::AesmCommunication(_)) | Err(ref err @ Error::Timeout { .. }) if self.retries > 0 => {
+                log::warn!("error communicating with AESM for {}, retrying: {}", kind, err);
+                self.retries -= 1;
+                Some(Duration::from_secs(0))
+            }
+            Err(ref err @ Error::AesmCode(_)) if err.is_transient() && self.transient_retries > 0 => {
+                log::warn!("transient error for {}, retrying in {:?}: {}", kind, self.backoff, err);
+                self.transient_retries -= 1;
+                let backoff = self.backoff;
+                self.backoff = next_retry_backoff(backoff);
+                Some(backoff)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How long requests may take, see `AesmClientBuilder::default_timeout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum RequestTimeout {
+    /// The default for the kind of request, see `default_request_timeout`.
+    PerRequest,
+    /// The same for all requests.
+    Fixed(Duration),
+    /// Requests don't time out.
+    Unlimited,
+}
+
+impl RequestTimeout {
+    /// The timeout configured with `AesmClientBuilder`.
+    pub(super) fn from_builder(timeout: Option<Option<Duration>>) -> Self {
+        match timeout {
+            None => RequestTimeout::PerRequest,
+            Some(timeout) => Self::fixed(timeout),
+        }
+    }
+
+    /// `timeout` for all requests, or no timeout if `None`.
+    pub(super) fn fixed(timeout: Option<Duration>) -> Self {
+        timeout.map_or(RequestTimeout::Unlimited, RequestTimeout::Fixed)
+    }
+
+    /// The timeout for requests of `kind`, `None` if they don't time out.
+    pub(super) fn for_request(self, kind: AesmRequestKind) -> Option<Duration> {
+        match self {
+            RequestTimeout::PerRequest => Some(default_request_timeout(kind)),
+            RequestTimeout::Fixed(timeout) => Some(timeout),
+            RequestTimeout::Unlimited => None,
+        }
+    }
+}
+
+/// The timeout for requests of `kind`, unless the client is configured
+/// otherwise.
+pub(super) fn default_request_timeout(kind: AesmRequestKind) -> Duration {
+    use crate::AesmRequestKind::*;
+    match kind {
+        GetExtendedEpidGroupId | GetPsCap | GetWhiteListSize | GetWhiteList | CloseSession => {
+            Duration::from_micros(LOCAL_AESM_TIMEOUT_US as _)
+        }
+        GetLaunchToken | GetSupportedAttKeyIdNum | GetSupportedAttKeyIds | SelectAttKeyId | GetQuoteSizeEx => LAUNCH_AESM_TIMEOUT,
+        GetQuote | GetQuoteEx => QUOTE_AESM_TIMEOUT,
+        InitQuote | InitQuoteEx => PROVISIONING_AESM_TIMEOUT,
+        CreateSession | ExchangeReport | InvokeService | CheckUpdateStatus | ReportAttestationStatus | RegisterWhiteList | Raw => {
+            Duration::from_micros(REMOTE_AESM_TIMEOUT_US as _)
+        }
+    }
+}
+
+/// How long to wait for the response to a request with `timeout`.
+pub(super) fn transport_timeout(timeout: Duration) -> Duration {
+    timeout + TRANSPORT_TIMEOUT_MARGIN
+}
+
+// ECDSA quotes include the PCK certificate chain, but are still only a few
+// kilobytes.
+pub(super) const DEFAULT_MAX_RESPONSE_SIZE: usize = 4 << 20;
+
+/// Check the length prefix of a response against the client's limit, before
+/// allocating a buffer for it.
+pub(super) fn check_response_size(len: u32, limit: usize) -> Result<usize> {
+    let announced = len as usize;
+    if announced > limit {
+        return Err(Error::ResponseTooLarge { limit, announced });
+    }
+    Ok(announced)
+}
+
+/// Convert a timeout to the representation in AESM request protobufs.
+fn timeout_us(timeout: Duration) -> u32 {
+    cmp::min(timeout.as_micros(), u32::MAX as u128) as u32
+}
+
+/// Give `req` the timeout configured for the client for its kind, or remove
+/// its timeout if it shouldn't time out. Returns how long to wait for the
+/// response.
+pub(super) fn set_request_timeout<T: AesmRequest>(req: &mut T, timeout: RequestTimeout) -> Option<Duration> {
+    let timeout = timeout.for_request(T::KIND);
+    match timeout {
+        Some(timeout) => req.set_timeout(timeout_us(timeout)),
+        None => req.clear_timeout(),
+    }
+    timeout.map(transport_timeout)
+}
+
 impl AesmClient {
     pub fn try_connect(&self) -> Result<()> {
         self.open_socket().map(|_| ())
     }
 
-    pub(super) fn transact<T: AesmRequest>(&self, req: T) -> Result<T::Response> {
-        let mut sock = self.open_socket()?;
+    /// Send AESM a trivial request, waiting at most `timeout`. Any response
+    /// counts, including an error code.
+    pub(super) fn probe_request(&self, timeout: Duration) -> Result<()> {
+        match self.with_timeout(timeout)?.get_extended_epid_group_id() {
+            Ok(_) | Err(Error::AesmCode(_)) | Err(Error::MissingExtendedEpidGroupId) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
 
-        // FIXME: remove conditional compilation after resolving https://github.com/fortanix/rust-sgx/issues/31
-        #[cfg(not(target_env = "sgx"))]
-        let _ = sock.set_read_timeout(req.get_timeout().map(|t| Duration::from_micros(t as _)))?;
+    pub(super) fn transact<T: AesmRequest>(&self, req: T) -> Result<T::Response> {
+        let mut measurement = Measurement::start(T::KIND);
+        let res = self.transact_measured(req, &mut measurement);
+        measurement.finish(self.metrics_hook.as_ref(), &res);
+        res
+    }
 
-        // impl Write appends to the vector. Reserve space to fill in the
-        // length after serializing.
-        let mut req_bytes = vec![0u8; size_of::<u32>()];
-        req.into()
-            .write_to_writer(&mut req_bytes)
-            .expect("Failed to serialize protobuf");
-        let req_len = (req_bytes.len() - size_of::<u32>()) as u32;
-        (&mut req_bytes[0..size_of::<u32>()]).write_u32::<NativeEndian>(req_len)?;
-        sock.write_all(&req_bytes)?;
+    fn transact_measured<T: AesmRequest>(&self, req: T, measurement: &mut Measurement) -> Result<T::Response> {
+        let kind = request_kind::<T>();
+        let (req_bytes, timeout) = self.prepare_request(req, measurement)?;
+        let mut retry = RetryState::new(self);
+        loop {
+            let res = self.exchange(&req_bytes, timeout);
+            let res = self.decode_exchanged::<T>(res, measurement);
+            match retry.retry_action(kind, &res) {
+                Some(delay) => thread::sleep(delay),
+                None => return log_result(kind, res),
+            }
+        }
+    }
 
-        let res_len = sock.read_u32::<NativeEndian>()?;
-        let mut res_bytes = vec![0; res_len as usize];
-        sock.read_exact(&mut res_bytes)?;
+    /// Serialize `req` with the client's timeout for it. Returns the request
+    /// and how long to wait for the response.
+    pub(super) fn prepare_request<T: AesmRequest>(&self, mut req: T, measurement: &mut Measurement) -> Result<(SecretBytes, Option<Duration>)> {
+        let timeout = set_request_timeout(&mut req, self.timeout);
+        let req_bytes = encode_request(req)?;
+        measurement.request_size = req_bytes.len();
+        wire_dump::dump(self.wire_dump, Direction::Request, request_kind::<T>(), &req_bytes[size_of::<u32>()..]);
+        log::debug!("sending {} to AESM ({} bytes)", request_kind::<T>(), req_bytes.len());
+        Ok((req_bytes, timeout))
+    }
 
-        let res = T::Response::from_response(Message::parse_from_bytes(&res_bytes))?;
-        Ok(res)
+    /// Parse the result of exchanging a request of type `T` with AESM.
+    pub(super) fn decode_exchanged<T: AesmRequest>(&self, res: Result<SecretBytes>, measurement: &mut Measurement) -> Result<T::Response> {
+        let kind = request_kind::<T>();
+        res.map_err(timeout_error(T::KIND)).and_then(|res| {
+            log::debug!("received response to {} from AESM ({} bytes)", kind, res.len());
+            measurement.response_size = Some(res.len());
+            wire_dump::dump(self.wire_dump, Direction::Response, kind, &res);
+            decode_response::<T>(&res)
+        })
     }
 
-    pub fn init_quote(&self) -> Result<QuoteInfo> {
-        let mut req = Request_InitQuoteRequest::new();
-        req.set_timeout(REMOTE_AESM_TIMEOUT_US);
-        let mut res = self.transact(req)?;
+    #[cfg(feature = "unstable")]
+    pub fn raw_request(&self, req: &[u8], timeout: Option<Duration>) -> Result<Vec<u8>> {
+        let mut req_bytes = secret_bytes(Vec::with_capacity(size_of::<u32>() + req.len()));
+        req_bytes.write_u32::<NativeEndian>(req.len() as u32)?;
+        req_bytes.extend_from_slice(req);
+        let mut measurement = Measurement::start(AesmRequestKind::Raw);
+        measurement.request_size = req_bytes.len();
+        wire_dump::dump(self.wire_dump, Direction::Request, "raw request", req);
+        let timeout = timeout.or_else(|| self.timeout.for_request(AesmRequestKind::Raw).map(transport_timeout));
+        let res = self.exchange(&req_bytes, timeout).map_err(timeout_error(AesmRequestKind::Raw)).map(|res| {
+            measurement.response_size = Some(res.len());
+            wire_dump::dump(self.wire_dump, Direction::Response, "raw request", &res);
+            res.to_vec()
+        });
+        measurement.finish(self.metrics_hook.as_ref(), &res);
+        res
+    }
 
-        let (target_info, gid) = (res.take_targetInfo(), res.take_gid());
+    fn exchange(&self, req_bytes: &[u8], timeout: Option<Duration>) -> Result<SecretBytes> {
+        self.with_socket(|sock| {
+            // The deadlines bound every read and write, so a wedged AESM that
+            // accepted the connection can't block the client forever. They're
+            // set for every request, as clients with different timeouts share
+            // the connection.
+            // FIXME: remove conditional compilation after resolving https://github.com/fortanix/rust-sgx/issues/31
+            #[cfg(not(target_env = "sgx"))]
+            {
+                sock.set_read_timeout(timeout)?;
+                sock.set_write_timeout(timeout)?;
+            }
+
+            // These retry reads and writes that are interrupted by a signal,
+            // so a client without timeout keeps waiting.
+            sock.write_all(req_bytes)?;
+
+            // Once AESM started responding, it has acted on the request, so
+            // losing the connection mustn't make the caller retry it.
+            let mut res = CountingReader { inner: sock, received: 0 };
+            read_response(&mut res, self.max_response_size).map_err(|err| match err {
+                ref err if res.received > 0 && is_disconnect(err) => Error::PartialResponse { received: res.received },
+                err => err,
+            })
+        })
+    }
 
-        Ok(QuoteInfo { target_info, pub_key_id: gid })
+    pub fn init_quote(&self) -> Result<QuoteInfo> {
+        let res = self.transact(init_quote_request())?;
+        init_quote_result(res)
     }
 
     pub fn get_quote(
@@ -74,155 +318,513 @@ impl AesmClient {
         spid: Vec<u8>,
         sig_rl: Vec<u8>,
         quote_type: QuoteType,
-        nonce: Vec<u8>,
+        nonce: Option<Vec<u8>>,
     ) -> Result<QuoteResult> {
-        let mut req = Request_GetQuoteRequest::new();
-        req.set_report(report);
-        req.set_quote_type(quote_type.into());
-        req.set_spid(spid);
-        req.set_nonce(nonce);
-        req.set_buf_size(quote_buffer_size(&sig_rl));
-        if sig_rl.len() != 0 {
-            req.set_sig_rl(sig_rl);
+        let qe_report_requested = nonce.is_some();
+        let res = self.transact(get_quote_request(report, spid, sig_rl, quote_type, nonce))?;
+        get_quote_result(res, qe_report_requested)
+    }
+
+    // Similar functionality to sgx_get_extended_epid_group_id in the Intel SGX
+    // Developer Reference.
+    pub fn get_extended_epid_group_id(&self) -> Result<u32> {
+        let req = Request_SGXGetExtendedEpidGroupIdRequest::new();
+
+        let res = self.transact(req)?;
+        if !res.has_x_group_id() {
+            return Err(Error::MissingExtendedEpidGroupId);
         }
-        req.set_qe_report(true);
+        Ok(res.get_x_group_id())
+    }
+
+    // Similar functionality to sgx_get_ps_cap in the Intel SGX Developer
+    // Reference.
+    pub fn get_ps_cap(&self) -> Result<u64> {
+        let req = Request_GetPsCapRequest::new();
+
+        let res = self.transact(req)?;
+        if !res.has_ps_cap() {
+            return Err(Error::aesm_bad_response("platform services capabilities"));
+        }
+        Ok(res.get_ps_cap())
+    }
+
+    // The requests below are sent by the untrusted runtime of the Intel SDK
+    // for sessions with the PSE, see `PseSession`.
 
-        req.set_timeout(REMOTE_AESM_TIMEOUT_US);
+    /// Returns the session ID and the first DH message.
+    #[cfg(feature = "pse")]
+    pub fn create_session(&self) -> Result<(u32, Vec<u8>)> {
+        let mut req = Request_CreateSessionRequest::new();
+        req.set_dh_msg1_size(crate::pse::DH_MSG1_SIZE);
 
         let mut res = self.transact(req)?;
+        if !res.has_session_id() {
+            return Err(Error::aesm_bad_response("PSE session ID"));
+        }
+        if !res.has_se_dh_msg1() {
+            return Err(Error::aesm_bad_response("first DH message"));
+        }
+        Ok((res.get_session_id(), res.take_se_dh_msg1()))
+    }
 
-        let (mut quote, qe_report) = (res.take_quote(), res.take_qe_report());
+    /// Returns the third DH message.
+    #[cfg(feature = "pse")]
+    pub fn exchange_report(&self, session_id: u32, dh_msg2: Vec<u8>) -> Result<Vec<u8>> {
+        let mut req = Request_ExchangeReportRequest::new();
+        req.set_session_id(session_id);
+        req.set_se_dh_msg2(dh_msg2);
+        req.set_se_dh_msg3_size(crate::pse::DH_MSG3_SIZE);
 
-        // AESM allocates a buffer of the size we supplied and returns the whole
-        // thing to us, regardless of how much space QE needed. Trim the excess.
-        // The signature length is a little endian word at offset 432 in the quote
-        // structure. See "QUOTE Structure" in the IAS API Spec.
-        let sig_len = (&quote[432..436]).read_u32::<LittleEndian>().unwrap();
-        let new_len = 436 + sig_len as usize;
-        if quote.len() < new_len {
-            // Quote is already too short, should not happen.
-            // Probably we are interpreting the quote structure incorrectly.
-            return Err(Error::InvalidQuoteSize);
+        let mut res = self.transact(req)?;
+        if !res.has_se_dh_msg3() {
+            return Err(Error::aesm_bad_response("third DH message"));
         }
-        quote.truncate(new_len);
+        Ok(res.take_se_dh_msg3())
+    }
+
+    /// The message identifies the session, so the request doesn't.
+    #[cfg(feature = "pse")]
+    pub fn invoke_service(&self, message: Vec<u8>, response_size: u32) -> Result<Vec<u8>> {
+        let mut req = Request_InvokeServiceRequest::new();
+        req.set_pse_message(message);
+        req.set_pse_resp_size(response_size);
 
-        Ok(QuoteResult::new(quote, qe_report))
+        let mut res = self.transact(req)?;
+        if !res.has_pse_message() {
+            return Err(Error::aesm_bad_response("PSE message"));
+        }
+        Ok(res.take_pse_message())
     }
 
-    // Similar functionality to sgx_get_supported_att_key_ids in page 177 at https://download.01.org/intel-sgx/sgx-linux/2.9.1/docs/Intel_SGX_Developer_Reference_Linux_2.9.1_Open_Source.pdf
-    pub fn get_supported_att_key_ids(&self) -> Result<Vec<Vec<u8>>> {
-        // expected key id length - size of C structure - sgx_att_key_id_ext_t
-        const SGX_KEY_ID_SIZE : u32 = 256;
+    #[cfg(feature = "pse")]
+    pub fn close_session(&self, session_id: u32) -> Result<()> {
+        let mut req = Request_CloseSessionRequest::new();
+        req.set_session_id(session_id);
 
-        #[allow(unused_mut)]
-        let mut req = Request_GetSupportedAttKeyIDNumRequest::new();
+        self.transact(req)?;
+        Ok(())
+    }
 
-        // FIXME: remove conditional compilation after resolving https://github.com/fortanix/rust-sgx/issues/31
-        #[cfg(not(target_env = "sgx"))]
-        req.set_timeout(REMOTE_AESM_TIMEOUT_US);
+    // Similar functionality to sgx_get_whitelist_size in the Intel SGX
+    // Developer Reference.
+    pub fn get_white_list_size(&self) -> Result<u32> {
+        let req = Request_GetWhiteListSizeRequest::new();
 
         let res = self.transact(req)?;
-        let num_key_ids : u32 = res.get_att_key_id_num();
+        if !res.has_white_list_size() {
+            return Err(Error::aesm_bad_response("white list size"));
+        }
+        Ok(res.get_white_list_size())
+    }
 
-        let mut req = Request_GetSupportedAttKeyIDsRequest::new();
+    // Similar functionality to sgx_get_whitelist in the Intel SGX Developer
+    // Reference.
+    pub fn get_white_list(&self, white_list_size: u32) -> Result<Vec<u8>> {
+        let mut req = Request_GetWhiteListRequest::new();
+        req.set_white_list_size(white_list_size);
+
+        let mut res = self.transact(req)?;
+        let white_list = res.take_white_list();
+        if white_list.len() as u32 != white_list_size {
+            return Err(Error::aesm_bad_response(&format!("{}-byte white list", white_list_size)));
+        }
+        Ok(white_list)
+    }
+
+    // Similar functionality to sgx_register_wl_cert_chain in the Intel SGX
+    // Developer Reference.
+    pub fn register_white_list(&self, cert_chain: Vec<u8>) -> Result<()> {
+        let mut req = Request_SGXRegisterRequest::new();
+        req.set_buf(cert_chain);
+        req.set_data_type(REGISTER_WHITE_LIST_CERT);
+
+        self.transact(req)?;
+        Ok(())
+    }
+
+    // Similar functionality to sgx_check_update_status in the Intel SGX
+    // Developer Reference. AESM reports that an update is available with the
+    // `UpdateAvailable` error code, along with the update info.
+    pub fn check_update_status(&self, platform_info: Option<Vec<u8>>) -> Result<UpdateStatus> {
+        let mut req = Request_CheckUpdateStatusRequest::new();
+        if let Some(platform_info) = platform_info {
+            req.set_platform_info(platform_info);
+        }
+        req.set_update_info_size(UPDATE_INFO_SIZE);
+        req.set_config(0);
+
+        let res = self.transact(req)?;
+        let update_available = AesmError::from(res.get_errorCode()) == AesmError::UpdateAvailable_20;
+        let status = if res.has_status() { Some(res.get_status()) } else { None };
+        Ok(UpdateStatus::new(update_available, res.get_platform_update_info(), status))
+    }
 
-        // FIXME: remove conditional compilation after resolving https://github.com/fortanix/rust-sgx/issues/31
-        #[cfg(not(target_env = "sgx"))]
-        req.set_timeout(REMOTE_AESM_TIMEOUT_US);
+    // Similar functionality to sgx_report_attestation_status in the Intel SGX
+    // Developer Reference. Like for `check_update_status`, AESM reports that
+    // an update is needed with the `UpdateAvailable` error code.
+    pub fn report_attestation_status(&self, platform_info: Vec<u8>, attestation_error: u32) -> Result<UpdateStatus> {
+        let mut req = Request_ReportAttestationErrorRequest::new();
+        req.set_platform_info(platform_info);
+        req.set_attestation_error_code(attestation_error);
+        req.set_update_info_size(UPDATE_INFO_SIZE);
+
+        let res = self.transact(req)?;
+        let update_available = AesmError::from(res.get_errorCode()) == AesmError::UpdateAvailable_20;
+        Ok(UpdateStatus::new(update_available, res.get_platform_update_info(), None))
+    }
+
+    // Similar functionality to sgx_get_supported_att_key_id_num in page 176 at https://download.01.org/intel-sgx/sgx-linux/2.9.1/docs/Intel_SGX_Developer_Reference_Linux_2.9.1_Open_Source.pdf
+    pub fn get_supported_att_key_id_num(&self) -> Result<u32> {
+        let req = Request_GetSupportedAttKeyIDNumRequest::new();
+
+        let res = self.transact(req)?;
+        if !res.has_att_key_id_num() {
+            return Err(Error::aesm_bad_response("number of attestation key IDs"));
+        }
+        Ok(res.get_att_key_id_num())
+    }
+
+    // Similar functionality to sgx_get_supported_att_key_ids in page 177 at https://download.01.org/intel-sgx/sgx-linux/2.9.1/docs/Intel_SGX_Developer_Reference_Linux_2.9.1_Open_Source.pdf
+    pub fn get_supported_att_key_ids(&self) -> Result<AesmKeyIds> {
+        let num_key_ids : u32 = self.get_supported_att_key_id_num()?;
+
+        let mut req = Request_GetSupportedAttKeyIDsRequest::new();
 
         // sgx defined protobuf returns an opaque byte array with size we give it. Byte array MUST have expected size of structure size*num.
-        let expected_buffer_size : u32 = num_key_ids * SGX_KEY_ID_SIZE;
+        let expected_buffer_size : u32 = num_key_ids * AesmKeyId::SIZE as u32;
         req.set_buf_size(expected_buffer_size);
 
         let mut res = self.transact(req)?;
 
         let key_ids_blob = res.take_att_key_ids();
         if key_ids_blob.len() as u32 != expected_buffer_size {
-            return Err(Error::aesm_bad_response("wrong buffer size returned by aesm service"));
+            return Err(Error::aesm_bad_response(&format!("{}-byte attestation key ID list", expected_buffer_size)));
         }
 
-        let key_ids = key_ids_blob.chunks_exact(SGX_KEY_ID_SIZE as usize).map(Vec::from).collect();
-        
-        Ok(key_ids)
+        AesmKeyIds::new(key_ids_blob)
     }
-    
-    // Similar functionality to sgx_init_quote_ex in page 165 at https://download.01.org/intel-sgx/sgx-linux/2.9.1/docs/Intel_SGX_Developer_Reference_Linux_2.9.1_Open_Source.pdf 
-    pub fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfo> {
-        let mut req = Request_InitQuoteExRequest::new();
-
-        // FIXME: remove conditional compilation after resolving https://github.com/fortanix/rust-sgx/issues/31
-        #[cfg(not(target_env = "sgx"))]
-        req.set_timeout(REMOTE_AESM_TIMEOUT_US);
 
-        req.set_att_key_id(att_key_id.clone());
-        req.set_b_pub_key_id(false);
-        
-        let res = self.transact(req)?;
-        let buf_size = res.get_pub_key_id_size();
+    // Similar functionality to sgx_select_att_key_id in the Intel SGX
+    // Developer Reference.
+    pub fn select_att_key_id(&self, key_id_list: Vec<u8>) -> Result<AesmKeyId> {
+        let mut req = Request_SelectAttKeyIDRequest::new();
+        if !key_id_list.is_empty() {
+            req.set_att_key_id_list(key_id_list);
+        }
 
-        let mut req = Request_InitQuoteExRequest::new();
-        
-        req.set_att_key_id(att_key_id);
-        req.set_b_pub_key_id(true);
-        req.set_buf_size(buf_size);
         let mut res = self.transact(req)?;
+        select_att_key_id_result(res.take_selected_att_key_id())
+    }
 
-        Ok(QuoteInfo { target_info : res.take_target_info(),
-                         pub_key_id : res.take_pub_key_id(),
-        })
+    // Similar functionality to sgx_init_quote_ex in page 165 at https://download.01.org/intel-sgx/sgx-linux/2.9.1/docs/Intel_SGX_Developer_Reference_Linux_2.9.1_Open_Source.pdf
+    pub fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfoEx> {
+        // Like sgx_init_quote_ex, ask for the size of the public key ID
+        // first, then for the ID itself.
+        let res = self.transact(init_quote_ex_request(att_key_id.clone(), None))?;
+        let pub_key_id_size = init_quote_ex_pub_key_id_size(&res)?;
+
+        let res = self.transact(init_quote_ex_request(att_key_id.clone(), Some(pub_key_id_size)))?;
+
+        // Also get the expected quote size for given key id, so that
+        // get_quote_ex doesn't have to.
+        let quote_size = self.get_quote_size_ex(att_key_id.clone())?;
+
+        init_quote_ex_result(att_key_id, res, pub_key_id_size, quote_size)
+    }
+
+    // Similar functionality to sgx_get_quote_size_ex, see the developer reference linked above
+    pub fn get_quote_size_ex(&self, att_key_id: Vec<u8>) -> Result<u32> {
+        let res = self.transact(get_quote_size_ex_request(att_key_id))?;
+        get_quote_size_ex_result(res)
     }
 
     // Similar functionality to sgx_get_quote_ex in page 173 at https://download.01.org/intel-sgx/sgx-linux/2.9.1/docs/Intel_SGX_Developer_Reference_Linux_2.9.1_Open_Source.pdf
     pub fn get_quote_ex(
         &self,
-        att_key_id: Vec<u8>,
-        report: Vec<u8>,
-        target_info: Vec<u8>,
-        nonce: Vec<u8>
+        quote_info: &QuoteInfoEx,
+        report: &[u8],
+        target_info: &[u8],
+        nonce: &[u8]
     ) -> Result<QuoteResult> {
-        // First request - get the expected quote size for given key id.
-        let mut req = Request_GetQuoteSizeExRequest::new();
+        let att_key_id = quote_info.att_key_id().to_owned();
+        let (req, report_start) = get_quote_ex_request(att_key_id.clone(), report, target_info, nonce, quote_info.quote_size());
+        match self.transact(req) {
+            Err(err @ Error::AesmCode(AesmError::ParameterError_3)) => {
+                // The buffer may be too small because the quote size changed
+                // since init_quote_ex. If so, try again with the new size.
+                let buf_size = self.get_quote_size_ex(att_key_id.clone())?;
+                if buf_size <= quote_info.quote_size() {
+                    return Err(err);
+                }
+                let (req, report_start) = get_quote_ex_request(att_key_id, report, target_info, nonce, buf_size);
+                let res = self.transact(req)?;
+                get_quote_ex_result(res, report_start)
+            }
+            res => get_quote_ex_result(res?, report_start),
+        }
+    }
+}
 
-        // FIXME: remove conditional compilation after resolving https://github.com/fortanix/rust-sgx/issues/31
-        #[cfg(not(target_env = "sgx"))]
-        req.set_timeout(REMOTE_AESM_TIMEOUT_US);
-        req.set_att_key_id(att_key_id.clone());
+// The functions below build AESM requests and interpret the responses. They
+// don't perform any I/O, so that the blocking and the asynchronous clients can
+// share them.
+
+/// Serialize a request, prefixed with its length, as expected by AESM.
+pub(super) fn encode_request<T: AesmRequest>(req: T) -> Result<SecretBytes> {
+    // impl Write appends to the vector. Reserve space to fill in the
+    // length after serializing.
+    let mut req_bytes = secret_bytes(vec![0u8; size_of::<u32>()]);
+    let buf: &mut Vec<u8> = &mut req_bytes;
+    #[allow(unused_mut)]
+    let mut req: Request = req.into();
+    req.write_to_writer(buf)
+        .expect("Failed to serialize protobuf");
+    #[cfg(feature = "zeroize")]
+    zeroize_request(&mut req);
+    let req_len = (req_bytes.len() - size_of::<u32>()) as u32;
+    (&mut req_bytes[0..size_of::<u32>()]).write_u32::<NativeEndian>(req_len)?;
+    Ok(req_bytes)
+}
 
-        let res = self.transact(req)?;
+/// Wipe the secrets in a request after it has been serialized.
+#[cfg(feature = "zeroize")]
+pub(super) fn zeroize_request(req: &mut Request) {
+    use zeroize::Zeroize;
 
-        if !res.has_quote_size() {
-            return Err(Error::AesmBadResponse("Size is not provided by AESM Service in GetQuoteSizeEx reply".to_string()));
+    if req.has_getQuoteReq() {
+        let req = req.mut_getQuoteReq();
+        req.mut_spid().zeroize();
+        req.mut_nonce().zeroize();
+    }
+    if req.has_getQuoteExReq() {
+        // Starts with the nonce.
+        req.mut_getQuoteExReq().mut_qe_report_info().zeroize();
+    }
+}
+
+/// Whether `err` means that AESM closed the connection.
+pub(super) fn is_disconnect(err: &Error) -> bool {
+    match err {
+        Error::AesmCommunication(err) => matches!(
+            err.kind(),
+            ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::UnexpectedEof
+        ),
+        _ => false,
+    }
+}
+
+/// Counts the bytes read from `inner`.
+struct CountingReader<'a, R> {
+    inner: &'a mut R,
+    received: usize,
+}
+
+impl<'a, R: Read> Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.received += n;
+        Ok(n)
+    }
+}
+
+/// Read a length-prefixed response of at most `limit` bytes.
+fn read_response<R: Read>(sock: &mut R, limit: usize) -> Result<SecretBytes> {
+    let res_len = check_response_size(sock.read_u32::<NativeEndian>()?, limit)?;
+    let mut res_bytes = secret_bytes(vec![0; res_len]);
+    sock.read_exact(&mut res_bytes[..])?;
+    Ok(res_bytes)
+}
+
+/// Maps reads and writes that missed the socket's deadline, which fail with
+/// `WouldBlock` or `TimedOut` depending on the platform, to `Error::Timeout`.
+pub(super) fn timeout_error(request: AesmRequestKind) -> impl FnOnce(Error) -> Error {
+    move |err| match err {
+        Error::AesmCommunication(ref err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            Error::Timeout { request: Some(request) }
         }
-        let buf_size = res.get_quote_size();
+        err => err,
+    }
+}
+
+/// The name of a request type for logging, e.g. `GetQuoteRequest`.
+pub(super) fn request_kind<T: AesmRequest>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.rsplit("::").next().unwrap_or(name);
+    name.trim_start_matches("Request_")
+}
+
+/// Log the error code of a failed request.
+pub(super) fn log_result<T>(kind: &str, res: Result<T>) -> Result<T> {
+    if let Err(Error::AesmCode(ref code)) = res {
+        log::debug!("AESM returned {:?} for {}", code, kind);
+    }
+    res
+}
+
+/// Parse the body of a response to a request of type `T`.
+pub(super) fn decode_response<T: AesmRequest>(res_bytes: &[u8]) -> Result<T::Response> {
+    T::Response::from_response(Message::parse_from_bytes(res_bytes))
+}
 
-        // second request - get the actual quote
-        let mut req = Request_GetQuoteExRequest::new();
-        
-        // FIXME: remove conditional compilation after resolving https://github.com/fortanix/rust-sgx/issues/31
-        #[cfg(not(target_env = "sgx"))]
-        req.set_timeout(REMOTE_AESM_TIMEOUT_US);
+pub(super) fn init_quote_request() -> Request_InitQuoteRequest {
+    Request_InitQuoteRequest::new()
+}
+
+pub(super) fn init_quote_result(mut res: Response_InitQuoteResponse) -> Result<QuoteInfo> {
+    let (target_info, gid) = (check_target_info(res.take_targetInfo())?, res.take_gid());
+
+    Ok(QuoteInfo { target_info, pub_key_id: gid })
+}
 
+/// Without a nonce, AESM doesn't return a QE report.
+pub(super) fn get_quote_request(
+    report: Vec<u8>,
+    spid: Vec<u8>,
+    sig_rl: Vec<u8>,
+    quote_type: QuoteType,
+    nonce: Option<Vec<u8>>,
+) -> Request_GetQuoteRequest {
+    let mut req = Request_GetQuoteRequest::new();
+    req.set_report(report);
+    req.set_quote_type(quote_type.into());
+    req.set_spid(spid);
+    if let Some(nonce) = nonce {
+        req.set_nonce(nonce);
+        req.set_qe_report(true);
+    }
+    req.set_buf_size(quote_buffer_size(&sig_rl));
+    if sig_rl.len() != 0 {
+        req.set_sig_rl(sig_rl);
+    }
+
+    req
+}
+
+pub(super) fn get_quote_result(mut res: Response_GetQuoteResponse, qe_report_requested: bool) -> Result<QuoteResult> {
+    if !res.has_quote() {
+        return Err(Error::aesm_bad_response("quote"));
+    }
+    let quote = res.take_quote();
+    let qe_report = if qe_report_requested { Some(res.take_qe_report()) } else { None };
+
+    Ok(QuoteResult::from_untrimmed_quote(quote, qe_report))
+}
+
+// In the requests for quotes with an attestation key ID below, an empty key ID
+// selects the default attestation key of the platform.
+
+pub(super) fn select_att_key_id_result(key: Vec<u8>) -> Result<AesmKeyId> {
+    if key.is_empty() {
+        return Err(Error::aesm_bad_response("selected attestation key ID"));
+    }
+    if key.len() != AesmKeyId::SIZE {
+        return Err(Error::MalformedResponse { field: "selected_att_key_id", reason: "wrong size", raw: key });
+    }
+    Ok(AesmKeyId { key })
+}
+
+/// Without `pub_key_id_size`, AESM only reports the size of the public key
+/// ID. Pass the reported size to actually obtain the public key ID.
+pub(super) fn init_quote_ex_request(att_key_id: Vec<u8>, pub_key_id_size: Option<u64>) -> Request_InitQuoteExRequest {
+    let mut req = Request_InitQuoteExRequest::new();
+
+    if !att_key_id.is_empty() {
         req.set_att_key_id(att_key_id);
+    }
+    req.set_b_pub_key_id(pub_key_id_size.is_some());
+    if let Some(buf_size) = pub_key_id_size {
         req.set_buf_size(buf_size);
+    }
 
-        // qe_report_info is of type sgx_ql_qe_report_info_t
-        // - nonce: sgx_quote_nonce_t
-        // - app_enclave_target_info: sgx_target_info_t
-        // - qe_report: sgx_report_t
+    req
+}
 
-        let mut qe_report_info : Vec<u8> = Vec::new();
-        qe_report_info.extend(nonce);
-        qe_report_info.extend(target_info);
-        let report_start = qe_report_info.len();
-        qe_report_info.resize(report_start + sgx_isa::Report::UNPADDED_SIZE, 0);
-        req.set_qe_report_info(qe_report_info);
+/// The size of the public key ID, from the response to a request without a
+/// buffer for it.
+pub(super) fn init_quote_ex_pub_key_id_size(res: &Response_InitQuoteExResponse) -> Result<u64> {
+    if !res.has_pub_key_id_size() {
+        return Err(Error::aesm_bad_response("public key ID size"));
+    }
+    Ok(res.get_pub_key_id_size())
+}
 
-        req.set_report(report);
+/// `pub_key_id_size` is the size reported by AESM in response to the first
+/// request, which the public key ID must match exactly.
+pub(super) fn init_quote_ex_result(
+    att_key_id: Vec<u8>,
+    mut res: Response_InitQuoteExResponse,
+    pub_key_id_size: u64,
+    quote_size: u32,
+) -> Result<QuoteInfoEx> {
+    let pub_key_id = res.take_pub_key_id();
+    if pub_key_id.len() as u64 != pub_key_id_size {
+        return Err(Error::aesm_bad_response(&format!("{}-byte public key ID", pub_key_id_size)));
+    }
+    QuoteInfoEx::new(att_key_id, check_target_info(res.take_target_info())?, pub_key_id, quote_size)
+}
 
-        let mut res = self.transact(req)?;
+pub(super) fn get_quote_size_ex_request(att_key_id: Vec<u8>) -> Request_GetQuoteSizeExRequest {
+    let mut req = Request_GetQuoteSizeExRequest::new();
 
-        let (quote, qe_report_info) = (res.take_quote(), res.take_qe_report_info());
+    if !att_key_id.is_empty() {
+        req.set_att_key_id(att_key_id);
+    }
+
+    req
+}
 
-        Ok(QuoteResult::new(quote, &qe_report_info[report_start..]))
+pub(super) fn get_quote_size_ex_result(res: Response_GetQuoteSizeExResponse) -> Result<u32> {
+    if !res.has_quote_size() {
+        return Err(Error::aesm_bad_response("quote size"));
     }
+    Ok(res.get_quote_size())
+}
+
+/// Returns the request, and the offset of the QE report in the
+/// `qe_report_info` that will be returned by AESM.
+///
+/// The request owns its fields, so `report`, `target_info` and `nonce` are
+/// copied into it exactly once.
+pub(super) fn get_quote_ex_request(
+    att_key_id: Vec<u8>,
+    report: &[u8],
+    target_info: &[u8],
+    nonce: &[u8],
+    buf_size: u32,
+) -> (Request_GetQuoteExRequest, usize) {
+    let mut req = Request_GetQuoteExRequest::new();
+
+    if !att_key_id.is_empty() {
+        req.set_att_key_id(att_key_id);
+    }
+    req.set_buf_size(buf_size);
+
+    // qe_report_info is of type sgx_ql_qe_report_info_t
+    // - nonce: sgx_quote_nonce_t
+    // - app_enclave_target_info: sgx_target_info_t
+    // - qe_report: sgx_report_t
+
+    let report_start = nonce.len() + target_info.len();
+    let mut qe_report_info : Vec<u8> = Vec::with_capacity(report_start + sgx_isa::Report::UNPADDED_SIZE);
+    qe_report_info.extend_from_slice(nonce);
+    qe_report_info.extend_from_slice(target_info);
+    qe_report_info.resize(report_start + sgx_isa::Report::UNPADDED_SIZE, 0);
+    req.set_qe_report_info(qe_report_info);
+
+    req.set_report(report.to_vec());
+
+    (req, report_start)
+}
+
+pub(super) fn get_quote_ex_result(mut res: Response_GetQuoteExResponse, report_start: usize) -> Result<QuoteResult> {
+    if !res.has_quote() {
+        return Err(Error::aesm_bad_response("quote"));
+    }
+    let (quote, qe_report_info) = (res.take_quote(), res.take_qe_report_info());
+    if qe_report_info.len() < report_start + sgx_isa::Report::UNPADDED_SIZE {
+        return Err(Error::MalformedResponse { field: "qe_report_info", reason: "too short for a QE report", raw: qe_report_info });
+    }
+
+    Ok(QuoteResult::from_qe_report_info(quote, qe_report_info, report_start))
 }
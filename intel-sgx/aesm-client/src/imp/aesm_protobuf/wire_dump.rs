@@ -0,0 +1,229 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Hex dumps of the frames exchanged with AESM, see
+//! `AesmClientBuilder::wire_dump`.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::key_id::SPID_OFFSET;
+use crate::{AesmKeyId, SPID_SIZE};
+
+const BYTES_PER_LINE: usize = 16;
+
+// Numbers of the fields that contain SPIDs, from aesm_proto.proto. Key IDs
+// contain the SPID of EPID keys.
+const REQUEST_GET_QUOTE: u32 = 2;
+const GET_QUOTE_SPID: u32 = 3;
+const REQUEST_INIT_QUOTE_EX: u32 = 15;
+const INIT_QUOTE_EX_ATT_KEY_ID: u32 = 1;
+const REQUEST_GET_QUOTE_SIZE_EX: u32 = 16;
+const GET_QUOTE_SIZE_EX_ATT_KEY_ID: u32 = 1;
+const REQUEST_GET_QUOTE_EX: u32 = 17;
+const GET_QUOTE_EX_ATT_KEY_ID: u32 = 2;
+const RESPONSE_GET_SUPPORTED_ATT_KEY_IDS: u32 = 22;
+const GET_SUPPORTED_ATT_KEY_IDS_ATT_KEY_IDS: u32 = 2;
+
+#[derive(Clone, Copy, Debug)]
+pub(in crate::imp) enum Direction {
+    Request,
+    Response,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Direction::Request => "request",
+            Direction::Response => "response",
+        })
+    }
+}
+
+/// Log the body of a frame as hex at trace level, with SPIDs masked. Does
+/// nothing unless `enabled`.
+#[inline]
+pub(in crate::imp) fn dump(enabled: bool, direction: Direction, kind: &str, body: &[u8]) {
+    if enabled && log::log_enabled!(log::Level::Trace) {
+        dump_lines(direction, kind, body);
+    }
+}
+
+fn dump_lines(direction: Direction, kind: &str, body: &[u8]) {
+    let masked = spid_ranges(direction, body);
+    log::trace!("{} {} ({} bytes):", kind, direction, body.len());
+    for line in lines(body, &masked) {
+        log::trace!("{}", line);
+    }
+}
+
+fn lines<'a>(body: &'a [u8], masked: &'a [Range<usize>]) -> impl Iterator<Item = Line<'a>> {
+    body.chunks(BYTES_PER_LINE).enumerate().map(move |(i, bytes)| Line { offset: i * BYTES_PER_LINE, bytes, masked })
+}
+
+/// A line of a hex dump, e.g. `0010: 0a 2b 12 ** **`.
+struct Line<'a> {
+    offset: usize,
+    bytes: &'a [u8],
+    masked: &'a [Range<usize>],
+}
+
+impl<'a> fmt::Display for Line<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:", self.offset)?;
+        for (i, b) in self.bytes.iter().enumerate() {
+            let offset = self.offset + i;
+            if self.masked.iter().any(|range| range.contains(&offset)) {
+                f.write_str(" **")?;
+            } else {
+                write!(f, " {:02x}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The ranges of the SPIDs in a serialized request or response.
+fn spid_ranges(direction: Direction, body: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    for (message, outer) in Fields::new(body) {
+        for (field, inner) in Fields::new(&body[outer.clone()]) {
+            let range = outer.start + inner.start..outer.start + inner.end;
+            match (direction, message, field) {
+                (Direction::Request, REQUEST_GET_QUOTE, GET_QUOTE_SPID) => ranges.push(range),
+                (Direction::Request, REQUEST_INIT_QUOTE_EX, INIT_QUOTE_EX_ATT_KEY_ID)
+                | (Direction::Request, REQUEST_GET_QUOTE_SIZE_EX, GET_QUOTE_SIZE_EX_ATT_KEY_ID)
+                | (Direction::Request, REQUEST_GET_QUOTE_EX, GET_QUOTE_EX_ATT_KEY_ID)
+                | (Direction::Response, RESPONSE_GET_SUPPORTED_ATT_KEY_IDS, GET_SUPPORTED_ATT_KEY_IDS_ATT_KEY_IDS) => {
+                    let mut key_id = range.start;
+                    while key_id + AesmKeyId::SIZE <= range.end {
+                        ranges.push(key_id + SPID_OFFSET..key_id + SPID_OFFSET + SPID_SIZE);
+                        key_id += AesmKeyId::SIZE;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    ranges
+}
+
+/// The length-delimited fields of a protobuf message, as the field number
+/// and the range of the value. Stops at the first malformed field.
+struct Fields<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Fields { buf, pos: 0 }
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let b = *self.buf.get(self.pos)?;
+            self.pos += 1;
+            value |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = (u32, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = self.varint()?;
+            let len = match key & 7 {
+                0 => {
+                    self.varint()?;
+                    continue;
+                }
+                1 => 8,
+                2 => self.varint()? as usize,
+                5 => 4,
+                _ => return None,
+            };
+            let start = self.pos;
+            let end = start.checked_add(len).filter(|&end| end <= self.buf.len())?;
+            self.pos = end;
+            if key & 7 == 2 {
+                return Some(((key >> 3) as u32, start..end));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sgx_isa::Report;
+
+    use super::*;
+    use crate::imp::aesm_protobuf::{encode_request, get_quote_ex_request, get_quote_request};
+    use crate::{QuoteType, Response, Response_GetSupportedAttKeyIDsResponse};
+    use protobuf::Message;
+
+    const SPID: [u8; 16] = [0x5a; 16];
+
+    fn dump_to_string(direction: Direction, body: &[u8]) -> String {
+        let masked = spid_ranges(direction, body);
+        lines(body, &masked).map(|line| format!("{}\n", line)).collect()
+    }
+
+    #[test]
+    fn get_quote_spid() {
+        let req = get_quote_request(vec![0; Report::UNPADDED_SIZE], SPID.to_vec(), vec![], QuoteType::Linkable, Some(vec![0x11; 16]));
+        let req_bytes = encode_request(req).unwrap();
+        let body = &req_bytes[4..];
+
+        let ranges = spid_ranges(Direction::Request, body);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(&body[ranges[0].clone()], &SPID);
+
+        let dump = dump_to_string(Direction::Request, body);
+        assert!(!dump.contains(" 5a"));
+        assert_eq!(dump.matches("**").count(), SPID.len());
+        assert!(dump.starts_with("0000: 12 "));
+        // The offset and 16 bytes per line.
+        assert!(dump.lines().all(|line| line.split(' ').count() <= 17));
+        assert!(dump.lines().nth(1).unwrap().starts_with("0010: "));
+    }
+
+    #[test]
+    fn key_id_spids() {
+        let mut key_id = vec![0; AesmKeyId::SIZE];
+        key_id[SPID_OFFSET..][..16].copy_from_slice(&SPID);
+
+        let (req, _) = get_quote_ex_request(key_id.clone(), &[0; Report::UNPADDED_SIZE], &[0; 512], &[0; 16], 4096);
+        let req_bytes = encode_request(req).unwrap();
+        let dump = dump_to_string(Direction::Request, &req_bytes[4..]);
+        assert!(!dump.contains(" 5a"));
+        assert_eq!(dump.matches("**").count(), SPID.len());
+
+        let mut body = Response_GetSupportedAttKeyIDsResponse::new();
+        body.set_errorCode(0);
+        body.set_att_key_ids([&key_id[..], &key_id[..]].concat());
+        let mut res = Response::new();
+        res.set_getSupportedAttKeyIDsRes(body);
+        let res_bytes = res.write_to_bytes().unwrap();
+        let dump = dump_to_string(Direction::Response, &res_bytes);
+        assert!(!dump.contains(" 5a"));
+        assert_eq!(dump.matches("**").count(), 2 * SPID.len());
+    }
+
+    #[test]
+    fn malformed() {
+        // A truncated length-delimited field.
+        assert_eq!(spid_ranges(Direction::Request, &[0x12, 0x10, 0x1a]), vec![]);
+        assert_eq!(dump_to_string(Direction::Request, &[0x12, 0x10, 0x1a]), "0000: 12 10 1a\n");
+    }
+}
@@ -1,35 +1,408 @@
+use std::fmt;
+use std::io::{self, Read, Write};
 use std::net::TcpStream;
-pub use error::{AesmError, Error, Result};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+pub use crate::error::{AesmError, Error, Result};
+use crate::AesmClientBuilder;
+use crate::metrics::MetricsHook;
 mod aesm_protobuf;
 
+/// How many times a request is sent on a fresh connection if AESM closed
+/// the connection before responding.
+const MAX_RECONNECTS: u32 = 2;
+
+/// The connection to AESM, e.g. a `TcpStream` or a stream provided by the
+/// enclave runner.
+pub(crate) trait AesmStream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> AesmStream for S {}
+
+struct Stream(Box<dyn AesmStream>);
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Stream")
+    }
+}
+
+/// Opens a new connection to AESM.
+#[derive(Clone)]
+struct Connector(Arc<dyn Fn() -> io::Result<Box<dyn AesmStream>> + Send + Sync>);
+
+impl Connector {
+    fn new<S, F>(connect: F) -> Connector
+    where
+        S: AesmStream + 'static,
+        F: Fn() -> io::Result<S> + Send + Sync + 'static,
+    {
+        Connector(Arc::new(move || Ok(Box::new(connect()?) as Box<dyn AesmStream>)))
+    }
+
+    fn connect(&self) -> Result<Stream> {
+        Ok(Stream((self.0)()?))
+    }
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Connector")
+    }
+}
+
 #[derive(Debug)]
 pub struct AesmClient {
-    tcp_stream: TcpStream,
+    // `None` until the first request if the client can connect itself,
+    // otherwise shared between clones, see `with_socket`.
+    conn: Arc<Mutex<Option<Stream>>>,
+    // `None` if the client was created with a stream, which can't be
+    // reopened.
+    connector: Option<Connector>,
+    timeout: aesm_protobuf::RequestTimeout,
+    retries: u32,
+    transient_retries: u32,
+    retry_backoff: Duration,
+    max_response_size: usize,
+    metrics_hook: Option<MetricsHook>,
+    wire_dump: bool,
 }
 
+/// Clones of a client that can connect itself open their own connection when
+/// they send their first request. Clones of a client created with a stream
+/// share it.
 impl Clone for AesmClient {
     fn clone(&self) -> Self {
+        let conn = match self.connector {
+            Some(_) => Arc::new(Mutex::new(None)),
+            None => self.conn.clone(),
+        };
         AesmClient {
-            tcp_stream: self.tcp_stream.try_clone().unwrap()
+            conn,
+            connector: self.connector.clone(),
+            timeout: self.timeout,
+            retries: self.retries,
+            transient_retries: self.transient_retries,
+            retry_backoff: self.retry_backoff,
+            max_response_size: self.max_response_size,
+            metrics_hook: self.metrics_hook.clone(),
+            wire_dump: self.wire_dump,
         }
     }
 }
 
 impl AesmClient {
-    fn open_socket(&self) -> Result<TcpStream> {
-        let sock = self.tcp_stream.try_clone().unwrap();
-        // FIXME: uncomment this after resolving https://github.com/fortanix/rust-sgx/issues/31
-        // let _ = sock.set_write_timeout(Some(Duration::from_micros(LOCAL_AESM_TIMEOUT_US as _)))?;
-        Ok(sock)
+    fn with_connection(conn: Option<Stream>, connector: Option<Connector>) -> Self {
+        AesmClient {
+            conn: Arc::new(Mutex::new(conn)),
+            connector,
+            timeout: aesm_protobuf::RequestTimeout::PerRequest,
+            retries: 0,
+            transient_retries: 0,
+            retry_backoff: aesm_protobuf::DEFAULT_RETRY_BACKOFF,
+            max_response_size: aesm_protobuf::DEFAULT_MAX_RESPONSE_SIZE,
+            metrics_hook: None,
+            wire_dump: false,
+        }
+    }
+
+    pub fn from_builder(builder: AesmClientBuilder) -> Result<Self> {
+        if builder.socket_path.is_some() {
+            return Err(Error::UnsupportedOption("socket_path"));
+        }
+        if builder.sgx_device_paths.is_some() {
+            return Err(Error::UnsupportedOption("sgx_device_paths"));
+        }
+        let address = builder.tcp_address.ok_or(Error::MissingOption("tcp_address"))?;
+        let connector = Connector::new(move || TcpStream::connect(&address));
+        // Connect right away, so that an unreachable address is reported by
+        // `build`.
+        let conn = connector.connect()?;
+        Ok(AesmClient {
+            timeout: aesm_protobuf::RequestTimeout::from_builder(builder.default_timeout),
+            retries: builder.retries.unwrap_or(0),
+            transient_retries: builder.transient_retries.unwrap_or(0),
+            retry_backoff: builder.retry_backoff.unwrap_or(aesm_protobuf::DEFAULT_RETRY_BACKOFF),
+            max_response_size: builder.max_response_size.unwrap_or(aesm_protobuf::DEFAULT_MAX_RESPONSE_SIZE),
+            metrics_hook: builder.metrics_hook,
+            wire_dump: builder.wire_dump.unwrap_or(false),
+            ..Self::with_connection(Some(conn), Some(connector))
+        })
+    }
+
+    /// Shares the connection of this client, unlike `clone`.
+    pub fn with_timeout(&self, timeout: Duration) -> Result<Self> {
+        Ok(AesmClient { timeout: aesm_protobuf::RequestTimeout::Fixed(timeout), conn: self.conn.clone(), ..self.clone() })
+    }
+
+    /// Connecting isn't bounded by `timeout`, see `try_connect`.
+    pub fn try_connect_timeout(&self, timeout: Duration) -> Result<()> {
+        if timeout == Duration::from_secs(0) {
+            return Err(Error::Timeout { request: None });
+        }
+        self.try_connect()
+    }
+
+    /// Check that AESM is up, see `wait_for_service`. If AESM can't be
+    /// reached, e.g. because it restarted, the connection is discarded, so
+    /// the next attempt connects again, if the client can.
+    pub fn probe_service(&self, timeout: Duration) -> Result<()> {
+        self.probe_request(timeout)
+    }
+
+    /// Connect to AESM, unless the client is connected already.
+    fn open_socket(&self) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        if let (None, Some(connector)) = (&*conn, &self.connector) {
+            *conn = Some(connector.connect()?);
+        }
+        Ok(())
+    }
+
+    /// AESM is reached over TCP, not a unix socket.
+    pub fn socket_path(&self) -> Option<std::path::PathBuf> {
+        None
+    }
+
+    /// The enclave can't look at the host's device nodes, so it assumes that
+    /// SGX is available.
+    pub fn check_sgx_available(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Run `f` on the connection to AESM, connecting first if necessary.
+    ///
+    /// The connection is locked while `f` runs, to keep concurrent requests
+    /// of clients sharing it from interleaving. If the client can connect
+    /// itself and the connection is closed before AESM responds, e.g.
+    /// because the proxy on the host dropped it, `f` is retried on a fresh
+    /// one, at most `MAX_RECONNECTS` times. After any other error the
+    /// connection is discarded, as it may still receive the response to the
+    /// failed request. A stream the client was created with is always kept.
+    fn with_socket<T, F: FnMut(&mut Box<dyn AesmStream>) -> Result<T>>(&self, mut f: F) -> Result<T> {
+        let mut conn = self.conn.lock().unwrap();
+        let connector = match self.connector {
+            Some(ref connector) => connector,
+            None => {
+                let sock = conn.as_mut().expect("clients created with a stream keep it");
+                return f(&mut sock.0);
+            }
+        };
+        let mut reconnects = MAX_RECONNECTS;
+        loop {
+            let mut sock = match conn.take() {
+                Some(sock) => sock,
+                None => connector.connect()?,
+            };
+            match f(&mut sock.0) {
+                Err(ref err) if aesm_protobuf::is_disconnect(err) && reconnects > 0 => {
+                    log::warn!("AESM closed the connection, reconnecting");
+                    reconnects -= 1;
+                }
+                res => {
+                    if res.is_ok() {
+                        *conn = Some(sock);
+                    }
+                    return res;
+                }
+            }
+        }
     }
 }
 
 impl crate::sgx::AesmClientExt for crate::AesmClient {
     fn new(tcp_stream: TcpStream) -> Self {
+        Self::from_stream(tcp_stream)
+    }
+
+    fn from_stream<S: Read + Write + Send + 'static>(stream: S) -> Self {
         crate::AesmClient {
-            inner: self::AesmClient {
-                tcp_stream
-            }
+            inner: self::AesmClient::with_connection(Some(Stream(Box::new(stream))), None),
+            quote_info: Default::default(),
+        }
+    }
+
+    fn with_connector<S, F>(connect: F) -> Self
+    where
+        S: Read + Write + Send + 'static,
+        F: Fn() -> io::Result<S> + Send + Sync + 'static,
+    {
+        crate::AesmClient {
+            inner: self::AesmClient::with_connection(None, Some(Connector::new(connect))),
+            quote_info: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+    use protobuf::Message;
+    use sgx_isa::Targetinfo;
+
+    use crate::sgx::AesmClientExt;
+    use crate::{AesmClient, Error, Response, Response_InitQuoteResponse};
+
+    // An init quote response, framed as by AESM.
+    fn init_quote_response() -> Vec<u8> {
+        let mut body = Response_InitQuoteResponse::new();
+        body.set_errorCode(0);
+        body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+        let mut res = Response::new();
+        res.set_initQuoteRes(body);
+        let res = res.write_to_bytes().unwrap();
+        let mut framed = vec![];
+        framed.write_u32::<NativeEndian>(res.len() as u32).unwrap();
+        framed.extend_from_slice(&res);
+        framed
+    }
+
+    // A proxy for AESM on the host, which responds to init quote requests.
+    // It closes the first `dropped` connections right after accepting them,
+    // and every connection after `requests_per_connection` requests.
+    struct FlakyAesm {
+        address: SocketAddr,
+        connections: Arc<AtomicUsize>,
+    }
+
+    impl FlakyAesm {
+        fn start(dropped: usize, requests_per_connection: usize) -> FlakyAesm {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let connections = Arc::new(AtomicUsize::new(0));
+            let accepted = connections.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => return,
+                    };
+                    if accepted.fetch_add(1, Ordering::SeqCst) < dropped {
+                        continue;
+                    }
+                    thread::spawn(move || {
+                        for _ in 0..requests_per_connection {
+                            let len = match stream.read_u32::<NativeEndian>() {
+                                Ok(len) => len,
+                                Err(_) => return,
+                            };
+                            let mut req = vec![0; len as usize];
+                            if stream.read_exact(&mut req).is_err() || stream.write_all(&init_quote_response()).is_err() {
+                                return;
+                            }
+                        }
+                    });
+                }
+            });
+            FlakyAesm { address, connections }
+        }
+
+        fn client(&self) -> AesmClient {
+            AesmClient::builder().tcp_address(self.address.to_string()).build().unwrap()
+        }
+
+        fn connections(&self) -> usize {
+            self.connections.load(Ordering::SeqCst)
+        }
+    }
+
+    // Records what the client writes and replays `response`.
+    struct MemoryStream {
+        written: Arc<Mutex<Vec<u8>>>,
+        response: Cursor<Vec<u8>>,
+    }
+
+    impl Read for MemoryStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.response.read(buf)
         }
     }
+
+    impl Write for MemoryStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn from_stream() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let client = AesmClient::from_stream(MemoryStream { written: written.clone(), response: Cursor::new(init_quote_response()) });
+        client.init_quote().unwrap();
+        let stream_request = written.lock().unwrap().clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let len = stream.read_u32::<NativeEndian>().unwrap();
+            let mut req = vec![0; len as usize];
+            stream.read_exact(&mut req).unwrap();
+            stream.write_all(&init_quote_response()).unwrap();
+            req
+        });
+        let client = AesmClient::new(TcpStream::connect(address).unwrap());
+        client.init_quote().unwrap();
+        let tcp_request = server.join().unwrap();
+
+        assert_eq!(stream_request[..4], (tcp_request.len() as u32).to_ne_bytes());
+        assert_eq!(stream_request[4..], tcp_request[..]);
+    }
+
+    #[test]
+    fn reconnect() {
+        let aesm = FlakyAesm::start(0, 1);
+        let client = aesm.client();
+        for _ in 0..3 {
+            client.init_quote().unwrap();
+        }
+        assert_eq!(aesm.connections(), 3);
+
+        // The proxy dropped the connections to AESM.
+        let aesm = FlakyAesm::start(1 + super::MAX_RECONNECTS as usize, usize::MAX);
+        let client = aesm.client();
+        match client.init_quote() {
+            Err(Error::AesmCommunication(_)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(aesm.connections(), 1 + super::MAX_RECONNECTS as usize);
+        client.init_quote().unwrap();
+        client.init_quote().unwrap();
+        assert_eq!(aesm.connections(), 2 + super::MAX_RECONNECTS as usize);
+    }
+
+    #[test]
+    fn clone() {
+        let aesm = FlakyAesm::start(0, usize::MAX);
+        let client = aesm.client();
+        let clone = client.clone();
+        clone.init_quote().unwrap();
+        client.init_quote().unwrap();
+        clone.init_quote().unwrap();
+        assert_eq!(aesm.connections(), 2);
+
+        let address = aesm.address;
+        let connected = Arc::new(AtomicUsize::new(0));
+        let counter = connected.clone();
+        let client = AesmClient::with_connector(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            TcpStream::connect(address)
+        });
+        let clone = client.clone();
+        assert_eq!(connected.load(Ordering::SeqCst), 0);
+        clone.init_quote().unwrap();
+        client.init_quote().unwrap();
+        client.init_quote().unwrap();
+        assert_eq!(connected.load(Ordering::SeqCst), 2);
+    }
 }
@@ -1,89 +1,2378 @@
+use std::cmp;
 use std::ffi::OsStr;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{IntoRawFd, RawFd};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use unix_socket::UnixStream;
-#[cfg(feature = "sgxs")]
-use sgxs::sigstruct::{Attributes, Sigstruct};
+use sgx_isa::Sigstruct;
+#[cfg(feature = "vsock")]
+use vsock::{Std, Vsock, VsockStream};
 
-pub use error::{AesmError, Error, Result};
+pub use crate::error::{AesmError, Error, Result};
+use crate::{AesmClientBuilder, SgxCheck};
+use crate::metrics::MetricsHook;
 
 mod aesm_protobuf;
+#[cfg(feature = "async")]
+pub mod unix_async;
+pub mod unix_nonblocking;
 
-#[cfg(feature = "sgxs")]
-use Request_GetLaunchTokenRequest;
+use crate::Request_GetLaunchTokenRequest;
 
-#[derive(Clone, Debug, Default)]
+/// The address of a unix socket that AESM listens on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SocketAddress {
+    Path(PathBuf),
+    /// A name in the abstract namespace, without the leading NUL. The name
+    /// includes any padding, as the length of abstract addresses matters.
+    Abstract(Vec<u8>),
+}
+
+impl SocketAddress {
+    /// The address as `unix_socket` takes it, and as it's reported to the
+    /// user: abstract addresses are paths starting with a NUL.
+    pub fn to_path_buf(&self) -> PathBuf {
+        match *self {
+            SocketAddress::Path(ref path) => path.clone(),
+            SocketAddress::Abstract(ref name) => {
+                let mut path = vec![0];
+                path.extend_from_slice(name);
+                OsStr::from_bytes(&path).into()
+            }
+        }
+    }
+
+    /// Whether the socket may exist. Abstract sockets don't exist in the
+    /// filesystem, so they always may.
+    fn may_exist(&self) -> bool {
+        match *self {
+            SocketAddress::Path(ref path) => path.exists(),
+            SocketAddress::Abstract(_) => true,
+        }
+    }
+}
+
+/// Paths starting with a NUL are abstract addresses.
+impl From<PathBuf> for SocketAddress {
+    fn from(path: PathBuf) -> Self {
+        match path.as_os_str().as_bytes().split_first() {
+            Some((0, name)) => SocketAddress::Abstract(name.to_owned()),
+            _ => SocketAddress::Path(path),
+        }
+    }
+}
+
+// Abstract addresses are written with an @ instead of the leading NUL, and
+// without padding.
+impl fmt::Display for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SocketAddress::Path(ref path) => write!(f, "{}", path.display()),
+            SocketAddress::Abstract(ref name) => {
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                write!(f, "@{}", String::from_utf8_lossy(name))
+            }
+        }
+    }
+}
+
+/// The vsock address of AESM, or of a proxy for it, on the host of a
+/// virtual machine.
+#[cfg(feature = "vsock")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct VsockAddress {
+    cid: u32,
+    port: u32,
+}
+
+/// A connection to AESM.
+enum Connection {
+    Unix(UnixStream),
+    #[cfg(feature = "vsock")]
+    Vsock(VsockStream<Std>),
+}
+
+impl Connection {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+        match *self {
+            Connection::Unix(ref sock) => sock.set_read_timeout(timeout),
+            #[cfg(feature = "vsock")]
+            Connection::Vsock(ref sock) => sock.set_read_timeout(timeout),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+        match *self {
+            Connection::Unix(ref sock) => sock.set_write_timeout(timeout),
+            #[cfg(feature = "vsock")]
+            Connection::Vsock(ref sock) => sock.set_write_timeout(timeout),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> IoResult<()> {
+        match *self {
+            Connection::Unix(ref sock) => sock.set_nonblocking(nonblocking),
+            #[cfg(feature = "vsock")]
+            Connection::Vsock(ref sock) => sock.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Whether the connection can't be used for another request: AESM closed
+    /// it, or sent data although no request is outstanding, which would be
+    /// mistaken for the next response.
+    fn is_stale(&mut self) -> bool {
+        if self.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let stale = match self.read(&mut [0]) {
+            Err(ref err) => err.kind() != ErrorKind::WouldBlock,
+            // Either the end of the stream or unexpected data.
+            Ok(_) => true,
+        };
+        stale || self.set_nonblocking(false).is_err()
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match *self {
+            Connection::Unix(ref mut sock) => sock.read(buf),
+            #[cfg(feature = "vsock")]
+            Connection::Vsock(ref mut sock) => sock.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match *self {
+            Connection::Unix(ref mut sock) => sock.write(buf),
+            #[cfg(feature = "vsock")]
+            Connection::Vsock(ref mut sock) => sock.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match *self {
+            Connection::Unix(ref mut sock) => sock.flush(),
+            #[cfg(feature = "vsock")]
+            Connection::Vsock(ref mut sock) => sock.flush(),
+        }
+    }
+}
+
+/// The nonblocking clients only read and write the socket, which works the
+/// same for vsock as for unix sockets.
+impl IntoRawFd for Connection {
+    fn into_raw_fd(self) -> RawFd {
+        match self {
+            Connection::Unix(sock) => sock.into_raw_fd(),
+            #[cfg(feature = "vsock")]
+            Connection::Vsock(sock) => sock.into_raw_fd(),
+        }
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Connection::Unix(ref sock) => f.debug_tuple("Unix").field(sock).finish(),
+            #[cfg(feature = "vsock")]
+            Connection::Vsock(_) => f.write_str("Vsock"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct AesmClient {
-    path: Option<PathBuf>,
+    address: Option<SocketAddress>,
+    // Takes precedence over `address`, see `with_vsock`.
+    #[cfg(feature = "vsock")]
+    vsock_address: Option<VsockAddress>,
+    // Shared between clones, see `discover_socket`.
+    discovered_address: Arc<Mutex<Option<SocketAddress>>>,
+    // Shared between clones, see `with_socket`.
+    conn: Arc<Mutex<Option<Connection>>>,
+    timeout: aesm_protobuf::RequestTimeout,
+    retries: u32,
+    transient_retries: u32,
+    retry_backoff: Duration,
+    max_response_size: usize,
+    metrics_hook: Option<MetricsHook>,
+    wire_dump: bool,
+    // `None` to look for the default device nodes.
+    sgx_device_paths: Option<Arc<[PathBuf]>>,
+}
+
+impl Default for AesmClient {
+    fn default() -> Self {
+        AesmClient {
+            address: None,
+            #[cfg(feature = "vsock")]
+            vsock_address: None,
+            discovered_address: Default::default(),
+            conn: Default::default(),
+            timeout: aesm_protobuf::RequestTimeout::PerRequest,
+            retries: 0,
+            transient_retries: 0,
+            retry_backoff: aesm_protobuf::DEFAULT_RETRY_BACKOFF,
+            max_response_size: aesm_protobuf::DEFAULT_MAX_RESPONSE_SIZE,
+            metrics_hook: None,
+            wire_dump: false,
+            sgx_device_paths: None,
+        }
+    }
 }
 
 impl AesmClient {
-    pub fn new() -> Self {
-        Default::default()
+    pub fn from_builder(builder: AesmClientBuilder) -> Result<Self> {
+        if builder.tcp_address.is_some() {
+            return Err(Error::UnsupportedOption("tcp_address"));
+        }
+        Ok(AesmClient {
+            address: builder.socket_path.map(SocketAddress::from),
+            #[cfg(feature = "vsock")]
+            vsock_address: None,
+            discovered_address: Default::default(),
+            conn: Default::default(),
+            timeout: aesm_protobuf::RequestTimeout::from_builder(builder.default_timeout),
+            retries: builder.retries.unwrap_or(0),
+            transient_retries: builder.transient_retries.unwrap_or(0),
+            retry_backoff: builder.retry_backoff.unwrap_or(aesm_protobuf::DEFAULT_RETRY_BACKOFF),
+            max_response_size: builder.max_response_size.unwrap_or(aesm_protobuf::DEFAULT_MAX_RESPONSE_SIZE),
+            metrics_hook: builder.metrics_hook,
+            wire_dump: builder.wire_dump.unwrap_or(false),
+            sgx_device_paths: builder.sgx_device_paths.map(Into::into),
+        })
     }
 
-    fn open_socket(&self) -> Result<UnixStream> {
-        lazy_static! {
-            static ref AESM_SOCKET_ABSTRACT_PATH: PathBuf = {
-                // This is defined in <linux/un.h>, although if aesm didn't pad
-                // its address we wouldn't need to use it here.
-                const UNIX_PATH_MAX: usize = 108;
-
-                // The address of the AESM socket is "sgx_aesm_socket_base" followed by
-                // enough NULs to pad to UNIX_PATH_MAX (and with a leading NUL to indicate
-                // the abstract namespace).
-                let mut path = [0; UNIX_PATH_MAX];
-                path[1..21].copy_from_slice(b"sgx_aesm_socket_base");
-                OsStr::from_bytes(&path).into()
-            };
-        };
-        static AESM_SOCKET_FILE_PATH: &'static str = "/var/run/aesmd/aesm.socket";
-
-        // AESM only accepts one request per connection, so we have to open
-        // a fresh socket here.
-        let path = if let Some(ref path) = self.path {
-            &**path
-        } else if Path::new(AESM_SOCKET_FILE_PATH).exists() {
-            Path::new(AESM_SOCKET_FILE_PATH)
-        } else {
-            &**AESM_SOCKET_ABSTRACT_PATH
-        };
+    pub fn with_timeout(&self, timeout: Duration) -> Result<Self> {
+        Ok(AesmClient { timeout: aesm_protobuf::RequestTimeout::Fixed(timeout), ..self.clone() })
+    }
+
+    /// A client with the same options that doesn't share the connection to
+    /// AESM with this one.
+    pub fn with_new_connection(&self) -> Self {
+        AesmClient { conn: Default::default(), ..self.clone() }
+    }
+
+    /// A client for a single long request, which waits at most `timeout`, or
+    /// as long as it takes if `None`. It has its own connection, so that
+    /// requests on the connection of this one aren't blocked meanwhile.
+    pub fn for_long_request(&self, timeout: Option<Duration>) -> Self {
+        AesmClient { timeout: aesm_protobuf::RequestTimeout::fixed(timeout), ..self.with_new_connection() }
+    }
 
-        let sock = UnixStream::connect_timeout(path, Duration::from_micros(aesm_protobuf::LOCAL_AESM_TIMEOUT_US as _))?;
+    /// Like `try_connect`, but gives up with `Error::Timeout` after
+    /// `timeout`. If AESM isn't accepting connections and its backlog is
+    /// full, connecting is retried until then. A `timeout` too long to
+    /// represent means retrying until connecting succeeds or fails otherwise.
+    pub fn try_connect_timeout(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            // Without a deadline, each attempt is bounded as in `try_connect`.
+            match self.open_socket_until(deadline.unwrap_or_else(local_deadline)) {
+                Ok(_) => return Ok(()),
+                Err(Error::AesmCommunication(ref err)) if err.kind() == ErrorKind::TimedOut && deadline.is_some() => return Err(Error::Timeout { request: None }),
+                Err(Error::AesmCommunication(ref err)) if err.kind() == ErrorKind::TimedOut || err.kind() == ErrorKind::WouldBlock => {
+                    let remaining = deadline.map_or(CONNECT_RETRY_INTERVAL, |deadline| deadline.saturating_duration_since(Instant::now()));
+                    if remaining == Duration::from_secs(0) {
+                        return Err(Error::Timeout { request: None });
+                    }
+                    thread::sleep(cmp::min(remaining, CONNECT_RETRY_INTERVAL));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Check that AESM is up, see `wait_for_service`. A new connection is
+    /// opened if there's none yet.
+    pub fn probe_service(&self, timeout: Duration) -> Result<()> {
+        self.probe_request(timeout)
+    }
+
+    fn open_socket(&self) -> Result<Connection> {
+        self.open_socket_until(local_deadline())
+    }
+
+    /// Connecting over vsock isn't bounded by `deadline`, the kernel gives up
+    /// after its own connect timeout.
+    fn open_socket_until(&self, deadline: Instant) -> Result<Connection> {
+        #[cfg(feature = "vsock")]
+        let sock = match self.vsock_address {
+            Some(address) => Connection::Vsock(connect_vsock(address)?),
+            None => Connection::Unix(self.open_unix_socket(deadline)?),
+        };
+        #[cfg(not(feature = "vsock"))]
+        let sock = Connection::Unix(self.open_unix_socket(deadline)?);
         let _ = sock.set_write_timeout(Some(Duration::from_micros(aesm_protobuf::LOCAL_AESM_TIMEOUT_US as _)))?;
         Ok(sock)
     }
 
-    /// Obtain launch token
-    #[cfg(feature = "sgxs")]
-    pub fn get_launch_token(
+    fn open_unix_socket(&self, deadline: Instant) -> Result<UnixStream> {
+        match self.address {
+            Some(ref address) => connect(address, deadline).map_err(|err| connect_error(address, err)),
+            None => self.discover_socket(&AESM_SOCKET_ADDRESSES, deadline),
+        }
+    }
+
+    /// The socket used to connect to AESM, if it's known.
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        self.address.clone().or_else(|| self.discovered_address.lock().unwrap().clone()).map(|address| address.to_path_buf())
+    }
+
+    /// Check whether SGX obviously isn't available on this host, see
+    /// `check_sgx_available`.
+    pub fn check_sgx_available(&self) -> Result<()> {
+        // AESM runs on the host, whose device nodes the virtual machine can't
+        // look at, so SGX is assumed to be available.
+        #[cfg(feature = "vsock")]
+        {
+            if self.vsock_address.is_some() {
+                return Ok(());
+            }
+        }
+        let sockets = match self.address {
+            Some(ref address) => std::slice::from_ref(address),
+            None => &AESM_SOCKET_ADDRESSES[..],
+        };
+        let devices = self.sgx_device_paths.as_deref().unwrap_or(&SGX_DEVICE_PATHS);
+        check_sgx_available(devices, sockets)
+    }
+
+    /// Connect to the first of `candidates` that AESM listens on.
+    ///
+    /// The address that worked is remembered and tried first next time, by
+    /// all clones of this client. If none of the candidates works, the error
+    /// lists all of them, unless access to one of them was denied.
+    fn discover_socket(&self, candidates: &[SocketAddress], deadline: Instant) -> Result<UnixStream> {
+        let mut discovered = self.discovered_address.lock().unwrap();
+        if let Some(ref address) = *discovered {
+            if let Ok(sock) = connect(address, deadline) {
+                return Ok(sock);
+            }
+        }
+        *discovered = None;
+
+        let mut errors = vec![];
+        let mut kind = ErrorKind::NotFound;
+        let mut denied = None;
+        for address in candidates {
+            match connect(address, deadline) {
+                Ok(sock) => {
+                    *discovered = Some(address.clone());
+                    return Ok(sock);
+                }
+                Err(err) => {
+                    kind = err.kind();
+                    if kind == ErrorKind::PermissionDenied && denied.is_none() {
+                        denied = Some(address);
+                    }
+                    errors.push(format!("{}: {}", address, err));
+                }
+            }
+        }
+        if let Some(address) = denied {
+            return Err(Error::PermissionDenied { path: address.to_path_buf() });
+        }
+        Err(IoError::new(kind, format!("couldn't connect to AESM ({})", errors.join("; "))).into())
+    }
+
+    /// Run `f` on the connection to AESM, connecting first if necessary.
+    ///
+    /// The connection is kept open for later requests and shared by all clones
+    /// of this client. Some versions of AESM close the connection after every
+    /// request, so a kept connection is only reused if it's still open, and
+    /// requests aren't sent in vain. If the connection is closed before AESM
+    /// responds anyway, e.g. because AESM was restarted, `f` is retried once
+    /// on a fresh one. After any other error the connection is discarded, as
+    /// it may still receive the response to the failed request.
+    fn with_socket<T, F: FnMut(&mut Connection) -> Result<T>>(&self, mut f: F) -> Result<T> {
+        let mut conn = self.conn.lock().unwrap();
+        let mut sock = match conn.take() {
+            Some(mut sock) => if sock.is_stale() { self.open_socket()? } else { sock },
+            None => self.open_socket()?,
+        };
+        let mut res = f(&mut sock);
+        if matches!(res, Err(ref err) if aesm_protobuf::is_disconnect(err)) {
+            log::warn!("AESM closed the connection, reconnecting");
+            sock = self.open_socket()?;
+            res = f(&mut sock);
+        }
+        if res.is_ok() {
+            *conn = Some(sock);
+        }
+        res
+    }
+
+    /// Obtain launch token. The caller checks the sizes of `sigstruct` and
+    /// `attributes`.
+    pub fn get_launch_token_raw(
         &self,
-        sigstruct: &Sigstruct,
-        attributes: Attributes,
+        sigstruct: &[u8],
+        attributes: &[u8],
     ) -> Result<Vec<u8>> {
+        let sigstruct = Sigstruct::try_copy_from(sigstruct).expect("validated by caller");
         let mut req = Request_GetLaunchTokenRequest::new();
         req.set_mr_enclave(sigstruct.enclavehash.to_vec());
         // The field in the request protobuf is called mr_signer, but it wants the modulus.
         req.set_mr_signer(sigstruct.modulus.to_vec());
-        req.set_se_attributes(attributes.as_ref().to_vec());
-        req.set_timeout(aesm_protobuf::REMOTE_AESM_TIMEOUT_US);
+        req.set_se_attributes(attributes.to_vec());
 
         let mut res = self.transact(req)?;
+        if !res.has_token() {
+            return Err(Error::aesm_bad_response("launch token"));
+        }
+
+        Ok(res.take_token())
+    }
+}
+
+lazy_static! {
+    // Where AESM listens, in the order they're tried, see `discover_socket`.
+    static ref AESM_SOCKET_ADDRESSES: Vec<SocketAddress> = vec![
+        SocketAddress::Path("/var/run/aesmd/aesm.socket".into()),
+        SocketAddress::Path("/run/aesmd/aesm.socket".into()),
+        AESM_SOCKET_ABSTRACT_ADDRESS.clone(),
+    ];
+
+    // The device nodes of the in-kernel, DCAP and out-of-tree SGX drivers.
+    static ref SGX_DEVICE_PATHS: Vec<PathBuf> = vec![
+        "/dev/sgx_enclave".into(),
+        "/dev/sgx/enclave".into(),
+        "/dev/isgx".into(),
+        "/dev/sgx".into(),
+    ];
 
-        let token = res.take_token();
+    // Used by old versions of AESM.
+    static ref AESM_SOCKET_ABSTRACT_ADDRESS: SocketAddress = {
+        // This is defined in <linux/un.h>, although if aesm didn't pad
+        // its address we wouldn't need to use it here.
+        const UNIX_PATH_MAX: usize = 108;
 
-        Ok(token)
+        // The address of the AESM socket is "sgx_aesm_socket_base" followed by
+        // enough NULs to pad to UNIX_PATH_MAX (after the leading NUL that
+        // indicates the abstract namespace).
+        let mut name = vec![0; UNIX_PATH_MAX - 1];
+        name[..20].copy_from_slice(b"sgx_aesm_socket_base");
+        SocketAddress::Abstract(name)
+    };
+}
+
+// How often `try_connect_timeout` tries again while AESM's backlog is full.
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+fn local_deadline() -> Instant {
+    Instant::now() + Duration::from_micros(aesm_protobuf::LOCAL_AESM_TIMEOUT_US as _)
+}
+
+fn connect(address: &SocketAddress, deadline: Instant) -> IoResult<UnixStream> {
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    if timeout == Duration::from_secs(0) {
+        return Err(IoError::new(ErrorKind::TimedOut, "timed out connecting to AESM"));
+    }
+    UnixStream::connect_timeout(address.to_path_buf(), timeout)
+}
+
+#[cfg(feature = "vsock")]
+fn connect_vsock(address: VsockAddress) -> IoResult<VsockStream<Std>> {
+    Vsock::new::<Std>()?.connect_with_cid_port(address.cid, address.port)
+}
+
+/// Returns `Error::SgxNotAvailable` if none of `devices` exists, or if none
+/// of the AESM `sockets` does.
+///
+/// Abstract sockets don't exist in the filesystem, so if there's one among
+/// `sockets`, the socket check passes.
+fn check_sgx_available(devices: &[PathBuf], sockets: &[SocketAddress]) -> Result<()> {
+    if !devices.iter().any(|path| path.exists()) {
+        return Err(Error::SgxNotAvailable(SgxCheck::DeviceNode));
+    }
+    if !sockets.iter().any(SocketAddress::may_exist) {
+        return Err(Error::SgxNotAvailable(SgxCheck::AesmSocket));
+    }
+    Ok(())
+}
+
+// `EACCES` and `EPERM` both have the kind `PermissionDenied`, usually because
+// the user isn't in the group that owns the socket.
+fn connect_error(address: &SocketAddress, err: IoError) -> Error {
+    match err.kind() {
+        ErrorKind::PermissionDenied => Error::PermissionDenied { path: address.to_path_buf() },
+        _ => err.into(),
     }
 }
 
 impl crate::unix::AesmClientExt for crate::AesmClient {
     fn with_path<P: AsRef<Path>>(path: P) -> Self {
-        crate::AesmClient {
-            inner: self::AesmClient {
-                path: Some(path.as_ref().to_owned()),
-            },
+        AesmClientBuilder::new().socket_path(path).build().expect("a socket path is supported on unix")
+    }
+
+    fn with_abstract_name(name: &[u8]) -> Self {
+        let mut client = AesmClientBuilder::new().build().expect("default options are supported on all targets");
+        client.inner.address = Some(SocketAddress::Abstract(name.to_owned()));
+        client
+    }
+
+    #[cfg(feature = "vsock")]
+    fn with_vsock(cid: u32, port: u32) -> Self {
+        let mut client = AesmClientBuilder::new().build().expect("default options are supported on all targets");
+        client.inner.vsock_address = Some(VsockAddress { cid, port });
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::test_support::{count_allocations, MockAesm};
+    use sgx_isa::{Report, Targetinfo};
+
+    use crate::{
+        AesmClient, AesmError, AesmKeyId, AttestationAlgorithm, DcapUnavailableReason, Error, QuoteInfo, QuoteInfoEx, QuoteRequest, QuoteType, Request, Response, Response_GetQuoteResponse, SgxCheck,
+        Response_GetQuoteExResponse, Response_GetQuoteSizeExResponse,
+        Response_GetSupportedAttKeyIDNumResponse, Response_GetSupportedAttKeyIDsResponse,
+        Response_InitQuoteExResponse, Response_InitQuoteResponse, Response_SGXGetExtendedEpidGroupIdResponse, Response_SelectAttKeyIDResponse,
+        Response_UnsupportedRequestResponse, SigRl,
+    };
+
+    #[test]
+    fn builder() {
+        let mock = MockAesm::start(|req| {
+            assert_eq!(req.get_initQuoteReq().get_timeout(), 5_000_000);
+            let mut body = Response_InitQuoteResponse::new();
+            body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder()
+            .socket_path(mock.path())
+            .default_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        client.init_quote().unwrap();
+    }
+
+    #[test]
+    fn no_timeout() {
+        let mock = MockAesm::start(|req| {
+            assert!(!req.get_initQuoteReq().has_timeout());
+            // Longer than the default timeout set below.
+            thread::sleep(Duration::from_millis(300));
+            let mut body = Response_InitQuoteResponse::new();
+            body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder()
+            .socket_path(mock.path())
+            .default_timeout(Duration::from_millis(100))
+            .no_timeout()
+            .build()
+            .unwrap();
+        client.init_quote().unwrap();
+    }
+
+    #[test]
+    fn per_call_timeout() {
+        let mock = MockAesm::start(|req| {
+            assert_eq!(req.get_initQuoteReq().get_timeout(), 2_000_000);
+            let mut body = Response_InitQuoteResponse::new();
+            body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        client.init_quote_with_timeout(Duration::from_secs(2)).unwrap();
+    }
+
+    #[test]
+    fn default_timeouts() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests2 = requests.clone();
+        let mock = MockAesm::start(move |req| {
+            requests2.lock().unwrap().push(req);
+            MockAesm::error_code::<Response_UnsupportedRequestResponse>(1)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap().inner;
+        let _ = client.get_extended_epid_group_id();
+        let _ = client.get_launch_token_raw(&[0; 1808], &[0; 16]);
+        let _ = client.get_supported_att_key_id_num();
+        let _ = client.get_quote(vec![0; Report::UNPADDED_SIZE], vec![0; 16], vec![], QuoteType::Linkable, None);
+        let _ = client.init_quote();
+        let _ = client.check_update_status(None);
+
+        let requests = requests.lock().unwrap();
+        let timeouts = [
+            requests[0].get_sgxGetExtendedEpidGroupIdReq().get_timeout(),
+            requests[1].get_getLicTokenReq().get_timeout(),
+            requests[2].get_getSupportedAttKeyIDNumReq().get_timeout(),
+            requests[3].get_getQuoteReq().get_timeout(),
+            requests[4].get_initQuoteReq().get_timeout(),
+            requests[5].get_checkUpdateStatusReq().get_timeout(),
+        ];
+        assert_eq!(timeouts, [1_000_000, 10_000_000, 10_000_000, 60_000_000, 300_000_000, 30_000_000]);
+    }
+
+    #[test]
+    fn stalled_requests_time_out() {
+        use std::time::Instant;
+
+        type Call = Box<dyn Fn(&super::AesmClient) -> crate::Result<()> + Send>;
+
+        let mock = MockAesm::start(|_| None);
+        let client = AesmClient::builder().socket_path(mock.path()).default_timeout(Duration::from_millis(100)).build().unwrap().inner;
+        let quote_info = QuoteInfoEx::new(vec![1; AesmKeyId::SIZE], vec![0; Targetinfo::UNPADDED_SIZE], vec![], 1000).unwrap();
+        let requests: Vec<(&str, Call)> = vec![
+            ("InitQuote", Box::new(|c| c.init_quote().map(drop))),
+            ("GetQuote", Box::new(|c| c.get_quote(vec![0; Report::UNPADDED_SIZE], vec![0; 16], vec![], QuoteType::Linkable, None).map(drop))),
+            ("GetLaunchToken", Box::new(|c| c.get_launch_token_raw(&[0; 1808], &[0; 16]).map(drop))),
+            ("GetExtendedEpidGroupId", Box::new(|c| c.get_extended_epid_group_id().map(drop))),
+            ("GetPsCap", Box::new(|c| c.get_ps_cap().map(drop))),
+            ("GetWhiteListSize", Box::new(|c| c.get_white_list_size().map(drop))),
+            ("GetWhiteList", Box::new(|c| c.get_white_list(200).map(drop))),
+            ("RegisterWhiteList", Box::new(|c| c.register_white_list(vec![0; 200]))),
+            ("CheckUpdateStatus", Box::new(|c| c.check_update_status(None).map(drop))),
+            ("ReportAttestationStatus", Box::new(|c| c.report_attestation_status(vec![0; 101], 1).map(drop))),
+            ("GetSupportedAttKeyIdNum", Box::new(|c| c.get_supported_att_key_id_num().map(drop))),
+            ("GetSupportedAttKeyIds", Box::new(|c| c.get_supported_att_key_ids().map(drop))),
+            ("SelectAttKeyId", Box::new(|c| c.select_att_key_id(vec![0; AesmKeyId::SIZE]).map(drop))),
+            ("InitQuoteEx", Box::new(|c| c.init_quote_ex(vec![1; AesmKeyId::SIZE]).map(drop))),
+            ("GetQuoteSizeEx", Box::new(|c| c.get_quote_size_ex(vec![1; AesmKeyId::SIZE]).map(drop))),
+            ("GetQuoteEx", Box::new(move |c| c.get_quote_ex(&quote_info, &[0; Report::UNPADDED_SIZE], &[0; Targetinfo::UNPADDED_SIZE], &[0; 16]).map(drop))),
+        ];
+
+        // The requests stall in parallel, each on its own connection.
+        let threads: Vec<_> = requests.into_iter().map(|(kind, request)| {
+            let client = client.with_new_connection();
+            thread::spawn(move || {
+                let start = Instant::now();
+                let res = request(&client);
+                (kind, start.elapsed(), res)
+            })
+        }).collect();
+        for thread in threads {
+            let (kind, elapsed, res) = thread.join().unwrap();
+            match res {
+                Err(Error::Timeout { request: Some(_) }) => {}
+                res => panic!("unexpected result for {}: {:?}", kind, res),
+            }
+            // The client waits a little longer than AESM was asked to.
+            assert!(elapsed >= Duration::from_millis(600) && elapsed < Duration::from_secs(5), "{} gave up after {:?}", kind, elapsed);
+        }
+    }
+
+    #[test]
+    fn silent_aesm_times_out() {
+        use std::os::unix::net::UnixListener;
+        use std::time::Instant;
+
+        // Accepts connections, but never reads or responds.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        let accepted = thread::spawn(move || listener.accept().unwrap());
+
+        let client = AesmClient::builder().socket_path(&path).default_timeout(Duration::from_millis(100)).build().unwrap();
+        let start = Instant::now();
+        match client.get_extended_epid_group_id() {
+            Err(Error::Timeout { request: Some(crate::AesmRequestKind::GetExtendedEpidGroupId) }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(600) && start.elapsed() < Duration::from_secs(5), "gave up after {:?}", start.elapsed());
+        drop(accepted.join().unwrap());
+    }
+
+    #[test]
+    fn no_timeout_clears_deadlines() {
+        let requests = AtomicUsize::new(0);
+        let mock = MockAesm::start(move |req| {
+            // Respond to the second request after the deadline of the first
+            // one has passed.
+            if requests.fetch_add(1, Ordering::SeqCst) == 1 {
+                thread::sleep(Duration::from_millis(800));
+            }
+            init_quote_response(req)
+        });
+        let client = AesmClient::builder().socket_path(mock.path()).no_timeout().build().unwrap();
+
+        client.init_quote_with_timeout(Duration::from_millis(10)).unwrap();
+        client.init_quote().unwrap();
+        // Both requests were sent on the same connection.
+        assert_eq!(mock.connections(), 1);
+    }
+
+    /// Answers with only the fields that are required by the protobuf
+    /// definitions, plus unknown ones.
+    fn minimal_response(req: Request) -> Option<Response> {
+        use crate::{Response_GetLaunchTokenResponse, Response_GetSupportedAttKeyIDNumResponse};
+        use protobuf::Message;
+
+        let mut res = if req.has_initQuoteReq() {
+            let mut body = Response_InitQuoteResponse::new();
+            body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+            body.mut_unknown_fields().add_varint(1000, 1);
+            MockAesm::response(0, body)
+        } else if req.has_getLicTokenReq() {
+            MockAesm::error_code::<Response_GetLaunchTokenResponse>(0)
+        } else if req.has_getSupportedAttKeyIDNumReq() {
+            MockAesm::error_code::<Response_GetSupportedAttKeyIDNumResponse>(0)
+        } else if req.has_getQuoteSizeExReq() {
+            MockAesm::error_code::<Response_GetQuoteSizeExResponse>(0)
+        } else {
+            let mut body = Response_GetQuoteExResponse::new();
+            body.set_quote(vec![0; 1000]);
+            body.mut_unknown_fields().add_length_delimited(1000, vec![1, 2, 3]);
+            MockAesm::response(0, body)
+        }?;
+        res.mut_unknown_fields().add_varint(1000, 1);
+        Some(res)
+    }
+
+    #[test]
+    fn missing_response_fields() {
+        let mock = MockAesm::start(minimal_response);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap().inner;
+
+        // The GID isn't needed, e.g. on platforms without EPID.
+        assert!(client.init_quote().unwrap().gid().is_empty());
+
+        let bad_response = |res: crate::Result<()>| match res {
+            Err(Error::AesmBadResponse(field)) => field,
+            res => panic!("unexpected result {:?}", res),
+        };
+        assert_eq!(bad_response(client.get_launch_token_raw(&[0; 1808], &[0; 16]).map(drop)), "launch token");
+        assert_eq!(bad_response(client.get_supported_att_key_id_num().map(drop)), "number of attestation key IDs");
+        assert_eq!(bad_response(client.get_quote_size_ex(vec![1; AesmKeyId::SIZE]).map(drop)), "quote size");
+
+        let quote_info = QuoteInfoEx::new(vec![1; AesmKeyId::SIZE], vec![0; Targetinfo::UNPADDED_SIZE], vec![], 1000).unwrap();
+        match client.get_quote_ex(&quote_info, &[0; Report::UNPADDED_SIZE], &[0; Targetinfo::UNPADDED_SIZE], &[0; 16]) {
+            Err(Error::MalformedResponse { field: "qe_report_info", .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn get_quote_for_report() {
+        let mut sig_rl = vec![0; crate::sig_rl::size(2)];
+        sig_rl[..4].copy_from_slice(&[0x02, 0x00, 0x00, 0x0e]);
+        sig_rl[15] = 2;
+        let sig_rl = SigRl::parse(&sig_rl).unwrap();
+
+        let expected = sig_rl.clone();
+        let mock = MockAesm::start(move |req| {
+            let report = req.get_getQuoteReq().get_report();
+            assert_eq!(report.len(), Report::UNPADDED_SIZE);
+            assert_eq!(Report::try_copy_from(report).unwrap(), Report::default());
+            assert_eq!(req.get_getQuoteReq().get_sig_rl(), expected.as_bytes());
+            assert_eq!(req.get_getQuoteReq().get_buf_size(), crate::quote_buffer_size(expected.as_bytes()));
+            MockAesm::error_code::<Response_GetQuoteResponse>(1)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.get_quote_for_report(&Report::default(), [0; 16], &sig_rl, QuoteType::Linkable, Some(&[0; 16])) {
+            Err(Error::AesmCode(_)) => {}
+            res => panic!("expected AESM error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn get_quote_req() {
+        // The platform is in EPID group 0xbad, AESM reports it little-endian.
+        let quote_info = QuoteInfo::new(vec![0; Targetinfo::UNPADDED_SIZE], vec![0xad, 0x0b, 0x00, 0x00]).unwrap();
+        let mut sig_rl = vec![0; crate::sig_rl::size(1)];
+        sig_rl[..8].copy_from_slice(&[0x02, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x0b, 0xad]);
+        sig_rl[15] = 1;
+        let sig_rl = SigRl::parse(&sig_rl).unwrap();
+
+        let expected = sig_rl.clone();
+        let mock = MockAesm::start(move |req| {
+            let req = req.get_getQuoteReq();
+            assert_eq!(req.get_spid(), &[0xab; 16]);
+            assert_eq!(req.get_nonce(), &[0xcd; 16]);
+            assert_eq!(req.get_sig_rl(), expected.as_bytes());
+            assert_eq!(req.get_quote_type(), QuoteType::Unlinkable as u32);
+            let mut body = Response_GetQuoteResponse::new();
+            body.set_quote(vec![1; 16]);
+            body.set_qe_report(vec![2; 16]);
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let req = QuoteRequest::new(&Report::default())
+            .nonce([0xcd; 16])
+            .quote_type(QuoteType::Unlinkable)
+            .sig_rl(&sig_rl)
+            .spid([0xab; 16]);
+        let quote = client.get_quote_req(&quote_info, req.clone()).unwrap();
+        assert_eq!((quote.quote(), quote.qe_report()), (&[1; 16][..], &[2; 16][..]));
+
+        // A revocation list of another group is caught before it's sent.
+        let other_group = QuoteInfo::new(vec![0; Targetinfo::UNPADDED_SIZE], vec![0xde, 0x0c, 0x00, 0x00]).unwrap();
+        match client.get_quote_req(&other_group, req) {
+            Err(Error::SigRlGroupMismatch { sig_rl: 0xbad, platform: 0xcde }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn get_quote_without_nonce() {
+        let mock = MockAesm::start(|req| {
+            let req = req.get_getQuoteReq();
+            // With a nonce, a QE report is requested.
+            let with_nonce = req.has_nonce();
+            assert_eq!(req.has_qe_report(), with_nonce);
+            let mut body = Response_GetQuoteResponse::new();
+            body.set_quote(vec![1; 16]);
+            if with_nonce {
+                body.set_qe_report(vec![]);
+            }
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let quote = client.get_quote_for_report(&Report::default(), [0; 16], &SigRl::empty(), QuoteType::Linkable, None).unwrap();
+        assert_eq!(quote.quote(), &[1; 16]);
+        assert!(quote.qe_report().is_empty());
+        assert!(!quote.has_qe_report());
+        assert!(format!("{:?}", quote).ends_with("qe_report: not requested }"));
+
+        // AESM returning an empty QE report is reported differently.
+        let quote = client.get_quote_for_report(&Report::default(), [0; 16], &SigRl::empty(), QuoteType::Linkable, Some(&[0; 16])).unwrap();
+        assert!(quote.has_qe_report());
+        assert!(format!("{:?}", quote).ends_with("qe_report: 0 bytes [] }"));
+    }
+
+    #[test]
+    fn get_quote_moves_quote() {
+        // Unusual sizes, so that other allocations aren't counted. QE fills
+        // the buffer, so the quote returned by AESM is longer than the quote.
+        const BUFFER_LEN: usize = 1_000_003;
+        const QUOTE_LEN: usize = 900_001;
+
+        let mut quote = vec![0; BUFFER_LEN];
+        quote[432..436].copy_from_slice(&(QUOTE_LEN as u32 - 436).to_le_bytes());
+        let mut body = Response_GetQuoteResponse::new();
+        body.set_quote(quote);
+        let res = MockAesm::response(0, body);
+        // Taken rather than cloned, so that the mock doesn't allocate a
+        // buffer of the same size.
+        let res = Mutex::new(res);
+        let mock = MockAesm::start(move |_| res.lock().unwrap().take());
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let ([buffers, quotes], quote) = count_allocations([BUFFER_LEN, QUOTE_LEN], || {
+            client.get_quote_for_report(&Report::default(), [0; 16], &SigRl::empty(), QuoteType::Linkable, None).unwrap()
+        });
+        assert_eq!(quote.quote().len(), QUOTE_LEN);
+        // The quote is decoded once from the response, and then moved into
+        // the result and trimmed in place.
+        assert_eq!(buffers, 1);
+        assert_eq!(quotes, 0);
+    }
+
+    #[test]
+    fn get_quote_from_slices() {
+        // An empty revocation list, shared like a cached one would be.
+        let sig_rl: Arc<[u8]> = vec![0; crate::sig_rl::size(0)].into();
+
+        let expected = sig_rl.clone();
+        let mock = MockAesm::start(move |req| {
+            assert_eq!(req.get_getQuoteReq().get_sig_rl(), &*expected);
+            MockAesm::error_code::<Response_GetQuoteResponse>(1)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let report = [0; Report::UNPADDED_SIZE];
+        match client.get_quote_from_slices(&report, &[0; 16], &sig_rl, QuoteType::Linkable, &[0; 16]) {
+            Err(Error::AesmCode(_)) => {}
+            res => panic!("expected AESM error, got {:?}", res),
+        }
+        match client.get_quote_from_slices(&report[1..], &[0; 16], &sig_rl, QuoteType::Linkable, &[0; 16]) {
+            Err(Error::InvalidArgument { field: "report", .. }) => {}
+            res => panic!("expected invalid report, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn get_quote_from_slices_copies_sig_rl_once() {
+        use protobuf::Message;
+        use std::io::Write;
+
+        // About 512 KiB, an unusual size, so that other allocations aren't
+        // counted.
+        const ENTRIES: usize = 4096;
+        let mut sig_rl = vec![0; crate::sig_rl::size(ENTRIES)];
+        sig_rl[12..16].copy_from_slice(&(ENTRIES as u32).to_be_bytes());
+        let sig_rl: Arc<[u8]> = sig_rl.into();
+
+        let res = MockAesm::error_code::<Response_GetQuoteResponse>(1).unwrap().write_to_bytes().unwrap();
+
+        // The raw server reads the request without decoding it, so only the
+        // client's copies are counted.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let server = serve_raw(&path, move |stream| {
+            stream.write_all(&(res.len() as u32).to_ne_bytes()).unwrap();
+            stream.write_all(&res).unwrap();
+        });
+        let client = AesmClient::builder().socket_path(&path).build().unwrap();
+        let report = [0; Report::UNPADDED_SIZE];
+        let ([copies, _], res) = count_allocations([sig_rl.len(), 0], || {
+            client.get_quote_from_slices(&report, &[0; 16], &sig_rl, QuoteType::Linkable, &[0; 16])
+        });
+        assert!(matches!(res, Err(Error::AesmCode(_))), "{:?}", res);
+        // Copied into the request once, encoding the request doesn't copy it
+        // again.
+        assert_eq!(copies, 1);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn get_quote_trims_padding() {
+        fn get_quote(quote: Vec<u8>) -> Vec<u8> {
+            let mock = MockAesm::start(move |_| {
+                let mut body = Response_GetQuoteResponse::new();
+                body.set_quote(quote.clone());
+                MockAesm::response(0, body)
+            });
+            let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+            let quote_info = QuoteInfo::new(vec![0; Targetinfo::UNPADDED_SIZE], vec![]).unwrap();
+            let req = QuoteRequest::new(&Report::default()).spid([0; 16]).quote_type(QuoteType::Linkable).nonce([0; 16]);
+            client.get_quote_req(&quote_info, req)
+                .unwrap()
+                .quote()
+                .to_owned()
+        }
+        fn quote(sig_len: u32, padding: usize) -> Vec<u8> {
+            let mut quote = vec![1; 432];
+            quote.extend_from_slice(&sig_len.to_le_bytes());
+            quote.extend(vec![2; sig_len as usize]);
+            quote.extend(vec![0; padding]);
+            quote
+        }
+
+        assert_eq!(get_quote(quote(680, 0)), quote(680, 0));
+        assert_eq!(get_quote(quote(680, 300)), quote(680, 0));
+        assert_eq!(get_quote(quote(0, 10)), quote(0, 0));
+        // Headers that don't parse are returned as is.
+        assert_eq!(get_quote(vec![1; 100]), vec![1; 100]);
+        let mut too_long = quote(680, 0);
+        too_long[432..436].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(get_quote(too_long.clone()), too_long);
+    }
+
+    #[test]
+    fn get_quote_with_generated_nonce() {
+        let nonces = Arc::new(Mutex::new(vec![]));
+        let sent = nonces.clone();
+        let mock = MockAesm::start(move |req| {
+            sent.lock().unwrap().push(req.get_getQuoteReq().get_nonce().to_owned());
+            let mut body = Response_GetQuoteResponse::new();
+            body.set_quote(vec![1; 16]);
+            MockAesm::response(0, body)
+        });
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+
+        let report = Report::default();
+        let (quote, first) = client.get_quote_with_generated_nonce(&report, [0; 16], &SigRl::empty(), QuoteType::Linkable).unwrap();
+        assert_eq!(quote.quote(), &[1; 16][..]);
+        let (_, second) = client.get_quote_with_generated_nonce(&report, [0; 16], &SigRl::empty(), QuoteType::Linkable).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(*nonces.lock().unwrap(), [first.to_vec(), second.to_vec()]);
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn attest_epid() {
+        use sha2::{Digest, Sha256};
+
+        use crate::AttestationStep;
+
+        // AESM fails the second quote, and binds the QE report of the third to
+        // the wrong nonce.
+        let quotes = Arc::new(AtomicUsize::new(0));
+        let mock = MockAesm::start(move |req| {
+            if req.has_initQuoteReq() {
+                let mut body = Response_InitQuoteResponse::new();
+                body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+                body.set_gid(vec![0; 4]);
+                return MockAesm::response(0, body);
+            }
+            let req = req.get_getQuoteReq();
+            match quotes.fetch_add(1, Ordering::SeqCst) {
+                1 => MockAesm::error_code::<Response_GetQuoteResponse>(18),
+                n => {
+                    let quote = vec![1; 16];
+                    let mut qe_report = Report::default();
+                    let nonce = if n == 0 { req.get_nonce() } else { &[0; 16][..] };
+                    let hash = Sha256::new().chain(nonce).chain(&quote).result();
+                    qe_report.reportdata[..32].copy_from_slice(&hash);
+                    let mut body = Response_GetQuoteResponse::new();
+                    body.set_quote(quote);
+                    body.set_qe_report(qe_report.as_ref().to_vec());
+                    MockAesm::response(0, body)
+                }
+            }
+        });
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let attest = || {
+            let report_for = |ti: &Targetinfo| Report { mrenclave: ti.measurement, ..Default::default() };
+            client.attest_epid(&[0; 16].into(), &SigRl::empty(), QuoteType::Linkable, report_for)
+        };
+
+        let attestation = attest().unwrap();
+        assert_eq!(attestation.quote().quote(), &[1; 16][..]);
+        assert_eq!(attestation.qe_report().as_ref(), attestation.quote().qe_report());
+
+        match attest() {
+            Err(Error::Attestation { step: AttestationStep::GetQuote, ref error }) => {
+                assert!(matches!(**error, Error::AesmCode(AesmError::Busy_18)), "{:?}", error)
+            }
+            res => panic!("expected failed quote, got {:?}", res),
+        }
+        match attest() {
+            Err(Error::Attestation { step: AttestationStep::VerifyQeReport, ref error }) => {
+                assert!(matches!(**error, Error::QeReportMismatch), "{:?}", error)
+            }
+            res => panic!("expected QE report mismatch, got {:?}", res),
+        }
+    }
+
+    // Target infos that AESM must not return: too short, too long, and with
+    // nonzero reserved fields.
+    fn malformed_target_infos() -> Vec<(Vec<u8>, &'static str)> {
+        let mut reserved = vec![0; Targetinfo::UNPADDED_SIZE];
+        reserved[Targetinfo::UNPADDED_SIZE - 1] = 1;
+        vec![
+            (vec![0; 16], "wrong size"),
+            (vec![0; Targetinfo::UNPADDED_SIZE + 1], "wrong size"),
+            (reserved, "reserved fields aren't zero"),
+        ]
+    }
+
+    fn assert_malformed_target_info<T: std::fmt::Debug>(res: crate::Result<T>, target_info: &[u8], expected: &str) {
+        match res {
+            Err(Error::MalformedResponse { field: "target_info", reason, ref raw }) => {
+                assert_eq!(reason, expected);
+                assert_eq!(raw, target_info);
+            }
+            res => panic!("expected malformed target info, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn targetinfo() {
+        fn quote_info(target_info: Vec<u8>) -> crate::Result<QuoteInfo> {
+            let mock = MockAesm::start(move |_| {
+                let mut body = Response_InitQuoteResponse::new();
+                body.set_targetInfo(target_info.clone());
+                body.set_gid(vec![0; 4]);
+                MockAesm::response(0, body)
+            });
+            AesmClient::builder().socket_path(mock.path()).build().unwrap().init_quote()
+        }
+
+        let targetinfo = Targetinfo { measurement: [1; 32], ..Default::default() };
+        assert_eq!(quote_info(targetinfo.as_ref().to_vec()).unwrap().targetinfo().unwrap(), targetinfo);
+        for (target_info, reason) in malformed_target_infos() {
+            assert_malformed_target_info(quote_info(target_info.clone()), &target_info, reason);
+        }
+    }
+
+    fn init_quote_response(_: Request) -> Option<Response> {
+        let mut body = Response_InitQuoteResponse::new();
+        body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+        MockAesm::response(0, body)
+    }
+
+    fn address(path: &std::path::Path) -> super::SocketAddress {
+        super::SocketAddress::Path(path.to_owned())
+    }
+
+    #[test]
+    fn discover_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.socket");
+        let (first, second) = (MockAesm::start(init_quote_response), MockAesm::start(init_quote_response));
+        let client = super::AesmClient::default();
+
+        let (missing, first_address, second_address) = (address(&missing), address(first.path()), address(second.path()));
+        client.discover_socket(&[missing.clone(), second_address.clone(), first_address.clone()], super::local_deadline()).unwrap();
+        assert_eq!(*client.discovered_address.lock().unwrap(), Some(second_address));
+        // The path that worked is tried first, and shared with clones.
+        client.clone().discover_socket(&[], super::local_deadline()).unwrap();
+
+        // The remembered path is forgotten when it stops working.
+        drop(second);
+        client.discover_socket(&[missing, first_address.clone()], super::local_deadline()).unwrap();
+        assert_eq!(*client.discovered_address.lock().unwrap(), Some(first_address));
+    }
+
+    #[test]
+    fn try_connect_timeout() {
+        use std::os::unix::io::AsRawFd;
+        use std::os::unix::net::{UnixListener, UnixStream};
+        use std::time::Instant;
+
+        let mock = MockAesm::start(init_quote_response);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        client.try_connect_timeout(Duration::from_secs(1)).unwrap();
+        // A timeout too long for a deadline doesn't overflow.
+        client.try_connect_timeout(Duration::new(u64::MAX, 0)).unwrap();
+
+        // A socket that never accepts: once its backlog is full, connecting
+        // fails until a connection is accepted.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        assert_eq!(unsafe { libc::listen(listener.as_raw_fd(), 0) }, 0);
+        let _queued = UnixStream::connect(&path).unwrap();
+
+        let client = AesmClient::builder().socket_path(&path).build().unwrap();
+        let start = Instant::now();
+        match client.try_connect_timeout(Duration::from_millis(100)) {
+            Err(Error::Timeout { request: None }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn wait_for_service() {
+        use std::time::Instant;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let client = AesmClient::builder().socket_path(&path).build().unwrap();
+
+        // AESM starts listening while the client waits.
+        let started = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                MockAesm::start_at(&path, |_| {
+                    // Like an AESM without EPID support.
+                    MockAesm::error_code::<Response_SGXGetExtendedEpidGroupIdResponse>(27) // ServiceNotAvailable
+                })
+            })
+        };
+        let start = Instant::now();
+        client.wait_for_service(Duration::from_secs(10)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        let mock = started.join().unwrap();
+        assert!(mock.connections() >= 1);
+
+        // A timeout too long for a deadline doesn't overflow.
+        client.wait_for_service(Duration::new(u64::MAX, 0)).unwrap();
+
+        // AESM never starts.
+        let client = AesmClient::builder().socket_path(dir.path().join("missing.socket")).build().unwrap();
+        let start = Instant::now();
+        match client.wait_for_service(Duration::from_millis(100)) {
+            Err(Error::AesmCommunication(ref err)) if err.kind() == std::io::ErrorKind::NotFound => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn discover_socket_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let candidates = [
+            address(&dir.path().join("a.socket")),
+            address(&dir.path().join("b.socket")),
+            super::SocketAddress::Abstract(b"aesm-client-test-missing".to_vec()),
+        ];
+        let client = super::AesmClient::default();
+        let err = match client.discover_socket(&candidates, super::local_deadline()) {
+            Err(Error::AesmCommunication(err)) => err.to_string(),
+            res => panic!("unexpected result {:?}", res),
+        };
+        assert!(err.contains(&format!("{}: ", dir.path().join("a.socket").display())), "{}", err);
+        assert!(err.contains(&format!("{}: ", dir.path().join("b.socket").display())), "{}", err);
+        assert!(err.contains("@aesm-client-test-missing: "), "{}", err);
+        assert_eq!(*client.discovered_address.lock().unwrap(), None);
+
+        assert_eq!(super::AESM_SOCKET_ABSTRACT_ADDRESS.to_string(), "@sgx_aesm_socket_base");
+    }
+
+    #[test]
+    fn explicit_socket_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = AesmClient::builder().socket_path(dir.path().join("aesm.socket")).build().unwrap();
+        match client.try_connect() {
+            Err(Error::AesmCommunication(err)) => assert!(!err.to_string().contains("couldn't connect to AESM")),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn diagnose() {
+        let mock = MockAesm::start(|req| {
+            // Pretend the platform doesn't support EPID unless a timeout is
+            // given.
+            match req.get_sgxGetExtendedEpidGroupIdReq().get_timeout() {
+                0 => MockAesm::error_code::<Response_SGXGetExtendedEpidGroupIdResponse>(2),
+                _ => {
+                    let mut body = Response_SGXGetExtendedEpidGroupIdResponse::new();
+                    body.set_x_group_id(0);
+                    MockAesm::response(0, body)
+                }
+            }
+        });
+
+        // Any existing file will do as a device node.
+        let devices = [mock.path()];
+        let client = AesmClient::builder().socket_path(mock.path()).sgx_device_paths(devices).build().unwrap();
+        let diagnostics = client.diagnose().unwrap();
+        assert_eq!(diagnostics.socket_path.as_deref(), Some(mock.path()));
+        assert!(diagnostics.connected && diagnostics.is_healthy());
+        assert!(diagnostics.latency.is_some());
+        assert!(diagnostics.aesm_error().is_none());
+
+        let client = AesmClient::builder().socket_path(mock.path()).sgx_device_paths(devices).no_timeout().build().unwrap();
+        let diagnostics = client.diagnose().unwrap();
+        assert!(diagnostics.connected && !diagnostics.is_healthy());
+        assert!(diagnostics.latency.is_some());
+        assert!(matches!(diagnostics.aesm_error(), Some(AesmError::NoDeviceError_2)));
+    }
+
+    #[test]
+    fn check_sgx_available() {
+        use std::fs::File;
+
+        let dir = tempfile::tempdir().unwrap();
+        let devices = [dir.path().join("sgx_enclave"), dir.path().join("isgx")];
+        let sockets = [address(&dir.path().join("aesm.socket"))];
+        match super::check_sgx_available(&devices, &sockets) {
+            Err(Error::SgxNotAvailable(SgxCheck::DeviceNode)) => {}
+            res => panic!("expected missing device node, got {:?}", res),
+        }
+
+        File::create(&devices[1]).unwrap();
+        match super::check_sgx_available(&devices, &sockets) {
+            Err(Error::SgxNotAvailable(SgxCheck::AesmSocket)) => {}
+            res => panic!("expected missing socket, got {:?}", res),
+        }
+        super::check_sgx_available(&devices, std::slice::from_ref(&super::AESM_SOCKET_ABSTRACT_ADDRESS)).unwrap();
+
+        File::create(dir.path().join("aesm.socket")).unwrap();
+        super::check_sgx_available(&devices, &sockets).unwrap();
+    }
+
+    #[test]
+    fn permission_denied() {
+        use std::fs::{self, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        let mock = MockAesm::start(init_quote_response);
+        fs::set_permissions(mock.path(), Permissions::from_mode(0o000)).unwrap();
+        fs::set_permissions(mock.path().parent().unwrap(), Permissions::from_mode(0o755)).unwrap();
+
+        let path = mock.path().to_owned();
+        let res = thread::spawn(move || {
+            // Root may access the socket anyway. File permissions are checked
+            // against the filesystem user ID, which is per thread on Linux, so
+            // drop it in this thread only.
+            #[cfg(target_os = "linux")]
+            unsafe {
+                if libc::geteuid() == 0 {
+                    libc::setfsuid(65534);
+                }
+            }
+            AesmClient::builder().socket_path(&path).build().unwrap().try_connect()
+        }).join().unwrap();
+        match res {
+            Err(Error::PermissionDenied { ref path }) => assert_eq!(path, mock.path()),
+            res => panic!("expected permission denied, got {:?}", res),
+        }
+
+        // Other errors aren't affected.
+        let dir = tempfile::tempdir().unwrap();
+        match AesmClient::builder().socket_path(dir.path().join("aesm.socket")).build().unwrap().try_connect() {
+            Err(Error::AesmCommunication(ref err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+            res => panic!("expected not found, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn diagnose_not_connected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let client = AesmClient::builder().socket_path(&path).sgx_device_paths([dir.path().join("isgx")]).build().unwrap();
+        match client.diagnose() {
+            Err(Error::SgxNotAvailable(SgxCheck::DeviceNode)) => {}
+            res => panic!("expected missing device node, got {:?}", res),
+        }
+
+        // Any existing file will do as a device node.
+        let client = AesmClient::builder().socket_path(&path).sgx_device_paths([dir.path()]).build().unwrap();
+        match client.diagnose() {
+            Err(Error::SgxNotAvailable(SgxCheck::AesmSocket)) => {}
+            res => panic!("expected missing socket, got {:?}", res),
+        }
+
+        // The socket exists, but AESM doesn't listen on it.
+        std::fs::File::create(&path).unwrap();
+        let diagnostics = client.diagnose().unwrap();
+        assert_eq!(diagnostics.socket_path, Some(path));
+        assert!(!diagnostics.connected && !diagnostics.is_healthy());
+        match diagnostics.connect_error {
+            Some(Error::AesmCommunication(ref err)) => assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused),
+            ref err => panic!("unexpected error {:?}", err),
+        }
+        assert!(diagnostics.latency.is_none() && diagnostics.request_error.is_none());
+    }
+
+    #[test]
+    fn persistent_connection() {
+        let mock = MockAesm::start(init_quote_response);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        client.init_quote().unwrap();
+        client.init_quote().unwrap();
+        // Clones share the connection.
+        client.clone().init_quote().unwrap();
+        assert_eq!(mock.connections(), 1);
+    }
+
+    #[test]
+    fn concurrent_requests() {
+        // Responds with the nonce of the request, so that every caller can
+        // check that it got the response to its own request.
+        let mock = MockAesm::start(|req| {
+            let mut body = Response_GetQuoteResponse::new();
+            body.set_quote(req.get_getQuoteReq().get_nonce().to_owned());
+            MockAesm::response(0, body)
+        });
+        let client = Arc::new(AesmClient::builder().socket_path(mock.path()).build().unwrap());
+
+        let threads = (0..8u8).map(|t| {
+            // Half of the threads use a clone, the others share the client.
+            let client = client.clone();
+            let clone = if t % 2 == 0 { Some((*client).clone()) } else { None };
+            thread::spawn(move || {
+                let client = clone.as_ref().unwrap_or(&client);
+                for i in 0..50u8 {
+                    let nonce = [t, i, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+                    let quote = client.get_quote_for_report(&Report::default(), [0; 16], &SigRl::empty(), QuoteType::Linkable, Some(&nonce))
+                        .unwrap();
+                    assert_eq!(quote.quote(), &nonce[..]);
+                }
+            })
+        }).collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(mock.connections(), 1);
+    }
+
+    #[test]
+    fn reconnect() {
+        let mock = MockAesm::start_one_request_per_connection(init_quote_response);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        for _ in 0..3 {
+            client.init_quote().unwrap();
+        }
+        assert_eq!(mock.connections(), 3);
+    }
+
+    #[test]
+    fn closed_connection_not_reused() {
+        let mock = MockAesm::start_one_request_per_connection(init_quote_response);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        for closed in 1..=3 {
+            client.init_quote().unwrap();
+            while mock.closed_connections() < closed {
+                thread::yield_now();
+            }
+        }
+        // No request was sent on a connection that AESM had closed.
+        assert_eq!(mock.requests(), 3);
+        assert_eq!(mock.connections(), 3);
+    }
+
+    /// Runs against AESM on the host if `AESM_VSOCK_PORT` is set. Otherwise
+    /// a mock listens on the loopback address, which closes every connection
+    /// after one request, like older versions of AESM.
+    #[cfg(feature = "vsock")]
+    #[test]
+    fn vsock() {
+        use std::io::{Read, Write};
+        use crate::unix::AesmClientExt;
+        use byteorder::{NativeEndian, ReadBytesExt};
+        use protobuf::Message;
+        use vsock::{Std, VsockListener, VMADDR_CID_HOST, VMADDR_CID_LOCAL};
+
+        let (cid, port) = match std::env::var("AESM_VSOCK_PORT") {
+            Ok(port) => (VMADDR_CID_HOST, port.parse().expect("AESM_VSOCK_PORT")),
+            Err(_) => {
+                let listener = match VsockListener::<Std>::bind_with_cid_port(VMADDR_CID_LOCAL, 0) {
+                    Ok(listener) => listener,
+                    // There's no loopback vsock, e.g. in a container.
+                    Err(_) => return,
+                };
+                let port = listener.local_addr().unwrap().port();
+                thread::spawn(move || loop {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let len = stream.read_u32::<NativeEndian>().unwrap();
+                    let mut req = vec![0; len as usize];
+                    stream.read_exact(&mut req).unwrap();
+                    assert!(Request::parse_from_bytes(&req).unwrap().has_initQuoteReq());
+                    let res = init_quote_response(Request::new()).unwrap().write_to_bytes().unwrap();
+                    stream.write_all(&(res.len() as u32).to_ne_bytes()).unwrap();
+                    stream.write_all(&res).unwrap();
+                });
+                (VMADDR_CID_LOCAL, port)
+            }
+        };
+
+        let client = crate::AesmClient::with_vsock(cid, port);
+        client.inner.check_sgx_available().unwrap();
+        assert_eq!(client.inner.socket_path(), None);
+        for _ in 0..3 {
+            client.init_quote().unwrap();
+        }
+    }
+
+    #[test]
+    fn reconnect_after_restart() {
+        // AESM restarted after the client connected.
+        let mock = MockAesm::start_dropping(1, init_quote_response);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        client.init_quote().unwrap();
+        assert_eq!(mock.connections(), 2);
+
+        // The request is only retried once.
+        let mock = MockAesm::start_dropping(2, init_quote_response);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.init_quote() {
+            Err(ref err) if super::aesm_protobuf::is_disconnect(err) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(mock.connections(), 2);
+    }
+
+    #[test]
+    fn partial_response() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let server = serve_raw(&path, |stream| {
+            stream.write_all(&large_quote_response(1024)[..6]).unwrap();
+        });
+        let client = AesmClient::builder().socket_path(&path).build().unwrap();
+        match get_quote(&client) {
+            Err(Error::PartialResponse { received: 6 }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn with_path() {
+        use crate::unix::AesmClientExt;
+
+        let mock = MockAesm::start_one_request_per_connection(init_quote_response);
+        let client = AesmClient::with_path(mock.path());
+        // Every request, including those on a new connection, goes to the
+        // socket at the path.
+        for _ in 0..2 {
+            client.init_quote().unwrap();
+        }
+        assert_eq!(mock.connections(), 2);
+
+        // The default client doesn't know about the mock. Whether there's an
+        // AESM at the default paths depends on the host.
+        let _ = AesmClient::new().init_quote();
+        assert_eq!(mock.connections(), 2);
+    }
+
+    #[test]
+    fn abstract_socket() {
+        use crate::unix::AesmClientExt;
+
+        let name = format!("aesm-client-test-{}", std::process::id());
+        let mock = MockAesm::start_abstract(name.as_bytes(), init_quote_response);
+        let client = AesmClient::with_abstract_name(name.as_bytes());
+        for _ in 0..2 {
+            client.init_quote().unwrap();
+        }
+        // The mock closes the connection after every request, so the client
+        // connects to the abstract address again.
+        assert_eq!(mock.connections(), 2);
+        assert_eq!(client.inner.socket_path(), Some(mock.path().to_owned()));
+
+        // Abstract names are matched exactly, without padding.
+        let padded = format!("{}\0", name);
+        match AesmClient::with_abstract_name(padded.as_bytes()).init_quote() {
+            Err(Error::AesmCommunication(ref err)) if err.kind() == std::io::ErrorKind::ConnectionRefused => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn provision_epid() {
+        // Provisioning only completes once another request was answered,
+        // which requires it not to block the client's connection.
+        let answered = Arc::new((Mutex::new(false), Condvar::new()));
+        let answered2 = answered.clone();
+        let mock = MockAesm::start(move |req| {
+            let (ref done, ref cvar) = *answered2;
+            if req.has_initQuoteReq() {
+                assert!(!req.get_initQuoteReq().has_timeout());
+                let (done, timeout) = cvar.wait_timeout_while(done.lock().unwrap(), Duration::from_secs(10), |done| !*done).unwrap();
+                assert!(*done && !timeout.timed_out(), "provisioning blocked another request");
+                let mut body = Response_InitQuoteResponse::new();
+                body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+                body.set_gid(vec![1; 4]);
+                MockAesm::response(0, body)
+            } else {
+                let mut body = Response_SGXGetExtendedEpidGroupIdResponse::new();
+                body.set_x_group_id(0);
+                *done.lock().unwrap() = true;
+                cvar.notify_all();
+                MockAesm::response(0, body)
+            }
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        client.try_connect().unwrap();
+        let provisioning = {
+            let client = client.clone();
+            thread::spawn(move || client.provision_epid(None))
+        };
+        thread::sleep(Duration::from_millis(50));
+        client.get_extended_epid_group_id().unwrap();
+        provisioning.join().unwrap().unwrap();
+        // The new quote info is cached.
+        assert_eq!(client.cached_quote_info().unwrap().gid(), vec![1; 4]);
+    }
+
+    #[test]
+    fn provision_epid_error() {
+        let mock = MockAesm::start(|req| {
+            assert_eq!(req.get_initQuoteReq().get_timeout(), 600_000_000);
+            MockAesm::error_code::<Response_InitQuoteResponse>(16) // SgxProvisionFailed
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.provision_epid(Some(Duration::from_secs(600))) {
+            Err(Error::AesmCode(AesmError::SgxProvisionFailed_16)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn cached_quote_info() {
+        let init_quotes = Arc::new(AtomicUsize::new(0));
+        let init_quotes2 = init_quotes.clone();
+        let mock = MockAesm::start(move |req| {
+            if req.has_initQuoteReq() {
+                init_quotes2.fetch_add(1, Ordering::SeqCst);
+                // Give a concurrent caller a chance to miss the cache.
+                thread::sleep(Duration::from_millis(50));
+                let mut body = Response_InitQuoteResponse::new();
+                body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+                body.set_gid(vec![0; 4]);
+                MockAesm::response(0, body)
+            } else {
+                MockAesm::error_code::<Response_GetQuoteResponse>(5) // EPID revoked
+            }
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let threads = (0..2).map(|_| {
+            let client = client.clone();
+            thread::spawn(move || client.cached_quote_info().unwrap())
+        }).collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(init_quotes.load(Ordering::SeqCst), 1);
+
+        client.cached_quote_info().unwrap();
+        assert_eq!(init_quotes.load(Ordering::SeqCst), 1);
+
+        client.get_quote_for_report(&Report::default(), [0; 16], &SigRl::empty(), QuoteType::Linkable, Some(&[0; 16])).unwrap_err();
+        client.cached_quote_info().unwrap();
+        assert_eq!(init_quotes.load(Ordering::SeqCst), 2);
+
+        client.refresh_quote_info().unwrap();
+        assert_eq!(init_quotes.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn get_quote_invalid_argument() {
+        // Arguments are checked before connecting to AESM.
+        let client = AesmClient::builder().socket_path("/nonexistent").build().unwrap();
+        let report = vec![0; Report::UNPADDED_SIZE];
+        let mut sig_rl = vec![0; 16 + 2 * 128 + 64];
+        sig_rl[15] = 2;
+
+        let get_quote = |report: &[u8], spid: &[u8], sig_rl: &[u8], nonce: &[u8]| {
+            client.get_quote(report.to_vec(), spid.to_vec(), sig_rl.to_vec(), QuoteType::Linkable, nonce.to_vec())
+        };
+        let assert_invalid = |res, field: &str, expected: usize, got: usize| match res {
+            Err(Error::InvalidArgument { field: f, expected: e, got: g }) if (f, e, g) == (field, expected, got) => {}
+            res => panic!("expected invalid {}, got {:?}", field, res),
+        };
+
+        assert_invalid(get_quote(&report[1..], &[0; 16], &[], &[0; 16]), "report", Report::UNPADDED_SIZE, Report::UNPADDED_SIZE - 1);
+        assert_invalid(get_quote(&report, &[0; 15], &[], &[0; 16]), "spid", 16, 15);
+        assert_invalid(get_quote(&report, &[0; 16], &[], &[0; 20]), "nonce", 16, 20);
+        assert_invalid(get_quote(&report, &[0; 16], &sig_rl[..8], &[0; 16]), "sig_rl", 80, 8);
+        assert_invalid(get_quote(&report, &[0; 16], &sig_rl[..sig_rl.len() - 1], &[0; 16]), "sig_rl", sig_rl.len(), sig_rl.len() - 1);
+
+        // Valid arguments get as far as connecting
+        match get_quote(&report, &[0; 16], &sig_rl, &[0; 16]) {
+            Err(Error::AesmCommunication(_)) => {}
+            res => panic!("expected communication error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn transient_retries() {
+        // Responds to init_quote with the given error codes, then succeeds.
+        fn start_mock(codes: Vec<u32>) -> (MockAesm, Arc<AtomicUsize>) {
+            let requests = Arc::new(AtomicUsize::new(0));
+            let requests2 = requests.clone();
+            let mock = MockAesm::start(move |_| {
+                let n = requests2.fetch_add(1, Ordering::SeqCst);
+                let mut body = Response_InitQuoteResponse::new();
+                body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+                MockAesm::response(codes.get(n).cloned().unwrap_or(0), body)
+            });
+            (mock, requests)
+        }
+        fn client(mock: &MockAesm, retries: u32) -> AesmClient {
+            AesmClient::builder()
+                .socket_path(mock.path())
+                .transient_retries(retries)
+                .retry_backoff(Duration::from_millis(1))
+                .build()
+                .unwrap()
+        }
+
+        let (mock, requests) = start_mock(vec![18, 19]);
+        client(&mock, 2).init_quote().unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        let (mock, requests) = start_mock(vec![18, 18]);
+        let err = client(&mock, 1).init_quote().unwrap_err();
+        assert!(err.is_transient());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Not transient
+        let (mock, requests) = start_mock(vec![3]);
+        let err = client(&mock, 2).init_quote().unwrap_err();
+        assert!(!err.is_transient());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn retry_backoff_capped() {
+        use super::aesm_protobuf::{next_retry_backoff, MAX_RETRY_BACKOFF};
+
+        assert_eq!(next_retry_backoff(Duration::from_millis(100)), Duration::from_millis(200));
+        assert_eq!(next_retry_backoff(Duration::from_secs(6)), MAX_RETRY_BACKOFF);
+        // A backoff too large to double doesn't overflow.
+        assert_eq!(next_retry_backoff(Duration::new(u64::MAX, 0)), MAX_RETRY_BACKOFF);
+    }
+
+    #[test]
+    fn get_supported_att_key_id_num() {
+        let mock = MockAesm::start(|req| {
+            assert!(req.has_getSupportedAttKeyIDNumReq());
+            let mut body = Response_GetSupportedAttKeyIDNumResponse::new();
+            body.set_att_key_id_num(2);
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        assert_eq!(client.get_supported_att_key_id_num().unwrap(), 2);
+    }
+
+    #[test]
+    fn get_extended_epid_group_id() {
+        let mock = MockAesm::start(|req| {
+            assert!(req.has_sgxGetExtendedEpidGroupIdReq());
+            let mut body = Response_SGXGetExtendedEpidGroupIdResponse::new();
+            if req.get_sgxGetExtendedEpidGroupIdReq().get_timeout() != 0 {
+                body.set_x_group_id(3);
+            }
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        assert_eq!(client.get_extended_epid_group_id().unwrap(), 3);
+    }
+
+    #[test]
+    fn get_extended_epid_group_id_missing() {
+        let mock = MockAesm::start(|_| {
+            MockAesm::error_code::<Response_SGXGetExtendedEpidGroupIdResponse>(0)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.get_extended_epid_group_id() {
+            Err(Error::MissingExtendedEpidGroupId) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    // Serves the requests for the supported attestation key IDs, with one key
+    // for each of `algorithms`.
+    fn start_key_ids_mock(algorithms: Vec<AttestationAlgorithm>) -> MockAesm {
+        MockAesm::start(move |req| {
+            if req.has_getSupportedAttKeyIDNumReq() {
+                let mut body = Response_GetSupportedAttKeyIDNumResponse::new();
+                body.set_att_key_id_num(algorithms.len() as u32);
+                MockAesm::response(0, body)
+            } else {
+                assert_eq!(req.get_getSupportedAttKeyIDsReq().get_buf_size(), (algorithms.len() * AesmKeyId::SIZE) as u32);
+                let mut body = Response_GetSupportedAttKeyIDsResponse::new();
+                body.set_att_key_ids(algorithms.iter().flat_map(|&algorithm| {
+                    let mut key = vec![0; AesmKeyId::SIZE];
+                    key[154..158].copy_from_slice(&u32::from(algorithm).to_le_bytes());
+                    key
+                }).collect());
+                MockAesm::response(0, body)
+            }
+        })
+    }
+
+    fn start_select_att_key_id_mock(selected: Vec<u8>) -> MockAesm {
+        MockAesm::start(move |req| {
+            let req = req.get_selectAttKeyIDReq();
+            assert_eq!(req.get_att_key_id_list().len(), if req.has_att_key_id_list() { 2 * AesmKeyId::SIZE } else { 0 });
+            let mut body = Response_SelectAttKeyIDResponse::new();
+            body.set_selected_att_key_id(selected.clone());
+            MockAesm::response(0, body)
+        })
+    }
+
+    #[test]
+    fn select_att_key_id() {
+        let mut selected = vec![0; AesmKeyId::SIZE];
+        selected[154] = 2; // ECDSA-P256
+        let mock = start_select_att_key_id_mock(selected.clone());
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let key_id = client.select_att_key_id(&[0; 2 * AesmKeyId::SIZE]).unwrap();
+        assert_eq!(key_id.key(), &selected[..]);
+        assert_eq!(key_id.algorithm(), AttestationAlgorithm::EcdsaP256);
+        // The platform default.
+        assert_eq!(client.select_att_key_id(&[]).unwrap().key(), &selected[..]);
+    }
+
+    #[test]
+    fn select_att_key_id_malformed() {
+        let mock = start_select_att_key_id_mock(vec![]);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.select_att_key_id(&[]) {
+            Err(Error::AesmBadResponse(_)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let mock = start_select_att_key_id_mock(vec![1; 100]);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.select_att_key_id(&[]) {
+            Err(Error::MalformedResponse { field: "selected_att_key_id", raw, .. }) => assert_eq!(raw, vec![1; 100]),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn get_att_key_id() {
+        let mock = start_key_ids_mock(vec![AttestationAlgorithm::Epid, AttestationAlgorithm::EcdsaP256]);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+
+        let key_id = client.get_att_key_id(AttestationAlgorithm::EcdsaP256).unwrap().unwrap();
+        assert_eq!(key_id.algorithm(), AttestationAlgorithm::EcdsaP256);
+        assert_eq!(client.get_att_key_id(AttestationAlgorithm::Epid).unwrap().unwrap().algorithm(), AttestationAlgorithm::Epid);
+        assert_eq!(client.get_att_key_id(AttestationAlgorithm::EcdsaP384).unwrap(), None);
+    }
+
+    #[test]
+    fn get_quote_size_ex() {
+        let mock = MockAesm::start(|req| {
+            let key_id = req.get_getQuoteSizeExReq().get_att_key_id();
+            if key_id[0] == 0 {
+                let mut body = Response_GetQuoteSizeExResponse::new();
+                body.set_quote_size(4578);
+                MockAesm::response(0, body)
+            } else {
+                MockAesm::error_code::<Response_GetQuoteSizeExResponse>(38)
+            }
+        });
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+
+        assert_eq!(client.get_quote_size_ex(&[0; AesmKeyId::SIZE]).unwrap(), 4578);
+        match client.get_quote_size_ex(&[1; AesmKeyId::SIZE]) {
+            Err(Error::UnsupportedAttKeyId) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match client.get_quote_size_ex(&[0; 10]) {
+            Err(Error::InvalidKeyIdSize) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn attest_dcap_no_ecdsa_key() {
+        let mock = start_key_ids_mock(vec![AttestationAlgorithm::Epid]);
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.attest_dcap(|_| unreachable!(), &[0; 16]) {
+            Err(Error::NoEcdsaAttestationKey) => {}
+            res => panic!("expected no ECDSA key, got {:?}", res),
+        }
+    }
+
+    // Serves the requests for quotes with an attestation key ID. Quotes are
+    // `quote_size` zero bytes.
+    fn start_quote_ex_mock(target_info: Vec<u8>, quote_size: Arc<AtomicUsize>) -> MockAesm {
+        MockAesm::start(move |req| {
+            let quote_size = quote_size.load(Ordering::SeqCst);
+            if req.has_initQuoteExReq() {
+                let req = req.get_initQuoteExReq();
+                assert_eq!(req.get_att_key_id(), [1; AesmKeyId::SIZE]);
+                let mut body = Response_InitQuoteExResponse::new();
+                body.set_target_info(target_info.clone());
+                body.set_pub_key_id_size(4);
+                if req.get_b_pub_key_id() {
+                    body.set_pub_key_id(vec![1, 2, 3, 4]);
+                }
+                MockAesm::response(0, body)
+            } else if req.has_getQuoteSizeExReq() {
+                let mut body = Response_GetQuoteSizeExResponse::new();
+                body.set_quote_size(quote_size as u32);
+                MockAesm::response(0, body)
+            } else {
+                let req = req.get_getQuoteExReq();
+                if (req.get_buf_size() as usize) < quote_size {
+                    MockAesm::error_code::<Response_GetQuoteExResponse>(3)
+                } else {
+                    let mut body = Response_GetQuoteExResponse::new();
+                    body.set_quote(vec![0; quote_size]);
+                    body.set_qe_report_info(req.get_qe_report_info().to_owned());
+                    MockAesm::response(0, body)
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn init_quote_ex() {
+        let mock = start_quote_ex_mock(vec![0; Targetinfo::UNPADDED_SIZE], Arc::new(AtomicUsize::new(1000)));
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let quote_info = client.init_quote_ex(vec![1; AesmKeyId::SIZE]).unwrap();
+        assert_eq!(quote_info.att_key_id(), &[1; AesmKeyId::SIZE][..]);
+        assert_eq!(quote_info.targetinfo().unwrap(), Targetinfo::default());
+        assert_eq!(quote_info.pub_key(), [1, 2, 3, 4]);
+        assert_eq!(quote_info.quote_size(), 1000);
+
+        for (target_info, reason) in malformed_target_infos() {
+            let mock = start_quote_ex_mock(target_info.clone(), Arc::new(AtomicUsize::new(1000)));
+            let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+            assert_malformed_target_info(client.init_quote_ex(vec![1; AesmKeyId::SIZE]), &target_info, reason);
+        }
+    }
+
+    // Serves InitQuoteEx and GetQuoteSizeEx requests. AESM reports
+    // `reported_size` as the size of the public key ID, and returns
+    // `returned_size` bytes when asked for it.
+    fn start_pub_key_id_mock(reported_size: u64, returned_size: usize) -> MockAesm {
+        MockAesm::start(move |req| {
+            if req.has_initQuoteExReq() {
+                let req = req.get_initQuoteExReq();
+                let mut body = Response_InitQuoteExResponse::new();
+                body.set_target_info(vec![0; Targetinfo::UNPADDED_SIZE]);
+                body.set_pub_key_id_size(reported_size);
+                if req.get_b_pub_key_id() {
+                    assert_eq!(req.get_buf_size(), reported_size);
+                    body.set_pub_key_id(vec![7; returned_size]);
+                }
+                MockAesm::response(0, body)
+            } else {
+                let mut body = Response_GetQuoteSizeExResponse::new();
+                body.set_quote_size(1000);
+                MockAesm::response(0, body)
+            }
+        })
+    }
+
+    #[test]
+    fn init_quote_ex_pub_key_id_size() {
+        // Larger than the 64 bytes of an ECDSA-P256 public key.
+        let mock = start_pub_key_id_mock(384, 384);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let quote_info = client.init_quote_ex(vec![1; AesmKeyId::SIZE]).unwrap();
+        assert_eq!(quote_info.pub_key(), &[7; 384][..]);
+
+        for &returned_size in &[383, 385] {
+            let mock = start_pub_key_id_mock(384, returned_size);
+            let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+            match client.init_quote_ex(vec![1; AesmKeyId::SIZE]) {
+                Err(Error::AesmBadResponse(_)) => {}
+                res => panic!("expected bad response, got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn get_quote_ex_for_report() {
+        let qe_target_info = Targetinfo { measurement: [5; 32], ..Default::default() };
+
+        let expected = qe_target_info.clone();
+        let mock = MockAesm::start(move |req| {
+            let req = req.get_getQuoteExReq();
+            // An sgx_ql_qe_report_info_t: the nonce, the target info for the
+            // QE report and room for the QE report.
+            let qe_report_info = req.get_qe_report_info();
+            assert_eq!(&qe_report_info[..16], &[9; 16]);
+            assert_eq!(&qe_report_info[16..][..Targetinfo::UNPADDED_SIZE], expected.as_ref());
+            let mut body = Response_GetQuoteExResponse::new();
+            body.set_quote(vec![0; 1000]);
+            body.set_qe_report_info(qe_report_info.to_owned());
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let quote_info = QuoteInfoEx::new(vec![1; AesmKeyId::SIZE], vec![0; Targetinfo::UNPADDED_SIZE], vec![], 1000).unwrap();
+        let quote = client.get_quote_ex_for_report(&quote_info, &Report::default(), Some(&qe_target_info), &[9; 16]).unwrap();
+        assert_eq!(quote.qe_report().len(), Report::UNPADDED_SIZE);
+    }
+
+    #[test]
+    fn get_quote_ex_size_changed() {
+        let quote_size = Arc::new(AtomicUsize::new(1000));
+        let mock = start_quote_ex_mock(vec![0; Targetinfo::UNPADDED_SIZE], quote_size.clone());
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let quote_info = client.init_quote_ex(vec![1; AesmKeyId::SIZE]).unwrap();
+
+        let report = vec![0; Report::UNPADDED_SIZE];
+        let quote = client.get_quote_ex(&quote_info, report.clone(), None, vec![0; 16]).unwrap();
+        assert_eq!(quote.quote().len(), 1000);
+
+        quote_size.store(2000, Ordering::SeqCst);
+        let quote = client.get_quote_ex_from_slices(&quote_info, &report, None, &[0; 16]).unwrap();
+        assert_eq!(quote.quote().len(), 2000);
+    }
+
+    #[test]
+    fn get_quote_ex_invalid_report() {
+        let client = AesmClient::builder().socket_path("/nonexistent").build().unwrap();
+        let quote_info = QuoteInfoEx::new(vec![1; AesmKeyId::SIZE], vec![0; Targetinfo::UNPADDED_SIZE], vec![], 1000).unwrap();
+        for target_info in &[None, Some(&[0; Targetinfo::UNPADDED_SIZE][..])] {
+            match client.get_quote_ex_from_slices(&quote_info, &[0; 10], *target_info, &[0; 16]) {
+                Err(Error::InvalidArgument { field: "report", expected, got: 10 }) if expected == Report::UNPADDED_SIZE => {}
+                res => panic!("expected invalid report, got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn init_quote_ex_default() {
+        let mock = MockAesm::start(|req| {
+            assert!(!req.get_initQuoteExReq().has_att_key_id());
+            MockAesm::error_code::<Response_InitQuoteExResponse>(38)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.init_quote_ex_default() {
+            Err(Error::NoDefaultAttestationKey) => {}
+            res => panic!("expected no default key, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn init_quote_ex_dcap_unavailable() {
+        let mock = MockAesm::start(|_| {
+            MockAesm::error_code::<Response_InitQuoteExResponse>(41) // PlatformLibUnavailable
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.init_quote_ex_default() {
+            Err(Error::DcapUnavailable(DcapUnavailableReason::QuoteProviderMissing)) => {}
+            res => panic!("expected missing quote provider, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn builder_unsupported() {
+        match AesmClient::builder().tcp_address("localhost:1234").build() {
+            Err(Error::UnsupportedOption("tcp_address")) => {}
+            res => panic!("expected unsupported option, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn logging() {
+        use std::thread::ThreadId;
+
+        use log::{Level, LevelFilter, Log, Metadata, Record};
+
+        lazy_static! {
+            static ref RECORDS: Mutex<Vec<(ThreadId, Level, String)>> = Mutex::new(vec![]);
+        }
+
+        struct CapturingLogger;
+
+        impl Log for CapturingLogger {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &Record) {
+                let record = (thread::current().id(), record.level(), record.args().to_string());
+                RECORDS.lock().unwrap().push(record);
+            }
+
+            fn flush(&self) {}
+        }
+
+        static LOGGER: CapturingLogger = CapturingLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(LevelFilter::Trace);
+
+        let mock = MockAesm::start(|req| {
+            if req.has_initQuoteReq() {
+                init_quote_response(req)
+            } else {
+                let mut body = Response_GetQuoteResponse::new();
+                body.set_quote(vec![0; 436]);
+                MockAesm::response(0, body)
+            }
+        });
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let quote_info = client.init_quote().unwrap();
+        let req = QuoteRequest::new(&Report::default()).spid([0xab; 16]).quote_type(QuoteType::Linkable).nonce([0xcd; 16]);
+        client.get_quote_req(&quote_info, req).unwrap();
+
+        // Ignore concurrently running tests.
+        let records = RECORDS.lock().unwrap().drain(..)
+            .filter(|(thread, _, _)| *thread == thread::current().id())
+            .map(|(_, level, msg)| (level, msg))
+            .collect::<Vec<_>>();
+        assert!(records.iter().any(|(_, msg)| msg.contains("GetQuoteRequest")), "{:?}", records);
+        for (level, msg) in records {
+            assert!(level > Level::Info, "unexpected {} record: {}", level, msg);
+            assert!(!msg.contains("ab, ab") && !msg.contains("171, 171"), "key material logged: {}", msg);
+        }
+    }
+
+    #[test]
+    fn get_launch_token_raw() {
+        use crate::Response_GetLaunchTokenResponse;
+        use sgx_isa::{Attributes, Sigstruct};
+
+        let sigstruct = Sigstruct { modulus: [1; 384], enclavehash: [2; 32], ..Default::default() };
+        let attributes = [3; Attributes::UNPADDED_SIZE];
+
+        let mock = MockAesm::start(|req| {
+            let req = req.get_getLicTokenReq();
+            assert_eq!(req.get_mr_signer(), &[1; 384][..]);
+            assert_eq!(req.get_mr_enclave(), &[2; 32]);
+            assert_eq!(req.get_se_attributes(), &[3; 16]);
+            let mut body = Response_GetLaunchTokenResponse::new();
+            body.set_token(vec![4; 304]);
+            MockAesm::response(0, body)
+        });
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        assert_eq!(client.get_launch_token_raw(sigstruct.as_ref(), &attributes).unwrap(), vec![4; 304]);
+
+        match client.get_launch_token_raw(&sigstruct.as_ref()[1..], &attributes) {
+            Err(Error::InvalidArgument { field: "sigstruct", expected: 1808, got: 1807 }) => {}
+            res => panic!("expected invalid sigstruct, got {:?}", res),
+        }
+        match client.get_launch_token_raw(sigstruct.as_ref(), &[0; 32]) {
+            Err(Error::InvalidArgument { field: "attributes", expected: 16, got: 32 }) => {}
+            res => panic!("expected invalid attributes, got {:?}", res),
         }
     }
+
+    #[cfg(feature = "sgxs")]
+    #[test]
+    fn get_launch_einittoken() {
+        use crate::Response_GetLaunchTokenResponse;
+        use sgx_isa::{Attributes, Einittoken, Sigstruct};
+
+        fn get_launch_einittoken(token_len: usize) -> crate::Result<Einittoken> {
+            let mock = MockAesm::start(move |_| {
+                let mut body = Response_GetLaunchTokenResponse::new();
+                body.set_token(vec![0; token_len]);
+                MockAesm::response(0, body)
+            });
+            let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+            client.get_launch_einittoken(&Sigstruct::default(), Attributes::default())
+        }
+
+        assert_eq!(get_launch_einittoken(Einittoken::UNPADDED_SIZE).unwrap(), Einittoken::default());
+        match get_launch_einittoken(Einittoken::UNPADDED_SIZE - 1) {
+            Err(Error::InvalidTokenSize) => {}
+            res => panic!("expected invalid token size, got {:?}", res),
+        }
+    }
+
+    #[cfg(feature = "sgxs")]
+    #[test]
+    fn einittoken_provider_retry() {
+        use crate::Response_GetLaunchTokenResponse;
+        use sgx_isa::{Attributes, Einittoken, Sigstruct};
+        use sgxs::einittoken::EinittokenProvider;
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests2 = requests.clone();
+        let mock = MockAesm::start(move |req| {
+            assert!(req.has_getLicTokenReq());
+            let n = requests2.fetch_add(1, Ordering::SeqCst);
+            let mut body = Response_GetLaunchTokenResponse::new();
+            body.set_token(vec![n as u8; Einittoken::UNPADDED_SIZE]);
+            MockAesm::response(0, body)
+        });
+
+        let mut client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        assert!(client.can_retry());
+        let first = client.token(&Sigstruct::default(), Attributes::default(), false).unwrap();
+        let second = client.token(&Sigstruct::default(), Attributes::default(), true).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_ne!(first, second);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn raw_request() {
+        use protobuf::Message;
+
+        let mock = MockAesm::start(init_quote_response);
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let mut req = Request::new();
+        req.set_initQuoteReq(Default::default());
+        let res = client.raw_request(&req.write_to_bytes().unwrap(), None).unwrap();
+        let res: Response = Message::parse_from_bytes(&res).unwrap();
+        assert!(res.has_initQuoteRes());
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn raw_request_short_read() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixListener;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut req = [0; 7];
+            stream.read_exact(&mut req).unwrap();
+            // Announce 100 bytes, but only send 3.
+            stream.write_all(&100u32.to_ne_bytes()).unwrap();
+            stream.write_all(&[1, 2, 3]).unwrap();
+        });
+
+        let client = AesmClient::builder().socket_path(&path).build().unwrap();
+        match client.raw_request(&[0; 3], Some(Duration::from_secs(5))) {
+            // The length and the 3 bytes.
+            Err(Error::PartialResponse { received: 7 }) => {}
+            res => panic!("expected partial response error, got {:?}", res),
+        }
+        server.join().unwrap();
+    }
+
+    // Accepts one connection at `path`, reads one request and lets `respond`
+    // write the response.
+    fn serve_raw<F>(path: &std::path::Path, respond: F) -> thread::JoinHandle<()>
+    where
+        F: FnOnce(&mut std::os::unix::net::UnixStream) + Send + 'static,
+    {
+        use std::io::Read;
+        use std::os::unix::net::UnixListener;
+
+        let listener = UnixListener::bind(path).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len = [0; 4];
+            stream.read_exact(&mut len).unwrap();
+            let mut req = vec![0; u32::from_ne_bytes(len) as usize];
+            stream.read_exact(&mut req).unwrap();
+            respond(&mut stream);
+        })
+    }
+
+    // A get quote response with a `size` byte quote, framed as by AESM.
+    fn large_quote_response(size: usize) -> Vec<u8> {
+        use protobuf::Message;
+
+        let mut body = Response_GetQuoteResponse::new();
+        body.set_quote(vec![0xaa; size]);
+        let res = MockAesm::response(0, body).unwrap().write_to_bytes().unwrap();
+        [&(res.len() as u32).to_ne_bytes()[..], &res].concat()
+    }
+
+    fn get_quote(client: &AesmClient) -> crate::Result<crate::QuoteResult> {
+        client.get_quote_for_report(&Report::default(), [0; 16], &SigRl::empty(), QuoteType::Linkable, Some(&[0; 16]))
+    }
+
+    #[test]
+    fn large_response() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let server = serve_raw(&path, |stream| {
+            for b in large_quote_response(128 * 1024) {
+                stream.write_all(&[b]).unwrap();
+            }
+        });
+
+        let client = AesmClient::builder().socket_path(&path).build().unwrap();
+        assert_eq!(get_quote(&client).unwrap().quote(), &[0xaa; 128 * 1024][..]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn response_too_large() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let server = serve_raw(&path, |stream| {
+            // Only the length is sent, the client mustn't wait for the rest.
+            stream.write_all(&u32::MAX.to_ne_bytes()).unwrap();
+        });
+        let client = AesmClient::builder().socket_path(&path).build().unwrap();
+        match get_quote(&client) {
+            Err(Error::ResponseTooLarge { limit: 4194304, announced }) if announced == u32::MAX as usize => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        server.join().unwrap();
+
+        let path = dir.path().join("aesm2.socket");
+        let server = serve_raw(&path, |stream| {
+            let _ = stream.write_all(&large_quote_response(128 * 1024));
+        });
+        let client = AesmClient::builder().socket_path(&path).max_response_size(64 * 1024).build().unwrap();
+        match get_quote(&client) {
+            Err(Error::ResponseTooLarge { limit: 65536, .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn response_size_limit() {
+        use protobuf::Message;
+
+        let mut body = Response_GetQuoteResponse::new();
+        body.set_quote(vec![0xaa; 16 * 1024]);
+        let res = MockAesm::response(0, body).unwrap();
+        let size = res.compute_size() as usize;
+        let mock = MockAesm::start(move |_| Some(res.clone()));
+
+        // A response of exactly the limit is accepted.
+        let client = AesmClient::builder().socket_path(mock.path()).max_response_size(size).build().unwrap();
+        assert_eq!(get_quote(&client).unwrap().quote(), &[0xaa; 16 * 1024][..]);
+
+        let client = AesmClient::builder().socket_path(mock.path()).max_response_size(size - 1).build().unwrap();
+        match get_quote(&client) {
+            Err(Error::ResponseTooLarge { limit, announced }) if (limit, announced) == (size - 1, size) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn metrics_hook() {
+        use crate::{AesmMetric, AesmOutcome, AesmRequestKind};
+
+        let mock = MockAesm::start(|req| {
+            if req.has_initQuoteReq() {
+                let mut body = Response_InitQuoteResponse::new();
+                body.set_targetInfo(vec![0; Targetinfo::UNPADDED_SIZE]);
+                MockAesm::response(0, body)
+            } else {
+                MockAesm::error_code::<Response_GetQuoteResponse>(1)
+            }
+        });
+
+        let metrics = Arc::new(Mutex::new(Vec::<AesmMetric>::new()));
+        let hook: Arc<dyn Fn(AesmMetric) + Send + Sync> = {
+            let metrics = metrics.clone();
+            Arc::new(move |metric| metrics.lock().unwrap().push(metric))
+        };
+        let client = AesmClient::builder().socket_path(mock.path()).metrics_hook(hook.clone()).build().unwrap();
+        client.init_quote().unwrap();
+        assert!(get_quote(&client).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let client = AesmClient::builder().socket_path(dir.path().join("missing.socket")).metrics_hook(hook).build().unwrap();
+        assert!(client.init_quote().is_err());
+
+        let metrics = metrics.lock().unwrap();
+        assert_eq!(metrics.len(), 3);
+
+        assert_eq!(metrics[0].kind, AesmRequestKind::InitQuote);
+        assert_eq!(metrics[0].outcome, AesmOutcome::Success);
+        assert!(metrics[0].request_size > 4);
+        assert!(metrics[0].response_size.unwrap() > 0);
+
+        assert_eq!(metrics[1].kind, AesmRequestKind::GetQuote);
+        assert!(matches!(metrics[1].outcome, AesmOutcome::AesmError(_)));
+        assert!(metrics[1].request_size > Report::UNPADDED_SIZE);
+
+        // The connection failed, so nothing was received.
+        assert_eq!(metrics[2].kind, AesmRequestKind::InitQuote);
+        assert_eq!(metrics[2].outcome, AesmOutcome::TransportError);
+        assert_eq!(metrics[2].response_size, None);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_request() {
+        use super::aesm_protobuf::{encode_request, get_quote_request, zeroize_request};
+        use zeroize::Zeroizing;
+
+        let req = get_quote_request(vec![0; Report::UNPADDED_SIZE], vec![1; 16], vec![], QuoteType::Linkable, Some(vec![2; 16]));
+        let req_bytes: Zeroizing<Vec<u8>> = encode_request(req.clone()).unwrap();
+        assert!(!req_bytes.is_empty());
+
+        let mut req = Request::from(req);
+        zeroize_request(&mut req);
+        assert!(req.get_getQuoteReq().get_spid().is_empty());
+        assert!(req.get_getQuoteReq().get_nonce().is_empty());
+    }
+
+    #[cfg(all(feature = "zeroize", feature = "sgxs"))]
+    #[test]
+    fn get_launch_token_zeroizing() {
+        use crate::Response_GetLaunchTokenResponse;
+        use sgx_isa::{Attributes, Einittoken, Sigstruct};
+        use zeroize::Zeroizing;
+
+        let mock = MockAesm::start(|_| {
+            let mut body = Response_GetLaunchTokenResponse::new();
+            body.set_token(vec![3; Einittoken::UNPADDED_SIZE]);
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let token: Zeroizing<Vec<u8>> = client.get_launch_token_zeroizing(&Sigstruct::default(), Attributes::default()).unwrap();
+        assert_eq!(&token[..], &[3; Einittoken::UNPADDED_SIZE][..]);
+    }
 }
@@ -0,0 +1,213 @@
+use std::io::{Error as IoError, ErrorKind};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use crate::error::{AesmError, Error, Result};
+use crate::metrics::Measurement;
+use crate::unix::AesmClientExt;
+use crate::{AesmRequest, QuoteInfo, QuoteInfoEx, QuoteResult, QuoteType};
+use super::aesm_protobuf::{
+    check_response_size, get_quote_ex_request, get_quote_ex_result,
+    get_quote_request, get_quote_result, get_quote_size_ex_request, get_quote_size_ex_result,
+    init_quote_ex_pub_key_id_size, init_quote_ex_request, init_quote_ex_result, init_quote_request,
+    init_quote_result, log_result, request_kind, secret_bytes, RetryState, SecretBytes,
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct AsyncAesmClient {
+    // The socket address is resolved the same way as for the blocking client.
+    client: super::AesmClient,
+}
+
+impl AsyncAesmClient {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_timeout(&self, timeout: Duration) -> Result<Self> {
+        Ok(AsyncAesmClient { client: self.client.with_timeout(timeout)? })
+    }
+
+    fn open_socket(&self) -> Result<UnixStream> {
+        // Connecting to a local socket completes immediately, so it's fine to
+        // connect with the blocking client. That also takes care of the
+        // abstract socket address, which tokio can't connect to.
+        let sock = self.client.open_socket()?;
+        let sock = unsafe { StdUnixStream::from_raw_fd(sock.into_raw_fd()) };
+        sock.set_nonblocking(true)?;
+        Ok(UnixStream::from_std(sock)?)
+    }
+
+    async fn transact<T: AesmRequest>(&self, req: T) -> Result<T::Response> {
+        let mut measurement = Measurement::start(T::KIND);
+        let res = self.transact_measured(req, &mut measurement).await;
+        measurement.finish(self.client.metrics_hook.as_ref(), &res);
+        res
+    }
+
+    async fn transact_measured<T: AesmRequest>(&self, req: T, measurement: &mut Measurement) -> Result<T::Response> {
+        let kind = request_kind::<T>();
+        let (req_bytes, timeout) = self.client.prepare_request(req, measurement)?;
+        let mut retry = RetryState::new(&self.client);
+        loop {
+            let res = self.exchange(&req_bytes, timeout).await;
+            let res = self.client.decode_exchanged::<T>(res, measurement);
+            match retry.retry_action(kind, &res) {
+                Some(delay) => tokio::time::delay_for(delay).await,
+                None => return log_result(kind, res),
+            }
+        }
+    }
+
+    async fn exchange(&self, req_bytes: &[u8], timeout: Option<Duration>) -> Result<SecretBytes> {
+        let mut sock = self.open_socket()?;
+
+        let exchange = async {
+            sock.write_all(req_bytes).await?;
+
+            let mut res_len = [0u8; 4];
+            sock.read_exact(&mut res_len).await?;
+            let res_len = check_response_size(u32::from_ne_bytes(res_len), self.client.max_response_size)?;
+            let mut res_bytes = secret_bytes(vec![0; res_len]);
+            sock.read_exact(&mut res_bytes[..]).await?;
+
+            Ok::<_, Error>(res_bytes)
+        };
+
+        // Unlike the blocking client, which limits the duration of every read,
+        // the timeout applies to the exchange as a whole.
+        let res_bytes = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange).await
+                .map_err(|_| IoError::new(ErrorKind::TimedOut, "timed out waiting for AESM response"))??,
+            None => exchange.await?,
+        };
+
+        Ok(res_bytes)
+    }
+
+    pub async fn init_quote(&self) -> Result<QuoteInfo> {
+        let res = self.transact(init_quote_request()).await?;
+        init_quote_result(res)
+    }
+
+    pub async fn get_quote(
+        &self,
+        report: Vec<u8>,
+        spid: Vec<u8>,
+        sig_rl: Vec<u8>,
+        quote_type: QuoteType,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<QuoteResult> {
+        let qe_report_requested = nonce.is_some();
+        let res = self.transact(get_quote_request(report, spid, sig_rl, quote_type, nonce)).await?;
+        get_quote_result(res, qe_report_requested)
+    }
+
+    pub async fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfoEx> {
+        let res = self.transact(init_quote_ex_request(att_key_id.clone(), None)).await?;
+        let pub_key_id_size = init_quote_ex_pub_key_id_size(&res)?;
+
+        let res = self.transact(init_quote_ex_request(att_key_id.clone(), Some(pub_key_id_size))).await?;
+
+        let quote_size = self.transact(get_quote_size_ex_request(att_key_id.clone())).await?;
+        let quote_size = get_quote_size_ex_result(quote_size)?;
+
+        init_quote_ex_result(att_key_id, res, pub_key_id_size, quote_size)
+    }
+
+    pub async fn get_quote_ex(
+        &self,
+        quote_info: &QuoteInfoEx,
+        report: &[u8],
+        target_info: &[u8],
+        nonce: &[u8]
+    ) -> Result<QuoteResult> {
+        let att_key_id = quote_info.att_key_id().to_owned();
+        let (req, report_start) = get_quote_ex_request(att_key_id.clone(), report, target_info, nonce, quote_info.quote_size());
+        match self.transact(req).await {
+            Err(err @ Error::AesmCode(AesmError::ParameterError_3)) => {
+                // See the blocking client
+                let res = self.transact(get_quote_size_ex_request(att_key_id.clone())).await?;
+                let buf_size = get_quote_size_ex_result(res)?;
+                if buf_size <= quote_info.quote_size() {
+                    return Err(err);
+                }
+                let (req, report_start) = get_quote_ex_request(att_key_id, report, target_info, nonce, buf_size);
+                let res = self.transact(req).await?;
+                get_quote_ex_result(res, report_start)
+            }
+            res => get_quote_ex_result(res?, report_start),
+        }
+    }
+}
+
+impl AesmClientExt for crate::AsyncAesmClient {
+    fn with_path<P: AsRef<Path>>(path: P) -> Self {
+        crate::AsyncAesmClient {
+            inner: self::AsyncAesmClient {
+                client: crate::AesmClient::with_path(path).inner,
+            },
+        }
+    }
+
+    fn with_abstract_name(name: &[u8]) -> Self {
+        crate::AsyncAesmClient {
+            inner: self::AsyncAesmClient {
+                client: crate::AesmClient::with_abstract_name(name).inner,
+            },
+        }
+    }
+
+    #[cfg(feature = "vsock")]
+    fn with_vsock(cid: u32, port: u32) -> Self {
+        crate::AsyncAesmClient {
+            inner: self::AsyncAesmClient {
+                client: crate::AesmClient::with_vsock(cid, port).inner,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::test_support::MockAesm;
+    use crate::unix::AesmClientExt;
+    use crate::{AesmRequestKind, AsyncAesmClient, Error, Response_InitQuoteResponse};
+    use super::super::aesm_protobuf::{default_request_timeout, transport_timeout};
+
+    #[tokio::test]
+    async fn init_quote() {
+        let mock = MockAesm::start(|req| {
+            assert!(req.has_initQuoteReq());
+            let mut body = Response_InitQuoteResponse::new();
+            body.set_targetInfo(vec![0; 512]);
+            body.set_gid(vec![1, 2, 3, 4]);
+            MockAesm::response(0, body)
+        });
+
+        let quote_info = AsyncAesmClient::with_path(mock.path()).init_quote().await.unwrap();
+        assert_eq!(quote_info.target_info(), &[0; 512][..]);
+        assert_eq!(quote_info.gid(), [4, 3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let mock = MockAesm::start(|_| None);
+        let client = AsyncAesmClient::with_path(mock.path());
+
+        tokio::time::pause();
+        let elapse = tokio::time::advance(transport_timeout(default_request_timeout(AesmRequestKind::InitQuote)) + Duration::from_micros(1));
+        let (res, ()) = tokio::join!(client.init_quote(), elapse);
+        match res {
+            Err(Error::Timeout { request: Some(AesmRequestKind::InitQuote) }) => {}
+            res => panic!("expected timeout, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use std::io::{ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::task::Poll;
+
+use crate::error::{Error, Result};
+use crate::metrics::{Measurement, MetricsHook};
+use crate::unix::AesmClientExt;
+use crate::{AesmRequest, QuoteInfo, QuoteResult, QuoteType};
+use super::aesm_protobuf::{
+    check_response_size, decode_response, encode_request, get_quote_request, get_quote_result,
+    init_quote_request, init_quote_result, log_result, request_kind, secret_bytes,
+    set_request_timeout, wire_dump, SecretBytes,
+};
+use super::aesm_protobuf::wire_dump::Direction;
+
+/// An AESM client for event loops, whose requests never block.
+///
+/// Every operation connects to AESM, and returns a pending request that owns
+/// the connection. Register its file descriptor with your event loop, and
+/// call `poll` whenever the socket becomes ready, until it returns
+/// `Poll::Ready`.
+///
+/// The client doesn't enforce the configured timeout or retry requests, as
+/// the event loop is in control of when requests make progress. The timeout
+/// is still passed to AESM.
+#[derive(Clone, Debug, Default)]
+pub struct NonblockingAesmClient {
+    // The socket address is resolved the same way as for the blocking client.
+    client: super::AesmClient,
+}
+
+impl NonblockingAesmClient {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Start obtaining target info from QE.
+    pub fn start_init_quote(&self) -> Result<PendingInitQuote> {
+        Ok(PendingInitQuote { inner: self.start(init_quote_request())? })
+    }
+
+    /// Start obtaining remote attestation quote from QE.
+    ///
+    /// The arguments are the same as for `AesmClient::get_quote_from_slices`.
+    pub fn start_get_quote(
+        &self,
+        report: &[u8],
+        spid: &[u8],
+        sig_rl: &[u8],
+        quote_type: QuoteType,
+        nonce: &[u8],
+    ) -> Result<PendingQuote> {
+        crate::check_get_quote_args(report, spid, sig_rl, Some(nonce))?;
+        let req = get_quote_request(report.to_vec(), spid.to_vec(), sig_rl.to_vec(), quote_type, Some(nonce.to_vec()));
+        Ok(PendingQuote { inner: self.start(req)? })
+    }
+
+    fn start<T: AesmRequest>(&self, req: T) -> Result<PendingRequest<T>> {
+        let mut measurement = Measurement::start(T::KIND);
+        let res = self.connect(req, &mut measurement);
+        if res.is_err() {
+            measurement.finish(self.client.metrics_hook.as_ref(), &res);
+        }
+        let (sock, req_bytes) = res?;
+
+        log::debug!("sending {} to AESM ({} bytes)", request_kind::<T>(), req_bytes.len());
+        Ok(PendingRequest {
+            sock,
+            state: State::Write { req_bytes, written: 0 },
+            max_response_size: self.client.max_response_size,
+            measurement,
+            metrics_hook: self.client.metrics_hook.clone(),
+            wire_dump: self.client.wire_dump,
+            request: PhantomData,
+        })
+    }
+
+    fn connect<T: AesmRequest>(&self, mut req: T, measurement: &mut Measurement) -> Result<(UnixStream, SecretBytes)> {
+        set_request_timeout(&mut req, self.client.timeout);
+        let req_bytes = encode_request(req)?;
+        measurement.request_size = req_bytes.len();
+        wire_dump::dump(self.client.wire_dump, Direction::Request, request_kind::<T>(), &req_bytes[4..]);
+
+        // Connecting to a local socket completes immediately, so it's fine to
+        // connect with the blocking client.
+        let sock = self.client.open_socket()?;
+        let sock = unsafe { UnixStream::from_raw_fd(sock.into_raw_fd()) };
+        sock.set_nonblocking(true)?;
+        Ok((sock, req_bytes))
+    }
+}
+
+impl From<crate::AesmClient> for NonblockingAesmClient {
+    /// A non-blocking client with the same configuration as `client`.
+    fn from(client: crate::AesmClient) -> Self {
+        NonblockingAesmClient { client: client.inner }
+    }
+}
+
+impl AesmClientExt for NonblockingAesmClient {
+    fn with_path<P: AsRef<Path>>(path: P) -> Self {
+        NonblockingAesmClient { client: crate::AesmClient::with_path(path).inner }
+    }
+
+    fn with_abstract_name(name: &[u8]) -> Self {
+        NonblockingAesmClient { client: crate::AesmClient::with_abstract_name(name).inner }
+    }
+
+    #[cfg(feature = "vsock")]
+    fn with_vsock(cid: u32, port: u32) -> Self {
+        NonblockingAesmClient { client: crate::AesmClient::with_vsock(cid, port).inner }
+    }
+}
+
+/// Where an exchange with AESM is at. Either side of the exchange may take
+/// any number of reads or writes.
+enum State {
+    Write { req_bytes: SecretBytes, written: usize },
+    ReadLength { len: [u8; 4], read: usize },
+    ReadBody { res_bytes: SecretBytes, read: usize },
+    Done,
+}
+
+struct PendingRequest<T> {
+    sock: UnixStream,
+    state: State,
+    max_response_size: usize,
+    // Not reported if the request is dropped before it completes.
+    measurement: Measurement,
+    metrics_hook: Option<MetricsHook>,
+    wire_dump: bool,
+    request: PhantomData<fn() -> T>,
+}
+
+impl<T: AesmRequest> PendingRequest<T> {
+    fn poll(&mut self) -> Poll<Result<T::Response>> {
+        let res = match self.advance() {
+            Ok(Some(res_bytes)) => {
+                log::debug!("received response to {} from AESM ({} bytes)", request_kind::<T>(), res_bytes.len());
+                self.measurement.response_size = Some(res_bytes.len());
+                wire_dump::dump(self.wire_dump, Direction::Response, request_kind::<T>(), &res_bytes);
+                decode_response::<T>(&res_bytes)
+            }
+            Ok(None) => return Poll::Pending,
+            Err(err) => Err(err),
+        };
+        self.state = State::Done;
+        self.measurement.finish(self.metrics_hook.as_ref(), &res);
+        Poll::Ready(log_result(request_kind::<T>(), res))
+    }
+
+    /// Read and write as much as possible without blocking. Returns the
+    /// response body once it has been received in full.
+    fn advance(&mut self) -> Result<Option<SecretBytes>> {
+        loop {
+            let next = match self.state {
+                State::Write { ref req_bytes, ref mut written } => {
+                    match self.sock.write(&req_bytes[*written..]) {
+                        Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero).into()),
+                        Ok(n) => *written += n,
+                        Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+                        Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+                        Err(err) => return Err(err.into()),
+                    }
+                    if *written < req_bytes.len() {
+                        continue;
+                    }
+                    State::ReadLength { len: [0; 4], read: 0 }
+                }
+                State::ReadLength { ref mut len, ref mut read } => {
+                    match read_some(&mut self.sock, &mut len[*read..])? {
+                        Some(n) => *read += n,
+                        None => return Ok(None),
+                    }
+                    if *read < len.len() {
+                        continue;
+                    }
+                    let res_len = check_response_size(u32::from_ne_bytes(*len), self.max_response_size)?;
+                    State::ReadBody { res_bytes: secret_bytes(vec![0; res_len]), read: 0 }
+                }
+                State::ReadBody { ref mut res_bytes, ref mut read } => {
+                    if *read < res_bytes.len() {
+                        match read_some(&mut self.sock, &mut res_bytes[*read..])? {
+                            Some(n) => *read += n,
+                            None => return Ok(None),
+                        }
+                        continue;
+                    }
+                    match std::mem::replace(&mut self.state, State::Done) {
+                        State::ReadBody { res_bytes, .. } => return Ok(Some(res_bytes)),
+                        _ => unreachable!(),
+                    }
+                }
+                State::Done => panic!("pending AESM request polled after completion"),
+            };
+            self.state = next;
+        }
+    }
+
+    fn wants_write(&self) -> bool {
+        matches!(self.state, State::Write { .. })
+    }
+}
+
+/// Returns `None` if the read would block.
+fn read_some(sock: &mut UnixStream, buf: &mut [u8]) -> Result<Option<usize>> {
+    loop {
+        match sock.read(buf) {
+            Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+            Ok(n) => return Ok(Some(n)),
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(err) => return Err(Error::AesmCommunication(err)),
+        }
+    }
+}
+
+macro_rules! define_pending {
+    ($(#[$attr:meta])* $name:ident($request:ty) -> $output:ty, $result:expr) => {
+        $(#[$attr])*
+        pub struct $name {
+            inner: PendingRequest<$request>,
+        }
+
+        impl $name {
+            /// Make as much progress as possible without blocking.
+            ///
+            /// Returns `Poll::Pending` if the socket isn't ready, in which
+            /// case call `poll` again once it is. Panics if called again
+            /// after returning `Poll::Ready`.
+            pub fn poll(&mut self) -> Poll<Result<$output>> {
+                self.inner.poll().map(|res| res.and_then($result))
+            }
+
+            /// Whether the request is still being sent. If so, wait for the
+            /// socket to become writable, otherwise for it to become
+            /// readable.
+            pub fn wants_write(&self) -> bool {
+                self.inner.wants_write()
+            }
+        }
+
+        impl AsRawFd for $name {
+            fn as_raw_fd(&self) -> RawFd {
+                self.inner.sock.as_raw_fd()
+            }
+        }
+
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("fd", &self.as_raw_fd())
+                    .field("wants_write", &self.wants_write())
+                    .finish()
+            }
+        }
+    };
+}
+
+define_pending!(
+    /// A request for target info from QE, see
+    /// `NonblockingAesmClient::start_init_quote`.
+    PendingInitQuote(crate::Request_InitQuoteRequest) -> QuoteInfo, init_quote_result
+);
+
+define_pending!(
+    /// A request for a quote from QE, see
+    /// `NonblockingAesmClient::start_get_quote`.
+    PendingQuote(crate::Request_GetQuoteRequest) -> QuoteResult, |res| get_quote_result(res, true)
+);
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::task::Poll;
+    use std::thread;
+    use std::time::Duration;
+
+    use sgx_isa::Report;
+
+    use super::NonblockingAesmClient;
+    use crate::test_support::MockAesm;
+    use crate::unix::AesmClientExt;
+    use crate::{AesmClient, Error, QuoteType, Response_GetQuoteResponse};
+
+    fn start_get_quote(client: &NonblockingAesmClient) -> crate::Result<super::PendingQuote> {
+        client.start_get_quote(&[0; Report::UNPADDED_SIZE], &[0; 16], &[], QuoteType::Linkable, &[7; 16])
+    }
+
+    fn wait<T, F: FnMut() -> Poll<crate::Result<T>>>(mut poll: F) -> crate::Result<T> {
+        loop {
+            if let Poll::Ready(res) = poll() {
+                return res;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn get_quote() {
+        let mock = MockAesm::start(|req| {
+            let mut body = Response_GetQuoteResponse::new();
+            body.set_quote(req.get_getQuoteReq().get_nonce().to_vec());
+            MockAesm::response(0, body)
+        });
+
+        let client = NonblockingAesmClient::with_path(mock.path());
+        let mut pending = start_get_quote(&client).unwrap();
+        let quote = wait(|| pending.poll()).unwrap();
+        assert_eq!(quote.quote(), &[7; 16]);
+    }
+
+    #[test]
+    fn partial_frames() {
+        use protobuf::Message;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Read the request one byte at a time, so the client sees it
+            // accepted in pieces if the socket buffer fills up.
+            let mut len = [0; 4];
+            stream.read_exact(&mut len).unwrap();
+            let mut req = vec![0; u32::from_ne_bytes(len) as usize];
+            for byte in req.iter_mut() {
+                stream.read_exact(std::slice::from_mut(byte)).unwrap();
+            }
+
+            let mut body = Response_GetQuoteResponse::new();
+            body.set_quote(vec![0xaa; 1000]);
+            let res = MockAesm::response(0, body).unwrap().write_to_bytes().unwrap();
+            for byte in [&(res.len() as u32).to_ne_bytes()[..], &res].concat() {
+                stream.write_all(&[byte]).unwrap();
+                stream.flush().unwrap();
+            }
+        });
+
+        let client = NonblockingAesmClient::with_path(&path);
+        // A large revocation list, so the request doesn't fit in the socket
+        // buffer.
+        let mut sig_rl = vec![0; crate::sig_rl::size(4096)];
+        sig_rl[12..16].copy_from_slice(&4096u32.to_be_bytes());
+        let mut pending = client.start_get_quote(&[0; Report::UNPADDED_SIZE], &[0; 16], &sig_rl, QuoteType::Linkable, &[0; 16]).unwrap();
+        let mut pending_polls = 0;
+        let quote = wait(|| {
+            let res = pending.poll();
+            if res.is_pending() {
+                pending_polls += 1;
+            }
+            res
+        }).unwrap();
+        assert_eq!(quote.quote(), &[0xaa; 1000][..]);
+        assert!(pending_polls > 0);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn error_code() {
+        let mock = MockAesm::start(|_| {
+            MockAesm::error_code::<Response_GetQuoteResponse>(1)
+        });
+
+        let client = NonblockingAesmClient::from(AesmClient::builder().socket_path(mock.path()).build().unwrap());
+        let mut pending = start_get_quote(&client).unwrap();
+        match wait(|| pending.poll()) {
+            Err(Error::AesmCode(_)) => {}
+            res => panic!("expected AESM error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn response_too_large() {
+        let mock = MockAesm::start(|_| {
+            let mut body = Response_GetQuoteResponse::new();
+            body.set_quote(vec![0; 2048]);
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).max_response_size(1024).build().unwrap();
+        let client = NonblockingAesmClient::from(client);
+        let mut pending = start_get_quote(&client).unwrap();
+        match wait(|| pending.poll()) {
+            Err(Error::ResponseTooLarge { limit: 1024, .. }) => {}
+            res => panic!("expected response too large, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let client = NonblockingAesmClient::with_path("/nonexistent");
+        match client.start_get_quote(&[0; 10], &[0; 16], &[], QuoteType::Linkable, &[0; 16]) {
+            Err(Error::InvalidArgument { field: "report", .. }) => {}
+            res => panic!("expected invalid report, got {:?}", res),
+        }
+    }
+}
@@ -1,12 +1,25 @@
+use std::ffi::OsStr;
+use std::io::{Error as IoError, ErrorKind};
+use std::mem;
+use std::os::windows::ffi::OsStrExt;
 use std::sync::Arc;
+use std::time::Duration;
 use std::fmt;
 
 use winapi::_core::ffi::c_void;
+use winapi::shared::winerror::ERROR_SERVICE_DOES_NOT_EXIST;
+use winapi::um::winsvc::{
+    CloseServiceHandle, OpenSCManagerW, OpenServiceW, QueryServiceStatusEx, SC_HANDLE, SC_MANAGER_CONNECT,
+    SC_STATUS_PROCESS_INFO, SERVICE_QUERY_STATUS, SERVICE_RUNNING, SERVICE_STATUS_PROCESS,
+};
 
 use sgx_isa::{Attributes, Sigstruct, Report, Targetinfo};
 
-use error::{Error, Result};
-use {quote_buffer_size, QuoteInfo, QuoteResult, QuoteType};
+use crate::error::{AesmError, Error, Result, SgxCheck};
+use crate::{
+    check_target_info, quote_buffer_size, AesmClientBuilder, AesmKeyId, AesmKeyIds, QuoteInfo, QuoteInfoEx, QuoteResult,
+    QuoteType,
+};
 
 extern crate libloading as dl;
 
@@ -15,8 +28,16 @@ pub struct AesmClient {
     library: Arc<Library>,
 }
 const LIBRARY: &str = "sgx_uae_service.dll";
+const AESM_SERVICE: &str = "AESMService";
+
+const SYM_SGX_GET_EXTENDED_EPID_GROUP_ID: &[u8] = b"sgx_get_extended_epid_group_id\0";
+type SgxGetExtendedEpidGroupIdFn = unsafe extern "system" fn(
+    p_extended_epid_group_id: *mut u32,
+) -> u32;
 
 const SYM_GET_LAUNCH_TOKEN: &[u8] = b"get_launch_token\0";
+// The size of `sgx_launch_token_t`.
+const LAUNCH_TOKEN_BUFFER_SIZE: usize = 1024;
 type GetLaunchTokenFn = unsafe extern "system" fn(
     signature: *const Sigstruct,
     attribute: *const Attributes,
@@ -42,11 +63,53 @@ type SgxInitQuoteFn = unsafe extern "system" fn(
     p_gid: *mut u8,
 ) -> u32;
 
+// The functions for quotes with an attestation key ID are missing from
+// older versions of the PSW.
+const SYM_SGX_GET_SUPPORTED_ATT_KEY_ID_NUM: &[u8] = b"sgx_get_supported_att_key_id_num\0";
+type SgxGetSupportedAttKeyIdNumFn = unsafe extern "system" fn(
+    p_att_key_id_num: *mut u32,
+) -> u32;
+
+const SYM_SGX_GET_SUPPORTED_ATT_KEY_IDS: &[u8] = b"sgx_get_supported_att_key_ids\0";
+type SgxGetSupportedAttKeyIdsFn = unsafe extern "system" fn(
+    p_att_key_id_list: *mut u8,
+    att_key_id_num: u32,
+) -> u32;
+
+const SYM_SGX_INIT_QUOTE_EX: &[u8] = b"sgx_init_quote_ex\0";
+type SgxInitQuoteExFn = unsafe extern "system" fn(
+    p_att_key_id: *const u8,
+    p_qe_target_info: *mut Targetinfo,
+    p_pub_key_id_size: *mut usize,
+    p_pub_key_id: *mut u8,
+) -> u32;
+
+const SYM_SGX_GET_QUOTE_SIZE_EX: &[u8] = b"sgx_get_quote_size_ex\0";
+type SgxGetQuoteSizeExFn = unsafe extern "system" fn(
+    p_att_key_id: *const u8,
+    p_quote_size: *mut u32,
+) -> u32;
+
+const SYM_SGX_GET_QUOTE_EX: &[u8] = b"sgx_get_quote_ex\0";
+type SgxGetQuoteExFn = unsafe extern "system" fn(
+    p_app_report: *const Report,
+    p_att_key_id: *const u8,
+    p_qe_report_info: *mut u8,
+    p_quote: *mut u8,
+    quote_size: u32,
+) -> u32;
+
 struct Library {
     library: dl::Library,
     init_quote: SgxInitQuoteFn,
     get_quote: SgxGetQuoteFn,
     get_launch_token: GetLaunchTokenFn,
+    get_extended_epid_group_id: Option<SgxGetExtendedEpidGroupIdFn>,
+    get_supported_att_key_id_num: Option<SgxGetSupportedAttKeyIdNumFn>,
+    get_supported_att_key_ids: Option<SgxGetSupportedAttKeyIdsFn>,
+    init_quote_ex: Option<SgxInitQuoteExFn>,
+    get_quote_size_ex: Option<SgxGetQuoteSizeExFn>,
+    get_quote_ex: Option<SgxGetQuoteExFn>,
 }
 
 impl fmt::Debug for Library {
@@ -56,6 +119,12 @@ impl fmt::Debug for Library {
             .field("init_quote", &(self.init_quote as *const c_void))
             .field("get_quote",&(self.get_quote as *const c_void))
             .field("get_launch_token",&(self.get_launch_token as *const c_void))
+            .field("get_extended_epid_group_id", &self.get_extended_epid_group_id.map(|f| f as *const c_void))
+            .field("get_supported_att_key_id_num", &self.get_supported_att_key_id_num.map(|f| f as *const c_void))
+            .field("get_supported_att_key_ids", &self.get_supported_att_key_ids.map(|f| f as *const c_void))
+            .field("init_quote_ex", &self.init_quote_ex.map(|f| f as *const c_void))
+            .field("get_quote_size_ex", &self.get_quote_size_ex.map(|f| f as *const c_void))
+            .field("get_quote_ex", &self.get_quote_ex.map(|f| f as *const c_void))
             .finish()
     }
 }
@@ -71,26 +140,206 @@ impl std::default::Default for Library {
                 .expect("failed to get dll api function sgx_get_quote");
             let get_launch_token = *library.get::<GetLaunchTokenFn>(SYM_GET_LAUNCH_TOKEN)
                 .expect("failed to get dll api function get_launch_token");
+            let get_extended_epid_group_id = library.get::<SgxGetExtendedEpidGroupIdFn>(SYM_SGX_GET_EXTENDED_EPID_GROUP_ID)
+                .ok().map(|f| *f);
+            let get_supported_att_key_id_num = library.get::<SgxGetSupportedAttKeyIdNumFn>(SYM_SGX_GET_SUPPORTED_ATT_KEY_ID_NUM)
+                .ok().map(|f| *f);
+            let get_supported_att_key_ids = library.get::<SgxGetSupportedAttKeyIdsFn>(SYM_SGX_GET_SUPPORTED_ATT_KEY_IDS)
+                .ok().map(|f| *f);
+            let init_quote_ex = library.get::<SgxInitQuoteExFn>(SYM_SGX_INIT_QUOTE_EX)
+                .ok().map(|f| *f);
+            let get_quote_size_ex = library.get::<SgxGetQuoteSizeExFn>(SYM_SGX_GET_QUOTE_SIZE_EX)
+                .ok().map(|f| *f);
+            let get_quote_ex = library.get::<SgxGetQuoteExFn>(SYM_SGX_GET_QUOTE_EX)
+                .ok().map(|f| *f);
 
             Library {
                 library,
                 init_quote,
                 get_quote,
                 get_launch_token,
+                get_extended_epid_group_id,
+                get_supported_att_key_id_num,
+                get_supported_att_key_ids,
+                init_quote_ex,
+                get_quote_size_ex,
+                get_quote_ex,
             }
         }
     }
 }
 
+/// Map an `sgx_status_t` returned by the PSW to the error AESM returns in
+/// the same situation on other platforms.
+fn status_error(status: u32) -> Error {
+    use self::AesmError::*;
+    let code = match status {
+        0x0001 => UnexpectedError_1,
+        0x0002 => ParameterError_3,
+        0x0003 => OutOfMemoryError_21,
+        0x0008 => InterfaceUnavailable_40,
+        0x2005 => OutOfEpc_29,
+        0x2006 => NoDeviceError_2,
+        0x4001 => ServiceUnavailable_30,
+        0x4002 => return Error::Timeout { request: None },
+        0x4003 => EpidblobError_4,
+        0x4005 => EpidRevokedError_5,
+        0x4006 => UpdateAvailable_20,
+        0x4007 => NetworkError_12,
+        0x4008 => SessionInvalid_7,
+        0x400a => Busy_18,
+        0x4011 => KdfMismatch_28,
+        0x4012 => UnrecognizedPlatform_31,
+        0x8001 => UnsupportedAttKeyId_38,
+        0x8002 => KeyCertificationError_50,
+        0x8003 => AttKeyNotInitialized_42,
+        0x8004 => AttKeyCertDataInvalid_43,
+        0x8005 => NoPlatformCertData_44,
+        status => Unknown(status),
+    };
+    Error::AesmCode(code)
+}
+
+/// The error for a function that this version of the PSW doesn't export.
+fn unavailable() -> Error {
+    Error::AesmCode(AesmError::InterfaceUnavailable_40)
+}
+
+/// A handle of the service control manager, closed when it's dropped.
+struct ServiceHandle(SC_HANDLE);
+
+impl ServiceHandle {
+    fn new(handle: SC_HANDLE) -> std::io::Result<Self> {
+        if handle.is_null() {
+            return Err(IoError::last_os_error());
+        }
+        Ok(ServiceHandle(handle))
+    }
+}
+
+impl Drop for ServiceHandle {
+    fn drop(&mut self) {
+        unsafe { CloseServiceHandle(self.0) };
+    }
+}
+
+/// Check that the AESM service is installed and running.
+fn check_service() -> Result<()> {
+    let name: Vec<u16> = OsStr::new(AESM_SERVICE).encode_wide().chain(Some(0)).collect();
+    let manager = ServiceHandle::new(unsafe { OpenSCManagerW(std::ptr::null(), std::ptr::null(), SC_MANAGER_CONNECT) })
+        .map_err(Error::AesmCommunication)?;
+    let service = match ServiceHandle::new(unsafe { OpenServiceW(manager.0, name.as_ptr(), SERVICE_QUERY_STATUS) }) {
+        Ok(service) => service,
+        Err(ref err) if err.raw_os_error() == Some(ERROR_SERVICE_DOES_NOT_EXIST as i32) => {
+            return Err(Error::SgxNotAvailable(SgxCheck::AesmService));
+        }
+        Err(err) => return Err(Error::AesmCommunication(err)),
+    };
+
+    let mut status: SERVICE_STATUS_PROCESS = unsafe { mem::zeroed() };
+    let mut needed = 0;
+    let ok = unsafe {
+        QueryServiceStatusEx(
+            service.0,
+            SC_STATUS_PROCESS_INFO,
+            &mut status as *mut _ as *mut u8,
+            mem::size_of::<SERVICE_STATUS_PROCESS>() as u32,
+            &mut needed,
+        )
+    };
+    if ok == 0 {
+        return Err(Error::AesmCommunication(IoError::last_os_error()));
+    }
+    if status.dwCurrentState != SERVICE_RUNNING {
+        return Err(Error::AesmCommunication(IoError::new(
+            ErrorKind::ConnectionRefused,
+            format!("the AESM service is installed, but not running (state {})", status.dwCurrentState),
+        )));
+    }
+    Ok(())
+}
+
+/// The PSW takes a null pointer for the default attestation key, AESM an
+/// empty key ID.
+fn att_key_id_ptr(att_key_id: &[u8]) -> Result<*const u8> {
+    match att_key_id.len() {
+        0 => Ok(std::ptr::null()),
+        AesmKeyId::SIZE => Ok(att_key_id.as_ptr()),
+        // AESM rejects key IDs of the wrong size with the same error.
+        _ => Err(Error::AesmCode(AesmError::ParameterError_3)),
+    }
+}
+
 impl AesmClient {
     pub fn new() -> Self {
         Default::default()
     }
 
+    pub fn from_builder(builder: AesmClientBuilder) -> Result<Self> {
+        let AesmClientBuilder {
+            socket_path, tcp_address, default_timeout, retries, transient_retries, retry_backoff, max_response_size, metrics_hook, wire_dump,
+            sgx_device_paths,
+        } = builder;
+        if socket_path.is_some() {
+            return Err(Error::UnsupportedOption("socket_path"));
+        } else if tcp_address.is_some() {
+            return Err(Error::UnsupportedOption("tcp_address"));
+        } else if let Some(timeout) = default_timeout {
+            return Err(Error::UnsupportedOption(if timeout.is_some() { "default_timeout" } else { "no_timeout" }));
+        } else if retries.is_some() {
+            return Err(Error::UnsupportedOption("retries"));
+        } else if transient_retries.is_some() {
+            return Err(Error::UnsupportedOption("transient_retries"));
+        } else if retry_backoff.is_some() {
+            return Err(Error::UnsupportedOption("retry_backoff"));
+        } else if max_response_size.is_some() {
+            return Err(Error::UnsupportedOption("max_response_size"));
+        } else if metrics_hook.is_some() {
+            return Err(Error::UnsupportedOption("metrics_hook"));
+        } else if wire_dump.is_some() {
+            return Err(Error::UnsupportedOption("wire_dump"));
+        } else if sgx_device_paths.is_some() {
+            return Err(Error::UnsupportedOption("sgx_device_paths"));
+        }
+        Ok(AesmClient::new())
+    }
+
+    pub fn with_timeout(&self, _timeout: Duration) -> Result<Self> {
+        Err(Error::UnsupportedOption("timeout"))
+    }
+
+    /// Returns `Error::SgxNotAvailable` if the AESM service isn't installed,
+    /// and `Error::AesmCommunication` if it's installed, but not running or
+    /// not responding.
     pub fn try_connect(&self) -> Result<()> {
-        // TODO: use QueryServiceStatusEx
-        libloading::Library::new("sgx_uae_service.dll")?;
-        Ok(())
+        check_service()?;
+        // Like `wait_for_service` on other platforms, ask for the extended
+        // EPID group ID: any response from AESM, even an error code, shows
+        // that it's up.
+        let get_extended_epid_group_id = match self.library.get_extended_epid_group_id {
+            Some(get_extended_epid_group_id) => get_extended_epid_group_id,
+            None => return Ok(()),
+        };
+        let mut group_id = 0;
+        match unsafe { get_extended_epid_group_id(&mut group_id) } {
+            // SGX_ERROR_SERVICE_UNAVAILABLE
+            0x4001 => Err(Error::AesmCommunication(IoError::new(
+                ErrorKind::ConnectionRefused,
+                "the AESM service is running, but not responding",
+            ))),
+            // SGX_ERROR_SERVICE_TIMEOUT
+            0x4002 => Err(Error::Timeout { request: None }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The PSW has no way to bound the time the check takes, see
+    /// `try_connect`.
+    pub fn try_connect_timeout(&self, timeout: Duration) -> Result<()> {
+        if timeout == Duration::from_secs(0) {
+            return Err(Error::Timeout { request: None });
+        }
+        self.try_connect()
     }
 
     pub fn init_quote(&self) -> Result<QuoteInfo> {
@@ -102,10 +351,10 @@ impl AesmClient {
                 gid.as_mut_ptr() as _,
             );
             if error != 0 {
-                return Err(Error::AesmCode(error.into()));
+                return Err(status_error(error));
             }
         }
-        let quote_info: QuoteInfo = QuoteInfo { target_info, pub_key_id: gid };
+        let quote_info: QuoteInfo = QuoteInfo { target_info: check_target_info(target_info)?, pub_key_id: gid };
 
         return Ok(quote_info);
     }
@@ -116,10 +365,13 @@ impl AesmClient {
         spid: Vec<u8>,
         sig_rl: Vec<u8>,
         quote_type: QuoteType,
-        nonce: Vec<u8>,
+        nonce: Option<Vec<u8>>,
     ) -> Result<QuoteResult> {
+        #[cfg(feature = "zeroize")]
+        let (spid, nonce) = (zeroize::Zeroizing::new(spid), nonce.map(zeroize::Zeroizing::new));
         let quote_buffer_size = quote_buffer_size(&sig_rl);
-        let mut qe_report: Vec<u8> = vec![0; Report::UNPADDED_SIZE];
+        // Without a nonce, no QE report is requested.
+        let mut qe_report: Option<Vec<u8>> = nonce.as_ref().map(|_| vec![0; Report::UNPADDED_SIZE]);
         let mut quote: Vec<u8> = vec![0; quote_buffer_size as usize];
 
         unsafe {
@@ -127,44 +379,190 @@ impl AesmClient {
                 0 => (std::ptr::null(), 0),
                 s => (sig_rl.as_ptr(), s)
             };
-            assert_eq!(qe_report.len(), Report::UNPADDED_SIZE);
+            let (nonce_in, qe_report_out) = match (&nonce, &mut qe_report) {
+                (Some(nonce), Some(qe_report)) => {
+                    assert_eq!(qe_report.len(), Report::UNPADDED_SIZE);
+                    assert_eq!(nonce.len(), 16);
+                    (nonce.as_ptr(), qe_report.as_mut_ptr())
+                }
+                _ => (std::ptr::null(), std::ptr::null_mut()),
+            };
             assert_eq!(spid.len(), 16);
-            assert_eq!(nonce.len(), 16);
             let error = (&self.library.get_quote)(
                     report.as_ptr() as _,
                     quote_type.into(),
                     spid.as_ptr() as _,
-                    &nonce[0],
+                    nonce_in,
                     sig_rl_in,
                     sig_rl_size_in as _,
-                    qe_report.as_mut_ptr() as _,
+                    qe_report_out as _,
                     quote.as_mut_ptr() as _,
                     quote_buffer_size,
                 );
             if error != 0 {
-                return Err(Error::AesmCode(error.into()));
+                return Err(status_error(error));
             }
         }
-        return Ok(QuoteResult::new(quote, qe_report));
+        return Ok(QuoteResult::from_untrimmed_quote(quote, qe_report));
     }
 
-    #[cfg(feature = "sgxs")]
-    pub fn get_launch_token(
+    /// Obtain launch token. The caller checks the sizes of `sigstruct` and
+    /// `attributes`.
+    pub fn get_launch_token_raw(
         &self,
-        sigstruct: &Sigstruct,
-        attributes: Attributes
+        sigstruct: &[u8],
+        attributes: &[u8]
     ) -> Result<Vec<u8>> {
-        let mut licence_token = vec![0; sgx_isa::Einittoken::UNPADDED_SIZE];
+        // The PSW fills an `sgx_launch_token_t`, which is larger than the
+        // token itself.
+        let mut launch_token = vec![0; LAUNCH_TOKEN_BUFFER_SIZE];
         unsafe {
             let error = (&self.library.get_launch_token)(
-                    sigstruct.as_ref().as_ptr() as _,
-                    attributes.as_ref().as_ptr() as _,
-                    licence_token.as_mut_ptr() as _,
+                    sigstruct.as_ptr() as _,
+                    attributes.as_ptr() as _,
+                    launch_token.as_mut_ptr() as _,
                 );
             if error != 0 {
-                return Err(Error::AesmCode(error.into()));
+                return Err(status_error(error));
             }
         }
-        return Ok(licence_token);
+        launch_token.truncate(sgx_isa::Einittoken::UNPADDED_SIZE);
+        Ok(launch_token)
+    }
+
+    // Similar functionality to sgx_get_supported_att_key_id_num in the Intel
+    // SGX Developer Reference.
+    pub fn get_supported_att_key_id_num(&self) -> Result<u32> {
+        let get_supported_att_key_id_num = self.library.get_supported_att_key_id_num.ok_or_else(unavailable)?;
+        let mut num = 0;
+        let error = unsafe { get_supported_att_key_id_num(&mut num) };
+        if error != 0 {
+            return Err(status_error(error));
+        }
+        Ok(num)
+    }
+
+    // Similar functionality to sgx_get_supported_att_key_ids in the Intel SGX
+    // Developer Reference.
+    pub fn get_supported_att_key_ids(&self) -> Result<AesmKeyIds> {
+        let num = self.get_supported_att_key_id_num()?;
+        // Unlike AESM, the PSW rejects an empty buffer.
+        if num == 0 {
+            return AesmKeyIds::new(vec![]);
+        }
+        let get_supported_att_key_ids = self.library.get_supported_att_key_ids.ok_or_else(unavailable)?;
+        let mut key_ids = vec![0; num as usize * AesmKeyId::SIZE];
+        let error = unsafe { get_supported_att_key_ids(key_ids.as_mut_ptr(), num) };
+        if error != 0 {
+            return Err(status_error(error));
+        }
+        AesmKeyIds::new(key_ids)
+    }
+
+    // Similar functionality to sgx_init_quote_ex in the Intel SGX Developer
+    // Reference.
+    pub fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfoEx> {
+        let init_quote_ex = self.library.init_quote_ex.ok_or_else(unavailable)?;
+        let att_key_id_in = att_key_id_ptr(&att_key_id)?;
+        let mut target_info: Vec<u8> = vec![0; Targetinfo::UNPADDED_SIZE];
+        // Like AESM, ask for the size of the public key ID first, then for
+        // the ID itself.
+        let mut pub_key_id_size = 0;
+        let error = unsafe {
+            init_quote_ex(att_key_id_in, target_info.as_mut_ptr() as _, &mut pub_key_id_size, std::ptr::null_mut())
+        };
+        if error != 0 {
+            return Err(status_error(error));
+        }
+        let mut pub_key_id = vec![0; pub_key_id_size];
+        let error = unsafe {
+            init_quote_ex(att_key_id_in, target_info.as_mut_ptr() as _, &mut pub_key_id_size, pub_key_id.as_mut_ptr())
+        };
+        if error != 0 {
+            return Err(status_error(error));
+        }
+        pub_key_id.truncate(pub_key_id_size);
+
+        // Also get the expected quote size for given key id, so that
+        // get_quote_ex doesn't have to.
+        let quote_size = self.get_quote_size_ex(att_key_id.clone())?;
+
+        QuoteInfoEx::new(att_key_id, check_target_info(target_info)?, pub_key_id, quote_size)
+    }
+
+    // Similar functionality to sgx_get_quote_size_ex in the Intel SGX
+    // Developer Reference.
+    pub fn get_quote_size_ex(&self, att_key_id: Vec<u8>) -> Result<u32> {
+        let get_quote_size_ex = self.library.get_quote_size_ex.ok_or_else(unavailable)?;
+        let mut quote_size = 0;
+        let error = unsafe { get_quote_size_ex(att_key_id_ptr(&att_key_id)?, &mut quote_size) };
+        if error != 0 {
+            return Err(status_error(error));
+        }
+        Ok(quote_size)
+    }
+
+    // Similar functionality to sgx_get_quote_ex in the Intel SGX Developer
+    // Reference.
+    pub fn get_quote_ex(
+        &self,
+        quote_info: &QuoteInfoEx,
+        report: &[u8],
+        target_info: &[u8],
+        nonce: &[u8]
+    ) -> Result<QuoteResult> {
+        // The PSW reads fixed-size structures, so check what AESM would
+        // check for us.
+        if report.len() != Report::UNPADDED_SIZE || target_info.len() != Targetinfo::UNPADDED_SIZE || nonce.len() != 16 {
+            return Err(Error::AesmCode(AesmError::ParameterError_3));
+        }
+        match self.get_quote_ex_with_size(quote_info.att_key_id(), report, target_info, nonce, quote_info.quote_size()) {
+            Err(err @ Error::AesmCode(AesmError::ParameterError_3)) => {
+                // The buffer may be too small because the quote size changed
+                // since init_quote_ex. If so, try again with the new size.
+                let quote_size = self.get_quote_size_ex(quote_info.att_key_id().to_owned())?;
+                if quote_size <= quote_info.quote_size() {
+                    return Err(err);
+                }
+                self.get_quote_ex_with_size(quote_info.att_key_id(), report, target_info, nonce, quote_size)
+            }
+            res => res,
+        }
+    }
+
+    fn get_quote_ex_with_size(
+        &self,
+        att_key_id: &[u8],
+        report: &[u8],
+        target_info: &[u8],
+        nonce: &[u8],
+        quote_size: u32,
+    ) -> Result<QuoteResult> {
+        let get_quote_ex = self.library.get_quote_ex.ok_or_else(unavailable)?;
+
+        // qe_report_info is of type sgx_ql_qe_report_info_t
+        // - nonce: sgx_quote_nonce_t
+        // - app_enclave_target_info: sgx_target_info_t
+        // - qe_report: sgx_report_t
+        let report_start = nonce.len() + target_info.len();
+        let mut qe_report_info: Vec<u8> = Vec::with_capacity(report_start + Report::UNPADDED_SIZE);
+        qe_report_info.extend_from_slice(nonce);
+        qe_report_info.extend_from_slice(target_info);
+        qe_report_info.resize(report_start + Report::UNPADDED_SIZE, 0);
+
+        let mut quote: Vec<u8> = vec![0; quote_size as usize];
+        let error = unsafe {
+            get_quote_ex(
+                report.as_ptr() as _,
+                att_key_id_ptr(att_key_id)?,
+                qe_report_info.as_mut_ptr(),
+                quote.as_mut_ptr(),
+                quote_size,
+            )
+        };
+        if error != 0 {
+            return Err(status_error(error));
+        }
+        Ok(QuoteResult::from_qe_report_info(quote, qe_report_info, report_start))
     }
 }
@@ -0,0 +1,86 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+use sgx_isa::{Attributes, Sigstruct};
+
+use crate::error::Result;
+use crate::QuoteInfoEx;
+use crate::{AesmClient, QuoteInfo, QuoteResult, QuoteType};
+
+/// The AESM operations used for attestation and launch.
+///
+/// Code that only needs these operations can accept an `impl AesmInterface`
+/// instead of an `AesmClient`, so that it can be tested without AESM, for
+/// example with `MockAesmClient`. The methods behave like the `AesmClient`
+/// methods of the same name.
+pub trait AesmInterface {
+    /// Obtain target info from QE.
+    fn init_quote(&self) -> Result<QuoteInfo>;
+
+    /// Obtain remote attestation quote from QE.
+    fn get_quote(
+        &self,
+        report: Vec<u8>,
+        spid: Vec<u8>,
+        sig_rl: Vec<u8>,
+        quote_type: QuoteType,
+        nonce: Vec<u8>,
+    ) -> Result<QuoteResult>;
+
+    #[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+    fn get_launch_token(&self, sigstruct: &Sigstruct, attributes: Attributes) -> Result<Vec<u8>>;
+
+    /// Obtain target info from QE for the attestation key `att_key_id`.
+    fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfoEx>;
+
+    /// Obtain remote attestation quote from QE for the attestation key that
+    /// `quote_info` was obtained for.
+    fn get_quote_ex(
+        &self,
+        quote_info: &QuoteInfoEx,
+        report: Vec<u8>,
+        target_info: Option<Vec<u8>>,
+        nonce: Vec<u8>,
+    ) -> Result<QuoteResult>;
+}
+
+impl AesmInterface for AesmClient {
+    fn init_quote(&self) -> Result<QuoteInfo> {
+        AesmClient::init_quote(self)
+    }
+
+    #[allow(deprecated)]
+    fn get_quote(
+        &self,
+        report: Vec<u8>,
+        spid: Vec<u8>,
+        sig_rl: Vec<u8>,
+        quote_type: QuoteType,
+        nonce: Vec<u8>,
+    ) -> Result<QuoteResult> {
+        AesmClient::get_quote(self, report, spid, sig_rl, quote_type, nonce)
+    }
+
+    #[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+    fn get_launch_token(&self, sigstruct: &Sigstruct, attributes: Attributes) -> Result<Vec<u8>> {
+        AesmClient::get_launch_token(self, sigstruct, attributes)
+    }
+
+    fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfoEx> {
+        AesmClient::init_quote_ex(self, att_key_id)
+    }
+
+    fn get_quote_ex(
+        &self,
+        quote_info: &QuoteInfoEx,
+        report: Vec<u8>,
+        target_info: Option<Vec<u8>>,
+        nonce: Vec<u8>,
+    ) -> Result<QuoteResult> {
+        AesmClient::get_quote_ex(self, quote_info, report, target_info, nonce)
+    }
+}
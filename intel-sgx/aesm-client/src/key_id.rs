@@ -0,0 +1,387 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::cmp;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::mem::size_of;
+
+use crate::ct::ct_eq;
+use crate::error::{Error, Result};
+use crate::hex::{HexBytes, Redacted};
+#[cfg(feature = "serde")]
+use crate::serde_support;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Offsets in sgx_att_key_id_ext_t, which is packed. The first 158 bytes are
+// an sgx_ql_att_key_id_t.
+const MRSIGNER_LENGTH_OFFSET: usize = 4;
+const MRSIGNER_OFFSET: usize = 6;
+const MRSIGNER_SIZE: usize = 48;
+const PROD_ID_OFFSET: usize = 54;
+const EXTENDED_PROD_ID_OFFSET: usize = 58;
+const CONFIG_ID_OFFSET: usize = 74;
+const FAMILY_ID_OFFSET: usize = 138;
+const ALGORITHM_ID_OFFSET: usize = 154;
+pub(crate) const SPID_OFFSET: usize = 158;
+const ATT_KEY_TYPE_OFFSET: usize = 174;
+
+/// An attestation algorithm, an `sgx_ql_attestation_algorithm_id_t` from
+/// sgx_quote.h in the Intel SDK.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum AttestationAlgorithm {
+    Epid,
+    EcdsaP256,
+    EcdsaP384,
+    /// An algorithm this crate doesn't know about yet.
+    Other(u32),
+}
+
+impl From<u32> for AttestationAlgorithm {
+    fn from(id: u32) -> Self {
+        use self::AttestationAlgorithm::*;
+        match id {
+            0 => Epid,
+            2 => EcdsaP256,
+            3 => EcdsaP384,
+            _ => Other(id),
+        }
+    }
+}
+
+impl From<AttestationAlgorithm> for u32 {
+    fn from(algorithm: AttestationAlgorithm) -> u32 {
+        use self::AttestationAlgorithm::*;
+        match algorithm {
+            Epid => 0,
+            EcdsaP256 => 2,
+            EcdsaP384 => 3,
+            Other(id) => id,
+        }
+    }
+}
+
+impl fmt::Display for AttestationAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AttestationAlgorithm::*;
+        match *self {
+            Epid => f.write_str("EPID"),
+            EcdsaP256 => f.write_str("ECDSA P-256"),
+            EcdsaP384 => f.write_str("ECDSA P-384"),
+            Other(id) => write!(f, "unknown algorithm {}", id),
+        }
+    }
+}
+
+/// An attestation key ID, as used by `init_quote_ex` and `get_quote_ex`.
+///
+/// This is an `sgx_att_key_id_ext_t` from sgx_quote.h in the Intel SDK.
+#[derive(Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(try_from = "serde_support::AesmKeyIdFields"))]
+pub struct AesmKeyId {
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::bytes"))]
+    pub(crate) key: Vec<u8>,
+}
+
+impl AesmKeyId {
+    /// The size of `sgx_att_key_id_ext_t`.
+    pub const SIZE: usize = 256;
+
+    /// The raw `sgx_att_key_id_ext_t`.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn field<'a, T>(&'a self, offset: usize) -> &'a T
+    where
+        &'a T: TryFrom<&'a [u8]>,
+    {
+        match self.key[offset..offset + size_of::<T>()].try_into() {
+            Ok(field) => field,
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// The attestation algorithm, an `sgx_ql_attestation_algorithm_id_t`.
+    pub fn algorithm_id(&self) -> u32 {
+        u32::from_le_bytes(*self.field(ALGORITHM_ID_OFFSET))
+    }
+
+    /// The attestation algorithm.
+    pub fn algorithm(&self) -> AttestationAlgorithm {
+        self.algorithm_id().into()
+    }
+
+    /// For EPID, the quote signature type, an `sgx_quote_sign_type_t`.
+    pub fn att_key_type(&self) -> u16 {
+        u16::from_le_bytes(*self.field(ATT_KEY_TYPE_OFFSET))
+    }
+
+    /// For EPID, the service provider ID. Other algorithms don't use this
+    /// field.
+    pub fn spid(&self) -> &[u8; 16] {
+        self.field(SPID_OFFSET)
+    }
+
+    /// The hash of the public key that signed the quoting enclave.
+    pub fn mrsigner(&self) -> &[u8] {
+        let len = u16::from_le_bytes(*self.field(MRSIGNER_LENGTH_OFFSET)) as usize;
+        &self.key[MRSIGNER_OFFSET..][..cmp::min(len, MRSIGNER_SIZE)]
+    }
+
+    /// The legacy product ID of the quoting enclave.
+    pub fn prod_id(&self) -> u32 {
+        u32::from_le_bytes(*self.field(PROD_ID_OFFSET))
+    }
+
+    /// The extended product ID of the quoting enclave.
+    pub fn extended_prod_id(&self) -> &[u8; 16] {
+        self.field(EXTENDED_PROD_ID_OFFSET)
+    }
+
+    /// The config ID of the quoting enclave.
+    pub fn config_id(&self) -> &[u8; 64] {
+        self.field(CONFIG_ID_OFFSET)
+    }
+
+    /// The family ID of the quoting enclave.
+    pub fn family_id(&self) -> &[u8; 16] {
+        self.field(FAMILY_ID_OFFSET)
+    }
+}
+
+// Shows the parsed fields rather than the raw key ID. The SPID is never
+// shown.
+impl fmt::Debug for AesmKeyId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AesmKeyId")
+            .field("algorithm", &self.algorithm())
+            .field("att_key_type", &self.att_key_type())
+            .field("mrsigner", &HexBytes(self.mrsigner()))
+            .field("prod_id", &self.prod_id())
+            .field("extended_prod_id", &HexBytes(self.extended_prod_id()))
+            .field("config_id", &HexBytes(self.config_id()))
+            .field("family_id", &HexBytes(self.family_id()))
+            .field("spid", &Redacted(self.spid()))
+            .finish()
+    }
+}
+
+/// The attestation key IDs supported by AESM, as returned by
+/// `get_supported_att_key_ids`.
+#[derive(Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(try_from = "serde_support::AesmKeyIdsFields"))]
+pub struct AesmKeyIds {
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::bytes"))]
+    keys: Vec<u8>,
+}
+
+impl AesmKeyIds {
+    /// Parse an array of `sgx_att_key_id_ext_t`.
+    pub fn new(keys: Vec<u8>) -> Result<Self> {
+        if !keys.chunks_exact(AesmKeyId::SIZE).remainder().is_empty() {
+            return Err(Error::InvalidKeyIdSize);
+        }
+        Ok(AesmKeyIds { keys })
+    }
+
+    /// The raw array of `sgx_att_key_id_ext_t`.
+    pub fn keys(&self) -> &[u8] {
+        &self.keys
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = AesmKeyId> + '_ {
+        self.keys.chunks_exact(AesmKeyId::SIZE).map(|key| AesmKeyId { key: key.to_owned() })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len() / AesmKeyId::SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The keys with the given key type and algorithm. Criteria that are
+    /// `None` match all keys.
+    pub fn filter(&self, key_type: Option<u16>, algorithm: Option<AttestationAlgorithm>) -> Vec<AesmKeyId> {
+        self.iter()
+            .filter(|key| key_type.is_none() || key_type == Some(key.att_key_type()))
+            .filter(|key| algorithm.is_none() || algorithm == Some(key.algorithm()))
+            .collect()
+    }
+
+    /// The attestation algorithms of the keys, without duplicates, in the
+    /// order AESM listed them.
+    pub fn algorithms(&self) -> Vec<AttestationAlgorithm> {
+        let mut algorithms = vec![];
+        for algorithm in self.iter().map(|key| key.algorithm()) {
+            if !algorithms.contains(&algorithm) {
+                algorithms.push(algorithm);
+            }
+        }
+        algorithms
+    }
+
+    /// Find the EPID key for the given service provider ID. The SPIDs are
+    /// compared in constant time.
+    pub fn select_by_spid(&self, spid: &[u8; 16]) -> Option<AesmKeyId> {
+        self.iter().find(|key| key.algorithm() == AttestationAlgorithm::Epid && ct_eq(key.spid(), spid))
+    }
+}
+
+impl fmt::Debug for AesmKeyIds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iter() {
+        let mut keys = vec![0; 2 * AesmKeyId::SIZE];
+        keys[AesmKeyId::SIZE] = 1;
+        let key_ids = AesmKeyIds::new(keys).unwrap();
+        assert_eq!(key_ids.len(), 2);
+        let keys = key_ids.iter().collect::<Vec<_>>();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].key(), &[0; AesmKeyId::SIZE][..]);
+        assert_eq!(keys[1].key()[0], 1);
+
+        assert!(AesmKeyIds::new(vec![]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn accessors() {
+        let mut key = vec![0; AesmKeyId::SIZE];
+        key[4..6].copy_from_slice(&32u16.to_le_bytes());
+        for (i, b) in key[6..54].iter_mut().enumerate() {
+            *b = i as u8 + 1;
+        }
+        key[54..58].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        key[58..74].copy_from_slice(&[2; 16]);
+        key[74..138].copy_from_slice(&[3; 64]);
+        key[138..154].copy_from_slice(&[4; 16]);
+        key[154..158].copy_from_slice(&2u32.to_le_bytes());
+        key[158..174].copy_from_slice(&[5; 16]);
+        key[174..176].copy_from_slice(&1u16.to_le_bytes());
+        key[176..].copy_from_slice(&[0xff; 80]);
+
+        let key_id = AesmKeyIds::new(key).unwrap().iter().next().unwrap();
+        assert_eq!(key_id.mrsigner(), (1..=32).collect::<Vec<u8>>().as_slice());
+        assert_eq!(key_id.prod_id(), 0x1234_5678);
+        assert_eq!(key_id.extended_prod_id(), &[2; 16]);
+        assert_eq!(&key_id.config_id()[..], &[3; 64][..]);
+        assert_eq!(key_id.family_id(), &[4; 16]);
+        assert_eq!(key_id.algorithm_id(), 2);
+        assert_eq!(key_id.algorithm(), AttestationAlgorithm::EcdsaP256);
+        assert_eq!(key_id.spid(), &[5; 16]);
+        assert_eq!(key_id.att_key_type(), 1);
+    }
+
+    fn key_id(algorithm: AttestationAlgorithm, spid: [u8; 16]) -> Vec<u8> {
+        let mut key = vec![0; AesmKeyId::SIZE];
+        key[154..158].copy_from_slice(&u32::from(algorithm).to_le_bytes());
+        key[158..174].copy_from_slice(&spid);
+        key
+    }
+
+    #[test]
+    fn filter() {
+        let mut linkable = key_id(AttestationAlgorithm::Epid, [1; 16]);
+        linkable[174] = 1;
+        let keys = [
+            key_id(AttestationAlgorithm::Epid, [0; 16]),
+            linkable,
+            key_id(AttestationAlgorithm::EcdsaP256, [0; 16]),
+        ].concat();
+        let key_ids = AesmKeyIds::new(keys).unwrap();
+        let types = |keys: Vec<AesmKeyId>| keys.iter().map(|k| (k.algorithm_id(), k.att_key_type())).collect::<Vec<_>>();
+
+        assert_eq!(types(key_ids.filter(None, None)), [(0, 0), (0, 1), (2, 0)]);
+        assert_eq!(types(key_ids.filter(Some(0), None)), [(0, 0), (2, 0)]);
+        assert_eq!(types(key_ids.filter(None, Some(AttestationAlgorithm::Epid))), [(0, 0), (0, 1)]);
+        assert_eq!(types(key_ids.filter(Some(1), Some(AttestationAlgorithm::Epid))), [(0, 1)]);
+        assert_eq!(types(key_ids.filter(Some(1), Some(AttestationAlgorithm::EcdsaP256))), []);
+    }
+
+    #[test]
+    fn algorithms() {
+        use super::AttestationAlgorithm::*;
+        let keys = [
+            key_id(EcdsaP256, [0; 16]),
+            key_id(Other(7), [0; 16]),
+            key_id(Epid, [1; 16]),
+            key_id(EcdsaP256, [0; 16]),
+            key_id(Epid, [2; 16]),
+            key_id(Other(7), [0; 16]),
+        ].concat();
+        let key_ids = AesmKeyIds::new(keys).unwrap();
+        assert_eq!(key_ids.algorithms(), [EcdsaP256, Other(7), Epid]);
+
+        assert_eq!(AesmKeyIds::new(vec![]).unwrap().algorithms(), []);
+    }
+
+    #[test]
+    fn select_by_spid() {
+        let keys = [
+            key_id(AttestationAlgorithm::EcdsaP256, [0; 16]),
+            key_id(AttestationAlgorithm::Epid, [1; 16]),
+            key_id(AttestationAlgorithm::Epid, [2; 16]),
+        ].concat();
+        let key_ids = AesmKeyIds::new(keys).unwrap();
+
+        assert_eq!(key_ids.select_by_spid(&[2; 16]).unwrap().spid(), &[2; 16]);
+        assert_eq!(key_ids.select_by_spid(&[1; 16]).unwrap().spid(), &[1; 16]);
+        assert_eq!(key_ids.select_by_spid(&[3; 16]), None);
+        // The ECDSA key doesn't have an SPID, even though the field is zero.
+        assert_eq!(key_ids.select_by_spid(&[0; 16]), None);
+    }
+
+    #[test]
+    fn algorithm() {
+        use super::AttestationAlgorithm::*;
+        for &(id, algorithm, name) in &[
+            (0, Epid, "EPID"),
+            (1, Other(1), "unknown algorithm 1"),
+            (2, EcdsaP256, "ECDSA P-256"),
+            (3, EcdsaP384, "ECDSA P-384"),
+            (4, Other(4), "unknown algorithm 4"),
+        ] {
+            assert_eq!(AttestationAlgorithm::from(id), algorithm);
+            assert_eq!(u32::from(algorithm), id);
+            assert_eq!(algorithm.to_string(), name);
+        }
+    }
+
+    #[test]
+    fn debug() {
+        let mut key = key_id(AttestationAlgorithm::Epid, [0xab; 16]);
+        key[4..6].copy_from_slice(&32u16.to_le_bytes());
+        let key_ids = AesmKeyIds::new(key).unwrap();
+        let key_id = key_ids.iter().next().unwrap();
+        for debug in &[format!("{:?}", key_id), format!("{:#?}", key_ids)] {
+            assert!(debug.contains("algorithm: Epid"), "{}", debug);
+            assert!(debug.contains("spid: 16 bytes [redacted]"), "{}", debug);
+            assert!(!debug.contains("ab"), "{}", debug);
+        }
+        assert!(format!("{:?}", key_id).contains("mrsigner: 32 bytes [00 00 00 00 … 00 00]"));
+    }
+
+    #[test]
+    fn invalid_size() {
+        for &len in &[1, AesmKeyId::SIZE - 1, AesmKeyId::SIZE + 1, 2 * AesmKeyId::SIZE - 1] {
+            match AesmKeyIds::new(vec![0; len]) {
+                Err(Error::InvalidKeyIdSize) => {}
+                res => panic!("expected invalid key ID size for length {}, got {:?}", len, res),
+            }
+        }
+    }
+}
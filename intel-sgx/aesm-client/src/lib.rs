@@ -6,7 +6,43 @@
 //! # Features
 //!
 //! * `sgxs`. Enable the `sgxs` feature to get an implemention of
-//!   `EinittokenProvider` that uses AESM.
+//!   `EinittokenProvider` that uses AESM, and the `CachedTokenProvider` and
+//!   `ChainedTokenProvider` wrappers.
+//! * `async`. Enable the `async` feature to get `AsyncAesmClient`, a client
+//!   whose methods are `async fn`s based on tokio. Only available on unix.
+//! * `serde`. Enable the `serde` feature to serialize and deserialize quote
+//!   info, quotes and attestation key IDs with serde.
+//! * `mock`. Enable the `mock` feature to get `MockAesmClient`, an
+//!   `AesmInterface` for testing code without AESM.
+//! * `verify`. Enable the `verify` feature to get
+//!   `QuoteResult::verify_qe_report`, which checks that the QE report is
+//!   bound to the nonce and quote, `AesmClient::attest_epid`, and
+//!   `QuoteInfoEx::report_data`, which binds the attestation public key.
+//! * `zeroize`. Enable the `zeroize` feature to wipe buffers that may contain
+//!   secrets, such as SPIDs and nonces, when they're dropped.
+//! * `cli`. Enable the `cli` feature to build `aesm-info`, a tool that prints
+//!   the attestation capabilities of the host, optionally as JSON. Not
+//!   available on Windows.
+//! * `bytes`. Enable the `bytes` feature to store quotes and quote info as
+//!   `bytes::Bytes`, and get accessors such as `QuoteResult::quote_bytes`
+//!   that return them without copying.
+//! * `pse`. Enable the `pse` feature to get `AesmClient::create_pse_session`,
+//!   for sessions with the platform services enclave. Not available on
+//!   Windows.
+//! * `unstable`. Enable the `unstable` feature to get
+//!   `AesmClient::raw_request`. This API may change in any release.
+//!
+//! # Constant-time comparisons
+//!
+//! Values that an attacker may try to guess byte by byte are compared in
+//! constant time, without exiting at the first mismatching byte:
+//!
+//! * the REPORTDATA of the QE report in `QuoteResult::verify_qe_report`,
+//! * the REPORTDATA of the quote in `QuoteResult::verify_report_data`,
+//! * `Basename`s, including in `Basename::is_derived_from_spid`,
+//! * `Spid`s, including in `AesmKeyIds::select_by_spid`.
+//!
+//! Only the lengths of the compared values may leak.
 
 #![doc(html_logo_url = "https://edp.fortanix.com/img/docs/edp-logo.svg",
        html_favicon_url = "https://edp.fortanix.com/favicon.ico",
@@ -17,31 +53,128 @@ extern crate byteorder;
 pub extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+extern crate getrandom;
 #[macro_use]
 #[cfg(unix)]
 extern crate lazy_static;
+extern crate log;
 extern crate protobuf;
 #[cfg(feature = "sgxs")]
 extern crate sgxs;
+#[cfg(all(test, unix))]
+extern crate tempfile;
+#[cfg(all(unix, feature = "async"))]
+extern crate tokio;
 #[cfg(unix)]
 extern crate unix_socket;
 #[cfg(windows)]
 extern crate winapi;
+#[cfg(feature = "zeroize")]
+extern crate zeroize;
 extern crate sgx_isa;
+#[cfg(feature = "serde")]
+extern crate serde;
+extern crate sha2;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_cbor;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
+use std::convert::TryFrom;
 #[cfg(feature = "sgxs")]
 use std::result::Result as StdResult;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(not(windows))]
+use std::time::Instant;
+use std::cmp;
+#[cfg(not(windows))]
+use std::thread;
 
 use protobuf::ProtobufResult;
 #[cfg(feature = "sgxs")]
 use sgxs::einittoken::{Einittoken, EinittokenProvider};
-#[cfg(all(not(target_env = "sgx"),feature = "sgxs"))]
+#[cfg(not(target_env = "sgx"))]
 use sgx_isa::{Attributes, Sigstruct};
+#[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+use sgx_isa::AttributesFlags;
+use sgx_isa::{Report, Targetinfo};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::hex::HexBytes;
 
 include!(concat!(env!("OUT_DIR"), "/mod_aesm_proto.rs"));
+mod builder;
+mod ct;
+#[cfg(unix)]
+mod capabilities;
+#[cfg(not(windows))]
+mod diagnostics;
 mod error;
+mod hex;
+mod interface;
+mod key_id;
+mod metrics;
+mod platform_info;
+#[cfg(feature = "mock")]
+mod mock;
+#[cfg(unix)]
+mod pool;
+#[cfg(not(windows))]
+mod ps_cap;
+#[cfg(all(not(windows), feature = "pse"))]
+mod pse;
+mod quote;
+mod quote_request;
+#[cfg(feature = "serde")]
+mod serde_support;
+#[cfg(not(windows))]
+mod service_info;
+mod sig_rl;
+mod spid;
+#[cfg(feature = "sgxs")]
+mod token_provider;
+#[cfg(not(windows))]
+mod update_status;
+#[cfg(feature = "verify")]
+mod verify;
+#[cfg(not(windows))]
+mod white_list;
 use self::aesm_proto::*;
-pub use error::{AesmError, Error, Result};
+pub use builder::AesmClientBuilder;
+#[cfg(unix)]
+pub use capabilities::{AesmCapabilities, Capability};
+#[cfg(not(windows))]
+pub use diagnostics::AesmDiagnostics;
+pub use error::{AesmError, AttestationStep, DcapUnavailableReason, Error, Result, SgxCheck, WhiteListRejection};
+pub use interface::AesmInterface;
+pub use key_id::{AesmKeyId, AesmKeyIds, AttestationAlgorithm};
+pub use metrics::{AesmMetric, AesmOutcome, AesmRequestKind};
+pub use platform_info::{PlatformInfo, TlvEntry};
+#[cfg(feature = "mock")]
+pub use mock::{MockAesmClient, MockMethod};
+#[cfg(unix)]
+pub use pool::{AesmClientPool, PooledClient};
+#[cfg(not(windows))]
+pub use ps_cap::PsCap;
+#[cfg(all(not(windows), feature = "pse"))]
+pub use pse::{PseSession, DEFAULT_PSE_RESPONSE_SIZE};
+pub use quote::{Basename, CertificationDataType, EcdsaQuote, EpidQuote};
+pub use quote_request::{MissingQuoteType, MissingSpid, QuoteRequest};
+#[cfg(not(windows))]
+pub use service_info::AesmServiceInfo;
+pub use sig_rl::SigRl;
+pub use spid::Spid;
+#[cfg(feature = "sgxs")]
+pub use token_provider::{CachedTokenProvider, ChainedTokenError, ChainedTokenProvider};
+#[cfg(not(windows))]
+pub use update_status::UpdateStatus;
+#[cfg(feature = "verify")]
+pub use verify::{EpidAttestation, PUB_KEY_BINDING_SCHEME};
+#[cfg(not(windows))]
+pub use white_list::WhiteList;
 #[cfg(windows)]
 #[path = "imp/windows.rs"]
 mod imp;
@@ -51,19 +184,60 @@ mod imp;
 #[cfg(target_env = "sgx")]
 #[path = "imp/sgx.rs"]
 mod imp;
+#[cfg(all(test, unix))]
+mod test_support;
+#[cfg(unix)]
+pub use imp::unix_nonblocking::{NonblockingAesmClient, PendingInitQuote, PendingQuote};
 #[cfg(unix)]
 pub mod unix {
     use std::path::Path;
     pub trait AesmClientExt {
+        /// A client for the AESM socket at `path`, e.g. if it's bind-mounted
+        /// into a container. Like `AesmClientBuilder::socket_path`: the
+        /// default locations aren't tried, and the client connects to `path`
+        /// again if the connection is lost.
         fn with_path<P: AsRef<Path>>(path: P) -> Self;
+
+        /// A client for the AESM socket with the name `name` in the abstract
+        /// namespace, without the leading NUL. Abstract addresses are
+        /// compared including their length, so `name` must include any
+        /// padding AESM uses.
+        fn with_abstract_name(name: &[u8]) -> Self;
+
+        /// A client for AESM, or a proxy for it, listening on vsock `port`
+        /// of the context `cid`, e.g. `VMADDR_CID_HOST` to reach the host of
+        /// a virtual machine. Requests are framed like on the unix socket,
+        /// and the client reconnects and times out requests the same way.
+        /// Connecting isn't bounded by the client's timeouts, though: the
+        /// kernel gives up after its own connect timeout. SGX is assumed to
+        /// be available, as the host's device nodes aren't visible.
+        #[cfg(feature = "vsock")]
+        fn with_vsock(cid: u32, port: u32) -> Self;
     }
 }
 
 #[cfg(target_env = "sgx")]
 pub mod sgx {
+    use std::io::{self, Read, Write};
     use std::net::TcpStream;
     pub trait AesmClientExt {
         fn new(tcp_stream: TcpStream) -> Self;
+
+        /// A client that talks to AESM over `stream`, e.g. a stream provided
+        /// by the enclave runner. Requests are written and responses read in
+        /// the same format as over TCP.
+        ///
+        /// The stream can't be reopened, so the client and its clones share
+        /// it, even if it's closed.
+        fn from_stream<S: Read + Write + Send + 'static>(stream: S) -> Self;
+
+        /// A client that calls `connect` to open a connection to AESM when
+        /// it sends its first request, and again if the connection is lost.
+        /// Every clone opens its own connection.
+        fn with_connector<S, F>(connect: F) -> Self
+        where
+            S: Read + Write + Send + 'static,
+            F: Fn() -> io::Result<S> + Send + Sync + 'static;
     }
 }
 
@@ -72,6 +246,7 @@ const AESM_SUCCESS: u32 = 0;
 
 // From SDK sgx_quote.h
 #[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum QuoteType {
     Unlinkable = 0,
     Linkable = 1,
@@ -98,18 +273,40 @@ impl QuoteType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(try_from = "serde_support::QuoteInfoFields"))]
 pub struct QuoteInfo {
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::bytes"))]
     target_info: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::bytes"))]
     pub_key_id: Vec<u8>,
 }
 
 impl QuoteInfo {
+    /// Reassemble quote info, e.g. after persisting it. Returns
+    /// `Error::InvalidTargetInfoSize` if `target_info` isn't a `Targetinfo`.
+    pub fn new(target_info: Vec<u8>, pub_key_id: Vec<u8>) -> Result<Self> {
+        if target_info.len() != Targetinfo::UNPADDED_SIZE {
+            return Err(Error::InvalidTargetInfoSize);
+        }
+        Ok(QuoteInfo { target_info, pub_key_id })
+    }
+
     pub fn target_info(&self) -> &[u8] {
         &self.target_info
     }
 
+    /// The target info of the QE, for use with `EREPORT`.
+    pub fn targetinfo(&self) -> Result<Targetinfo> {
+        Targetinfo::try_copy_from(&self.target_info).ok_or(Error::InvalidTargetInfoSize)
+    }
+
     /// EPID only: EPID group ID, big-endian byte order
+    ///
+    /// AESM returns the group ID little-endian, as in `pub_key_id`, so this
+    /// is `pub_key_id` reversed. For group ID 0x00000b2f, this is `[0x00,
+    /// 0x00, 0x0b, 0x2f]`. IAS expects the group ID in this order, see
+    /// `gid_hex`.
     pub fn gid(&self) -> Vec<u8> {
         // AESM gives it to us little-endian, we want big-endian for writing into IAS URL with to_hex()
         let mut pk = self.pub_key_id.clone();
@@ -117,45 +314,328 @@ impl QuoteInfo {
         pk
     }
 
+    /// EPID only: EPID group ID as a number, e.g. to compare it with the
+    /// group ID of a signature revocation list.
+    ///
+    /// If AESM returned a group ID that isn't 4 bytes, its first 4 bytes are
+    /// used, padded with zeros.
+    pub fn gid_u32(&self) -> u32 {
+        let mut gid = [0; 4];
+        let len = cmp::min(gid.len(), self.pub_key_id.len());
+        gid[..len].copy_from_slice(&self.pub_key_id[..len]);
+        u32::from_le_bytes(gid)
+    }
+
+    /// EPID only: EPID group ID as 8 lowercase hex digits, most significant
+    /// first, e.g. `00000b2f`. This is the format the IAS sigrl endpoint
+    /// expects in its URL.
+    pub fn gid_hex(&self) -> String {
+        format!("{:08x}", self.gid_u32())
+    }
+
     pub fn pub_key_id(&self) -> &[u8] {
         &self.pub_key_id
     }
 }
 
-// The value returned here can depend on number of sigrl entries, and
-// possibly other factors. Although why the client needs to pass a length
-// in a protobuf API is beyond me.
-fn quote_buffer_size(sig_rl: &[u8]) -> u32 {
-    // Refer to se_quote_internal.h and sgx_quote.h in the Intel SDK.
-    let quote_length = 436 + 288 + 12 + 4 + 16;
+/// Check the target info of QE as returned by AESM, so that a broken AESM
+/// doesn't cause `EREPORT` to fault in the enclave later on.
+pub(crate) fn check_target_info(target_info: Vec<u8>) -> Result<Vec<u8>> {
+    let reason = match Targetinfo::try_copy_from(&target_info) {
+        None => "wrong size",
+        Some(ti) if ti._reserved1 != [0; 4] || ti._reserved2.iter().any(|&b| b != 0) => "reserved fields aren't zero",
+        Some(_) => return Ok(target_info),
+    };
+    Err(Error::MalformedResponse { field: "target_info", reason, raw: target_info })
+}
+
+// The Debug impls of the quote types show buffers abbreviated in hex, see
+// `HexBytes`. Use `{:#?}` to see them in full.
+impl fmt::Debug for QuoteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QuoteInfo")
+            .field("target_info", &HexBytes(&self.target_info))
+            .field("pub_key_id", &HexBytes(&self.pub_key_id))
+            .finish()
+    }
+}
+
+/// The storage of quotes and quote info, see the `bytes` feature.
+#[cfg(feature = "bytes")]
+type Buffer = bytes::Bytes;
+#[cfg(not(feature = "bytes"))]
+type Buffer = Vec<u8>;
+
+#[cfg(feature = "bytes")]
+fn buffer(bytes: Vec<u8>) -> Buffer {
+    bytes.into()
+}
+
+#[cfg(not(feature = "bytes"))]
+fn buffer(bytes: Vec<u8>) -> Buffer {
+    bytes
+}
+
+/// `bytes` without its first `start` bytes. With the `bytes` feature, this
+/// doesn't copy.
+#[cfg(feature = "bytes")]
+fn buffer_from(bytes: Vec<u8>, start: usize) -> Buffer {
+    Buffer::from(bytes).slice(start..)
+}
+
+#[cfg(not(feature = "bytes"))]
+fn buffer_from(mut bytes: Vec<u8>, start: usize) -> Buffer {
+    bytes.drain(..start);
+    bytes
+}
+
+/// With the `bytes` feature, this always copies, as `Bytes` can't give up
+/// its buffer.
+#[cfg(feature = "bytes")]
+fn buffer_into_vec(bytes: Buffer) -> Vec<u8> {
+    bytes.to_vec()
+}
+
+#[cfg(not(feature = "bytes"))]
+fn buffer_into_vec(bytes: Buffer) -> Vec<u8> {
+    bytes
+}
+
+/// Information about the QE and attestation key, as returned by
+/// `init_quote_ex`.
+#[derive(Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(try_from = "serde_support::QuoteInfoExFields"))]
+pub struct QuoteInfoEx {
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::bytes"))]
+    att_key_id: Buffer,
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::bytes"))]
+    target_info: Buffer,
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::bytes"))]
+    pub_key: Buffer,
+    quote_size: u32,
+}
+
+impl QuoteInfoEx {
+    /// Reassemble quote info, e.g. after persisting it. Returns
+    /// `Error::InvalidTargetInfoSize` if `target_info` isn't a `Targetinfo`.
+    pub fn new(att_key_id: Vec<u8>, target_info: Vec<u8>, pub_key: Vec<u8>, quote_size: u32) -> Result<Self> {
+        if target_info.len() != Targetinfo::UNPADDED_SIZE {
+            return Err(Error::InvalidTargetInfoSize);
+        }
+        Ok(QuoteInfoEx {
+            att_key_id: buffer(att_key_id),
+            target_info: buffer(target_info),
+            pub_key: buffer(pub_key),
+            quote_size,
+        })
+    }
+
+    /// The attestation key ID this info is for. Empty for the default
+    /// attestation key of the platform.
+    pub fn att_key_id(&self) -> &[u8] {
+        &self.att_key_id
+    }
+
+    /// Like `att_key_id`, without copying.
+    #[cfg(feature = "bytes")]
+    pub fn att_key_id_bytes(&self) -> bytes::Bytes {
+        self.att_key_id.clone()
+    }
+
+    pub fn target_info(&self) -> &[u8] {
+        &self.target_info
+    }
+
+    /// Like `target_info`, without copying.
+    #[cfg(feature = "bytes")]
+    pub fn target_info_bytes(&self) -> bytes::Bytes {
+        self.target_info.clone()
+    }
+
+    /// The target info of the QE, for use with `EREPORT`.
+    pub fn targetinfo(&self) -> Result<Targetinfo> {
+        Targetinfo::try_copy_from(&self.target_info).ok_or(Error::InvalidTargetInfoSize)
+    }
+
+    /// The public key ID of the attestation key. For EPID, this is the EPID
+    /// group ID, little-endian.
+    pub fn pub_key(&self) -> &[u8] {
+        &self.pub_key
+    }
+
+    /// Like `pub_key`, without copying.
+    #[cfg(feature = "bytes")]
+    pub fn pub_key_bytes(&self) -> bytes::Bytes {
+        self.pub_key.clone()
+    }
+
+    /// The size of quotes generated with this attestation key, as reported by
+    /// AESM during `init_quote_ex`.
+    pub fn quote_size(&self) -> u32 {
+        self.quote_size
+    }
+}
+
+impl fmt::Debug for QuoteInfoEx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QuoteInfoEx")
+            .field("att_key_id", &HexBytes(&self.att_key_id))
+            .field("target_info", &HexBytes(&self.target_info))
+            .field("pub_key", &HexBytes(&self.pub_key))
+            .field("quote_size", &self.quote_size)
+            .finish()
+    }
+}
+
+// Refer to se_quote_internal.h and sgx_quote.h in the Intel SDK.
+const QUOTE_LENGTH: u32 = 436 + 288 + 12 + 4 + 16;
+// Refer to epid/common/types.h in the Intel SDK. An EPID signature has one
+// non-revoked proof for every sig_rl entry.
+const EPID_SIGNATURE_LENGTH: u32 = 352 + 4 + 4;
+const NR_PROOF_LENGTH: u32 = 160;
+
+/// The size of the buffer needed for an EPID quote with the signature
+/// revocation list `sig_rl`.
+///
+/// The size depends on the number of entries in `sig_rl`. If `sig_rl` can't
+/// be parsed, a conservative estimate is returned instead.
+pub fn quote_buffer_size(sig_rl: &[u8]) -> u32 {
+    let entries = if sig_rl.is_empty() {
+        Some(0)
+    } else {
+        sig_rl::entries(sig_rl).ok()
+    };
+    match entries.and_then(|n| (n as u32).checked_mul(NR_PROOF_LENGTH)) {
+        Some(nr_proofs_length) => QUOTE_LENGTH + EPID_SIGNATURE_LENGTH + nr_proofs_length,
+        // An nr_proof is 5 field elements, a sig_rl entry is four. Add some
+        // slop for sig_rl headers.
+        None => QUOTE_LENGTH + EPID_SIGNATURE_LENGTH + (sig_rl.len() as u32 * 5 / 4) + 128,
+    }
+}
+
+// The signature length is a little endian word at offset 432 in the quote
+// structure, followed by the signature. See "QUOTE Structure" in the IAS API
+// Spec.
+const QUOTE_SIGNATURE_LEN_OFFSET: usize = 432;
+const QUOTE_SIGNATURE_OFFSET: usize = 436;
+
+// QE fills the whole buffer of the size we supplied, regardless of how much
+// space it needed, see `quote_buffer_size`. The length of the quote without
+// the excess.
+fn trimmed_quote_len(quote: &[u8]) -> usize {
+    let sig_len = match quote.get(QUOTE_SIGNATURE_LEN_OFFSET..QUOTE_SIGNATURE_OFFSET) {
+        Some(b) => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize,
+        None => {
+            log::warn!("quote of {} bytes is too short for a quote header, not trimming it", quote.len());
+            return quote.len();
+        }
+    };
+    let len = QUOTE_SIGNATURE_OFFSET.saturating_add(sig_len);
+    if quote.len() < len {
+        // Probably we are interpreting the quote structure incorrectly.
+        log::warn!("quote of {} bytes is too short for its signature length {}, not trimming it", quote.len(), sig_len);
+        return quote.len();
+    }
+    len
+}
+
+// From SDK sgx_quote.h
+const SPID_SIZE: usize = 16;
+const NONCE_SIZE: usize = 16;
+
+fn check_size(field: &'static str, expected: usize, got: usize) -> Result<()> {
+    if expected != got {
+        return Err(Error::InvalidArgument { field, expected, got });
+    }
+    Ok(())
+}
+
+// Catch malformed arguments before sending them to AESM, which only returns
+// a generic error for them.
+fn check_get_quote_args(report: &[u8], spid: &[u8], sig_rl: &[u8], nonce: Option<&[u8]>) -> Result<()> {
+    check_size("report", Report::UNPADDED_SIZE, report.len())?;
+    check_size("spid", SPID_SIZE, spid.len())?;
+    if let Some(nonce) = nonce {
+        check_size("nonce", NONCE_SIZE, nonce.len())?;
+    }
+    if !sig_rl.is_empty() {
+        sig_rl::entries(sig_rl)?;
+    }
+    Ok(())
+}
 
-    // Refer to epid/common/types.h in the Intel SDK.
-    // This is the truly correct way to compute sig_length:
-    //let nr_proof_length = 160;
-    //let sig_length = 352 + 4 + 4 + sig_rl_entries * nr_proof_length;
-    // Instead we do something that should be conservative, and doesn't
-    // require interpreting the sig_rl structure to determine the entry
-    // count. An nr_proof is 5 field elements, a sig_rl entry is four.
-    // Add some slop for sig_rl headers.
-    let sig_length = 352 + 4 + 4 + (sig_rl.len() as u32 * 5 / 4) + 128;
+/// Check that `attributes` match the attributes of `sigstruct` in the bits
+/// of its attribute mask.
+#[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+fn check_attributes(sigstruct: &Sigstruct, attributes: Attributes) -> Result<()> {
+    let masked = |attributes: &Attributes| Attributes {
+        flags: AttributesFlags::from_bits_truncate(attributes.flags.bits() & sigstruct.attributemask[0]),
+        xfrm: attributes.xfrm & sigstruct.attributemask[1],
+    };
+    let (requested, allowed) = (masked(&attributes), masked(&sigstruct.attributes));
+    if requested != allowed {
+        return Err(Error::AttributesMismatch { requested: attributes, allowed });
+    }
+    Ok(())
+}
 
-    quote_length + sig_length
+fn generate_nonce() -> Result<[u8; NONCE_SIZE]> {
+    let mut nonce = [0; NONCE_SIZE];
+    getrandom::getrandom(&mut nonce).map_err(|e| Error::NonceGeneration(e.into()))?;
+    Ok(nonce)
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(try_from = "serde_support::QuoteResultFields"))]
 pub struct QuoteResult {
     /// For Intel attestations, the EPID signature from Intel QE.
-    quote: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::bytes"))]
+    quote: Buffer,
 
     /// SGX report (EREPORT) from the Intel quoting enclave for the quote.
-    qe_report: Vec<u8>,
+    /// `None` if no QE report was requested, i.e. the quote was requested
+    /// without a nonce.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serde_support::bytes::serialize_optional"))]
+    qe_report: Option<Buffer>,
 }
 
 impl QuoteResult {
     pub fn new<T: Into<Vec<u8>>, U: Into<Vec<u8>>>(quote: T, qe_report: U) -> Self {
         QuoteResult {
-            quote: quote.into(),
-            qe_report: qe_report.into(),
+            quote: buffer(quote.into()),
+            qe_report: Some(buffer(qe_report.into())),
+        }
+    }
+
+    /// A quote that was requested without a QE report.
+    pub fn without_qe_report<T: Into<Vec<u8>>>(quote: T) -> Self {
+        QuoteResult {
+            quote: buffer(quote.into()),
+            qe_report: None,
+        }
+    }
+
+    /// A quote as filled in by QE, see `trimmed_quote_len`, and the QE report
+    /// if it was requested.
+    ///
+    /// The quote is trimmed after it's moved into the buffer: with the `bytes`
+    /// feature, converting the trimmed `Vec` would reallocate it to shrink it.
+    fn from_untrimmed_quote(quote: Vec<u8>, qe_report: Option<Vec<u8>>) -> Self {
+        let len = trimmed_quote_len(&quote);
+        let mut quote = buffer(quote);
+        quote.truncate(len);
+        QuoteResult {
+            quote,
+            qe_report: qe_report.map(buffer),
+        }
+    }
+
+    /// A quote and the QE report that's at `report_start` in `qe_report_info`,
+    /// as returned for `get_quote_ex`.
+    fn from_qe_report_info(quote: Vec<u8>, qe_report_info: Vec<u8>, report_start: usize) -> Self {
+        QuoteResult {
+            quote: buffer(quote),
+            qe_report: Some(buffer_from(qe_report_info, report_start)),
         }
     }
 
@@ -163,22 +643,116 @@ impl QuoteResult {
         &self.quote
     }
 
+    /// Like `quote`, without copying.
+    #[cfg(feature = "bytes")]
+    pub fn quote_bytes(&self) -> bytes::Bytes {
+        self.quote.clone()
+    }
+
+    /// The QE report, which is empty if none was requested.
     pub fn qe_report(&self) -> &[u8] {
-        &self.qe_report
+        self.qe_report.as_deref().unwrap_or(&[])
+    }
+
+    /// Like `qe_report`, without copying.
+    #[cfg(feature = "bytes")]
+    pub fn qe_report_bytes(&self) -> bytes::Bytes {
+        self.qe_report.clone().unwrap_or_default()
+    }
+
+    /// Whether a QE report was requested with the quote. If so, AESM may
+    /// still have returned an empty one.
+    pub fn has_qe_report(&self) -> bool {
+        self.qe_report.is_some()
+    }
+
+    /// The quote and the QE report. The QE report is empty if none was
+    /// requested. With the `bytes` feature, they're always copied, see
+    /// `quote_bytes` and `qe_report_bytes` instead.
+    pub fn into_parts(self) -> (Vec<u8>, Vec<u8>) {
+        (buffer_into_vec(self.quote), self.qe_report.map(buffer_into_vec).unwrap_or_default())
+    }
+
+    /// The quote. The QE report is dropped. With the `bytes` feature, the
+    /// quote is always copied, see `quote_bytes` instead.
+    pub fn into_quote(self) -> Vec<u8> {
+        buffer_into_vec(self.quote)
     }
 }
 
+impl From<QuoteResult> for Vec<u8> {
+    /// The quote, see `QuoteResult::into_quote`.
+    fn from(quote: QuoteResult) -> Vec<u8> {
+        quote.into_quote()
+    }
+}
+
+/// The quote.
+impl AsRef<[u8]> for QuoteResult {
+    fn as_ref(&self) -> &[u8] {
+        &self.quote
+    }
+}
+
+impl fmt::Debug for QuoteResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("QuoteResult");
+        s.field("quote", &HexBytes(&self.quote));
+        match self.qe_report {
+            Some(ref qe_report) => s.field("qe_report", &HexBytes(qe_report)),
+            None => s.field("qe_report", &format_args!("not requested")),
+        };
+        s.finish()
+    }
+}
+
+/// A client for AESM.
+///
+/// `AesmClient` is `Send` and `Sync`, and can be used from several threads at
+/// once, either through a shared reference or through clones, which share the
+/// connection to AESM. In SGX, clones of a client that can connect itself
+/// open their own connection instead, see `sgx::AesmClientExt`. On unix and
+/// in SGX, the connection is locked for the duration of a single request and
+/// its response, so concurrent requests are sent one after the other, but
+/// can't get each other's responses. The lock isn't held while waiting to
+/// retry a request. To send requests in parallel on unix, use an
+/// `AesmClientPool`.
 #[cfg_attr(not(target_env = "sgx"), derive(Default))]
 #[derive(Debug, Clone)]
 pub struct AesmClient {
-    inner: imp::AesmClient
+    inner: imp::AesmClient,
+    // Shared between clones, see `cached_quote_info`.
+    quote_info: Arc<Mutex<Option<QuoteInfo>>>,
+}
+
+// Users rely on `AesmClient` being usable from several threads.
+#[allow(dead_code)]
+fn assert_aesm_client_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<AesmClient>();
 }
 
 
 impl AesmClient {
+    /// A client with the default options.
+    ///
+    /// On unix, this logs a warning if SGX obviously isn't available on this
+    /// host, see `diagnose`. The client is returned anyway, as the checks may
+    /// not know every device node.
     #[cfg(not(target_env = "sgx"))]
     pub fn new() -> Self {
-        AesmClient { inner: imp::AesmClient::new() }
+        let client = AesmClientBuilder::new().build().expect("default options are supported on all targets");
+        #[cfg(unix)]
+        {
+            if let Err(err) = client.inner.check_sgx_available() {
+                log::warn!("{}", err);
+            }
+        }
+        client
+    }
+
+    pub fn builder() -> AesmClientBuilder {
+        AesmClientBuilder::new()
     }
 
     /// Test the connection with AESM.
@@ -186,16 +760,278 @@ impl AesmClient {
     /// This should only be used for diagnostic purposes. This method returning
     /// `Ok` is not a guarantee that any of the other methods will function
     /// correctly.
+    ///
+    /// On Windows, returns `Error::SgxNotAvailable` if the AESM service isn't
+    /// installed, and `Error::AesmCommunication` if it's installed, but not
+    /// running or not responding.
     pub fn try_connect(&self) -> Result<()> {
         self.inner.try_connect()
     }
 
+    /// Test the connection with AESM, giving up after `timeout`.
+    ///
+    /// Like `try_connect`, but returns `Error::Timeout` if the connection
+    /// isn't established in time, e.g. because AESM doesn't accept
+    /// connections. Suitable for readiness probes.
+    pub fn try_connect_timeout(&self, timeout: Duration) -> Result<()> {
+        self.inner.try_connect_timeout(timeout)
+    }
+
+    /// Wait at most `timeout` for AESM to be up, e.g. while the system is
+    /// booting.
+    ///
+    /// AESM is up once it responds to a trivial request for the extended
+    /// EPID group ID, even with an error code. Until then, the request is
+    /// retried with exponential backoff, up to a second between attempts.
+    /// On timeout, the error of the last attempt is returned. A `timeout` too
+    /// long to represent means waiting as long as it takes. On the sgx
+    /// target, the client connects again after a failed attempt, unless it
+    /// was created with a stream.
+    #[cfg(not(windows))]
+    pub fn wait_for_service(&self, timeout: Duration) -> Result<()> {
+        const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+        const MAX_BACKOFF: Duration = Duration::from_secs(1);
+        // How long each attempt waits for a response if there's no deadline.
+        const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+        let deadline = Instant::now().checked_add(timeout);
+        let remaining = || deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            // A request can't be sent with a zero timeout.
+            let probe_timeout = remaining().map_or(PROBE_TIMEOUT, |remaining| cmp::max(remaining, Duration::from_millis(1)));
+            let err = match self.inner.probe_service(probe_timeout) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            let sleep = match remaining() {
+                Some(remaining) if remaining == Duration::from_secs(0) => return Err(err),
+                Some(remaining) => cmp::min(backoff, remaining),
+                None => backoff,
+            };
+            thread::sleep(sleep);
+            backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+        }
+    }
+
+    /// Check the connection with AESM, and report in detail what's wrong, if
+    /// anything.
+    ///
+    /// This connects to AESM and sends it a trivial request, asking for the
+    /// extended EPID group ID. Like `try_connect`, this is meant for
+    /// diagnostic purposes, such as health checks.
+    ///
+    /// On unix, returns `Error::SgxNotAvailable` if there's no SGX device
+    /// node, or if there's no AESM socket and connecting fails.
+    #[cfg(not(windows))]
+    pub fn diagnose(&self) -> Result<AesmDiagnostics> {
+        let sgx_unavailable = self.inner.check_sgx_available().err();
+        if let Some(err @ Error::SgxNotAvailable(SgxCheck::DeviceNode)) = sgx_unavailable {
+            return Err(err);
+        }
+        let connect_error = self.inner.try_connect().err();
+        if let (Some(err), Some(_)) = (sgx_unavailable, &connect_error) {
+            return Err(err);
+        }
+        let mut diagnostics = AesmDiagnostics {
+            socket_path: self.inner.socket_path(),
+            connected: connect_error.is_none(),
+            connect_error,
+            latency: None,
+            request_error: None,
+        };
+        if diagnostics.connected {
+            let start = Instant::now();
+            let res = self.inner.get_extended_epid_group_id();
+            if matches!(res, Ok(_) | Err(Error::AesmCode(_))) {
+                diagnostics.latency = Some(start.elapsed());
+            }
+            diagnostics.request_error = res.err();
+        }
+        Ok(diagnostics)
+    }
+
+    /// Probe which kinds of attestation AESM supports on this host.
+    ///
+    /// This sends one request for each capability, with a short timeout:
+    /// `init_quote` for EPID, `get_supported_att_key_ids` for DCAP, and a
+    /// request for a launch token for an invalid enclave, which AESM rejects
+    /// with `GetLicensetokenError_6` if its launch service works. A capability
+    /// whose request fails for another reason is reported as
+    /// `Capability::Unknown`.
+    ///
+    /// Returns an error only if the client can't connect to AESM.
+    #[cfg(unix)]
+    pub fn capabilities(&self) -> Result<AesmCapabilities> {
+        let client = self.inner.with_timeout(capabilities::PROBE_TIMEOUT)?;
+        client.try_connect()?;
+        let epid = client.init_quote().map(|_| true);
+        let dcap = client.get_supported_att_key_ids().map(|key_ids| {
+            key_ids.algorithms().iter().any(|algorithm| {
+                matches!(algorithm, AttestationAlgorithm::EcdsaP256 | AttestationAlgorithm::EcdsaP384)
+            })
+        });
+        let launch_token = match client.get_launch_token_raw(&[0; Sigstruct::UNPADDED_SIZE], &[0; Attributes::UNPADDED_SIZE]) {
+            Ok(_) | Err(Error::AesmCode(AesmError::GetLicensetokenError_6)) => Capability::Available,
+            // AESM rejects malformed requests before they reach the launch
+            // service, so this says nothing about it.
+            Err(err @ Error::AesmCode(AesmError::ParameterError_3)) => Capability::Unknown(err),
+            Err(err) => Capability::from_probe(Err(err)),
+        };
+        Ok(AesmCapabilities {
+            epid: Capability::from_probe(epid),
+            dcap: Capability::from_probe(dcap),
+            launch_token,
+        })
+    }
+
+    /// Identify the AESM service, e.g. for an inventory of hosts.
+    ///
+    /// This asks AESM for the launch enclave white list, whether the platform
+    /// software is up to date, and the extended EPID group ID. Fields that
+    /// AESM doesn't provide are `None`. Returns an error if AESM doesn't
+    /// respond.
+    #[cfg(not(windows))]
+    pub fn service_info(&self) -> Result<AesmServiceInfo> {
+        let white_list_size = service_info::optional(self.inner.get_white_list_size())?;
+        let white_list_version = match white_list_size {
+            Some(size) => service_info::optional(self.inner.get_white_list(size))?
+                .and_then(|white_list| white_list::version(&white_list)),
+            None => None,
+        };
+        Ok(AesmServiceInfo {
+            white_list_version,
+            white_list_size,
+            up_to_date: service_info::optional(self.inner.check_update_status(None))?.map(|status| !status.update_available),
+            extended_epid_group_id: service_info::optional(self.inner.get_extended_epid_group_id())?,
+        })
+    }
+
+    /// Returns the launch enclave white list installed on the platform.
+    ///
+    /// Returns `Error::WhiteListNotApplicable` if AESM has no launch service,
+    /// e.g. on platforms with flexible launch control, where enclaves are
+    /// launched without a launch enclave.
+    #[cfg(not(windows))]
+    pub fn get_white_list(&self) -> Result<WhiteList> {
+        let size = white_list::not_applicable(self.inner.get_white_list_size())?;
+        let cert_chain = white_list::not_applicable(self.inner.get_white_list(size))?;
+        WhiteList::parse(cert_chain)
+    }
+
+    /// Install the launch enclave white list certificate chain
+    /// `cert_chain`, a `wl_cert_chain_t`, e.g. on hosts without access to
+    /// Intel's servers.
+    ///
+    /// Returns `Error::WhiteListRejected` if `cert_chain` is malformed, older
+    /// than the installed white list, or AESM can't verify its signature.
+    /// Registering the installed version again succeeds. Returns
+    /// `Error::WhiteListNotApplicable` if AESM has no launch service.
+    #[cfg(not(windows))]
+    pub fn register_white_list(&self, cert_chain: &[u8]) -> Result<()> {
+        white_list::register(&self.inner, cert_chain)
+    }
+
+    /// Install a launch enclave white list certificate chain.
+    ///
+    /// Like `register_white_list`, but waits at most `timeout` for AESM to
+    /// respond, instead of the client's default timeout.
+    #[cfg(not(windows))]
+    pub fn register_white_list_with_timeout(&self, cert_chain: &[u8], timeout: Duration) -> Result<()> {
+        white_list::register(&self.inner.with_timeout(timeout)?, cert_chain)
+    }
+
+    /// Ask AESM whether the platform needs to be updated, e.g. after an
+    /// attestation failed.
+    ///
+    /// `platform_info` is the platform info blob from the attestation
+    /// report, which AESM needs to determine which updates are needed. It's
+    /// passed to AESM as is. Without it, AESM only reports whether an update
+    /// is available.
+    #[cfg(not(windows))]
+    pub fn check_update_status(&self, platform_info: Option<&[u8]>) -> Result<UpdateStatus> {
+        self.inner.check_update_status(platform_info.map(<[u8]>::to_vec))
+    }
+
+    /// Report the outcome of an attestation to AESM, so that it can act on
+    /// it, e.g. provision the platform again, and return which updates the
+    /// platform needs.
+    ///
+    /// `platform_info` is the platform info blob from the attestation
+    /// report, and `attestation_error` is 0 if the attestation succeeded, and
+    /// nonzero otherwise. If AESM reports that an update is needed, this
+    /// returns an `UpdateStatus` with `update_available` set rather than an
+    /// error. Like `sgx_report_attestation_status` of the Intel SDK.
+    #[cfg(not(windows))]
+    pub fn report_attestation_status(&self, platform_info: &[u8], attestation_error: u32) -> Result<UpdateStatus> {
+        self.inner.report_attestation_status(platform_info.to_vec(), attestation_error)
+    }
+
+    /// Make AESM provision the platform for EPID attestation, if it needs
+    /// to, e.g. after a TCB recovery.
+    ///
+    /// The AESM protocol has no dedicated provisioning request: AESM
+    /// provisions the platform when it's asked for the target info of QE and
+    /// its EPID blob is missing or no longer valid. This sends that request,
+    /// waiting at most `timeout`, or as long as it takes if `None`, since
+    /// provisioning involves Intel's provisioning service and may take long.
+    /// If provisioning fails, the error code from AESM is returned.
+    ///
+    /// The request is sent on a connection of its own, so it doesn't block
+    /// other requests of this client or its clones. On success, the cached
+    /// quote info is replaced, as the EPID group may have changed.
+    #[cfg(unix)]
+    pub fn provision_epid(&self, timeout: Option<Duration>) -> Result<()> {
+        let quote_info = self.inner.for_long_request(timeout).init_quote()?;
+        *self.quote_info.lock().unwrap() = Some(quote_info);
+        Ok(())
+    }
+
     /// Obtain target info from QE.
     pub fn init_quote(&self) -> Result<QuoteInfo> {
         self.inner.init_quote()
     }
 
+    /// Obtain target info from QE, reusing the result of a previous call.
+    ///
+    /// The target info and EPID group ID rarely change, so this avoids a
+    /// request to AESM for every quote. The cache is shared by all clones of
+    /// this client. It's cleared when `get_quote` fails because the EPID group
+    /// was revoked or needs to be updated, or by calling `refresh_quote_info`.
+    pub fn cached_quote_info(&self) -> Result<QuoteInfo> {
+        // Holding the lock while talking to AESM ensures that concurrent
+        // callers wait for the first one instead of sending their own request.
+        let mut cached = self.quote_info.lock().unwrap();
+        if let Some(ref quote_info) = *cached {
+            return Ok(quote_info.clone());
+        }
+        let quote_info = self.init_quote()?;
+        *cached = Some(quote_info.clone());
+        Ok(quote_info)
+    }
+
+    /// Obtain target info from QE, replacing the result cached by
+    /// `cached_quote_info`.
+    pub fn refresh_quote_info(&self) -> Result<QuoteInfo> {
+        let mut cached = self.quote_info.lock().unwrap();
+        *cached = None;
+        let quote_info = self.init_quote()?;
+        *cached = Some(quote_info.clone());
+        Ok(quote_info)
+    }
+
+    fn invalidate_quote_info<T>(&self, res: Result<T>) -> Result<T> {
+        if let Err(Error::AesmCode(AesmError::EpidRevokedError_5)) | Err(Error::AesmCode(AesmError::UpdateAvailable_20)) = res {
+            *self.quote_info.lock().unwrap() = None;
+        }
+        res
+    }
+
     /// Obtain remote attestation quote from QE.
+    ///
+    /// `report` is the raw report as returned by `EREPORT` and `spid` the
+    /// raw 16-byte SPID.
+    #[deprecated(note = "use `get_quote_req`, which can't be passed the arguments in the wrong order")]
     pub fn get_quote(
         &self,
         report: Vec<u8>,
@@ -204,63 +1040,588 @@ impl AesmClient {
         quote_type: QuoteType,
         nonce: Vec<u8>,
     ) -> Result<QuoteResult> {
-        self.inner.get_quote(
+        check_get_quote_args(&report, &spid, &sig_rl, Some(&nonce))?;
+        let spid = Spid::try_from(&spid[..])?;
+        self.send_quote_request(QuoteRequest::from_raw(report, spid, sig_rl, quote_type, nonce))
+    }
+
+    /// Obtain remote attestation quote from QE for the request `req`.
+    ///
+    /// `quote_info` is the result of `init_quote` that the report in `req`
+    /// targets. Returns `Error::SigRlGroupMismatch` if the signature
+    /// revocation list is for a different EPID group than the platform's.
+    pub fn get_quote_req(&self, quote_info: &QuoteInfo, req: QuoteRequest<Spid, QuoteType>) -> Result<QuoteResult> {
+        if let (Some(sig_rl), Ok(platform)) = (sig_rl::gid(&req.sig_rl), <[u8; 4]>::try_from(&quote_info.gid()[..])) {
+            let platform = u32::from_be_bytes(platform);
+            if sig_rl != platform {
+                return Err(Error::SigRlGroupMismatch { sig_rl, platform });
+            }
+        }
+        self.send_quote_request(req)
+    }
+
+    fn send_quote_request(&self, req: QuoteRequest<Spid, QuoteType>) -> Result<QuoteResult> {
+        self.invalidate_quote_info(self.inner.get_quote(
+            req.report,
+            req.spid.as_bytes().to_vec(),
+            req.sig_rl,
+            req.quote_type,
+            req.nonce,
+        ))
+    }
+
+    /// Obtain remote attestation quote from QE.
+    ///
+    /// Like `get_quote`, but borrows its arguments. They're copied into the
+    /// request exactly once, which saves the caller a copy if it doesn't own
+    /// the buffers, e.g. a signature revocation list shared between threads.
+    pub fn get_quote_from_slices(
+        &self,
+        report: &[u8],
+        spid: &[u8],
+        sig_rl: &[u8],
+        quote_type: QuoteType,
+        nonce: &[u8],
+    ) -> Result<QuoteResult> {
+        check_get_quote_args(report, spid, sig_rl, Some(nonce))?;
+        self.invalidate_quote_info(self.inner.get_quote(
+            report.to_vec(),
+            spid.to_vec(),
+            sig_rl.to_vec(),
+            quote_type,
+            Some(nonce.to_vec()),
+        ))
+    }
+
+    /// Obtain remote attestation quote from QE for `report`.
+    ///
+    /// An SPID in raw bytes can be converted with `Spid::try_from`, and a
+    /// signature revocation list with `SigRl::parse`. Without a `nonce`, QE
+    /// doesn't return a QE report, and the `qe_report` of the result is
+    /// empty.
+    pub fn get_quote_for_report<S: Into<Spid>>(
+        &self,
+        report: &Report,
+        spid: S,
+        sig_rl: &SigRl,
+        quote_type: QuoteType,
+        nonce: Option<&[u8; NONCE_SIZE]>,
+    ) -> Result<QuoteResult> {
+        let (report, spid): (&[u8], _) = (report.as_ref(), spid.into());
+        check_get_quote_args(report, spid.as_bytes(), sig_rl.as_bytes(), nonce.map(|nonce| &nonce[..]))?;
+        self.invalidate_quote_info(self.inner.get_quote(
+            report.to_vec(),
+            spid.as_bytes().to_vec(),
+            sig_rl.as_bytes().to_vec(),
+            quote_type,
+            nonce.map(|nonce| nonce.to_vec()),
+        ))
+    }
+
+    /// Obtain remote attestation quote from QE for `report`, using a freshly
+    /// generated random nonce.
+    ///
+    /// The nonce is returned with the quote, so that the caller can check
+    /// that the QE report is bound to it.
+    pub fn get_quote_with_generated_nonce<S: Into<Spid>>(
+        &self,
+        report: &Report,
+        spid: S,
+        sig_rl: &SigRl,
+        quote_type: QuoteType,
+    ) -> Result<(QuoteResult, [u8; NONCE_SIZE])> {
+        let nonce = generate_nonce()?;
+        let quote = self.get_quote_for_report(report, spid, sig_rl, quote_type, Some(&nonce))?;
+        Ok((quote, nonce))
+    }
+
+    /// Obtain an EPID quote for a report, and check that QE bound its report
+    /// to the quote.
+    ///
+    /// `make_report` is called with the target info of QE and should return a
+    /// report targeting it. The quote is requested with a freshly generated
+    /// random nonce. If a step fails, `Error::Attestation` says which one,
+    /// and holds its error, e.g. the `Error::AesmCode` that AESM returned.
+    ///
+    /// ```no_run
+    /// # use aesm_client::{AesmClient, QuoteType, SigRl, Spid};
+    /// # use sgx_isa::{Report, Targetinfo};
+    /// # fn enclave_report(_: &Targetinfo) -> Report { unimplemented!() }
+    /// # let (spid, sig_rl) = (Spid::from([0; 16]), SigRl::empty());
+    /// let attestation = AesmClient::new().attest_epid(&spid, &sig_rl, QuoteType::Linkable, enclave_report)?;
+    /// # Ok::<(), aesm_client::Error>(())
+    /// ```
+    #[cfg(feature = "verify")]
+    pub fn attest_epid<F: FnOnce(&Targetinfo) -> Report>(
+        &self,
+        spid: &Spid,
+        sig_rl: &SigRl,
+        quote_type: QuoteType,
+        make_report: F,
+    ) -> Result<EpidAttestation> {
+        use self::AttestationStep::*;
+
+        let quote_info = self.init_quote().map_err(Error::at_step(InitQuote))?;
+        let targetinfo = quote_info.targetinfo().map_err(Error::at_step(InitQuote))?;
+        let report = make_report(&targetinfo);
+        let nonce = generate_nonce().map_err(Error::at_step(GenerateNonce))?;
+        let req = QuoteRequest::new(&report).spid(*spid).sig_rl(sig_rl).quote_type(quote_type).nonce(nonce);
+        let quote = self.get_quote_req(&quote_info, req).map_err(Error::at_step(GetQuote))?;
+        let qe_report = quote.verify_qe_report(&nonce).map_err(Error::at_step(VerifyQeReport))?;
+        Ok(EpidAttestation { quote, qe_report, nonce })
+    }
+
+    /// Obtain target info from QE.
+    ///
+    /// Like `init_quote`, but waits at most `timeout` for AESM to respond,
+    /// instead of the client's default timeout.
+    pub fn init_quote_with_timeout(&self, timeout: Duration) -> Result<QuoteInfo> {
+        self.inner.with_timeout(timeout)?.init_quote()
+    }
+
+    /// Obtain remote attestation quote from QE.
+    ///
+    /// Like `get_quote`, but waits at most `timeout` for AESM to respond,
+    /// instead of the client's default timeout.
+    pub fn get_quote_with_timeout(
+        &self,
+        report: Vec<u8>,
+        spid: Vec<u8>,
+        sig_rl: Vec<u8>,
+        quote_type: QuoteType,
+        nonce: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<QuoteResult> {
+        check_get_quote_args(&report, &spid, &sig_rl, Some(&nonce))?;
+        self.invalidate_quote_info(self.inner.with_timeout(timeout)?.get_quote(
             report,
             spid,
             sig_rl,
             quote_type,
-            nonce,
-        )
+            Some(nonce),
+        ))
     }
 
+    /// Obtain a launch token for the enclave of `sigstruct`, to be
+    /// initialized with `attributes`.
+    ///
+    /// Returns `Error::AttributesMismatch` without asking AESM if the bits of
+    /// `attributes` selected by the sigstruct's attribute mask differ from
+    /// the sigstruct's attributes, as `EINIT` would fail. This covers e.g.
+    /// requesting `DEBUG` for a production enclave.
     #[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
     pub fn get_launch_token(
         &self,
         sigstruct: &Sigstruct,
         attributes: Attributes,
     ) -> Result<Vec<u8>> {
-        self.inner.get_launch_token(
+        check_attributes(sigstruct, attributes)?;
+        self.get_launch_token_unchecked(sigstruct, attributes)
+    }
+
+    /// Like `get_launch_token`, but always asks AESM, even if `attributes`
+    /// don't match the sigstruct. Useful for testing how AESM behaves.
+    #[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+    pub fn get_launch_token_unchecked(
+        &self,
+        sigstruct: &Sigstruct,
+        attributes: Attributes,
+    ) -> Result<Vec<u8>> {
+        self.get_launch_token_raw(
+            sigstruct.as_ref(),
+            attributes.as_ref(),
+        )
+    }
+
+    /// Like `get_launch_token`, but takes the raw `SIGSTRUCT` and
+    /// `ATTRIBUTES`, so it's available without the `sgxs` feature.
+    ///
+    /// Returns `Error::InvalidArgument` if they're not 1808 and 16 bytes.
+    #[cfg(not(target_env = "sgx"))]
+    pub fn get_launch_token_raw(
+        &self,
+        sigstruct: &[u8],
+        attributes: &[u8],
+    ) -> Result<Vec<u8>> {
+        check_size("sigstruct", Sigstruct::UNPADDED_SIZE, sigstruct.len())?;
+        check_size("attributes", Attributes::UNPADDED_SIZE, attributes.len())?;
+        self.inner.get_launch_token_raw(
             sigstruct,
             attributes,
         )
     }
 
-    /// Returns all keys supported by AESM service.
+    /// Like `get_launch_token`, but returns the token as an `Einittoken`.
+    ///
+    /// Returns `Error::InvalidTokenSize` if AESM returns a token of the wrong
+    /// size.
+    #[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+    pub fn get_launch_einittoken(
+        &self,
+        sigstruct: &Sigstruct,
+        attributes: Attributes,
+    ) -> Result<Einittoken> {
+        let token = self.get_launch_token(
+            sigstruct,
+            attributes,
+        )?;
+        #[cfg(feature = "zeroize")]
+        let token = zeroize::Zeroizing::new(token);
+        Einittoken::try_copy_from(&token).ok_or(Error::InvalidTokenSize)
+    }
+
+    /// Like `get_launch_token`, but the token is wiped when it's dropped.
+    #[cfg(all(not(target_env = "sgx"), feature = "sgxs", feature = "zeroize"))]
+    pub fn get_launch_token_zeroizing(
+        &self,
+        sigstruct: &Sigstruct,
+        attributes: Attributes,
+    ) -> Result<zeroize::Zeroizing<Vec<u8>>> {
+        self.get_launch_token(sigstruct, attributes).map(zeroize::Zeroizing::new)
+    }
+
+    /// Returns the extended EPID group ID of the platform. An extended group
+    /// ID of 0 means the platform uses Intel's attestation service.
+    #[cfg(not(windows))]
+    pub fn get_extended_epid_group_id(&self) -> Result<u32> {
+        self.inner.get_extended_epid_group_id()
+    }
+
+    /// Returns the platform services capabilities of the platform as a
+    /// bitmask, see `PsCap`. Like `sgx_get_ps_cap` of the Intel SDK.
     #[cfg(not(windows))]
-    pub fn get_supported_att_key_ids(&self) -> Result<Vec<Vec<u8>>> {
+    pub fn get_ps_cap(&self) -> Result<u64> {
+        self.inner.get_ps_cap()
+    }
+
+    /// Returns the platform services capabilities of the platform, e.g. to
+    /// check whether monotonic counters are available.
+    ///
+    /// Unlike `get_ps_cap`, this returns `PsCap::unsupported()` instead of an
+    /// error if the platform has no platform services.
+    #[cfg(not(windows))]
+    pub fn pse_capabilities(&self) -> Result<PsCap> {
+        ps_cap::from_result(self.get_ps_cap())
+    }
+
+    /// Create a session with the platform services enclave.
+    ///
+    /// The session uses the connection of this client, see `PseSession`.
+    #[cfg(all(not(windows), feature = "pse"))]
+    pub fn create_pse_session(&self) -> Result<PseSession> {
+        let (session_id, dh_msg1) = self.inner.create_session()?;
+        Ok(PseSession::new(self.inner.clone(), session_id, dh_msg1))
+    }
+
+    /// Send a serialized `Request` protobuf to AESM and return the serialized
+    /// `Response`.
+    ///
+    /// This is an escape hatch for messages that `AesmClient` doesn't wrap
+    /// yet. Only the framing is handled here: the response is not parsed, so
+    /// AESM error codes in it are not turned into `Error::AesmCode`, and
+    /// requests are not retried. `timeout` limits how long to wait for the
+    /// response, and defaults to the client's default timeout. It's not
+    /// added to the request. If AESM closes the connection after sending part
+    /// of the response, returns `Error::PartialResponse`.
+    #[cfg(all(not(windows), feature = "unstable"))]
+    pub fn raw_request(&self, req: &[u8], timeout: Option<Duration>) -> Result<Vec<u8>> {
+        self.inner.raw_request(req, timeout)
+    }
+
+    /// Returns the number of keys supported by AESM service.
+    pub fn get_supported_att_key_id_num(&self) -> Result<u32> {
+        self.inner.get_supported_att_key_id_num()
+    }
+
+    /// Returns all keys supported by AESM service.
+    pub fn get_supported_att_key_ids(&self) -> Result<AesmKeyIds> {
         self.inner.get_supported_att_key_ids()
     }
 
-    /// Obtain target info from QE.
+    /// Returns the first key supported by AESM service for `algorithm`, or
+    /// `None` if there is no such key.
+    pub fn get_att_key_id(&self, algorithm: AttestationAlgorithm) -> Result<Option<AesmKeyId>> {
+        Ok(self.get_supported_att_key_ids()?.filter(None, Some(algorithm)).into_iter().next())
+    }
+
+    /// Let AESM select the attestation key to use from `key_id_list`, an
+    /// `sgx_ql_att_key_id_list_t`, according to the platform's policy. An
+    /// empty list selects the platform's default key.
     ///
-    /// Like `init_quote`, but allows specifying the attestation key id.
+    /// Like `sgx_select_att_key_id` of the Intel SDK. The selected key ID
+    /// can be passed to `init_quote_ex` with `AesmKeyId::key`, and then its
+    /// `QuoteInfoEx` to `get_quote_ex`. Returns `Error::AesmBadResponse` if
+    /// AESM doesn't return a key ID, and `Error::MalformedResponse` if the
+    /// key ID it returns isn't an `sgx_att_key_id_ext_t`.
     #[cfg(not(windows))]
-    pub fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfo> {
-        self.inner.init_quote_ex(att_key_id)
+    pub fn select_att_key_id(&self, key_id_list: &[u8]) -> Result<AesmKeyId> {
+        self.inner.select_att_key_id(key_id_list.to_vec())
+    }
+
+    /// Obtain an ECDSA quote for a report, using the ECDSA-P256 attestation
+    /// key.
+    ///
+    /// `report_for` is called with the target info of the QE and should
+    /// return a report targeting it. Returns `Error::NoEcdsaAttestationKey` if
+    /// AESM doesn't have an ECDSA key, and `Error::DcapUnavailable` if it
+    /// can't use it.
+    ///
+    /// ```no_run
+    /// # use aesm_client::AesmClient;
+    /// # use sgx_isa::{Report, Targetinfo};
+    /// # fn enclave_report(_: &Targetinfo) -> Report { unimplemented!() }
+    /// let quote = AesmClient::new().attest_dcap(enclave_report, &[0; 16])?;
+    /// # Ok::<(), aesm_client::Error>(())
+    /// ```
+    pub fn attest_dcap<F: FnOnce(&Targetinfo) -> Report>(&self, report_for: F, nonce: &[u8; 16]) -> Result<QuoteResult> {
+        let key_id = self.get_att_key_id(AttestationAlgorithm::EcdsaP256)?
+            .ok_or(Error::NoEcdsaAttestationKey)?;
+        let quote_info = self.init_quote_ex(key_id.key().to_vec())?;
+        let report = report_for(&quote_info.targetinfo()?);
+        self.get_quote_ex_from_slices(&quote_info, report.as_ref(), None, nonce)
+    }
+
+    /// Returns the size of the buffer `get_quote_ex` needs for a quote with
+    /// the attestation key `att_key_id`.
+    ///
+    /// Returns `Error::InvalidKeyIdSize` if `att_key_id` isn't an
+    /// `sgx_att_key_id_ext_t`, and `Error::UnsupportedAttKeyId` if AESM
+    /// doesn't know the key.
+    pub fn get_quote_size_ex(&self, att_key_id: &[u8]) -> Result<u32> {
+        if att_key_id.len() != AesmKeyId::SIZE {
+            return Err(Error::InvalidKeyIdSize);
+        }
+        match self.inner.get_quote_size_ex(att_key_id.to_owned()) {
+            Err(Error::AesmCode(AesmError::UnsupportedAttKeyId_38)) => Err(Error::UnsupportedAttKeyId),
+            res => res.map_err(Error::dcap_unavailable),
+        }
+    }
+
+    /// Obtain target info from QE.
+    ///
+    /// Like `init_quote`, but allows specifying the attestation key id. An
+    /// empty `att_key_id` selects the default attestation key of the platform.
+    ///
+    /// The result isn't cached, every call asks AESM. AESM's interface has no
+    /// way to request that the attestation key be regenerated, unlike
+    /// `sgx_ql_init_quote` of the DCAP quoting library. After a TCB recovery,
+    /// call this again and discard `QuoteInfoEx` values obtained before it.
+    ///
+    /// Returns `Error::DcapUnavailable` if AESM can't produce quotes for an
+    /// ECDSA key on this platform, e.g. because the quote provider library
+    /// isn't installed. Callers may fall back to EPID attestation then.
+    pub fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfoEx> {
+        if att_key_id.is_empty() {
+            return self.init_quote_ex_default();
+        }
+        self.inner.init_quote_ex(att_key_id).map_err(Error::dcap_unavailable)
+    }
+
+    /// Obtain target info from QE for the default attestation key of the
+    /// platform.
+    ///
+    /// Returns `Error::NoDefaultAttestationKey` if the platform doesn't have
+    /// one.
+    pub fn init_quote_ex_default(&self) -> Result<QuoteInfoEx> {
+        no_default_key(self.inner.init_quote_ex(vec![])).map_err(Error::dcap_unavailable)
     }
 
     /// Obtain remote attestation quote from QE.
     ///
-    /// Like `get_quote`, but allows specifying the attestation key id.
+    /// Like `get_quote`, but for the attestation key that `quote_info` was
+    /// obtained for with `init_quote_ex`.
     ///
     /// If `target_info` is not supplied, it's determined from `report` so that
     /// the quote may be verified by the enclave it's for.
-    #[cfg(not(windows))]
     pub fn get_quote_ex(
         &self,
-        att_key_id: Vec<u8>,
+        quote_info: &QuoteInfoEx,
         report: Vec<u8>,
         target_info: Option<Vec<u8>>,
         nonce: Vec<u8>
     ) -> Result<QuoteResult> {
-        let target_info = target_info.unwrap_or_else( ||
-            AsRef::<[u8]>::as_ref(&sgx_isa::Targetinfo::from(sgx_isa::Report::try_copy_from(&report).unwrap()))
-                .to_owned()
-        );
-        self.inner.get_quote_ex(att_key_id, report, target_info, nonce)
+        self.get_quote_ex_from_slices(quote_info, &report, target_info.as_deref(), &nonce)
+    }
+
+    /// Obtain remote attestation quote from QE.
+    ///
+    /// Like `get_quote_ex`, but borrows its arguments. They're copied into the
+    /// request exactly once.
+    pub fn get_quote_ex_from_slices(
+        &self,
+        quote_info: &QuoteInfoEx,
+        report: &[u8],
+        target_info: Option<&[u8]>,
+        nonce: &[u8]
+    ) -> Result<QuoteResult> {
+        check_size("report", Report::UNPADDED_SIZE, report.len())?;
+        let default_target_info;
+        let target_info = match target_info {
+            Some(target_info) => target_info,
+            None => {
+                default_target_info = Targetinfo::from(Report::try_copy_from(report).expect("checked above"));
+                default_target_info.as_ref()
+            }
+        };
+        if quote_info.att_key_id().is_empty() {
+            return no_default_key(self.inner.get_quote_ex(quote_info, report, target_info, nonce))
+                .map_err(Error::dcap_unavailable);
+        }
+        self.inner.get_quote_ex(quote_info, report, target_info, nonce).map_err(Error::dcap_unavailable)
+    }
+
+    /// Obtain remote attestation quote from QE for `report`, with a QE
+    /// report that targets `qe_target_info`.
+    ///
+    /// Pass the target info of the enclave that will check the QE report,
+    /// so that it can verify the report's MAC with `EGETKEY`. If `None`, the
+    /// QE report targets the enclave that `report` is for. The MAC only shows
+    /// that the QE report is genuine: the enclave should also check that the
+    /// report is bound to the quote and to a nonce it chose itself, see
+    /// `QuoteResult::verify_qe_report`.
+    pub fn get_quote_ex_for_report(
+        &self,
+        quote_info: &QuoteInfoEx,
+        report: &Report,
+        qe_target_info: Option<&Targetinfo>,
+        nonce: &[u8],
+    ) -> Result<QuoteResult> {
+        self.get_quote_ex_from_slices(quote_info, report.as_ref(), qe_target_info.map(AsRef::as_ref), nonce)
+    }
+}
+
+// AESM doesn't distinguish between an unsupported key and a missing default
+// key.
+fn no_default_key<T>(res: Result<T>) -> Result<T> {
+    match res {
+        Err(Error::AesmCode(AesmError::UnsupportedAttKeyId_38)) => Err(Error::NoDefaultAttestationKey),
+        res => res,
+    }
+}
+
+/// Asynchronous client for AESM.
+///
+/// The available operations are the same as those of `AesmClient`, except
+/// that timeouts apply to the request as a whole rather than to individual
+/// socket reads.
+#[cfg(all(unix, feature = "async"))]
+#[derive(Debug, Clone, Default)]
+pub struct AsyncAesmClient {
+    inner: imp::unix_async::AsyncAesmClient
+}
+
+#[cfg(all(unix, feature = "async"))]
+impl AsyncAesmClient {
+    pub fn new() -> Self {
+        AsyncAesmClient { inner: imp::unix_async::AsyncAesmClient::new() }
+    }
+
+    /// Obtain target info from QE.
+    pub async fn init_quote(&self) -> Result<QuoteInfo> {
+        self.inner.init_quote().await
+    }
+
+    /// Obtain remote attestation quote from QE.
+    pub async fn get_quote(
+        &self,
+        report: Vec<u8>,
+        spid: Vec<u8>,
+        sig_rl: Vec<u8>,
+        quote_type: QuoteType,
+        nonce: Vec<u8>,
+    ) -> Result<QuoteResult> {
+        check_get_quote_args(&report, &spid, &sig_rl, Some(&nonce))?;
+        self.inner.get_quote(
+            report,
+            spid,
+            sig_rl,
+            quote_type,
+            Some(nonce),
+        ).await
+    }
+
+    /// Obtain target info from QE.
+    ///
+    /// Like `init_quote`, but waits at most `timeout` for AESM to respond,
+    /// instead of the client's default timeout.
+    pub async fn init_quote_with_timeout(&self, timeout: Duration) -> Result<QuoteInfo> {
+        self.inner.with_timeout(timeout)?.init_quote().await
+    }
+
+    /// Obtain remote attestation quote from QE.
+    ///
+    /// Like `get_quote`, but waits at most `timeout` for AESM to respond,
+    /// instead of the client's default timeout.
+    pub async fn get_quote_with_timeout(
+        &self,
+        report: Vec<u8>,
+        spid: Vec<u8>,
+        sig_rl: Vec<u8>,
+        quote_type: QuoteType,
+        nonce: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<QuoteResult> {
+        check_get_quote_args(&report, &spid, &sig_rl, Some(&nonce))?;
+        self.inner.with_timeout(timeout)?.get_quote(
+            report,
+            spid,
+            sig_rl,
+            quote_type,
+            Some(nonce),
+        ).await
+    }
+
+    /// Obtain target info from QE.
+    ///
+    /// Like `init_quote`, but allows specifying the attestation key id.
+    pub async fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfoEx> {
+        self.inner.init_quote_ex(att_key_id).await.map_err(Error::dcap_unavailable)
+    }
+
+    /// Obtain remote attestation quote from QE.
+    ///
+    /// Like `get_quote`, but for the attestation key that `quote_info` was
+    /// obtained for with `init_quote_ex`.
+    ///
+    /// If `target_info` is not supplied, it's determined from `report` so that
+    /// the quote may be verified by the enclave it's for.
+    pub async fn get_quote_ex(
+        &self,
+        quote_info: &QuoteInfoEx,
+        report: Vec<u8>,
+        target_info: Option<Vec<u8>>,
+        nonce: Vec<u8>
+    ) -> Result<QuoteResult> {
+        self.get_quote_ex_from_slices(quote_info, &report, target_info.as_deref(), &nonce).await
+    }
+
+    /// Obtain remote attestation quote from QE.
+    ///
+    /// Like `get_quote_ex`, but borrows its arguments. They're copied into the
+    /// request exactly once.
+    pub async fn get_quote_ex_from_slices(
+        &self,
+        quote_info: &QuoteInfoEx,
+        report: &[u8],
+        target_info: Option<&[u8]>,
+        nonce: &[u8]
+    ) -> Result<QuoteResult> {
+        check_size("report", Report::UNPADDED_SIZE, report.len())?;
+        let default_target_info;
+        let target_info = match target_info {
+            Some(target_info) => target_info,
+            None => {
+                default_target_info = Targetinfo::from(Report::try_copy_from(report).expect("checked above"));
+                default_target_info.as_ref()
+            }
+        };
+        self.inner.get_quote_ex(quote_info, report, target_info, nonce).await.map_err(Error::dcap_unavailable)
     }
 }
 
+/// Tokens are not cached, every call to `token` requests a new token from
+/// AESM. So if the loader retries with `retry` set, e.g. after `EINIT` failed
+/// because the platform's TCB changed, it gets a fresh token.
 #[cfg(feature = "sgxs")]
 impl EinittokenProvider for AesmClient {
     fn token(
@@ -269,22 +1630,25 @@ impl EinittokenProvider for AesmClient {
         attributes: Attributes,
         _retry: bool,
     ) -> StdResult<Einittoken, ::failure::Error> {
-        let token = self.get_launch_token(
+        Ok(self.get_launch_einittoken(
             sigstruct,
             attributes,
-        )?;
-        Einittoken::try_copy_from(&token).ok_or(Error::InvalidTokenSize.into())
+        )?)
     }
 
     fn can_retry(&self) -> bool {
-        false
+        true
     }
 }
 
 trait AesmRequest: protobuf::Message + Into<Request> {
     type Response: protobuf::Message + FromResponse;
 
-    fn get_timeout(&self) -> Option<u32>;
+    const KIND: AesmRequestKind;
+
+    fn set_timeout(&mut self, timeout: u32);
+
+    fn clear_timeout(&mut self);
 }
 
 // This could be replaced with TryFrom when stable.
@@ -292,17 +1656,26 @@ trait FromResponse: Sized {
     fn from_response(res: ProtobufResult<Response>) -> Result<Self>;
 }
 
+// AESM returns the payload of some responses along with an error code, e.g.
+// the update info with `UpdateAvailable`. These codes are listed after the
+// kind, and for them `from_response` returns the body, so that the caller can
+// check the code.
 macro_rules! define_aesm_message {
-    ($request:ident, $response:ident, $set:ident, $has:ident, $take:ident) => {
+    ($request:ident, $response:ident, $set:ident, $has:ident, $take:ident, $kind:ident) => {
+        define_aesm_message!($request, $response, $set, $has, $take, $kind, []);
+    };
+    ($request:ident, $response:ident, $set:ident, $has:ident, $take:ident, $kind:ident, [$($payload_error:ident),*]) => {
         impl AesmRequest for $request {
             type Response = $response;
 
-            fn get_timeout(&self) -> Option<u32> {
-                if self.has_timeout() {
-                    Some(Self::get_timeout(self))
-                } else {
-                    None
-                }
+            const KIND: AesmRequestKind = AesmRequestKind::$kind;
+
+            fn set_timeout(&mut self, timeout: u32) {
+                Self::set_timeout(self, timeout)
+            }
+
+            fn clear_timeout(&mut self) {
+                Self::clear_timeout(self)
             }
         }
         impl From<$request> for Request {
@@ -319,26 +1692,299 @@ macro_rules! define_aesm_message {
                         let body = res.$take();
                         match body.get_errorCode() {
                             AESM_SUCCESS => Ok(body),
+                            $(code if AesmError::from(code) == AesmError::$payload_error => Ok(body),)*
                             code => Err(Error::aesm_code(code)),
                         }
                     }
-                    _ => Err(Error::aesm_bad_response(stringify!($response))),
+                    // Older or newer versions of AESM may return another
+                    // payload, e.g. `unsupportedRequestResponse`. Unknown
+                    // fields are ignored by the decoder.
+                    res => {
+                        if let Err(err) = res {
+                            log::debug!("failed to decode response from AESM: {}", err);
+                        }
+                        Err(Error::aesm_bad_response(&format!("{} payload", &stringify!($take)["take_".len()..])))
+                    }
+                }
+            }
+        }
+    }
+}
+
+define_aesm_message!(Request_GetQuoteRequest,    Response_GetQuoteResponse,    set_getQuoteReq,    has_getQuoteRes,    take_getQuoteRes, GetQuote);
+define_aesm_message!(Request_InitQuoteRequest,   Response_InitQuoteResponse,   set_initQuoteReq,   has_initQuoteRes,   take_initQuoteRes, InitQuote);
+define_aesm_message!(Request_GetLaunchTokenRequest, Response_GetLaunchTokenResponse, set_getLicTokenReq, has_getLicTokenRes, take_getLicTokenRes, GetLaunchToken);
+define_aesm_message!(Request_SGXGetExtendedEpidGroupIdRequest, Response_SGXGetExtendedEpidGroupIdResponse, set_sgxGetExtendedEpidGroupIdReq, has_sgxGetExtendedEpidGroupIdRes, take_sgxGetExtendedEpidGroupIdRes, GetExtendedEpidGroupId);
+define_aesm_message!(Request_CreateSessionRequest, Response_CreateSessionResponse, set_createSessionReq, has_createSessionRes, take_createSessionRes, CreateSession);
+define_aesm_message!(Request_ExchangeReportRequest, Response_ExchangeReportResponse, set_exchangeReportReq, has_exchangeReportRes, take_exchangeReportRes, ExchangeReport);
+define_aesm_message!(Request_InvokeServiceRequest, Response_InvokeServiceResponse, set_invokeServiceReq, has_invokeServiceRes, take_invokeServiceRes, InvokeService);
+define_aesm_message!(Request_CloseSessionRequest, Response_CloseSessionResponse, set_closeSessionReq, has_closeSessionRes, take_closeSessionRes, CloseSession);
+define_aesm_message!(Request_GetPsCapRequest, Response_GetPsCapResponse, set_getPsCapReq, has_getPsCapRes, take_getPsCapRes, GetPsCap);
+define_aesm_message!(Request_GetWhiteListSizeRequest, Response_GetWhiteListSizeResponse, set_getWhiteListSizeReq, has_getWhiteListSizeRes, take_getWhiteListSizeRes, GetWhiteListSize);
+define_aesm_message!(Request_GetWhiteListRequest, Response_GetWhiteListResponse, set_getWhiteListReq, has_getWhiteListRes, take_getWhiteListRes, GetWhiteList);
+define_aesm_message!(Request_ReportAttestationErrorRequest, Response_ReportAttestationErrorResponse, set_reportErrReq, has_reportErrRes, take_reportErrRes, ReportAttestationStatus, [UpdateAvailable_20]);
+define_aesm_message!(Request_SGXRegisterRequest, Response_SGXRegisterResponse, set_sgxRegisterReq, has_sgxRegisterRes, take_sgxRegisterRes, RegisterWhiteList);
+define_aesm_message!(Request_CheckUpdateStatusRequest, Response_CheckUpdateStatusResponse, set_checkUpdateStatusReq, has_checkUpdateStatusRes, take_checkUpdateStatusRes, CheckUpdateStatus, [UpdateAvailable_20]);
+
+define_aesm_message!(Request_GetQuoteExRequest,  Response_GetQuoteExResponse,  set_getQuoteExReq,  has_getQuoteExRes,  take_getQuoteExRes, GetQuoteEx);
+define_aesm_message!(Request_InitQuoteExRequest, Response_InitQuoteExResponse, set_initQuoteExReq, has_initQuoteExRes, take_initQuoteExRes, InitQuoteEx);
+define_aesm_message!(Request_GetQuoteSizeExRequest, Response_GetQuoteSizeExResponse,  set_getQuoteSizeExReq, has_getQuoteSizeExRes, take_getQuoteSizeExRes, GetQuoteSizeEx);
+define_aesm_message!(Request_GetSupportedAttKeyIDNumRequest, Response_GetSupportedAttKeyIDNumResponse, set_getSupportedAttKeyIDNumReq, has_getSupportedAttKeyIDNumRes, take_getSupportedAttKeyIDNumRes, GetSupportedAttKeyIdNum);
+define_aesm_message!(Request_SelectAttKeyIDRequest, Response_SelectAttKeyIDResponse, set_selectAttKeyIDReq, has_selectAttKeyIDRes, take_selectAttKeyIDRes, SelectAttKeyId);
+define_aesm_message!(Request_GetSupportedAttKeyIDsRequest,   Response_GetSupportedAttKeyIDsResponse,   set_getSupportedAttKeyIDsReq,   has_getSupportedAttKeyIDsRes,   take_getSupportedAttKeyIDsRes, GetSupportedAttKeyIds);
+
+
+#[cfg(test)]
+mod quote_buffer_size_tests {
+    use super::*;
+
+    // A sig_rl as returned by IAS: sver, blob ID, gid, RLver, n2, the
+    // entries and an ECDSA signature.
+    fn sig_rl(entries: u32) -> Vec<u8> {
+        let mut sig_rl = vec![0x02, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x0b, 0xad, 0x00, 0x00, 0x00, 0x01];
+        sig_rl.extend_from_slice(&entries.to_be_bytes());
+        sig_rl.extend(vec![0x5a; entries as usize * 128]);
+        sig_rl.extend(vec![0xa5; 64]);
+        sig_rl
+    }
+
+    // The quote size according to sgx_calc_quote_size in the Intel SDK.
+    fn sdk_quote_size(entries: u32) -> u32 {
+        let sign_size = 352 + 4 + 4 + entries * 160;
+        436 + 288 + 12 + 4 + 16 + sign_size
+    }
+
+    #[test]
+    fn exact() {
+        assert_eq!(quote_buffer_size(&[]), sdk_quote_size(0));
+        for &entries in &[0, 1, 2, 1000] {
+            assert_eq!(quote_buffer_size(&sig_rl(entries)), sdk_quote_size(entries));
+        }
+    }
+
+    #[test]
+    fn unparseable() {
+        // Too short for a header.
+        assert!(quote_buffer_size(&[0; 10]) >= sdk_quote_size(0));
+        // Inconsistent entry count.
+        let mut truncated = sig_rl(10);
+        truncated.truncate(16 + 5 * 128 + 64);
+        assert!(quote_buffer_size(&truncated) >= sdk_quote_size(5));
+        let mut overstated = sig_rl(3);
+        overstated[12..16].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(quote_buffer_size(&overstated) >= sdk_quote_size(3));
+    }
+}
+
+#[cfg(all(test, feature = "sgxs"))]
+mod check_attributes_tests {
+    use sgx_isa::AttributesFlags;
+
+    use super::*;
+
+    // A production enclave that must be initialized in 64-bit mode, with
+    // AVX enabled.
+    fn sigstruct() -> Sigstruct {
+        Sigstruct {
+            attributes: Attributes { flags: AttributesFlags::MODE64BIT, xfrm: 0x7 },
+            attributemask: [(AttributesFlags::DEBUG | AttributesFlags::MODE64BIT).bits(), 0x7],
+            ..Default::default()
+        }
+    }
+
+    fn attributes(flags: AttributesFlags, xfrm: u64) -> Attributes {
+        Attributes { flags: flags | AttributesFlags::INIT, xfrm }
+    }
+
+    #[test]
+    fn matching() {
+        check_attributes(&sigstruct(), attributes(AttributesFlags::MODE64BIT, 0x7)).unwrap();
+        // Bits outside the mask may be set.
+        check_attributes(&sigstruct(), attributes(AttributesFlags::MODE64BIT | AttributesFlags::PROVISIONKEY, 0x1f)).unwrap();
+    }
+
+    #[test]
+    fn mismatch() {
+        let allowed = Attributes { flags: AttributesFlags::MODE64BIT, xfrm: 0x7 };
+        let table = [
+            attributes(AttributesFlags::MODE64BIT | AttributesFlags::DEBUG, 0x7),
+            attributes(AttributesFlags::empty(), 0x7),
+            attributes(AttributesFlags::MODE64BIT, 0x3),
+        ];
+        for requested in &table {
+            match check_attributes(&sigstruct(), *requested) {
+                Err(Error::AttributesMismatch { requested: r, allowed: a }) => {
+                    assert_eq!(r, *requested);
+                    assert_eq!(a, allowed);
                 }
+                res => panic!("expected mismatch for {:?}, got {:?}", requested, res),
             }
         }
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn get_launch_token() {
+        let client = AesmClient::builder().socket_path("/nonexistent").build().unwrap();
+        let requested = attributes(AttributesFlags::MODE64BIT | AttributesFlags::DEBUG, 0x7);
+        match client.get_launch_token(&sigstruct(), requested) {
+            Err(Error::AttributesMismatch { .. }) => {}
+            res => panic!("expected mismatch, got {:?}", res),
+        }
+        // The unchecked variant asks AESM, which isn't there.
+        match client.get_launch_token_unchecked(&sigstruct(), requested) {
+            Err(Error::AesmCommunication(_)) => {}
+            res => panic!("expected communication error, got {:?}", res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod quote_info_tests {
+    use super::*;
+
+    fn quote_info(gid: Vec<u8>) -> QuoteInfo {
+        QuoteInfo::new(vec![0; Targetinfo::UNPADDED_SIZE], gid).unwrap()
+    }
+
+    #[test]
+    fn gid() {
+        // AESM returns group ID 0x00000b2f as `2f 0b 00 00`.
+        let info = quote_info(vec![0x2f, 0x0b, 0x00, 0x00]);
+        assert_eq!(info.gid(), [0x00, 0x00, 0x0b, 0x2f]);
+        assert_eq!(info.gid_u32(), 0xb2f);
+        assert_eq!(info.gid_hex(), "00000b2f");
+
+        let info = quote_info(vec![0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(info.gid(), [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(info.gid_u32(), 0x12345678);
+        assert_eq!(info.gid_hex(), "12345678");
+    }
+
+    #[test]
+    fn gid_wrong_size() {
+        assert_eq!(quote_info(vec![]).gid_hex(), "00000000");
+        assert_eq!(quote_info(vec![0x2f, 0x0b]).gid_hex(), "00000b2f");
+        assert_eq!(quote_info(vec![0x2f, 0x0b, 0, 0, 0xff]).gid_u32(), 0xb2f);
+    }
 }
 
-define_aesm_message!(Request_GetQuoteRequest,    Response_GetQuoteResponse,    set_getQuoteReq,    has_getQuoteRes,    take_getQuoteRes);
-define_aesm_message!(Request_InitQuoteRequest,   Response_InitQuoteResponse,   set_initQuoteReq,   has_initQuoteRes,   take_initQuoteRes);
-define_aesm_message!(Request_GetLaunchTokenRequest, Response_GetLaunchTokenResponse, set_getLicTokenReq, has_getLicTokenRes, take_getLicTokenRes);
+#[cfg(test)]
+mod quote_result_tests {
+    use super::*;
 
-define_aesm_message!(Request_GetQuoteExRequest,  Response_GetQuoteExResponse,  set_getQuoteExReq,  has_getQuoteExRes,  take_getQuoteExRes);
-define_aesm_message!(Request_InitQuoteExRequest, Response_InitQuoteExResponse, set_initQuoteExReq, has_initQuoteExRes, take_initQuoteExRes);
-define_aesm_message!(Request_GetQuoteSizeExRequest, Response_GetQuoteSizeExResponse,  set_getQuoteSizeExReq, has_getQuoteSizeExRes, take_getQuoteSizeExRes);
-define_aesm_message!(Request_GetSupportedAttKeyIDNumRequest, Response_GetSupportedAttKeyIDNumResponse, set_getSupportedAttKeyIDNumReq, has_getSupportedAttKeyIDNumRes, take_getSupportedAttKeyIDNumRes);
-define_aesm_message!(Request_GetSupportedAttKeyIDsRequest,   Response_GetSupportedAttKeyIDsResponse,   set_getSupportedAttKeyIDsReq,   has_getSupportedAttKeyIDsRes,   take_getSupportedAttKeyIDsRes);
+    fn quote_result() -> QuoteResult {
+        QuoteResult::new((0..=255).collect::<Vec<u8>>(), (0..=255).rev().collect::<Vec<u8>>())
+    }
 
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn bytes() {
+        let quote = QuoteResult::from_qe_report_info(vec![1; 8192], vec![2; 16 + 512 + 384], 16 + 512);
+        // The accessors share the buffers.
+        assert_eq!(quote.quote_bytes().as_ptr(), quote.quote().as_ptr());
+        assert_eq!(quote.qe_report_bytes().as_ptr(), quote.qe_report().as_ptr());
+        assert_eq!(quote.qe_report_bytes(), vec![2; 384]);
+        assert!(QuoteResult::without_qe_report(vec![1; 16]).qe_report_bytes().is_empty());
+    }
+
+    #[test]
+    fn into_parts() {
+        let (quote, qe_report) = quote_result().into_parts();
+        assert_eq!(quote, quote_result().quote());
+        assert_eq!(qe_report, quote_result().qe_report());
+        assert_eq!(QuoteResult::new(quote, qe_report), quote_result());
+    }
+
+    #[test]
+    fn into_quote() {
+        let result = quote_result();
+        let ptr = result.quote().as_ptr();
+        let quote = result.into_quote();
+        assert_eq!(quote, quote_result().quote());
+        // The buffer is moved, not copied, unless it's shared.
+        #[cfg(not(feature = "bytes"))]
+        assert_eq!(quote.as_ptr(), ptr);
+        #[cfg(feature = "bytes")]
+        let _ = ptr;
+
+        assert_eq!(Vec::from(quote_result()), quote_result().quote());
+        assert_eq!(quote_result().as_ref(), quote_result().quote());
+    }
+}
+
+#[cfg(test)]
+mod response_tests {
+    use super::*;
+    use protobuf::Message;
+
+    // A field number that no version of AESM uses.
+    const UNKNOWN_FIELD: u32 = 1000;
+
+    fn decode<T: FromResponse>(bytes: &[u8]) -> Result<T> {
+        T::from_response(Message::parse_from_bytes(bytes))
+    }
+
+    // For every response: a payload with only the error code, and unknown
+    // fields in it and next to it, is accepted. A response without the
+    // payload names the missing field.
+    macro_rules! check_responses {
+        ($($response:ident, $set:ident, $field:expr;)*) => {
+            $(
+                let mut body = $response::new();
+                body.set_errorCode(0);
+                body.mut_unknown_fields().add_varint(UNKNOWN_FIELD, 1);
+                body.mut_unknown_fields().add_length_delimited(UNKNOWN_FIELD + 1, vec![1, 2, 3]);
+                let mut res = Response::new();
+                res.$set(body);
+                res.mut_unknown_fields().add_fixed32(UNKNOWN_FIELD, 2);
+                let body = decode::<$response>(&res.write_to_bytes().unwrap()).expect(stringify!($response));
+                assert_eq!(body.get_errorCode(), 0);
+
+                let mut res = Response::new();
+                res.mut_unknown_fields().add_varint(UNKNOWN_FIELD, 1);
+                match decode::<$response>(&res.write_to_bytes().unwrap()) {
+                    Err(Error::AesmBadResponse(ref field)) => assert_eq!(field, concat!($field, " payload")),
+                    res => panic!("unexpected result for {}: {:?}", stringify!($response), res),
+                }
+            )*
+        }
+    }
+
+    #[test]
+    fn unknown_and_missing_fields() {
+        check_responses! {
+            Response_GetQuoteResponse, set_getQuoteRes, "getQuoteRes";
+            Response_InitQuoteResponse, set_initQuoteRes, "initQuoteRes";
+            Response_GetLaunchTokenResponse, set_getLicTokenRes, "getLicTokenRes";
+            Response_SGXGetExtendedEpidGroupIdResponse, set_sgxGetExtendedEpidGroupIdRes, "sgxGetExtendedEpidGroupIdRes";
+            Response_CreateSessionResponse, set_createSessionRes, "createSessionRes";
+            Response_ExchangeReportResponse, set_exchangeReportRes, "exchangeReportRes";
+            Response_InvokeServiceResponse, set_invokeServiceRes, "invokeServiceRes";
+            Response_CloseSessionResponse, set_closeSessionRes, "closeSessionRes";
+            Response_GetPsCapResponse, set_getPsCapRes, "getPsCapRes";
+            Response_GetWhiteListSizeResponse, set_getWhiteListSizeRes, "getWhiteListSizeRes";
+            Response_GetWhiteListResponse, set_getWhiteListRes, "getWhiteListRes";
+            Response_ReportAttestationErrorResponse, set_reportErrRes, "reportErrRes";
+            Response_SGXRegisterResponse, set_sgxRegisterRes, "sgxRegisterRes";
+            Response_CheckUpdateStatusResponse, set_checkUpdateStatusRes, "checkUpdateStatusRes";
+            Response_GetQuoteExResponse, set_getQuoteExRes, "getQuoteExRes";
+            Response_InitQuoteExResponse, set_initQuoteExRes, "initQuoteExRes";
+            Response_GetQuoteSizeExResponse, set_getQuoteSizeExRes, "getQuoteSizeExRes";
+            Response_GetSupportedAttKeyIDNumResponse, set_getSupportedAttKeyIDNumRes, "getSupportedAttKeyIDNumRes";
+            Response_SelectAttKeyIDResponse, set_selectAttKeyIDRes, "selectAttKeyIDRes";
+            Response_GetSupportedAttKeyIDsResponse, set_getSupportedAttKeyIDsRes, "getSupportedAttKeyIDsRes";
+        }
+    }
+
+    #[test]
+    fn undecodable_response() {
+        match decode::<Response_InitQuoteResponse>(&[0xff]) {
+            Err(Error::AesmBadResponse(ref field)) => assert_eq!(field, "initQuoteRes payload"),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
 
 #[cfg(all(test, feature = "test-sgx"))]
 mod tests {
@@ -348,9 +1994,6 @@ mod tests {
     use self::sgx_isa::{Report, Targetinfo};
     use super::*;
 
-    const SPID_SIZE: usize = 16;
-    const NONCE_SIZE: usize = 16;
-
     #[test]
     fn test_init_quote() {
         let quote = AesmClient::new().init_quote().unwrap();
@@ -361,6 +2004,16 @@ mod tests {
         assert!(quote.gid().len() != 0);
     }
 
+    #[test]
+    fn test_get_supported_att_key_ids() {
+        // Key IDs are reported the same way on all platforms.
+        let client = AesmClient::new();
+        let num = client.get_supported_att_key_id_num().unwrap();
+        let key_ids = client.get_supported_att_key_ids().unwrap();
+        assert_eq!(key_ids.len(), num as usize);
+        assert_eq!(key_ids.keys().len(), num as usize * AesmKeyId::SIZE);
+    }
+
     #[test]
     fn test_get_quote() {
         // Doing a meaningful test of this requires creating an enclave, this is
@@ -368,17 +2021,13 @@ mod tests {
         // back. The node attest flow in testsetup.sh exercises the real case.
         let client = AesmClient::new();
 
-        let _quote_info = client.init_quote().unwrap();
-
-        let quote = client
-            .get_quote(
-                vec![0u8; Report::UNPADDED_SIZE],
-                vec![0u8; SPID_SIZE],
-                vec![],
-                QuoteType::Linkable,
-                vec![0u8; NONCE_SIZE],
-            )
-            .unwrap_err();
+        let quote_info = client.init_quote().unwrap();
+
+        let req = QuoteRequest::new(&Report::default())
+            .spid([0u8; SPID_SIZE])
+            .quote_type(QuoteType::Linkable)
+            .nonce([0u8; NONCE_SIZE]);
+        let quote = client.get_quote_req(&quote_info, req).unwrap_err();
 
         assert!(if let Error::AesmCode(_) = quote {
             true
@@ -0,0 +1,160 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(not(windows))]
+use std::time::Instant;
+
+use crate::error::AesmError;
+#[cfg(not(windows))]
+use crate::error::{Error, Result};
+
+/// The kind of an AESM request, as reported to the metrics hook.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum AesmRequestKind {
+    InitQuote,
+    GetQuote,
+    GetLaunchToken,
+    GetExtendedEpidGroupId,
+    CreateSession,
+    ExchangeReport,
+    InvokeService,
+    CloseSession,
+    GetPsCap,
+    GetWhiteListSize,
+    GetWhiteList,
+    CheckUpdateStatus,
+    ReportAttestationStatus,
+    RegisterWhiteList,
+    InitQuoteEx,
+    GetQuoteSizeEx,
+    GetQuoteEx,
+    GetSupportedAttKeyIdNum,
+    GetSupportedAttKeyIds,
+    SelectAttKeyId,
+    /// A request sent with `AesmClient::raw_request`.
+    Raw,
+}
+
+impl AesmRequestKind {
+    /// The name of the request kind, e.g. for use as a metrics label.
+    pub fn as_str(&self) -> &'static str {
+        use self::AesmRequestKind::*;
+        match *self {
+            InitQuote => "InitQuote",
+            GetQuote => "GetQuote",
+            GetLaunchToken => "GetLaunchToken",
+            GetExtendedEpidGroupId => "GetExtendedEpidGroupId",
+            CreateSession => "CreateSession",
+            ExchangeReport => "ExchangeReport",
+            InvokeService => "InvokeService",
+            CloseSession => "CloseSession",
+            GetPsCap => "GetPsCap",
+            GetWhiteListSize => "GetWhiteListSize",
+            GetWhiteList => "GetWhiteList",
+            CheckUpdateStatus => "CheckUpdateStatus",
+            ReportAttestationStatus => "ReportAttestationStatus",
+            RegisterWhiteList => "RegisterWhiteList",
+            InitQuoteEx => "InitQuoteEx",
+            GetQuoteSizeEx => "GetQuoteSizeEx",
+            GetQuoteEx => "GetQuoteEx",
+            GetSupportedAttKeyIdNum => "GetSupportedAttKeyIdNum",
+            GetSupportedAttKeyIds => "GetSupportedAttKeyIds",
+            SelectAttKeyId => "SelectAttKeyId",
+            Raw => "Raw",
+        }
+    }
+}
+
+impl fmt::Display for AesmRequestKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How a request to AESM ended.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AesmOutcome {
+    Success,
+    /// AESM responded with an error code.
+    AesmError(AesmError),
+    /// The client couldn't connect to AESM, or didn't get a valid response.
+    TransportError,
+}
+
+impl AesmOutcome {
+    #[cfg(not(windows))]
+    fn of<T>(res: &Result<T>) -> Self {
+        match *res {
+            Ok(_) => AesmOutcome::Success,
+            Err(Error::AesmCode(code)) => AesmOutcome::AesmError(code),
+            Err(_) => AesmOutcome::TransportError,
+        }
+    }
+}
+
+/// A completed request to AESM, as reported to the metrics hook.
+///
+/// Retries are part of the request: `duration` includes them, and `outcome`
+/// is that of the last attempt.
+#[derive(Clone, Debug)]
+pub struct AesmMetric {
+    pub kind: AesmRequestKind,
+    /// From the start of the request, including connecting to AESM, to the
+    /// decoded response.
+    pub duration: Duration,
+    /// The size of the serialized request, including the length prefix. 0 if
+    /// the request failed before it was serialized.
+    pub request_size: usize,
+    /// The size of the last response, without the length prefix. `None` if
+    /// no response was received.
+    pub response_size: Option<usize>,
+    pub outcome: AesmOutcome,
+}
+
+/// A function called after every request to AESM, see
+/// `AesmClientBuilder::metrics_hook`.
+#[derive(Clone)]
+// Windows doesn't support the option, so the hook is never called there.
+#[cfg_attr(windows, allow(dead_code))]
+pub(crate) struct MetricsHook(pub(crate) Arc<dyn Fn(AesmMetric) + Send + Sync>);
+
+impl fmt::Debug for MetricsHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("MetricsHook")
+    }
+}
+
+/// Collects the measurements of a request while it's in progress.
+#[cfg(not(windows))]
+pub(crate) struct Measurement {
+    kind: AesmRequestKind,
+    start: Instant,
+    pub(crate) request_size: usize,
+    pub(crate) response_size: Option<usize>,
+}
+
+#[cfg(not(windows))]
+impl Measurement {
+    pub(crate) fn start(kind: AesmRequestKind) -> Self {
+        Measurement { kind, start: Instant::now(), request_size: 0, response_size: None }
+    }
+
+    /// Report the request to `hook`, if there is one.
+    pub(crate) fn finish<T>(&self, hook: Option<&MetricsHook>, res: &Result<T>) {
+        if let Some(hook) = hook {
+            (hook.0)(AesmMetric {
+                kind: self.kind,
+                duration: self.start.elapsed(),
+                request_size: self.request_size,
+                response_size: self.response_size,
+                outcome: AesmOutcome::of(res),
+            });
+        }
+    }
+}
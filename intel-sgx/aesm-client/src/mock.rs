@@ -0,0 +1,216 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+#[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+use sgx_isa::{Attributes, Einittoken, Sigstruct};
+use sgx_isa::{Report, Targetinfo};
+
+use crate::error::{Error, Result};
+use crate::QuoteInfoEx;
+use crate::{check_get_quote_args, AesmInterface, QuoteInfo, QuoteResult, QuoteType};
+
+/// An `AesmInterface` method, see `MockAesmClient::fail_next`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MockMethod {
+    InitQuote,
+    GetQuote,
+    GetLaunchToken,
+    InitQuoteEx,
+    GetQuoteEx,
+}
+
+/// An `AesmInterface` that returns canned values instead of talking to AESM.
+///
+/// Every successful call returns a copy of the value configured with the
+/// `with_*` methods, or an all-zero value by default. Errors can be scripted
+/// per method with `fail_next`:
+///
+/// ```
+/// # use aesm_client::{AesmError, AesmInterface, Error, MockAesmClient, MockMethod};
+/// let aesm = MockAesmClient::new();
+/// aesm.fail_next(MockMethod::InitQuote, Error::AesmCode(AesmError::Busy_18));
+/// assert!(aesm.init_quote().is_err());
+/// assert!(aesm.init_quote().is_ok());
+/// ```
+///
+/// Like `AesmClient`, `get_quote` checks the sizes of its arguments before
+/// anything else.
+#[derive(Debug)]
+pub struct MockAesmClient {
+    quote_info: QuoteInfo,
+    quote_result: QuoteResult,
+    #[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+    launch_token: Vec<u8>,
+    quote_info_ex: QuoteInfoEx,
+    errors: Mutex<VecDeque<(MockMethod, Error)>>,
+}
+
+impl Default for MockAesmClient {
+    fn default() -> Self {
+        let target_info = vec![0; Targetinfo::UNPADDED_SIZE];
+        MockAesmClient {
+            quote_info: QuoteInfo::new(target_info.clone(), vec![0; 4]).unwrap(),
+            quote_result: QuoteResult::new(vec![], vec![0; Report::UNPADDED_SIZE]),
+            #[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+            launch_token: vec![0; Einittoken::UNPADDED_SIZE],
+            quote_info_ex: QuoteInfoEx::new(vec![], target_info, vec![], 0).unwrap(),
+            errors: Default::default(),
+        }
+    }
+}
+
+impl MockAesmClient {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The value returned by `init_quote`.
+    pub fn with_quote_info(mut self, quote_info: QuoteInfo) -> Self {
+        self.quote_info = quote_info;
+        self
+    }
+
+    /// The value returned by `get_quote` and `get_quote_ex`.
+    pub fn with_quote_result(mut self, quote_result: QuoteResult) -> Self {
+        self.quote_result = quote_result;
+        self
+    }
+
+    /// The value returned by `get_launch_token`.
+    #[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+    pub fn with_launch_token(mut self, launch_token: Vec<u8>) -> Self {
+        self.launch_token = launch_token;
+        self
+    }
+
+    /// The value returned by `init_quote_ex`.
+    pub fn with_quote_info_ex(mut self, quote_info_ex: QuoteInfoEx) -> Self {
+        self.quote_info_ex = quote_info_ex;
+        self
+    }
+
+    /// Make the next call of `method` return `error`. Errors scripted for the
+    /// same method are returned in the order they were added.
+    pub fn fail_next(&self, method: MockMethod, error: Error) {
+        self.errors.lock().unwrap().push_back((method, error));
+    }
+
+    fn call<T: Clone>(&self, method: MockMethod, value: &T) -> Result<T> {
+        let mut errors = self.errors.lock().unwrap();
+        match errors.iter().position(|&(m, _)| m == method) {
+            Some(i) => Err(errors.remove(i).unwrap().1),
+            None => Ok(value.clone()),
+        }
+    }
+}
+
+impl AesmInterface for MockAesmClient {
+    fn init_quote(&self) -> Result<QuoteInfo> {
+        self.call(MockMethod::InitQuote, &self.quote_info)
+    }
+
+    fn get_quote(
+        &self,
+        report: Vec<u8>,
+        spid: Vec<u8>,
+        sig_rl: Vec<u8>,
+        _quote_type: QuoteType,
+        nonce: Vec<u8>,
+    ) -> Result<QuoteResult> {
+        check_get_quote_args(&report, &spid, &sig_rl, Some(&nonce))?;
+        self.call(MockMethod::GetQuote, &self.quote_result)
+    }
+
+    #[cfg(all(not(target_env = "sgx"), feature = "sgxs"))]
+    fn get_launch_token(&self, _sigstruct: &Sigstruct, _attributes: Attributes) -> Result<Vec<u8>> {
+        self.call(MockMethod::GetLaunchToken, &self.launch_token)
+    }
+
+    fn init_quote_ex(&self, _att_key_id: Vec<u8>) -> Result<QuoteInfoEx> {
+        self.call(MockMethod::InitQuoteEx, &self.quote_info_ex)
+    }
+
+    fn get_quote_ex(
+        &self,
+        _quote_info: &QuoteInfoEx,
+        _report: Vec<u8>,
+        _target_info: Option<Vec<u8>>,
+        _nonce: Vec<u8>,
+    ) -> Result<QuoteResult> {
+        self.call(MockMethod::GetQuoteEx, &self.quote_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AesmError;
+
+    // What a downstream attestation flow might look like.
+    fn attest<A: AesmInterface>(aesm: &A) -> Result<Vec<u8>> {
+        let quote_info = aesm.init_quote()?;
+        let report = vec![quote_info.target_info()[0]; Report::UNPADDED_SIZE];
+        let quote = aesm.get_quote(report, vec![0; 16], vec![], QuoteType::Linkable, vec![0; 16])?;
+        Ok(quote.quote().to_owned())
+    }
+
+    #[test]
+    fn canned_values() {
+        let aesm = MockAesmClient::new()
+            .with_quote_info(QuoteInfo::new(vec![1; Targetinfo::UNPADDED_SIZE], vec![2; 4]).unwrap())
+            .with_quote_result(QuoteResult::new(vec![3; 8], vec![4; Report::UNPADDED_SIZE]));
+        assert_eq!(aesm.init_quote().unwrap().pub_key_id(), &[2; 4]);
+        assert_eq!(attest(&aesm).unwrap(), [3; 8]);
+        // Values are returned for every call.
+        assert_eq!(attest(&aesm).unwrap(), [3; 8]);
+    }
+
+    #[test]
+    fn scripted_errors() {
+        let aesm = MockAesmClient::new();
+        aesm.fail_next(MockMethod::GetQuote, Error::AesmCode(AesmError::EpidRevokedError_5));
+        aesm.fail_next(MockMethod::InitQuote, Error::AesmCode(AesmError::Busy_18));
+        aesm.fail_next(MockMethod::GetQuote, Error::AesmCode(AesmError::ServiceUnavailable_30));
+
+        match attest(&aesm) {
+            Err(Error::AesmCode(AesmError::Busy_18)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match attest(&aesm) {
+            Err(Error::AesmCode(AesmError::EpidRevokedError_5)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match attest(&aesm) {
+            Err(Error::AesmCode(AesmError::ServiceUnavailable_30)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(attest(&aesm).is_ok());
+    }
+
+    #[test]
+    fn get_quote_invalid_argument() {
+        let aesm = MockAesmClient::new();
+        match aesm.get_quote(vec![0; 10], vec![0; 16], vec![], QuoteType::Linkable, vec![0; 16]) {
+            Err(Error::InvalidArgument { field: "report", .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn quote_ex() {
+        let quote_info_ex = QuoteInfoEx::new(vec![5; 256], vec![6; Targetinfo::UNPADDED_SIZE], vec![7; 64], 1000).unwrap();
+        let aesm = MockAesmClient::new().with_quote_info_ex(quote_info_ex.clone());
+        aesm.fail_next(MockMethod::GetQuoteEx, Error::AesmCode(AesmError::UnsupportedAttKeyId_38));
+
+        let quote_info = aesm.init_quote_ex(vec![5; 256]).unwrap();
+        assert_eq!(quote_info, quote_info_ex);
+        assert!(aesm.get_quote_ex(&quote_info, vec![], None, vec![]).is_err());
+        assert!(aesm.get_quote_ex(&quote_info, vec![], None, vec![]).is_ok());
+    }
+}
@@ -0,0 +1,357 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::convert::TryInto;
+use std::fmt;
+
+use crate::error::{Error, Result};
+
+// The platform info blob returned by the attestation service is a sequence
+// of TLV entries. See tlv_common.h and platform_info_blob.h in the Intel SDK,
+// and "Platform Info Blob" in the IAS API Spec. An entry starts with its type
+// and version, and then the size of its value as a big-endian word, or as a
+// big-endian dword if the type has the large flag set.
+const TLV_LARGE_FLAG: u8 = 0x80;
+const TLV_HEADER_SIZE: usize = 4;
+const TLV_LARGE_HEADER_SIZE: usize = 6;
+
+// The entry with the platform info proper, `platform_info_blob_t`. All
+// multi-byte fields are big-endian.
+const TLV_PLATFORM_INFO: u8 = 21;
+const PLATFORM_INFO_SIZE: usize = 101;
+const PSVN_SIZE: usize = 18;
+
+// sgx_epid_group_flags
+const QE_EPID_GROUP_REVOKED: u8 = 0x01;
+const PERF_REKEY_FOR_QE_EPID_GROUP_AVAILABLE: u8 = 0x02;
+const QE_EPID_GROUP_OUT_OF_DATE: u8 = 0x04;
+
+// sgx_tcb_evaluation_flags
+const QUOTE_CPUSVN_OUT_OF_DATE: u16 = 0x0001;
+const QUOTE_ISVSVN_QE_OUT_OF_DATE: u16 = 0x0002;
+const QUOTE_ISVSVN_PCE_OUT_OF_DATE: u16 = 0x0004;
+const PLATFORM_CONFIGURATION_NEEDED: u16 = 0x0008;
+
+// pse_evaluation_flags
+const PSE_ISVSVN_OUT_OF_DATE: u16 = 0x0001;
+const EPID_GROUP_ID_BY_PS_HW_GID_REVOKED: u16 = 0x0002;
+const SVN_FROM_PS_HW_SEC_INFO_OUT_OF_DATE: u16 = 0x0004;
+const SIGRL_VER_FROM_PS_HW_SIG_RLVER_OUT_OF_DATE: u16 = 0x0008;
+const PRIVRL_VER_FROM_PS_HW_PRV_KEY_RLVER_OUT_OF_DATE: u16 = 0x0010;
+
+/// An entry of a platform info blob other than the platform info, e.g. one
+/// added by a newer version of the attestation service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlvEntry {
+    entry_type: u8,
+    version: u8,
+    value: Vec<u8>,
+}
+
+impl TlvEntry {
+    /// The type, without the flag for large entries.
+    pub fn entry_type(&self) -> u8 {
+        self.entry_type
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+}
+
+/// The platform info blob that the attestation service includes in its
+/// report when the platform needs attention, e.g. because its TCB is out of
+/// date.
+///
+/// The flags are those of the evaluation by the attestation service. Entries
+/// other than the platform info are kept in `unknown_entries`, and
+/// `as_bytes` returns the blob as it was parsed, so that it can be passed on
+/// unchanged.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PlatformInfo {
+    blob: Vec<u8>,
+    epid_group_flags: u8,
+    tcb_evaluation_flags: u16,
+    pse_evaluation_flags: u16,
+    latest_equivalent_tcb_psvn: [u8; PSVN_SIZE],
+    latest_pse_isvsvn: u16,
+    latest_psda_svn: u32,
+    xeid: u32,
+    gid: u32,
+    unknown_entries: Vec<TlvEntry>,
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes(bytes.try_into().unwrap())
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes.try_into().unwrap())
+}
+
+impl PlatformInfo {
+    /// Parse a platform info blob, as decoded from the hex string in the
+    /// attestation report.
+    ///
+    /// Returns `Error::InvalidPlatformInfo` if the blob isn't a sequence of
+    /// TLV entries, or doesn't contain the platform info. Fields that a
+    /// newer version may have appended to the platform info are ignored.
+    pub fn parse(blob: &[u8]) -> Result<PlatformInfo> {
+        let mut platform_info = None;
+        let mut unknown_entries = vec![];
+        let mut rest = blob;
+        while !rest.is_empty() {
+            let (entry_type, version, value, next) = split_entry(rest)?;
+            if entry_type == TLV_PLATFORM_INFO && platform_info.is_none() {
+                if value.len() < PLATFORM_INFO_SIZE {
+                    return Err(Error::InvalidPlatformInfo("platform info entry is too short"));
+                }
+                platform_info = Some(value);
+            } else {
+                unknown_entries.push(TlvEntry { entry_type, version, value: value.to_vec() });
+            }
+            rest = next;
+        }
+        let info = platform_info.ok_or(Error::InvalidPlatformInfo("no platform info entry"))?;
+
+        // Offsets in `platform_info_blob_t`, after the signature of which
+        // newer versions may append fields.
+        let (flags, info) = info.split_at(5);
+        let (psvn, info) = info.split_at(PSVN_SIZE);
+        Ok(PlatformInfo {
+            blob: blob.to_vec(),
+            epid_group_flags: flags[0],
+            tcb_evaluation_flags: be_u16(&flags[1..3]),
+            pse_evaluation_flags: be_u16(&flags[3..5]),
+            latest_equivalent_tcb_psvn: psvn.try_into().unwrap(),
+            latest_pse_isvsvn: be_u16(&info[0..2]),
+            latest_psda_svn: be_u32(&info[2..6]),
+            xeid: be_u32(&info[6..10]),
+            gid: be_u32(&info[10..14]),
+            unknown_entries,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.blob
+    }
+
+    /// The EPID group ID of the platform.
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// The extended EPID group ID of the platform.
+    pub fn xeid(&self) -> u32 {
+        self.xeid
+    }
+
+    pub fn epid_group_flags(&self) -> u8 {
+        self.epid_group_flags
+    }
+
+    pub fn epid_group_revoked(&self) -> bool {
+        self.epid_group_flags & QE_EPID_GROUP_REVOKED != 0
+    }
+
+    /// A performance rekey is available for the EPID group.
+    pub fn epid_group_rekey_available(&self) -> bool {
+        self.epid_group_flags & PERF_REKEY_FOR_QE_EPID_GROUP_AVAILABLE != 0
+    }
+
+    pub fn epid_group_out_of_date(&self) -> bool {
+        self.epid_group_flags & QE_EPID_GROUP_OUT_OF_DATE != 0
+    }
+
+    pub fn tcb_evaluation_flags(&self) -> u16 {
+        self.tcb_evaluation_flags
+    }
+
+    /// The CPU microcode is out of date.
+    pub fn cpu_svn_out_of_date(&self) -> bool {
+        self.tcb_evaluation_flags & QUOTE_CPUSVN_OUT_OF_DATE != 0
+    }
+
+    /// The quoting enclave is out of date.
+    pub fn qe_out_of_date(&self) -> bool {
+        self.tcb_evaluation_flags & QUOTE_ISVSVN_QE_OUT_OF_DATE != 0
+    }
+
+    /// The provisioning certification enclave is out of date.
+    pub fn pce_out_of_date(&self) -> bool {
+        self.tcb_evaluation_flags & QUOTE_ISVSVN_PCE_OUT_OF_DATE != 0
+    }
+
+    /// The platform needs to be configured, e.g. in the BIOS, to be up to
+    /// date.
+    pub fn configuration_needed(&self) -> bool {
+        self.tcb_evaluation_flags & PLATFORM_CONFIGURATION_NEEDED != 0
+    }
+
+    /// The latest TCB that's equivalent to the platform's: the CPU SVN
+    /// followed by the SVN of the QE, as `psvn_t` in the Intel SDK.
+    pub fn latest_equivalent_tcb_psvn(&self) -> &[u8; PSVN_SIZE] {
+        &self.latest_equivalent_tcb_psvn
+    }
+
+    /// Flags of the evaluation of the platform services enclave, which only
+    /// platforms with platform services report.
+    pub fn pse_evaluation_flags(&self) -> u16 {
+        self.pse_evaluation_flags
+    }
+
+    pub fn pse_out_of_date(&self) -> bool {
+        self.pse_evaluation_flags & PSE_ISVSVN_OUT_OF_DATE != 0
+    }
+
+    /// The EPID group of the platform services hardware is revoked.
+    pub fn pse_epid_group_revoked(&self) -> bool {
+        self.pse_evaluation_flags & EPID_GROUP_ID_BY_PS_HW_GID_REVOKED != 0
+    }
+
+    /// The platform services hardware, or one of its revocation lists, is out
+    /// of date.
+    pub fn pse_hardware_out_of_date(&self) -> bool {
+        const OUT_OF_DATE: u16 = SVN_FROM_PS_HW_SEC_INFO_OUT_OF_DATE
+            | SIGRL_VER_FROM_PS_HW_SIG_RLVER_OUT_OF_DATE
+            | PRIVRL_VER_FROM_PS_HW_PRV_KEY_RLVER_OUT_OF_DATE;
+        self.pse_evaluation_flags & OUT_OF_DATE != 0
+    }
+
+    pub fn latest_pse_isvsvn(&self) -> u16 {
+        self.latest_pse_isvsvn
+    }
+
+    pub fn latest_psda_svn(&self) -> u32 {
+        self.latest_psda_svn
+    }
+
+    /// Entries of the blob other than the platform info, in order.
+    pub fn unknown_entries(&self) -> &[TlvEntry] {
+        &self.unknown_entries
+    }
+}
+
+/// Split the first TLV entry off `blob`, returning its type, version and
+/// value, and the rest of `blob`.
+fn split_entry(blob: &[u8]) -> Result<(u8, u8, &[u8], &[u8])> {
+    let truncated = || Error::InvalidPlatformInfo("truncated TLV entry");
+    let (header_size, size) = if blob[0] & TLV_LARGE_FLAG == 0 {
+        let size = blob.get(2..TLV_HEADER_SIZE).ok_or_else(truncated)?;
+        (TLV_HEADER_SIZE, be_u16(size) as usize)
+    } else {
+        let size = blob.get(2..TLV_LARGE_HEADER_SIZE).ok_or_else(truncated)?;
+        (TLV_LARGE_HEADER_SIZE, be_u32(size) as usize)
+    };
+    let end = header_size.checked_add(size).filter(|&end| end <= blob.len()).ok_or_else(truncated)?;
+    Ok((blob[0] & !TLV_LARGE_FLAG, blob[1], &blob[header_size..end], &blob[end..]))
+}
+
+impl fmt::Debug for PlatformInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PlatformInfo")
+            .field("gid", &format_args!("{:08x}", self.gid))
+            .field("epid_group_flags", &format_args!("{:#04x}", self.epid_group_flags))
+            .field("tcb_evaluation_flags", &format_args!("{:#06x}", self.tcb_evaluation_flags))
+            .field("pse_evaluation_flags", &format_args!("{:#06x}", self.pse_evaluation_flags))
+            .field("unknown_entries", &self.unknown_entries.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shaped like the platformInfoBlob of an attestation report of a
+    // platform whose microcode and configuration are out of date: an EPID
+    // group that is out of date, CPU SVN out of date and configuration
+    // needed, and no platform services.
+    const BLOB: &str = concat!(
+        "15020065",
+        "04", "0009", "0000",
+        "0f0f0205ff8000000000000000000000", "000b",
+        "0000", "00000000",
+        "00000000", "00000b2f",
+        "ba5e0bad3d2f6a92c08d8f11e7a3ad35c40ed5f0e8c85b1f1b6a3c06f0c2d9a1",
+        "4b0e42c7e2a8f3c96d1a5e7b0c4f9d2e8a6b3c1f0e9d8c7b6a5f4e3d2c1b0a99",
+    );
+
+    fn blob() -> Vec<u8> {
+        (0..BLOB.len()).step_by(2).map(|i| u8::from_str_radix(&BLOB[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn assert_invalid(blob: &[u8], reason: &str) {
+        match PlatformInfo::parse(blob) {
+            Err(Error::InvalidPlatformInfo(r)) if r == reason => {}
+            res => panic!("expected {:?}, got {:?}", reason, res),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let blob = blob();
+        let info = PlatformInfo::parse(&blob).unwrap();
+        assert_eq!(info.as_bytes(), &blob[..]);
+        assert_eq!((info.gid(), info.xeid()), (0xb2f, 0));
+        assert!(info.epid_group_out_of_date() && !info.epid_group_revoked() && !info.epid_group_rekey_available());
+        assert!(info.cpu_svn_out_of_date() && info.configuration_needed());
+        assert!(!info.qe_out_of_date() && !info.pce_out_of_date());
+        assert_eq!(info.latest_equivalent_tcb_psvn()[..4], [0x0f, 0x0f, 0x02, 0x05]);
+        assert_eq!(info.latest_equivalent_tcb_psvn()[16..], [0x00, 0x0b]);
+        assert!(!info.pse_out_of_date() && !info.pse_epid_group_revoked() && !info.pse_hardware_out_of_date());
+        assert_eq!((info.latest_pse_isvsvn(), info.latest_psda_svn()), (0, 0));
+        assert!(info.unknown_entries().is_empty());
+        assert_eq!(
+            format!("{:?}", info),
+            "PlatformInfo { gid: 00000b2f, epid_group_flags: 0x04, tcb_evaluation_flags: 0x0009, \
+             pse_evaluation_flags: 0x0000, unknown_entries: 0 }"
+        );
+    }
+
+    #[test]
+    fn pse_flags() {
+        let mut blob = blob();
+        blob[8] = 0x13;
+        let info = PlatformInfo::parse(&blob).unwrap();
+        assert!(info.pse_out_of_date() && info.pse_epid_group_revoked() && info.pse_hardware_out_of_date());
+    }
+
+    #[test]
+    fn unknown_entries() {
+        // An unknown entry before the platform info, a large one after it,
+        // and fields appended to the platform info by a newer version.
+        let mut blob = vec![0x30, 0x01, 0x00, 0x02, 0xaa, 0xbb];
+        let mut platform_info = self::blob();
+        platform_info[3] += 3;
+        platform_info.extend(&[1, 2, 3]);
+        blob.extend(platform_info);
+        blob.extend(&[0x80 | 0x31, 0x01, 0x00, 0x00, 0x00, 0x01, 0xcc]);
+
+        let info = PlatformInfo::parse(&blob).unwrap();
+        assert_eq!(info.gid(), 0xb2f);
+        assert_eq!(info.as_bytes(), &blob[..]);
+        let entries = info.unknown_entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].entry_type(), entries[0].version(), entries[0].value()), (0x30, 1, &[0xaa, 0xbb][..]));
+        assert_eq!((entries[1].entry_type(), entries[1].version(), entries[1].value()), (0x31, 1, &[0xcc][..]));
+    }
+
+    #[test]
+    fn invalid() {
+        let blob = blob();
+        assert_invalid(&[], "no platform info entry");
+        assert_invalid(&[0x30, 0x01, 0x00, 0x00], "no platform info entry");
+        assert_invalid(&blob[..3], "truncated TLV entry");
+        assert_invalid(&blob[..blob.len() - 1], "truncated TLV entry");
+        assert_invalid(&[0x80 | 0x30, 0x01, 0xff, 0xff, 0xff, 0xff], "truncated TLV entry");
+        assert_invalid(&[0x15, 0x02, 0x00, 0x01, 0x00], "platform info entry is too short");
+    }
+}
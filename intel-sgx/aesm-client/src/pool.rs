@@ -0,0 +1,270 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::error::Result;
+use crate::{AesmClient, AesmKeyIds, QuoteInfo, QuoteInfoEx, QuoteRequest, QuoteResult, QuoteType, Spid};
+
+/// A fixed number of clients with independent connections to AESM, so that
+/// requests from several threads are processed in parallel.
+///
+/// A client is checked out for every request and checked back in when the
+/// request completes. If all clients are in use, `checkout` waits for one to
+/// be checked in. Connections are only opened when first used, and a
+/// connection on which a request failed is closed and reopened by the next
+/// request, like with `AesmClient`.
+///
+/// Clones of the pool share its clients.
+#[derive(Clone)]
+pub struct AesmClientPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    idle: Mutex<Vec<AesmClient>>,
+    checked_in: Condvar,
+    size: usize,
+}
+
+impl AesmClientPool {
+    /// A pool of `size` clients with the default options.
+    ///
+    /// Panics if `size` is 0.
+    pub fn new(size: usize) -> Self {
+        Self::with_client(&AesmClient::new(), size)
+    }
+
+    /// A pool of `size` clients with the same options as `client`, e.g. one
+    /// built with `AesmClient::builder`. The clients share the cached quote
+    /// info of `client`, but not its connection.
+    ///
+    /// Panics if `size` is 0.
+    pub fn with_client(client: &AesmClient, size: usize) -> Self {
+        assert!(size > 0, "AesmClientPool needs at least one client");
+        let idle = (0..size)
+            .map(|_| AesmClient { inner: client.inner.with_new_connection(), quote_info: client.quote_info.clone() })
+            .collect();
+        AesmClientPool {
+            inner: Arc::new(Inner { idle: Mutex::new(idle), checked_in: Condvar::new(), size }),
+        }
+    }
+
+    /// The number of clients in the pool.
+    pub fn size(&self) -> usize {
+        self.inner.size
+    }
+
+    /// Check out a client, waiting for one to be checked in if all are in
+    /// use. The client is checked back in when the guard is dropped.
+    pub fn checkout(&self) -> PooledClient<'_> {
+        let mut idle = self.inner.idle.lock().unwrap();
+        loop {
+            if let Some(client) = idle.pop() {
+                return PooledClient { pool: &self.inner, client: Some(client) };
+            }
+            idle = self.inner.checked_in.wait(idle).unwrap();
+        }
+    }
+
+    /// Check out a client if one isn't in use.
+    pub fn try_checkout(&self) -> Option<PooledClient<'_>> {
+        let client = self.inner.idle.lock().unwrap().pop()?;
+        Some(PooledClient { pool: &self.inner, client: Some(client) })
+    }
+
+    /// See `AesmClient::init_quote`.
+    pub fn init_quote(&self) -> Result<QuoteInfo> {
+        self.checkout().init_quote()
+    }
+
+    /// See `AesmClient::get_quote`.
+    #[deprecated(note = "use `get_quote_req`, which can't be passed the arguments in the wrong order")]
+    #[allow(deprecated)]
+    pub fn get_quote(
+        &self,
+        report: Vec<u8>,
+        spid: Vec<u8>,
+        sig_rl: Vec<u8>,
+        quote_type: QuoteType,
+        nonce: Vec<u8>,
+    ) -> Result<QuoteResult> {
+        self.checkout().get_quote(report, spid, sig_rl, quote_type, nonce)
+    }
+
+    /// See `AesmClient::get_quote_req`.
+    pub fn get_quote_req(&self, quote_info: &QuoteInfo, req: QuoteRequest<Spid, QuoteType>) -> Result<QuoteResult> {
+        self.checkout().get_quote_req(quote_info, req)
+    }
+
+    /// See `AesmClient::get_quote_from_slices`.
+    pub fn get_quote_from_slices(
+        &self,
+        report: &[u8],
+        spid: &[u8],
+        sig_rl: &[u8],
+        quote_type: QuoteType,
+        nonce: &[u8],
+    ) -> Result<QuoteResult> {
+        self.checkout().get_quote_from_slices(report, spid, sig_rl, quote_type, nonce)
+    }
+
+    /// See `AesmClient::get_supported_att_key_ids`.
+    pub fn get_supported_att_key_ids(&self) -> Result<AesmKeyIds> {
+        self.checkout().get_supported_att_key_ids()
+    }
+
+    /// See `AesmClient::init_quote_ex`.
+    pub fn init_quote_ex(&self, att_key_id: Vec<u8>) -> Result<QuoteInfoEx> {
+        self.checkout().init_quote_ex(att_key_id)
+    }
+
+    /// See `AesmClient::get_quote_ex`.
+    pub fn get_quote_ex(
+        &self,
+        quote_info: &QuoteInfoEx,
+        report: Vec<u8>,
+        target_info: Option<Vec<u8>>,
+        nonce: Vec<u8>,
+    ) -> Result<QuoteResult> {
+        self.checkout().get_quote_ex(quote_info, report, target_info, nonce)
+    }
+}
+
+impl fmt::Debug for AesmClientPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AesmClientPool")
+            .field("size", &self.inner.size)
+            .field("idle", &self.inner.idle.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// A client checked out of an `AesmClientPool`, see
+/// `AesmClientPool::checkout`.
+pub struct PooledClient<'a> {
+    pool: &'a Inner,
+    // Only `None` while being dropped.
+    client: Option<AesmClient>,
+}
+
+impl<'a> Deref for PooledClient<'a> {
+    type Target = AesmClient;
+
+    fn deref(&self) -> &AesmClient {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledClient<'a> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.idle.lock().unwrap().push(client);
+            self.pool.checked_in.notify_one();
+        }
+    }
+}
+
+impl<'a> fmt::Debug for PooledClient<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PooledClient").field(&**self).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::test_support::MockAesm;
+    use crate::{AesmClient, Response, Response_InitQuoteResponse};
+    use super::AesmClientPool;
+
+    fn init_quote_response() -> Option<Response> {
+        let mut body = Response_InitQuoteResponse::new();
+        body.set_targetInfo(vec![0; sgx_isa::Targetinfo::UNPADDED_SIZE]);
+        MockAesm::response(0, body)
+    }
+
+    #[test]
+    fn parallel_requests() {
+        const SIZE: usize = 4;
+
+        // Every request waits until all of them have arrived, which only
+        // happens if they're sent on separate connections at once.
+        let in_flight = Arc::new((Mutex::new(0), Condvar::new()));
+        let counter = in_flight.clone();
+        let mock = MockAesm::start(move |_| {
+            let (ref count, ref arrived) = *counter;
+            let mut count = count.lock().unwrap();
+            *count += 1;
+            arrived.notify_all();
+            let (count, timeout) = arrived.wait_timeout_while(count, Duration::from_secs(10), |count| *count < SIZE).unwrap();
+            assert!(!timeout.timed_out(), "only {} requests in parallel", *count);
+            init_quote_response()
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let pool = AesmClientPool::with_client(&client, SIZE);
+        let threads = (0..SIZE)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || pool.init_quote().unwrap())
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(mock.connections(), SIZE);
+
+        // The connections are reused.
+        for _ in 0..SIZE {
+            pool.init_quote().unwrap();
+        }
+        assert_eq!(mock.connections(), SIZE);
+    }
+
+    #[test]
+    fn checkout() {
+        let mock = MockAesm::start(|_| init_quote_response());
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let pool = AesmClientPool::with_client(&client, 2);
+
+        let first = pool.checkout();
+        let second = pool.try_checkout().unwrap();
+        assert!(pool.try_checkout().is_none());
+
+        // Waits for a client to be checked in.
+        let waiter = {
+            let pool = pool.clone();
+            thread::spawn(move || pool.init_quote().unwrap())
+        };
+        thread::sleep(Duration::from_millis(50));
+        first.init_quote().unwrap();
+        drop(first);
+        waiter.join().unwrap();
+
+        second.init_quote().unwrap();
+        drop(second);
+        assert!(pool.try_checkout().is_some());
+    }
+
+    #[test]
+    fn reconnect() {
+        // Every connection is closed after a request, so every connection
+        // kept by the pool turns out to be broken.
+        let mock = MockAesm::start_one_request_per_connection(|_| init_quote_response());
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let pool = AesmClientPool::with_client(&client, 1);
+        for _ in 0..3 {
+            pool.init_quote().unwrap();
+        }
+        assert_eq!(mock.connections(), 3);
+    }
+}
@@ -0,0 +1,130 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::error::{AesmError, Error, Result};
+
+// Bits of `sgx_ps_cap_t`, see sgx_uae_service.h in the Intel SDK. The bitmask
+// AESM returns has `ps_cap0` in its lower and `ps_cap1` in its upper half.
+const PS_CAP_TRUSTED_TIME: u64 = 0x1;
+const PS_CAP_MONOTONIC_COUNTER: u64 = 0x2;
+
+/// The platform services capabilities, as returned by
+/// `AesmClient::pse_capabilities`.
+///
+/// Platform services were removed from recent platforms. There, AESM doesn't
+/// support the request, which is reported as `PsCap::unsupported()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PsCap {
+    bits: u64,
+    supported: bool,
+}
+
+impl PsCap {
+    /// The capabilities in the bitmask `bits`, as returned by
+    /// `AesmClient::get_ps_cap`.
+    pub fn from_bits(bits: u64) -> PsCap {
+        PsCap { bits, supported: true }
+    }
+
+    /// The capabilities of a platform without platform services.
+    pub fn unsupported() -> PsCap {
+        PsCap { bits: 0, supported: false }
+    }
+
+    /// Whether AESM supports platform services at all.
+    pub fn is_supported(&self) -> bool {
+        self.supported
+    }
+
+    /// The raw bitmask, 0 if platform services aren't supported.
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    /// Whether the trusted time service is available.
+    pub fn trusted_time(&self) -> bool {
+        self.bits & PS_CAP_TRUSTED_TIME != 0
+    }
+
+    /// Whether monotonic counters are available.
+    pub fn monotonic_counter(&self) -> bool {
+        self.bits & PS_CAP_MONOTONIC_COUNTER != 0
+    }
+}
+
+/// Maps the errors of AESM without platform services to
+/// `PsCap::unsupported()`: it either reports that the PSE isn't available,
+/// or doesn't know the request at all.
+pub(crate) fn from_result(res: Result<u64>) -> Result<PsCap> {
+    match res {
+        Ok(bits) => Ok(PsCap::from_bits(bits)),
+        Err(Error::AesmCode(AesmError::PsdaUnavailable_9)) | Err(Error::AesmCode(AesmError::ServiceNotAvailable_27))
+            | Err(Error::AesmBadResponse(_)) => Ok(PsCap::unsupported()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::test_support::MockAesm;
+    #[cfg(unix)]
+    use crate::{AesmClient, Response_GetPsCapResponse, Response_UnsupportedRequestResponse};
+
+    #[test]
+    fn bits() {
+        let table = [(0, false, false), (0x1, true, false), (0x2, false, true), (0x3, true, true), (0x1_0000_0002, false, true)];
+        for &(bits, trusted_time, monotonic_counter) in &table {
+            let cap = PsCap::from_bits(bits);
+            assert_eq!((cap.trusted_time(), cap.monotonic_counter()), (trusted_time, monotonic_counter), "{:#x}", bits);
+            assert_eq!(cap.bits(), bits);
+            assert!(cap.is_supported());
+        }
+        let unsupported = PsCap::unsupported();
+        assert!(!unsupported.is_supported() && !unsupported.trusted_time() && !unsupported.monotonic_counter());
+        assert_ne!(unsupported, PsCap::from_bits(0));
+    }
+
+    #[test]
+    fn from_result() {
+        assert_eq!(super::from_result(Ok(3)).unwrap(), PsCap::from_bits(3));
+        for &code in &[9, 27] {
+            assert_eq!(super::from_result(Err(Error::aesm_code(code))).unwrap(), PsCap::unsupported());
+        }
+        let err = Error::aesm_bad_response("Response_GetPsCapResponse");
+        assert_eq!(super::from_result(Err(err)).unwrap(), PsCap::unsupported());
+        assert!(matches!(super::from_result(Err(Error::aesm_code(1))), Err(Error::AesmCode(AesmError::UnexpectedError_1))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn get_ps_cap() {
+        let mock = MockAesm::start(|_| {
+            let mut body = Response_GetPsCapResponse::new();
+            body.set_ps_cap(0x2);
+            MockAesm::response(0, body)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        assert_eq!(client.get_ps_cap().unwrap(), 0x2);
+        let cap = client.pse_capabilities().unwrap();
+        assert!(cap.is_supported() && cap.monotonic_counter() && !cap.trusted_time());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn get_ps_cap_unsupported() {
+        // Like an AESM that doesn't know the request.
+        let mock = MockAesm::start(|_| {
+            MockAesm::error_code::<Response_UnsupportedRequestResponse>(1)
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        assert!(matches!(client.get_ps_cap(), Err(Error::AesmBadResponse(_))));
+        assert_eq!(client.pse_capabilities().unwrap(), PsCap::unsupported());
+    }
+}
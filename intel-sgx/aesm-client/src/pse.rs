@@ -0,0 +1,206 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+
+use crate::error::{Error, Result};
+use crate::hex::HexBytes;
+use crate::imp;
+
+// Sizes of the DH messages, `sgx_dh_msg1_t` and `sgx_dh_msg3_t` in the Intel
+// SDK. The third message has no additional properties.
+pub(crate) const DH_MSG1_SIZE: u32 = 64 + 512;
+pub(crate) const DH_MSG3_SIZE: u32 = 16 + 432 + 4;
+
+/// The size of the buffer for the response to `PseSession::invoke`. The
+/// responses of the platform services are much smaller.
+pub const DEFAULT_PSE_RESPONSE_SIZE: u32 = 4096;
+
+/// A session with the platform services enclave (PSE), as created by
+/// `AesmClient::create_pse_session`.
+///
+/// The session is established by sending the second DH message, which the
+/// enclave derives from `dh_msg1`, with `exchange_report`. Afterwards,
+/// messages for the platform services can be sent with `invoke`. The
+/// session is closed with `close`, or when it's dropped.
+///
+/// If a request fails, the session may be out of sync with the PSE, so it
+/// becomes unusable: later requests return `Error::PseSessionUnusable`. It
+/// can still be closed.
+pub struct PseSession {
+    client: imp::AesmClient,
+    session_id: u32,
+    dh_msg1: Vec<u8>,
+    usable: bool,
+    closed: bool,
+}
+
+impl PseSession {
+    pub(crate) fn new(client: imp::AesmClient, session_id: u32, dh_msg1: Vec<u8>) -> PseSession {
+        PseSession { client, session_id, dh_msg1, usable: true, closed: false }
+    }
+
+    /// The ID of the session, as assigned by AESM.
+    pub fn session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    /// The first DH message, an `sgx_dh_msg1_t` from the PSE.
+    pub fn dh_msg1(&self) -> &[u8] {
+        &self.dh_msg1
+    }
+
+    /// Whether requests can still be sent in this session.
+    pub fn is_usable(&self) -> bool {
+        self.usable
+    }
+
+    fn request<T, F: FnOnce(&imp::AesmClient, u32) -> Result<T>>(&mut self, f: F) -> Result<T> {
+        if !self.usable {
+            return Err(Error::PseSessionUnusable);
+        }
+        let res = f(&self.client, self.session_id);
+        if res.is_err() {
+            self.usable = false;
+        }
+        res
+    }
+
+    /// Send the second DH message `dh_msg2`, an `sgx_dh_msg2_t`, and return
+    /// the third one, an `sgx_dh_msg3_t`, for the enclave to verify.
+    pub fn exchange_report(&mut self, dh_msg2: &[u8]) -> Result<Vec<u8>> {
+        self.request(|client, session_id| client.exchange_report(session_id, dh_msg2.to_vec()))
+    }
+
+    /// Send the encrypted message `payload` to the platform services and
+    /// return their encrypted response, of at most
+    /// `DEFAULT_PSE_RESPONSE_SIZE` bytes.
+    pub fn invoke(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        self.invoke_with_response_size(payload, DEFAULT_PSE_RESPONSE_SIZE)
+    }
+
+    /// Like `invoke`, for responses of at most `response_size` bytes.
+    pub fn invoke_with_response_size(&mut self, payload: &[u8], response_size: u32) -> Result<Vec<u8>> {
+        self.request(|client, _| client.invoke_service(payload.to_vec(), response_size))
+    }
+
+    /// Close the session. Dropping the session closes it too, but ignores
+    /// errors.
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.client.close_session(self.session_id)
+    }
+}
+
+impl Drop for PseSession {
+    fn drop(&mut self) {
+        if !self.closed {
+            if let Err(err) = self.client.close_session(self.session_id) {
+                log::warn!("failed to close PSE session {}: {}", self.session_id, err);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PseSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PseSession")
+            .field("session_id", &self.session_id)
+            .field("dh_msg1", &HexBytes(&self.dh_msg1))
+            .field("usable", &self.usable)
+            .finish()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_support::MockAesm;
+    use crate::{
+        AesmClient, AesmError, Response_CloseSessionResponse, Response_CreateSessionResponse, Response_ExchangeReportResponse,
+        Response_InvokeServiceResponse,
+    };
+
+    /// Scripts the PSE side of a session with ID 7, logging the requests.
+    fn start_pse_mock(log: Arc<Mutex<Vec<&'static str>>>) -> MockAesm {
+        MockAesm::start(move |req| {
+            if req.has_createSessionReq() {
+                log.lock().unwrap().push("create");
+                assert_eq!(req.get_createSessionReq().get_dh_msg1_size(), 576);
+                let mut body = Response_CreateSessionResponse::new();
+                body.set_session_id(7);
+                body.set_se_dh_msg1(vec![1; 576]);
+                MockAesm::response(0, body)
+            } else if req.has_exchangeReportReq() {
+                log.lock().unwrap().push("exchange");
+                let req = req.get_exchangeReportReq();
+                assert_eq!((req.get_session_id(), req.get_se_dh_msg2(), req.get_se_dh_msg3_size()), (7, &[2; 512][..], 452));
+                let mut body = Response_ExchangeReportResponse::new();
+                body.set_se_dh_msg3(vec![3; 452]);
+                MockAesm::response(0, body)
+            } else if req.has_invokeServiceReq() {
+                log.lock().unwrap().push("invoke");
+                // An empty message fails, like one the PSE can't decrypt.
+                if req.get_invokeServiceReq().get_pse_message().is_empty() {
+                    MockAesm::error_code::<Response_InvokeServiceResponse>(7)
+                } else {
+                    assert_eq!(req.get_invokeServiceReq().get_pse_resp_size(), 4096);
+                    let mut body = Response_InvokeServiceResponse::new();
+                    body.set_pse_message(vec![5; 32]);
+                    MockAesm::response(0, body)
+                }
+            } else {
+                log.lock().unwrap().push("close");
+                assert_eq!(req.get_closeSessionReq().get_session_id(), 7);
+                MockAesm::error_code::<Response_CloseSessionResponse>(0)
+            }
+        })
+    }
+
+    #[test]
+    fn pse_session() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let mock = start_pse_mock(log.clone());
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+
+        let mut session = client.create_pse_session().unwrap();
+        assert_eq!(session.session_id(), 7);
+        assert_eq!(session.dh_msg1(), &[1; 576][..]);
+        assert_eq!(session.exchange_report(&[2; 512]).unwrap(), vec![3; 452]);
+        assert_eq!(session.invoke(&[4; 64]).unwrap(), vec![5; 32]);
+        assert!(session.is_usable());
+        session.close().unwrap();
+        assert_eq!(*log.lock().unwrap(), ["create", "exchange", "invoke", "close"]);
+
+        // Dropping the session closes it too.
+        log.lock().unwrap().clear();
+        drop(client.create_pse_session().unwrap());
+        assert_eq!(*log.lock().unwrap(), ["create", "close"]);
+    }
+
+    #[test]
+    fn pse_session_unusable() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let mock = start_pse_mock(log.clone());
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+
+        let mut session = client.create_pse_session().unwrap();
+        session.exchange_report(&[2; 512]).unwrap();
+        match session.invoke(&[]) {
+            Err(Error::AesmCode(AesmError::SessionInvalid_7)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(!session.is_usable());
+        match session.invoke(&[4; 64]) {
+            Err(Error::PseSessionUnusable) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        session.close().unwrap();
+        assert_eq!(*log.lock().unwrap(), ["create", "exchange", "invoke", "close"]);
+    }
+}
@@ -0,0 +1,629 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::convert::TryInto;
+use std::fmt;
+
+use sgx_isa::Report;
+
+use crate::error::{Error, Result};
+use crate::ct::ct_eq;
+use crate::hex::{HexBytes, Redacted};
+use crate::{QuoteResult, QuoteType, Spid, QUOTE_SIGNATURE_LEN_OFFSET, QUOTE_SIGNATURE_OFFSET, SPID_SIZE};
+
+// Offsets in `sgx_quote_t`, see sgx_quote.h in the Intel SDK.
+const VERSION_OFFSET: usize = 0;
+const SIGN_TYPE_OFFSET: usize = 2;
+const EPID_GROUP_ID_OFFSET: usize = 4;
+const QE_SVN_OFFSET: usize = 8;
+const PCE_SVN_OFFSET: usize = 10;
+const BASENAME_OFFSET: usize = 16;
+const REPORT_BODY_OFFSET: usize = 48;
+// `sgx_report_body_t` is a `Report` without the KEYID and MAC fields.
+const REPORT_BODY_SIZE: usize = QUOTE_SIGNATURE_LEN_OFFSET - REPORT_BODY_OFFSET;
+// REPORTDATA in the report body. ECDSA quotes have a header of the same size,
+// so it's at the same offset in them.
+#[cfg(feature = "verify")]
+pub(crate) const REPORT_DATA_OFFSET: usize = REPORT_BODY_OFFSET + 320;
+
+/// An EPID quote, as returned by `AesmClient::get_quote`.
+///
+/// The quote is checked for consistency when it's parsed, so the accessors
+/// don't fail.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EpidQuote {
+    quote: Vec<u8>,
+}
+
+impl EpidQuote {
+    /// Parse `quote`. Returns `Error::InvalidQuoteSize` if `quote` is
+    /// truncated or its size doesn't match its signature length, and
+    /// `Error::InvalidQuoteType` if it has an unknown signature type.
+    pub fn parse(quote: &[u8]) -> Result<EpidQuote> {
+        let sig_len = quote.get(QUOTE_SIGNATURE_LEN_OFFSET..QUOTE_SIGNATURE_OFFSET)
+            .ok_or(Error::InvalidQuoteSize)?;
+        let sig_len = u32::from_le_bytes(sig_len.try_into().unwrap()) as usize;
+        if quote.len() - QUOTE_SIGNATURE_OFFSET != sig_len {
+            return Err(Error::InvalidQuoteSize);
+        }
+        let quote = EpidQuote { quote: quote.to_owned() };
+        QuoteType::from_u32(quote.u16_at(SIGN_TYPE_OFFSET).into())?;
+        Ok(quote)
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.quote[offset], self.quote[offset + 1]])
+    }
+
+    pub fn version(&self) -> u16 {
+        self.u16_at(VERSION_OFFSET)
+    }
+
+    pub fn sign_type(&self) -> QuoteType {
+        QuoteType::from_u32(self.u16_at(SIGN_TYPE_OFFSET).into()).unwrap()
+    }
+
+    pub fn epid_group_id(&self) -> [u8; 4] {
+        self.quote[EPID_GROUP_ID_OFFSET..QE_SVN_OFFSET].try_into().unwrap()
+    }
+
+    pub fn qe_svn(&self) -> u16 {
+        self.u16_at(QE_SVN_OFFSET)
+    }
+
+    pub fn pce_svn(&self) -> u16 {
+        self.u16_at(PCE_SVN_OFFSET)
+    }
+
+    pub fn basename(&self) -> Basename {
+        Basename(self.quote[BASENAME_OFFSET..REPORT_BODY_OFFSET].try_into().unwrap())
+    }
+
+    /// The report of the quoted enclave. The quote doesn't contain the
+    /// report's KEYID and MAC, so they are zero.
+    pub fn report_body(&self) -> Report {
+        report_from_body(&self.quote[REPORT_BODY_OFFSET..QUOTE_SIGNATURE_LEN_OFFSET])
+    }
+
+    /// The EPID signature, which is encrypted for the attestation service.
+    pub fn signature(&self) -> &[u8] {
+        &self.quote[QUOTE_SIGNATURE_OFFSET..]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.quote
+    }
+}
+
+// Offsets in `sgx_quote3_t`, see sgx_quote_3.h in the Intel SDK. The header
+// has the same size as that of `sgx_quote_t`, so the report body and the
+// signature length are at the same offsets as in EPID quotes.
+const ECDSA_QUOTE_VERSION: u16 = 3;
+const ATT_KEY_TYPE_OFFSET: usize = 2;
+const ECDSA_QE_SVN_OFFSET: usize = 8;
+const ECDSA_PCE_SVN_OFFSET: usize = 10;
+const QE_VENDOR_ID_OFFSET: usize = 12;
+const USER_DATA_OFFSET: usize = 28;
+// `sgx_attestation_algorithm_id_t` for ECDSA-256-with-P-256 curve, the only
+// key type of version 3 quotes.
+const ATT_KEY_TYPE_ECDSA_P256: u16 = 2;
+// Sizes in `sgx_ql_ecdsa_sig_data_t`, which is the signature of the quote.
+const ECDSA_SIGNATURE_SIZE: usize = 64;
+const ECDSA_PUBLIC_KEY_SIZE: usize = 64;
+
+/// The type of the certification data of an ECDSA quote, an
+/// `sgx_ql_cert_key_type_t` from sgx_quote_3.h in the Intel SDK.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum CertificationDataType {
+    PpidCleartext,
+    PpidRsa2048Encrypted,
+    PpidRsa3072Encrypted,
+    PckCleartext,
+    /// The PCK certificate chain, PEM-encoded.
+    PckCertChain,
+    EcdsaSigAuxData,
+    /// A type this crate doesn't know about yet.
+    Other(u16),
+}
+
+impl From<u16> for CertificationDataType {
+    fn from(id: u16) -> Self {
+        use self::CertificationDataType::*;
+        match id {
+            1 => PpidCleartext,
+            2 => PpidRsa2048Encrypted,
+            3 => PpidRsa3072Encrypted,
+            4 => PckCleartext,
+            5 => PckCertChain,
+            6 => EcdsaSigAuxData,
+            _ => Other(id),
+        }
+    }
+}
+
+impl From<CertificationDataType> for u16 {
+    fn from(ty: CertificationDataType) -> u16 {
+        use self::CertificationDataType::*;
+        match ty {
+            PpidCleartext => 1,
+            PpidRsa2048Encrypted => 2,
+            PpidRsa3072Encrypted => 3,
+            PckCleartext => 4,
+            PckCertChain => 5,
+            EcdsaSigAuxData => 6,
+            Other(id) => id,
+        }
+    }
+}
+
+/// An ECDSA quote in the quote3 format, as returned by
+/// `AesmClient::get_quote_ex` for an ECDSA attestation key.
+///
+/// Like `EpidQuote`, the quote is checked for consistency when it's parsed,
+/// so the accessors don't fail. The signatures aren't verified.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EcdsaQuote {
+    quote: Vec<u8>,
+    // Offsets of the variable-length parts of the signature data.
+    auth_data: (usize, usize),
+    cert_data_type: u16,
+    cert_data: (usize, usize),
+}
+
+/// Bounds-checked reads from a buffer, returning `Error::InvalidEcdsaQuote`
+/// with `reason` if it's too short.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    reason: &'static str,
+}
+
+impl<'a> Cursor<'a> {
+    /// Returns the offsets of the next `len` bytes.
+    fn take(&mut self, len: usize) -> Result<(usize, usize)> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.buf.len())
+            .ok_or(Error::InvalidEcdsaQuote(self.reason))?;
+        let range = (self.pos, end);
+        self.pos = end;
+        Ok(range)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let (start, end) = self.take(2)?;
+        Ok(u16::from_le_bytes(self.buf[start..end].try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let (start, end) = self.take(4)?;
+        Ok(u32::from_le_bytes(self.buf[start..end].try_into().unwrap()))
+    }
+}
+
+impl EcdsaQuote {
+    /// Parse `quote`. Returns `Error::InvalidQuoteSize` if `quote` is
+    /// truncated or its size doesn't match its signature length, and
+    /// `Error::InvalidEcdsaQuote` if it isn't a version 3 quote for an
+    /// ECDSA-P256 attestation key, or its signature data is malformed.
+    ///
+    /// Certification data of unknown types is returned as is, see
+    /// `CertificationDataType::Other`.
+    pub fn parse(quote: &[u8]) -> Result<EcdsaQuote> {
+        let sig_len = quote.get(QUOTE_SIGNATURE_LEN_OFFSET..QUOTE_SIGNATURE_OFFSET)
+            .ok_or(Error::InvalidQuoteSize)?;
+        let sig_len = u32::from_le_bytes(sig_len.try_into().unwrap()) as usize;
+        if quote.len() - QUOTE_SIGNATURE_OFFSET != sig_len {
+            return Err(Error::InvalidQuoteSize);
+        }
+        let header = |offset| u16::from_le_bytes([quote[offset], quote[offset + 1]]);
+        if header(VERSION_OFFSET) != ECDSA_QUOTE_VERSION {
+            return Err(Error::InvalidEcdsaQuote("unsupported quote version"));
+        }
+        if header(ATT_KEY_TYPE_OFFSET) != ATT_KEY_TYPE_ECDSA_P256 {
+            return Err(Error::InvalidEcdsaQuote("unsupported attestation key type"));
+        }
+
+        let mut sig_data = Cursor { buf: quote, pos: QUOTE_SIGNATURE_OFFSET, reason: "truncated signature data" };
+        sig_data.take(ECDSA_SIGNATURE_SIZE + ECDSA_PUBLIC_KEY_SIZE + REPORT_BODY_SIZE + ECDSA_SIGNATURE_SIZE)?;
+        let auth_data_len = sig_data.u16()?;
+        sig_data.reason = "truncated QE authentication data";
+        let auth_data = sig_data.take(auth_data_len.into())?;
+        sig_data.reason = "truncated certification data";
+        let cert_data_type = sig_data.u16()?;
+        let cert_data_len = sig_data.u32()?;
+        let cert_data = sig_data.take(cert_data_len as usize)?;
+        Ok(EcdsaQuote { quote: quote.to_owned(), auth_data, cert_data_type, cert_data })
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.quote[offset], self.quote[offset + 1]])
+    }
+
+    /// The part of the signature data at `offset` from its start.
+    fn sig_data(&self, offset: usize, len: usize) -> &[u8] {
+        let start = QUOTE_SIGNATURE_OFFSET + offset;
+        &self.quote[start..start + len]
+    }
+
+    pub fn version(&self) -> u16 {
+        self.u16_at(VERSION_OFFSET)
+    }
+
+    /// The type of the attestation key, always 2 (ECDSA-P256).
+    pub fn att_key_type(&self) -> u16 {
+        self.u16_at(ATT_KEY_TYPE_OFFSET)
+    }
+
+    pub fn qe_svn(&self) -> u16 {
+        self.u16_at(ECDSA_QE_SVN_OFFSET)
+    }
+
+    pub fn pce_svn(&self) -> u16 {
+        self.u16_at(ECDSA_PCE_SVN_OFFSET)
+    }
+
+    pub fn qe_vendor_id(&self) -> [u8; 16] {
+        self.quote[QE_VENDOR_ID_OFFSET..USER_DATA_OFFSET].try_into().unwrap()
+    }
+
+    /// Custom data of the QE. Intel's QE puts the first 16 bytes of the
+    /// platform's QE ID here.
+    pub fn user_data(&self) -> [u8; 20] {
+        self.quote[USER_DATA_OFFSET..REPORT_BODY_OFFSET].try_into().unwrap()
+    }
+
+    /// The report of the quoted enclave. The quote doesn't contain the
+    /// report's KEYID and MAC, so they are zero.
+    pub fn report_body(&self) -> Report {
+        report_from_body(&self.quote[REPORT_BODY_OFFSET..QUOTE_SIGNATURE_LEN_OFFSET])
+    }
+
+    /// The ECDSA signature of the header and report body, by the attestation
+    /// key.
+    pub fn signature(&self) -> &[u8] {
+        self.sig_data(0, ECDSA_SIGNATURE_SIZE)
+    }
+
+    /// The public part of the attestation key.
+    pub fn attestation_public_key(&self) -> &[u8] {
+        self.sig_data(ECDSA_SIGNATURE_SIZE, ECDSA_PUBLIC_KEY_SIZE)
+    }
+
+    /// The report of QE, whose REPORTDATA binds the attestation key and the
+    /// QE authentication data. Its KEYID and MAC are zero.
+    pub fn qe_report_body(&self) -> Report {
+        report_from_body(self.sig_data(ECDSA_SIGNATURE_SIZE + ECDSA_PUBLIC_KEY_SIZE, REPORT_BODY_SIZE))
+    }
+
+    /// The ECDSA signature of the QE report body, by the PCK.
+    pub fn qe_report_signature(&self) -> &[u8] {
+        self.sig_data(ECDSA_SIGNATURE_SIZE + ECDSA_PUBLIC_KEY_SIZE + REPORT_BODY_SIZE, ECDSA_SIGNATURE_SIZE)
+    }
+
+    pub fn qe_auth_data(&self) -> &[u8] {
+        &self.quote[self.auth_data.0..self.auth_data.1]
+    }
+
+    pub fn certification_data_type(&self) -> CertificationDataType {
+        self.cert_data_type.into()
+    }
+
+    /// The certification data, as is. Its format depends on
+    /// `certification_data_type`.
+    pub fn certification_data(&self) -> &[u8] {
+        &self.quote[self.cert_data.0..self.cert_data.1]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.quote
+    }
+}
+
+fn report_from_body(body: &[u8]) -> Report {
+    let mut report = [0; Report::UNPADDED_SIZE];
+    report[..REPORT_BODY_SIZE].copy_from_slice(body);
+    Report::try_copy_from(&report).unwrap()
+}
+
+impl fmt::Debug for EcdsaQuote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EcdsaQuote")
+            .field("version", &self.version())
+            .field("att_key_type", &self.att_key_type())
+            .field("qe_svn", &self.qe_svn())
+            .field("pce_svn", &self.pce_svn())
+            .field("qe_vendor_id", &HexBytes(&self.qe_vendor_id()))
+            .field("user_data", &HexBytes(&self.user_data()))
+            .field("report_body", &self.report_body())
+            .field("attestation_public_key", &HexBytes(self.attestation_public_key()))
+            .field("qe_report_body", &self.qe_report_body())
+            .field("qe_auth_data", &HexBytes(self.qe_auth_data()))
+            .field("certification_data_type", &self.certification_data_type())
+            .field("certification_data", &HexBytes(self.certification_data()))
+            .finish()
+    }
+}
+
+/// The basename of an EPID quote. Quotes of the same platform with the same
+/// basename are linkable if they're of type `QuoteType::Linkable`.
+///
+/// Basenames are compared in constant time.
+#[derive(Clone, Copy, Eq)]
+pub struct Basename([u8; 32]);
+
+impl Basename {
+    /// The basename QE uses for linkable quotes requested with `spid`: the
+    /// SPID, padded with zeros.
+    pub fn from_spid(spid: &Spid) -> Basename {
+        let mut basename = [0; 32];
+        basename[..SPID_SIZE].copy_from_slice(spid.as_bytes());
+        Basename(basename)
+    }
+
+    /// Whether this is the basename of linkable quotes requested with
+    /// `spid`, see `from_spid`.
+    pub fn is_derived_from_spid(&self, spid: &Spid) -> bool {
+        *self == Basename::from_spid(spid)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for Basename {
+    fn from(basename: [u8; 32]) -> Basename {
+        Basename(basename)
+    }
+}
+
+impl PartialEq for Basename {
+    fn eq(&self, other: &Basename) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+/// The basename is redacted, as it's usually derived from the SPID.
+impl fmt::Debug for Basename {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Basename").field(&Redacted(&self.0)).finish()
+    }
+}
+
+impl QuoteResult {
+    /// The basename of the quote, without parsing the rest of it like
+    /// `EpidQuote::parse`. Returns `Error::InvalidQuoteSize` if the quote is
+    /// too short to contain one.
+    pub fn basename(&self) -> Result<Basename> {
+        let basename = self.quote().get(BASENAME_OFFSET..REPORT_BODY_OFFSET).ok_or(Error::InvalidQuoteSize)?;
+        Ok(Basename(basename.try_into().unwrap()))
+    }
+}
+
+impl fmt::Debug for EpidQuote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EpidQuote")
+            .field("version", &self.version())
+            .field("sign_type", &self.sign_type())
+            .field("epid_group_id", &self.epid_group_id())
+            .field("qe_svn", &self.qe_svn())
+            .field("pce_svn", &self.pce_svn())
+            .field("basename", &self.basename())
+            .field("report_body", &self.report_body())
+            .field("signature_len", &self.signature().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A linkable quote with a 680 byte signature, with the layout of
+    // `sgx_quote_t`.
+    fn fixture() -> Vec<u8> {
+        let mut quote = vec![];
+        quote.extend_from_slice(&2u16.to_le_bytes()); // version
+        quote.extend_from_slice(&1u16.to_le_bytes()); // sign_type
+        quote.extend_from_slice(&[0xb, 0xa, 0, 0]); // epid_group_id
+        quote.extend_from_slice(&11u16.to_le_bytes()); // qe_svn
+        quote.extend_from_slice(&7u16.to_le_bytes()); // pce_svn
+        quote.extend_from_slice(&[0; 4]); // xeid
+        quote.extend_from_slice(&[0x42; 32]); // basename
+        let report = Report { isvprodid: 3, isvsvn: 5, mrenclave: [0x11; 32], ..Default::default() };
+        quote.extend_from_slice(&AsRef::<[u8]>::as_ref(&report)[..REPORT_BODY_SIZE]);
+        quote.extend_from_slice(&680u32.to_le_bytes());
+        quote.extend(vec![0x99; 680]);
+        quote
+    }
+
+    #[test]
+    fn parse() {
+        let quote = EpidQuote::parse(&fixture()).unwrap();
+        assert_eq!(quote.version(), 2);
+        assert_eq!(quote.sign_type(), QuoteType::Linkable);
+        assert_eq!(quote.epid_group_id(), [0xb, 0xa, 0, 0]);
+        assert_eq!(quote.qe_svn(), 11);
+        assert_eq!(quote.pce_svn(), 7);
+        assert_eq!(quote.basename(), Basename::from([0x42; 32]));
+        let report = quote.report_body();
+        assert_eq!((report.isvprodid, report.isvsvn, report.mrenclave), (3, 5, [0x11; 32]));
+        assert_eq!(quote.signature(), &[0x99; 680][..]);
+        assert_eq!(quote.as_bytes(), &fixture()[..]);
+    }
+
+    // An ECDSA quote with the layout of `sgx_quote3_t`, with a PCK
+    // certificate chain of `cert_data_len` bytes as certification data.
+    fn ecdsa_fixture(cert_data_type: u16, cert_data_len: usize) -> Vec<u8> {
+        let mut sig_data = vec![];
+        sig_data.extend_from_slice(&[0x51; 64]); // isv_enclave_report_sig
+        sig_data.extend_from_slice(&[0x52; 64]); // attest_pub_key
+        let qe_report = Report { isvprodid: 1, isvsvn: 6, mrenclave: [0x22; 32], ..Default::default() };
+        sig_data.extend_from_slice(&AsRef::<[u8]>::as_ref(&qe_report)[..REPORT_BODY_SIZE]);
+        sig_data.extend_from_slice(&[0x53; 64]); // qe_report_sig
+        sig_data.extend_from_slice(&32u16.to_le_bytes());
+        sig_data.extend_from_slice(&[0x54; 32]); // qe auth data
+        sig_data.extend_from_slice(&cert_data_type.to_le_bytes());
+        sig_data.extend_from_slice(&(cert_data_len as u32).to_le_bytes());
+        sig_data.extend(vec![0x55; cert_data_len]);
+
+        let mut quote = vec![];
+        quote.extend_from_slice(&3u16.to_le_bytes()); // version
+        quote.extend_from_slice(&2u16.to_le_bytes()); // att_key_type
+        quote.extend_from_slice(&[0; 4]); // reserved
+        quote.extend_from_slice(&5u16.to_le_bytes()); // qe_svn
+        quote.extend_from_slice(&10u16.to_le_bytes()); // pce_svn
+        quote.extend_from_slice(&[0x93; 16]); // qe_vendor_id
+        quote.extend_from_slice(&[0x44; 20]); // user_data
+        let report = Report { isvprodid: 3, isvsvn: 5, mrenclave: [0x11; 32], ..Default::default() };
+        quote.extend_from_slice(&AsRef::<[u8]>::as_ref(&report)[..REPORT_BODY_SIZE]);
+        quote.extend_from_slice(&(sig_data.len() as u32).to_le_bytes());
+        quote.extend(sig_data);
+        quote
+    }
+
+    #[test]
+    fn parse_ecdsa() {
+        let quote = EcdsaQuote::parse(&ecdsa_fixture(5, 3000)).unwrap();
+        assert_eq!((quote.version(), quote.att_key_type()), (3, 2));
+        assert_eq!((quote.qe_svn(), quote.pce_svn()), (5, 10));
+        assert_eq!(quote.qe_vendor_id(), [0x93; 16]);
+        assert_eq!(quote.user_data(), [0x44; 20]);
+        let report = quote.report_body();
+        assert_eq!((report.isvprodid, report.isvsvn, report.mrenclave), (3, 5, [0x11; 32]));
+        assert_eq!(quote.signature(), &[0x51; 64][..]);
+        assert_eq!(quote.attestation_public_key(), &[0x52; 64][..]);
+        let qe_report = quote.qe_report_body();
+        assert_eq!((qe_report.isvprodid, qe_report.isvsvn, qe_report.mrenclave), (1, 6, [0x22; 32]));
+        assert_eq!(quote.qe_report_signature(), &[0x53; 64][..]);
+        assert_eq!(quote.qe_auth_data(), &[0x54; 32][..]);
+        assert_eq!(quote.certification_data_type(), CertificationDataType::PckCertChain);
+        assert_eq!(quote.certification_data(), &[0x55; 3000][..]);
+        assert_eq!(quote.as_bytes(), &ecdsa_fixture(5, 3000)[..]);
+
+        let unknown = EcdsaQuote::parse(&ecdsa_fixture(0x1234, 0)).unwrap();
+        assert_eq!(unknown.certification_data_type(), CertificationDataType::Other(0x1234));
+        assert_eq!(u16::from(unknown.certification_data_type()), 0x1234);
+        assert!(unknown.certification_data().is_empty());
+    }
+
+    #[test]
+    fn invalid_ecdsa() {
+        fn assert_invalid(quote: &[u8], reason: &str) {
+            match EcdsaQuote::parse(quote) {
+                Err(Error::InvalidEcdsaQuote(r)) if r == reason => {}
+                res => panic!("expected {:?}, got {:?}", reason, res),
+            }
+        }
+
+        // An EPID quote.
+        assert_invalid(&fixture(), "unsupported quote version");
+        let mut key_type = ecdsa_fixture(5, 100);
+        key_type[ATT_KEY_TYPE_OFFSET] = 3;
+        assert_invalid(&key_type, "unsupported attestation key type");
+
+        // Signature data that's inconsistent with its length.
+        let quote = ecdsa_fixture(5, 100);
+        let sig_data_len = quote.len() - QUOTE_SIGNATURE_OFFSET;
+        let with_sig_data = |len: usize| {
+            let mut quote = quote[..QUOTE_SIGNATURE_OFFSET + len].to_vec();
+            quote[QUOTE_SIGNATURE_LEN_OFFSET..QUOTE_SIGNATURE_OFFSET].copy_from_slice(&(len as u32).to_le_bytes());
+            quote
+        };
+        assert_invalid(&with_sig_data(64 + 64 + 384 + 64 + 1), "truncated signature data");
+        assert_invalid(&with_sig_data(64 + 64 + 384 + 64 + 2 + 31), "truncated QE authentication data");
+        assert_invalid(&with_sig_data(64 + 64 + 384 + 64 + 2 + 32 + 5), "truncated certification data");
+        assert_invalid(&with_sig_data(sig_data_len - 1), "truncated certification data");
+        let mut huge_cert_data = quote.clone();
+        let cert_data_len_offset = quote.len() - 100 - 4;
+        huge_cert_data[cert_data_len_offset..][..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_invalid(&huge_cert_data, "truncated certification data");
+
+        for len in &[0, 100, QUOTE_SIGNATURE_OFFSET + 1, quote.len() - 1] {
+            match EcdsaQuote::parse(&quote[..*len]) {
+                Err(Error::InvalidQuoteSize) => {}
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
+    }
+
+    // Parsing arbitrary input mustn't panic.
+    #[test]
+    fn ecdsa_no_panics() {
+        let quote = ecdsa_fixture(5, 100);
+        for len in 0..quote.len() + 10 {
+            let mut input = quote.clone();
+            input.resize(len, 0xff);
+            let _ = EcdsaQuote::parse(&input).map(|q| format!("{:?}", q));
+            if len >= QUOTE_SIGNATURE_OFFSET {
+                let sig_len = (len - QUOTE_SIGNATURE_OFFSET) as u32;
+                input[QUOTE_SIGNATURE_LEN_OFFSET..QUOTE_SIGNATURE_OFFSET].copy_from_slice(&sig_len.to_le_bytes());
+                let _ = EcdsaQuote::parse(&input).map(|q| format!("{:?}", q));
+            }
+        }
+    }
+
+    #[test]
+    fn basename() {
+        let spid = Spid::from([0x42; 16]);
+        let mut quote = fixture();
+        quote[BASENAME_OFFSET + SPID_SIZE..REPORT_BODY_OFFSET].copy_from_slice(&[0; 16]);
+        let basename = EpidQuote::parse(&quote).unwrap().basename();
+        assert!(basename.is_derived_from_spid(&spid));
+        assert!(!basename.is_derived_from_spid(&Spid::from([0x43; 16])));
+        assert_eq!(basename, Basename::from_spid(&spid));
+        assert_eq!(&basename.as_bytes()[..SPID_SIZE], spid.as_bytes());
+        assert_eq!(format!("{:?}", basename), "Basename(32 bytes [redacted])");
+
+        // Without parsing the whole quote.
+        let truncated = QuoteResult::without_qe_report(&quote[..REPORT_BODY_OFFSET]);
+        assert_eq!(truncated.basename().unwrap(), basename);
+        match QuoteResult::without_qe_report(&quote[..REPORT_BODY_OFFSET - 1]).basename() {
+            Err(Error::InvalidQuoteSize) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        fn assert_invalid_size(quote: &[u8]) {
+            match EpidQuote::parse(quote) {
+                Err(Error::InvalidQuoteSize) => {}
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
+
+        let quote = fixture();
+        for len in &[0, 100, QUOTE_SIGNATURE_LEN_OFFSET, QUOTE_SIGNATURE_OFFSET, quote.len() - 1] {
+            assert_invalid_size(&quote[..*len]);
+        }
+        let mut padded = quote.clone();
+        padded.push(0);
+        assert_invalid_size(&padded);
+        let mut huge_sig_len = quote.clone();
+        huge_sig_len[QUOTE_SIGNATURE_LEN_OFFSET..QUOTE_SIGNATURE_OFFSET].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_invalid_size(&huge_sig_len);
+
+        let mut sign_type = quote;
+        sign_type[SIGN_TYPE_OFFSET] = 2;
+        match EpidQuote::parse(&sign_type) {
+            Err(Error::InvalidQuoteType(2)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    // Parsing arbitrary input mustn't panic.
+    #[test]
+    fn no_panics() {
+        let quote = fixture();
+        for len in 0..quote.len() + 10 {
+            let mut input = quote.clone();
+            input.resize(len, 0xff);
+            let _ = EpidQuote::parse(&input).map(|q| format!("{:?}", q));
+            input[QUOTE_SIGNATURE_LEN_OFFSET.min(len)..].iter_mut().for_each(|b| *b = 0);
+            let _ = EpidQuote::parse(&input).map(|q| format!("{:?}", q));
+        }
+    }
+}
@@ -0,0 +1,143 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+
+use sgx_isa::Report;
+
+use crate::hex::{HexBytes, Redacted};
+use crate::{QuoteType, SigRl, Spid, NONCE_SIZE};
+
+/// The SPID of a `QuoteRequest` that hasn't been set yet.
+#[derive(Clone, Copy, Debug)]
+pub struct MissingSpid;
+
+/// The quote type of a `QuoteRequest` that hasn't been set yet.
+#[derive(Clone, Copy, Debug)]
+pub struct MissingQuoteType;
+
+/// The arguments of `AesmClient::get_quote_req`.
+///
+/// ```no_run
+/// # use aesm_client::{AesmClient, QuoteRequest, QuoteType, SigRl, Spid};
+/// # use sgx_isa::{Report, Targetinfo};
+/// # fn enclave_report(_: &Targetinfo) -> Report { unimplemented!() }
+/// # let (spid, sig_rl) = (Spid::from([0; 16]), SigRl::empty());
+/// let client = AesmClient::new();
+/// let quote_info = client.init_quote()?;
+/// let req = QuoteRequest::new(&enclave_report(&quote_info.targetinfo()?))
+///     .spid(spid)
+///     .sig_rl(&sig_rl)
+///     .quote_type(QuoteType::Linkable)
+///     .nonce([0; 16]);
+/// let quote = client.get_quote_req(&quote_info, req)?;
+/// # Ok::<(), aesm_client::Error>(())
+/// ```
+///
+/// The SPID and the quote type are required: a request can only be passed to
+/// `get_quote_req` once both have been set, which the type parameters keep
+/// track of.
+///
+/// ```compile_fail
+/// # use aesm_client::{AesmClient, QuoteRequest, QuoteType};
+/// # let client = AesmClient::new();
+/// # let quote_info = client.init_quote().unwrap();
+/// let req = QuoteRequest::new(&Default::default()).quote_type(QuoteType::Linkable);
+/// client.get_quote_req(&quote_info, req);
+/// ```
+///
+/// The signature revocation list defaults to the empty list. Without a
+/// nonce, QE doesn't return a QE report.
+#[derive(Clone)]
+pub struct QuoteRequest<S = MissingSpid, T = MissingQuoteType> {
+    pub(crate) report: Vec<u8>,
+    pub(crate) spid: S,
+    pub(crate) sig_rl: Vec<u8>,
+    pub(crate) quote_type: T,
+    pub(crate) nonce: Option<Vec<u8>>,
+}
+
+impl QuoteRequest {
+    /// A request for a quote of `report`.
+    pub fn new(report: &Report) -> QuoteRequest {
+        QuoteRequest {
+            report: report.as_ref().to_vec(),
+            spid: MissingSpid,
+            sig_rl: vec![],
+            quote_type: MissingQuoteType,
+            nonce: None,
+        }
+    }
+}
+
+impl<S, T> QuoteRequest<S, T> {
+    pub fn spid<P: Into<Spid>>(self, spid: P) -> QuoteRequest<Spid, T> {
+        QuoteRequest {
+            report: self.report,
+            spid: spid.into(),
+            sig_rl: self.sig_rl,
+            quote_type: self.quote_type,
+            nonce: self.nonce,
+        }
+    }
+
+    pub fn quote_type(self, quote_type: QuoteType) -> QuoteRequest<S, QuoteType> {
+        QuoteRequest {
+            report: self.report,
+            spid: self.spid,
+            sig_rl: self.sig_rl,
+            quote_type,
+            nonce: self.nonce,
+        }
+    }
+
+    /// The signature revocation list of the platform's EPID group.
+    pub fn sig_rl(mut self, sig_rl: &SigRl) -> Self {
+        self.sig_rl = sig_rl.as_bytes().to_vec();
+        self
+    }
+
+    /// The nonce that the QE report is bound to.
+    pub fn nonce(mut self, nonce: [u8; NONCE_SIZE]) -> Self {
+        self.nonce = Some(nonce.to_vec());
+        self
+    }
+}
+
+impl QuoteRequest<Spid, QuoteType> {
+    /// A request from raw arguments, as passed to `AesmClient::get_quote`.
+    /// The caller checks their sizes.
+    pub(crate) fn from_raw(report: Vec<u8>, spid: Spid, sig_rl: Vec<u8>, quote_type: QuoteType, nonce: Vec<u8>) -> Self {
+        QuoteRequest { report, spid, sig_rl, quote_type, nonce: Some(nonce) }
+    }
+}
+
+/// The SPID and the nonce are redacted.
+impl<S: fmt::Debug, T: fmt::Debug> fmt::Debug for QuoteRequest<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QuoteRequest")
+            .field("report", &HexBytes(&self.report))
+            .field("spid", &self.spid)
+            .field("sig_rl", &HexBytes(&self.sig_rl))
+            .field("quote_type", &self.quote_type)
+            .field("nonce", &self.nonce.as_deref().map(Redacted))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug() {
+        let req = QuoteRequest::new(&Report::default()).spid([0x42; 16]).quote_type(QuoteType::Linkable).nonce([7; 16]);
+        let debug = format!("{:?}", req);
+        assert!(debug.starts_with("QuoteRequest { report: 432 bytes [00 00 00 00 … 00 00], spid: Spid(16 bytes [redacted]), sig_rl: 0 bytes [], "), "{}", debug);
+        assert!(debug.ends_with("nonce: Some(16 bytes [redacted]) }"), "{}", debug);
+        assert!(!debug.contains("42") && !debug.contains("07"), "{}", debug);
+    }
+}
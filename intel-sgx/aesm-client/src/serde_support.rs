@@ -0,0 +1,245 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Serde support for the quote types.
+//!
+//! Byte fields are serialized with `serialize_bytes`, so that formats with a
+//! native byte string type don't encode them as a sequence of integers.
+//! Deserialization goes through the `*Fields` structs below, which are
+//! converted with the same length checks as the constructors, so that
+//! tampered data can't produce inconsistent values.
+
+use std::convert::TryFrom;
+
+use serde::Deserialize;
+use sgx_isa::Report;
+
+use crate::error::{Error, Result};
+use crate::{check_size, AesmKeyId, AesmKeyIds, QuoteInfo, QuoteInfoEx, QuoteResult};
+
+pub(crate) mod bytes {
+    use std::fmt;
+
+    use serde::de::{Deserializer, Error, SeqAccess, Visitor};
+    use serde::ser::Serializer;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    /// Serializes `None` as empty bytes.
+    pub fn serialize_optional<S: Serializer, B: AsRef<[u8]>>(bytes: &Option<B>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes.as_ref().map_or(&[], AsRef::as_ref))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        struct BytesVisitor;
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "a byte array")
+            }
+            fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                Ok(v.to_owned())
+            }
+            fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                Ok(v)
+            }
+            // For formats without byte strings, such as JSON.
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+                while let Some(b) = seq.next_element()? {
+                    bytes.push(b);
+                }
+                Ok(bytes)
+            }
+        }
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct QuoteInfoFields {
+    #[serde(with = "bytes")]
+    target_info: Vec<u8>,
+    #[serde(with = "bytes")]
+    pub_key_id: Vec<u8>,
+}
+
+impl TryFrom<QuoteInfoFields> for QuoteInfo {
+    type Error = Error;
+
+    fn try_from(fields: QuoteInfoFields) -> Result<Self> {
+        QuoteInfo::new(fields.target_info, fields.pub_key_id)
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct QuoteInfoExFields {
+    #[serde(with = "bytes")]
+    att_key_id: Vec<u8>,
+    #[serde(with = "bytes")]
+    target_info: Vec<u8>,
+    #[serde(with = "bytes")]
+    pub_key: Vec<u8>,
+    quote_size: u32,
+}
+
+impl TryFrom<QuoteInfoExFields> for QuoteInfoEx {
+    type Error = Error;
+
+    fn try_from(fields: QuoteInfoExFields) -> Result<Self> {
+        if !fields.att_key_id.is_empty() {
+            check_size("att_key_id", AesmKeyId::SIZE, fields.att_key_id.len())?;
+        }
+        QuoteInfoEx::new(fields.att_key_id, fields.target_info, fields.pub_key, fields.quote_size)
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct QuoteResultFields {
+    #[serde(with = "bytes")]
+    quote: Vec<u8>,
+    #[serde(with = "bytes")]
+    qe_report: Vec<u8>,
+}
+
+impl TryFrom<QuoteResultFields> for QuoteResult {
+    type Error = Error;
+
+    fn try_from(fields: QuoteResultFields) -> Result<Self> {
+        // A quote requested without a QE report.
+        if fields.qe_report.is_empty() {
+            return Ok(QuoteResult::without_qe_report(fields.quote));
+        }
+        check_size("qe_report", Report::UNPADDED_SIZE, fields.qe_report.len())?;
+        Ok(QuoteResult::new(fields.quote, fields.qe_report))
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AesmKeyIdFields {
+    #[serde(with = "bytes")]
+    key: Vec<u8>,
+}
+
+impl TryFrom<AesmKeyIdFields> for AesmKeyId {
+    type Error = Error;
+
+    fn try_from(fields: AesmKeyIdFields) -> Result<Self> {
+        if fields.key.len() != AesmKeyId::SIZE {
+            return Err(Error::InvalidKeyIdSize);
+        }
+        Ok(AesmKeyId { key: fields.key })
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AesmKeyIdsFields {
+    #[serde(with = "bytes")]
+    keys: Vec<u8>,
+}
+
+impl TryFrom<AesmKeyIdsFields> for AesmKeyIds {
+    type Error = Error;
+
+    fn try_from(fields: AesmKeyIdsFields) -> Result<Self> {
+        AesmKeyIds::new(fields.keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use sgx_isa::Targetinfo;
+
+    use super::*;
+    use crate::AttestationAlgorithm;
+
+    fn key_id(algorithm: AttestationAlgorithm) -> Vec<u8> {
+        let mut key = vec![0; AesmKeyId::SIZE];
+        key[154..158].copy_from_slice(&u32::from(algorithm).to_le_bytes());
+        key
+    }
+
+    fn quote_info_ex() -> QuoteInfoEx {
+        QuoteInfoEx::new(key_id(AttestationAlgorithm::EcdsaP256), vec![1; Targetinfo::UNPADDED_SIZE], vec![2; 64], 4578).unwrap()
+    }
+
+    fn quote_result() -> QuoteResult {
+        QuoteResult::new(vec![3; 1116], vec![4; Report::UNPADDED_SIZE])
+    }
+
+    fn round_trip<T: Serialize + DeserializeOwned + Debug + PartialEq>(value: &T) {
+        let cbor = serde_cbor::to_vec(value).unwrap();
+        assert_eq!(&serde_cbor::from_slice::<T>(&cbor).unwrap(), value);
+        let json = serde_json::to_string(value).unwrap();
+        assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), value);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(&quote_info_ex());
+        round_trip(&QuoteInfoEx::new(vec![], vec![1; Targetinfo::UNPADDED_SIZE], vec![], 0).unwrap());
+        round_trip(&quote_result());
+        round_trip(&QuoteResult::without_qe_report(vec![3; 1116]));
+        round_trip(&AesmKeyIds::new(key_id(AttestationAlgorithm::Epid)).unwrap().iter().next().unwrap());
+        round_trip(&AesmKeyIds::new([key_id(AttestationAlgorithm::Epid), key_id(AttestationAlgorithm::EcdsaP256)].concat()).unwrap());
+        round_trip(&AesmKeyIds::new(vec![]).unwrap());
+
+        // QuoteInfo doesn't implement PartialEq.
+        let quote_info = QuoteInfo::new(vec![5; Targetinfo::UNPADDED_SIZE], vec![6; 4]).unwrap();
+        let cbor = serde_cbor::to_vec(&quote_info).unwrap();
+        let decoded: QuoteInfo = serde_cbor::from_slice(&cbor).unwrap();
+        assert_eq!((decoded.target_info(), decoded.pub_key_id()), (quote_info.target_info(), quote_info.pub_key_id()));
+        let json = serde_json::to_string(&quote_info).unwrap();
+        let decoded: QuoteInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!((decoded.target_info(), decoded.pub_key_id()), (quote_info.target_info(), quote_info.pub_key_id()));
+    }
+
+    #[test]
+    fn bytes_are_compact() {
+        // A CBOR byte string has a 3 byte header for this length, an array of
+        // integers would take at least one byte per element plus the header.
+        let cbor = serde_cbor::to_vec(&quote_result()).unwrap();
+        assert!(cbor.len() < 1116 + Report::UNPADDED_SIZE + 32, "encoded length {}", cbor.len());
+    }
+
+    // Serialize `value`, replace the byte field `field` with `bytes` and
+    // deserialize again.
+    fn tamper<T: Serialize + DeserializeOwned>(value: &T, field: &str, bytes: Vec<u8>) -> serde_cbor::Result<T> {
+        let mut map: serde_cbor::Value = serde_cbor::value::to_value(value).unwrap();
+        match map {
+            serde_cbor::Value::Map(ref mut map) => {
+                map.insert(serde_cbor::Value::Text(field.into()), serde_cbor::Value::Bytes(bytes));
+            }
+            _ => panic!("expected a map"),
+        }
+        serde_cbor::value::from_value(map)
+    }
+
+    #[test]
+    fn tampered() {
+        let quote_info_ex = quote_info_ex();
+        assert!(tamper(&quote_info_ex, "target_info", vec![1; 511]).is_err());
+        assert!(tamper(&quote_info_ex, "att_key_id", vec![0; AesmKeyId::SIZE - 1]).is_err());
+        assert!(tamper(&quote_info_ex, "att_key_id", vec![]).is_ok());
+        assert!(!tamper(&quote_result(), "qe_report", vec![]).unwrap().has_qe_report());
+        assert!(tamper(&quote_result(), "qe_report", vec![1; 383]).is_err());
+        assert!(tamper(&quote_result(), "quote", vec![]).is_ok());
+        let key_ids = AesmKeyIds::new(key_id(AttestationAlgorithm::Epid)).unwrap();
+        assert!(tamper(&key_ids.iter().next().unwrap(), "key", vec![0; AesmKeyId::SIZE + 1]).is_err());
+        assert!(tamper(&key_ids, "keys", vec![0; AesmKeyId::SIZE + 1]).is_err());
+        let quote_info = QuoteInfo::new(vec![5; Targetinfo::UNPADDED_SIZE], vec![6; 4]).unwrap();
+        assert!(tamper(&quote_info, "target_info", vec![]).is_err());
+
+        let json = serde_json::to_string(&quote_result()).unwrap().replace("]}", ",0]}");
+        assert!(serde_json::from_str::<QuoteResult>(&json).is_err());
+    }
+}
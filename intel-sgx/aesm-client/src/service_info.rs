@@ -0,0 +1,89 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Identification data of the AESM service, as returned by
+/// `AesmClient::service_info`.
+///
+/// AESM doesn't report its own version, so this is what can be derived from
+/// its responses. A field is `None` if AESM didn't provide it, e.g. because
+/// the corresponding service isn't available on the platform. The field
+/// names are stable, including in their serialized form.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AesmServiceInfo {
+    /// The version of the launch enclave white list, which AESM updates
+    /// along with the platform software.
+    pub white_list_version: Option<u32>,
+    /// The size of the launch enclave white list in bytes.
+    pub white_list_size: Option<u32>,
+    /// Whether AESM reports the platform software as up to date.
+    pub up_to_date: Option<bool>,
+    /// The extended EPID group ID of the platform, see
+    /// `AesmClient::get_extended_epid_group_id`.
+    pub extended_epid_group_id: Option<u32>,
+}
+
+/// The result of a request for an optional field: `None` if AESM responded
+/// with an error or without the field, and an error if AESM didn't respond.
+pub(crate) fn optional<T>(res: Result<T>) -> Result<Option<T>> {
+    match res {
+        Ok(value) => Ok(Some(value)),
+        Err(Error::AesmCode(_)) | Err(Error::AesmBadResponse(_)) | Err(Error::MissingExtendedEpidGroupId) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::MockAesm;
+    use crate::{
+        AesmClient, Response_CheckUpdateStatusResponse, Response_GetWhiteListResponse, Response_GetWhiteListSizeResponse,
+        Response_SGXGetExtendedEpidGroupIdResponse,
+    };
+
+    #[test]
+    fn service_info() {
+        let mock = MockAesm::start(|req| {
+            if req.has_getWhiteListSizeReq() {
+                let mut body = Response_GetWhiteListSizeResponse::new();
+                body.set_white_list_size(200);
+                MockAesm::response(0, body)
+            } else if req.has_getWhiteListReq() {
+                assert_eq!(req.get_getWhiteListReq().get_white_list_size(), 200);
+                let mut white_list = vec![0; 200];
+                white_list[144..148].copy_from_slice(&[0, 0, 1, 0x2c]);
+                let mut body = Response_GetWhiteListResponse::new();
+                body.set_white_list(white_list);
+                MockAesm::response(0, body)
+            } else if req.has_checkUpdateStatusReq() {
+                assert!(!req.get_checkUpdateStatusReq().has_platform_info());
+                MockAesm::error_code::<Response_CheckUpdateStatusResponse>(20) // UpdateAvailable
+            } else {
+                // Like an AESM without EPID support.
+                MockAesm::error_code::<Response_SGXGetExtendedEpidGroupIdResponse>(27) // ServiceNotAvailable
+            }
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let info = client.service_info().unwrap();
+        assert_eq!(info, AesmServiceInfo {
+            white_list_version: Some(300),
+            white_list_size: Some(200),
+            up_to_date: Some(false),
+            extended_epid_group_id: None,
+        });
+
+        // Without AESM, there's nothing to identify.
+        let client = AesmClient::builder().socket_path("/nonexistent").build().unwrap();
+        assert!(client.service_info().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::error::{Error, Result};
+
+// Refer to epid/common/types.h in the Intel SDK and "Retrieve SigRL" in the
+// IAS API Spec. A signature revocation list is a header (the sver and blob
+// ID, the gid, the version and the number of entries, all big-endian), the
+// entries, and an ECDSA signature.
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 128;
+const SIGNATURE_SIZE: usize = 64;
+const VERSION: [u8; 4] = [0x02, 0x00, 0x00, 0x0e];
+
+/// The size of a signature revocation list with `entries` entries.
+pub(crate) fn size(entries: usize) -> usize {
+    entries.saturating_mul(ENTRY_SIZE)
+        .saturating_add(HEADER_SIZE + SIGNATURE_SIZE)
+}
+
+/// The number of entries in the non-empty signature revocation list
+/// `sig_rl`. Returns `Error::InvalidArgument` if its size isn't consistent
+/// with the count in its header.
+pub(crate) fn entries(sig_rl: &[u8]) -> Result<usize> {
+    let entries = match sig_rl.get(12..HEADER_SIZE) {
+        Some(n2) => u32::from_be_bytes([n2[0], n2[1], n2[2], n2[3]]) as usize,
+        None => 0,
+    };
+    if size(entries) != sig_rl.len() {
+        return Err(Error::InvalidArgument { field: "sig_rl", expected: size(entries), got: sig_rl.len() });
+    }
+    Ok(entries)
+}
+
+/// The EPID group ID of the non-empty signature revocation list `sig_rl`.
+pub(crate) fn gid(sig_rl: &[u8]) -> Option<u32> {
+    let gid = sig_rl.get(4..8)?;
+    Some(u32::from_be_bytes([gid[0], gid[1], gid[2], gid[3]]))
+}
+
+/// An EPID signature revocation list, as returned by the attestation
+/// service for the EPID group of the platform.
+///
+/// Unlike raw bytes, a `SigRl` has been checked to be well-formed, so that a
+/// corrupted list is caught before it's sent to AESM, which only returns a
+/// generic error for it.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct SigRl {
+    sig_rl: Vec<u8>,
+    entries: usize,
+}
+
+impl SigRl {
+    /// The empty list, for EPID groups without revoked signatures.
+    pub fn empty() -> SigRl {
+        SigRl::default()
+    }
+
+    /// Parse a signature revocation list. An empty `sig_rl` is the empty
+    /// list.
+    ///
+    /// Returns `Error::InvalidSigRlVersion` if the header isn't that of a
+    /// signature revocation list, and `Error::InvalidArgument` if `sig_rl` is
+    /// truncated or its size doesn't match the number of entries in the
+    /// header.
+    pub fn parse(sig_rl: &[u8]) -> Result<SigRl> {
+        if sig_rl.is_empty() {
+            return Ok(SigRl::empty());
+        }
+        if let Some(version) = sig_rl.get(..VERSION.len()) {
+            if version != VERSION {
+                return Err(Error::InvalidSigRlVersion([version[0], version[1], version[2], version[3]]));
+            }
+        }
+        let entries = entries(sig_rl)?;
+        Ok(SigRl { sig_rl: sig_rl.to_vec(), entries })
+    }
+
+    /// The number of revoked signatures.
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    /// The EPID group ID, or `None` for the empty list.
+    pub fn gid(&self) -> Option<u32> {
+        gid(&self.sig_rl)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.sig_rl
+    }
+}
+
+impl TryFrom<&[u8]> for SigRl {
+    type Error = Error;
+
+    fn try_from(sig_rl: &[u8]) -> Result<SigRl> {
+        SigRl::parse(sig_rl)
+    }
+}
+
+impl AsRef<[u8]> for SigRl {
+    fn as_ref(&self) -> &[u8] {
+        &self.sig_rl
+    }
+}
+
+impl fmt::Debug for SigRl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("SigRl");
+        if let Some(gid) = self.gid() {
+            s.field("gid", &format_args!("{:08x}", gid));
+        }
+        s.field("entries", &self.entries).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A sig_rl as returned by IAS: sver, blob ID, gid, RLver, n2, the
+    // entries and an ECDSA signature.
+    fn sig_rl(entries: u32) -> Vec<u8> {
+        let mut sig_rl = vec![0x02, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x0b, 0xad, 0x00, 0x00, 0x00, 0x01];
+        sig_rl.extend_from_slice(&entries.to_be_bytes());
+        sig_rl.extend(vec![0x5a; entries as usize * 128]);
+        sig_rl.extend(vec![0xa5; 64]);
+        sig_rl
+    }
+
+    fn assert_invalid_size(res: Result<SigRl>, expected: usize, got: usize) {
+        match res {
+            Err(Error::InvalidArgument { field: "sig_rl", expected: e, got: g }) if (e, g) == (expected, got) => {}
+            res => panic!("expected invalid sig_rl size, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        for &n in &[0, 1, 3] {
+            let bytes = sig_rl(n);
+            let parsed = SigRl::parse(&bytes).unwrap();
+            assert_eq!(parsed.entries(), n as usize);
+            assert_eq!(parsed.gid(), Some(0xbad));
+            assert_eq!(parsed.as_bytes(), &bytes[..]);
+            assert_eq!(SigRl::try_from(&bytes[..]).unwrap(), parsed);
+        }
+        assert_eq!(format!("{:?}", SigRl::parse(&sig_rl(2)).unwrap()), "SigRl { gid: 00000bad, entries: 2 }");
+
+        let empty = SigRl::parse(&[]).unwrap();
+        assert_eq!(empty, SigRl::empty());
+        assert_eq!((empty.entries(), empty.gid(), empty.as_bytes()), (0, None, &[][..]));
+        assert_eq!(format!("{:?}", empty), "SigRl { entries: 0 }");
+    }
+
+    #[test]
+    fn truncated() {
+        // Truncated in the header.
+        assert_invalid_size(SigRl::parse(&sig_rl(0)[..10]), size(0), 10);
+        // Truncated in the entries.
+        let bytes = sig_rl(3);
+        assert_invalid_size(SigRl::parse(&bytes[..16 + 128]), size(3), 16 + 128);
+        // Missing the signature.
+        assert_invalid_size(SigRl::parse(&bytes[..bytes.len() - 64]), size(3), bytes.len() - 64);
+    }
+
+    #[test]
+    fn inconsistent_count() {
+        let mut understated = sig_rl(3);
+        understated[12..16].copy_from_slice(&2u32.to_be_bytes());
+        assert_invalid_size(SigRl::parse(&understated), size(2), size(3));
+
+        let mut overstated = sig_rl(3);
+        overstated[12..16].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_invalid_size(SigRl::parse(&overstated), size(u32::MAX as usize), size(3));
+    }
+
+    #[test]
+    fn invalid_version() {
+        let mut bytes = sig_rl(1);
+        // The blob ID of a private key revocation list.
+        bytes[3] = 0x0d;
+        match SigRl::parse(&bytes) {
+            Err(Error::InvalidSigRlVersion([0x02, 0x00, 0x00, 0x0d])) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
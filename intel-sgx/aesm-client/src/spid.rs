@@ -0,0 +1,165 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::ct::ct_eq;
+use crate::error::{Error, Result};
+use crate::hex::Redacted;
+use crate::SPID_SIZE;
+
+/// A service provider ID, as assigned by Intel when registering for the
+/// attestation service.
+///
+/// SPIDs are compared in constant time.
+#[derive(Clone, Copy, Eq)]
+pub struct Spid([u8; SPID_SIZE]);
+
+impl PartialEq for Spid {
+    fn eq(&self, other: &Spid) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+// Must agree with `PartialEq`, which can't be derived.
+impl Hash for Spid {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl Spid {
+    /// Parse an SPID in the hex format used by the attestation service
+    /// portal, e.g. `"0123456789ABCDEF0123456789ABCDEF"`.
+    ///
+    /// Returns `Error::InvalidSpidLength` if `hex` isn't 32 characters long,
+    /// and `Error::InvalidSpidCharacter` if it contains a non-hex character.
+    pub fn from_hex(hex: &str) -> Result<Spid> {
+        let len = hex.chars().count();
+        if len != SPID_SIZE * 2 {
+            return Err(Error::InvalidSpidLength(len));
+        }
+        let mut spid = [0; SPID_SIZE];
+        for (i, c) in hex.chars().enumerate() {
+            let digit = c.to_digit(16).ok_or(Error::InvalidSpidCharacter(c))?;
+            spid[i / 2] |= (digit as u8) << if i % 2 == 0 { 4 } else { 0 };
+        }
+        Ok(Spid(spid))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; SPID_SIZE] {
+        &self.0
+    }
+}
+
+impl From<[u8; SPID_SIZE]> for Spid {
+    fn from(spid: [u8; SPID_SIZE]) -> Spid {
+        Spid(spid)
+    }
+}
+
+/// Raw bytes, e.g. from an older API that used `Vec<u8>`. Returns
+/// `Error::InvalidArgument` if `spid` isn't 16 bytes.
+impl TryFrom<&[u8]> for Spid {
+    type Error = Error;
+
+    fn try_from(spid: &[u8]) -> Result<Spid> {
+        match <[u8; SPID_SIZE]>::try_from(spid) {
+            Ok(spid) => Ok(Spid(spid)),
+            Err(_) => Err(Error::InvalidArgument { field: "spid", expected: SPID_SIZE, got: spid.len() }),
+        }
+    }
+}
+
+impl FromStr for Spid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Spid> {
+        Spid::from_hex(s)
+    }
+}
+
+impl fmt::Display for Spid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in &self.0 {
+            write!(f, "{:02X}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// The SPID is redacted, as it identifies the service provider. Use `Display`
+/// to print it.
+impl fmt::Debug for Spid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Spid").field(&Redacted(&self.0)).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BYTES: [u8; 16] = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0x0f];
+
+    #[test]
+    fn from_hex() {
+        let spid = Spid::from_hex("0123456789ABCDEF00000000ffffff0f").unwrap();
+        assert_eq!(spid.as_bytes(), &BYTES);
+        assert_eq!(spid, Spid::from(BYTES));
+        assert_eq!("0123456789abcdef00000000FFFFFF0F".parse::<Spid>().unwrap(), spid);
+        assert_eq!(spid.to_string(), "0123456789ABCDEF00000000FFFFFF0F");
+        assert_eq!(format!("{:?}", spid), "Spid(16 bytes [redacted])");
+    }
+
+    #[test]
+    fn invalid_hex() {
+        match Spid::from_hex("0123456789ABCDEF") {
+            Err(Error::InvalidSpidLength(16)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match Spid::from_hex("0123456789ABCDEF00000000ffffff0f0") {
+            Err(Error::InvalidSpidLength(33)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match Spid::from_hex("0123456789ABCDEF00000000ffffff0g") {
+            Err(Error::InvalidSpidCharacter('g')) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        // Multi-byte characters are counted as one.
+        match Spid::from_hex("0123456789ABCDEF00000000ffffff0é") {
+            Err(Error::InvalidSpidCharacter('é')) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn eq() {
+        use std::collections::HashSet;
+
+        assert_eq!(Spid::from(BYTES), Spid::from(BYTES));
+        for i in 0..SPID_SIZE {
+            let mut other = BYTES;
+            other[i] ^= 1;
+            assert_ne!(Spid::from(BYTES), Spid::from(other));
+        }
+        let set = vec![Spid::from(BYTES), Spid::from(BYTES), Spid::from([0; 16])].into_iter().collect::<HashSet<_>>();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn raw_bytes() {
+        assert_eq!(Spid::try_from(&BYTES[..]).unwrap(), Spid::from(BYTES));
+        // A hex string passed as bytes.
+        match Spid::try_from("0123456789ABCDEF00000000ffffff0f".as_bytes()) {
+            Err(Error::InvalidArgument { field: "spid", expected: 16, got: 32 }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
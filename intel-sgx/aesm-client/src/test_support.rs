@@ -0,0 +1,337 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A mock AESM service listening on a unix socket, for testing the client
+//! without aesmd, and an allocator that counts large allocations.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ffi::OsStr;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use protobuf::Message;
+use tempfile::TempDir;
+
+use crate::aesm_proto::*;
+
+pub struct MockAesm {
+    // `None` if the socket is in a directory of the caller.
+    _dir: Option<TempDir>,
+    path: PathBuf,
+    connections: Arc<AtomicUsize>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicUsize,
+    closed_connections: AtomicUsize,
+}
+
+struct Shared<F> {
+    handler: F,
+    // Close every connection after responding to one request, like older
+    // versions of AESM.
+    one_request_per_connection: bool,
+    counters: Arc<Counters>,
+    // The number of connections still to be closed right after accepting
+    // them, like a restarting AESM.
+    to_drop: AtomicUsize,
+    // Connections on which the handler didn't respond. They're kept open so
+    // that the client waits for a response that never arrives.
+    stalled: Mutex<Vec<UnixStream>>,
+}
+
+impl MockAesm {
+    /// Serve requests on a fresh socket. `handler` is called for every
+    /// request. If it returns `None`, no response is sent.
+    pub fn start<F>(handler: F) -> MockAesm
+    where
+        F: Fn(Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        Self::spawn(handler, UnixListener::bind(&path).unwrap(), Some(dir), path, false, 0)
+    }
+
+    /// Like `start`, but listen on `path`, e.g. to start AESM after the
+    /// client.
+    pub fn start_at<F>(path: &Path, handler: F) -> MockAesm
+    where
+        F: Fn(Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        Self::spawn(handler, UnixListener::bind(path).unwrap(), None, path.to_owned(), false, 0)
+    }
+
+    /// Like `start_one_request_per_connection`, but listen on the abstract
+    /// socket address `name`. Its `path` is the address with a leading NUL.
+    pub fn start_abstract<F>(name: &[u8], handler: F) -> MockAesm
+    where
+        F: Fn(Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        // The standard library can't bind abstract addresses.
+        let mut path = vec![0];
+        path.extend_from_slice(name);
+        let path = PathBuf::from(OsStr::from_bytes(&path));
+        let listener = unix_socket::UnixListener::bind(&path).unwrap();
+        let listener = unsafe { UnixListener::from_raw_fd(listener.into_raw_fd()) };
+        Self::spawn(handler, listener, None, path, true, 0)
+    }
+
+    /// Like `start`, but close every connection after one request. Requests
+    /// the client sends on a closed connection anyway are still counted by
+    /// `requests`.
+    pub fn start_one_request_per_connection<F>(handler: F) -> MockAesm
+    where
+        F: Fn(Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        Self::spawn(handler, UnixListener::bind(&path).unwrap(), Some(dir), path, true, 0)
+    }
+
+    /// Like `start`, but close the first `dropped` connections without
+    /// reading the request. They're counted by `connections`.
+    pub fn start_dropping<F>(dropped: usize, handler: F) -> MockAesm
+    where
+        F: Fn(Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+        Self::spawn(handler, UnixListener::bind(&path).unwrap(), Some(dir), path, false, dropped)
+    }
+
+    fn spawn<F>(
+        handler: F,
+        listener: UnixListener,
+        dir: Option<TempDir>,
+        path: PathBuf,
+        one_request_per_connection: bool,
+        dropped: usize,
+    ) -> MockAesm
+    where
+        F: Fn(Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        let counters = Arc::new(Counters::default());
+        let shared = Arc::new(Shared {
+            handler,
+            one_request_per_connection,
+            counters: counters.clone(),
+            to_drop: AtomicUsize::new(dropped),
+            stalled: Mutex::new(vec![]),
+        });
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let accepted = connections.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                accepted.fetch_add(1, Ordering::SeqCst);
+                if shared.to_drop.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+                    continue;
+                }
+                let shared = shared.clone();
+                thread::spawn(move || serve(stream, &shared));
+            }
+        });
+
+        MockAesm { _dir: dir, path, connections, counters }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// The number of requests received so far.
+    pub fn requests(&self) -> usize {
+        self.counters.requests.load(Ordering::SeqCst)
+    }
+
+    /// The number of connections closed after one request so far, see
+    /// `start_one_request_per_connection`.
+    pub fn closed_connections(&self) -> usize {
+        self.counters.closed_connections.load(Ordering::SeqCst)
+    }
+
+    /// A response with `body` and the error `code`, for use in handlers.
+    pub fn response<B: ResponseBody>(code: u32, mut body: B) -> Option<Response> {
+        body.set_error_code(code);
+        Some(body.into_response())
+    }
+
+    /// A response of type `B` with only the error `code`.
+    pub fn error_code<B: ResponseBody>(code: u32) -> Option<Response> {
+        Self::response(code, B::new())
+    }
+}
+
+/// The body of a response to one kind of request.
+pub trait ResponseBody: Message {
+    fn set_error_code(&mut self, code: u32);
+
+    fn into_response(self) -> Response;
+}
+
+macro_rules! impl_response_body {
+    ($($body:ident, $set:ident;)*) => {
+        $(
+            impl ResponseBody for $body {
+                fn set_error_code(&mut self, code: u32) {
+                    self.set_errorCode(code)
+                }
+
+                fn into_response(self) -> Response {
+                    let mut res = Response::new();
+                    res.$set(self);
+                    res
+                }
+            }
+        )*
+    }
+}
+
+impl_response_body! {
+    Response_GetQuoteResponse, set_getQuoteRes;
+    Response_InitQuoteResponse, set_initQuoteRes;
+    Response_GetLaunchTokenResponse, set_getLicTokenRes;
+    Response_SGXGetExtendedEpidGroupIdResponse, set_sgxGetExtendedEpidGroupIdRes;
+    Response_CreateSessionResponse, set_createSessionRes;
+    Response_ExchangeReportResponse, set_exchangeReportRes;
+    Response_InvokeServiceResponse, set_invokeServiceRes;
+    Response_CloseSessionResponse, set_closeSessionRes;
+    Response_GetPsCapResponse, set_getPsCapRes;
+    Response_GetWhiteListSizeResponse, set_getWhiteListSizeRes;
+    Response_GetWhiteListResponse, set_getWhiteListRes;
+    Response_ReportAttestationErrorResponse, set_reportErrRes;
+    Response_SGXRegisterResponse, set_sgxRegisterRes;
+    Response_CheckUpdateStatusResponse, set_checkUpdateStatusRes;
+    Response_GetQuoteExResponse, set_getQuoteExRes;
+    Response_InitQuoteExResponse, set_initQuoteExRes;
+    Response_GetQuoteSizeExResponse, set_getQuoteSizeExRes;
+    Response_GetSupportedAttKeyIDNumResponse, set_getSupportedAttKeyIDNumRes;
+    Response_SelectAttKeyIDResponse, set_selectAttKeyIDRes;
+    Response_GetSupportedAttKeyIDsResponse, set_getSupportedAttKeyIDsRes;
+    Response_UnsupportedRequestResponse, set_unsupportedRequestResponse;
+}
+
+fn serve<F: Fn(Request) -> Option<Response>>(mut stream: UnixStream, shared: &Shared<F>) {
+    let mut closed = false;
+    loop {
+        let req_len = match stream.read_u32::<NativeEndian>() {
+            Ok(len) => len,
+            Err(_) => return,
+        };
+        let mut req_bytes = vec![0; req_len as usize];
+        if stream.read_exact(&mut req_bytes).is_err() {
+            return;
+        }
+        shared.counters.requests.fetch_add(1, Ordering::SeqCst);
+        if closed {
+            continue;
+        }
+        let req = Message::parse_from_bytes(&req_bytes).expect("invalid request");
+
+        match (shared.handler)(req) {
+            Some(res) => {
+                let res_bytes = res.write_to_bytes().unwrap();
+                let mut frame = vec![];
+                frame.write_u32::<NativeEndian>(res_bytes.len() as u32).unwrap();
+                frame.extend(res_bytes);
+                if stream.write_all(&frame).is_err() {
+                    return;
+                }
+                if shared.one_request_per_connection {
+                    // Only stop writing, so that requests sent on the
+                    // connection anyway are counted.
+                    if stream.shutdown(Shutdown::Write).is_err() {
+                        return;
+                    }
+                    shared.counters.closed_connections.fetch_add(1, Ordering::SeqCst);
+                    closed = true;
+                }
+            }
+            None => {
+                shared.stalled.lock().unwrap().push(stream);
+                return;
+            }
+        }
+    }
+}
+
+/// Counts the allocations of the sizes passed to `count_allocations`, to
+/// check that large buffers aren't copied. It's installed for all unit tests,
+/// so the sizes should be unusual enough not to be allocated by other tests
+/// running concurrently.
+struct CountingAllocator;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+static COUNTED_SIZES: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static ALLOCATIONS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+impl CountingAllocator {
+    fn count(size: usize) {
+        for (counted, allocations) in COUNTED_SIZES.iter().zip(&ALLOCATIONS) {
+            if size != 0 && counted.load(Ordering::SeqCst) == size {
+                allocations.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        Self::count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Run `f`, returning the number of allocations (including reallocations)
+/// of each of `sizes` while it ran. Calls must not overlap.
+pub fn count_allocations<R, F: FnOnce() -> R>(sizes: [usize; 2], f: F) -> ([usize; 2], R) {
+    for (counted, (allocations, &size)) in COUNTED_SIZES.iter().zip(ALLOCATIONS.iter().zip(&sizes)) {
+        allocations.store(0, Ordering::SeqCst);
+        counted.store(size, Ordering::SeqCst);
+    }
+    let res = f();
+    let mut counts = [0; 2];
+    for (i, (counted, allocations)) in COUNTED_SIZES.iter().zip(&ALLOCATIONS).enumerate() {
+        counted.store(0, Ordering::SeqCst);
+        counts[i] = allocations.load(Ordering::SeqCst);
+    }
+    (counts, res)
+}
@@ -0,0 +1,312 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use failure::Error;
+use sgxs::einittoken::{Einittoken, EinittokenProvider};
+use sgx_isa::{Attributes, Sigstruct};
+use sha2::{Digest, Sha256};
+
+// ENCLAVEHASH, MRSIGNER and ATTRIBUTES
+type CacheKey = ([u8; 32], [u8; 32], Vec<u8>);
+
+fn cache_key(sigstruct: &Sigstruct, attributes: Attributes) -> CacheKey {
+    let mut mrsigner = [0; 32];
+    mrsigner.copy_from_slice(&Sha256::digest(&sigstruct.modulus));
+    (sigstruct.enclavehash, mrsigner, AsRef::<[u8]>::as_ref(&attributes).to_vec())
+}
+
+/// An `EinittokenProvider` that caches the tokens of another provider.
+///
+/// Launch tokens don't change until the platform is power cycled, so tokens
+/// are cached by the enclave hash and signer of the `SIGSTRUCT` and the
+/// requested attributes. If `token` is called with `retry` set, the cached token is
+/// discarded and a new one is obtained from the inner provider.
+///
+/// `token` is also available through a shared reference, so that one
+/// provider can be used by several threads.
+pub struct CachedTokenProvider<P> {
+    inner: Mutex<P>,
+    cache: Mutex<HashMap<CacheKey, Einittoken>>,
+}
+
+impl<P: EinittokenProvider> CachedTokenProvider<P> {
+    pub fn new(inner: P) -> Self {
+        CachedTokenProvider {
+            inner: Mutex::new(inner),
+            cache: Default::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.inner.into_inner().unwrap()
+    }
+
+    /// Like `EinittokenProvider::token`.
+    pub fn token(&self, sigstruct: &Sigstruct, attributes: Attributes, retry: bool) -> Result<Einittoken, Error> {
+        let key = cache_key(sigstruct, attributes);
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if retry {
+                cache.remove(&key);
+            } else if let Some(token) = cache.get(&key) {
+                return Ok(token.clone());
+            }
+        }
+        // The cache isn't locked while the inner provider is busy, so that
+        // other enclaves' cached tokens are still available.
+        let token = self.inner.lock().unwrap().token(sigstruct, attributes, retry)?;
+        self.cache.lock().unwrap().insert(key, token.clone());
+        Ok(token)
+    }
+
+    /// Discard all cached tokens.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+impl<P: EinittokenProvider> EinittokenProvider for CachedTokenProvider<P> {
+    fn token(&mut self, sigstruct: &Sigstruct, attributes: Attributes, retry: bool) -> Result<Einittoken, Error> {
+        CachedTokenProvider::token(self, sigstruct, attributes, retry)
+    }
+
+    fn can_retry(&self) -> bool {
+        true
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for CachedTokenProvider<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachedTokenProvider")
+            .field("inner", &self.inner)
+            .field("cached", &self.cache.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// An `EinittokenProvider` that tries several providers in turn.
+///
+/// For example, an FLC provider that signs tokens itself can be tried before
+/// AESM. If a provider fails and `can_retry` says it may behave differently
+/// when retrying, it's retried once before moving on to the next provider.
+/// If all providers fail, the error is a `ChainedTokenError` with the errors
+/// of all providers.
+#[derive(Debug)]
+pub struct ChainedTokenProvider {
+    providers: Vec<Box<dyn EinittokenProvider>>,
+}
+
+impl ChainedTokenProvider {
+    pub fn new(providers: Vec<Box<dyn EinittokenProvider>>) -> Self {
+        ChainedTokenProvider { providers }
+    }
+}
+
+impl EinittokenProvider for ChainedTokenProvider {
+    fn token(&mut self, sigstruct: &Sigstruct, attributes: Attributes, retry: bool) -> Result<Einittoken, Error> {
+        let mut errors = vec![];
+        for provider in &mut self.providers {
+            match provider.token(sigstruct, attributes, retry) {
+                Ok(token) => return Ok(token),
+                Err(e) => errors.push(e),
+            }
+            if !retry && provider.can_retry() {
+                match provider.token(sigstruct, attributes, true) {
+                    Ok(token) => return Ok(token),
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+        Err(ChainedTokenError { errors }.into())
+    }
+
+    fn can_retry(&self) -> bool {
+        self.providers.iter().any(|p| p.can_retry())
+    }
+}
+
+/// The errors of all providers of a `ChainedTokenProvider`, in the order they
+/// occurred.
+#[derive(Debug, Fail)]
+pub struct ChainedTokenError {
+    pub errors: Vec<Error>,
+}
+
+impl fmt::Display for ChainedTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "all token providers failed")?;
+        for (i, e) in self.errors.iter().enumerate() {
+            write!(f, "{} {}", if i == 0 { ":" } else { ";" }, e)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    // Returns a token with a different MAC for every call.
+    #[derive(Debug, Default)]
+    struct CountingProvider {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl EinittokenProvider for CountingProvider {
+        fn token(&mut self, _: &Sigstruct, _: Attributes, _: bool) -> Result<Einittoken, Error> {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(Einittoken { mac: [n as u8; 16], ..Default::default() })
+        }
+
+        fn can_retry(&self) -> bool {
+            false
+        }
+    }
+
+    #[derive(Debug)]
+    struct FailingProvider {
+        name: &'static str,
+        can_retry: bool,
+    }
+
+    impl EinittokenProvider for FailingProvider {
+        fn token(&mut self, _: &Sigstruct, _: Attributes, retry: bool) -> Result<Einittoken, Error> {
+            Err(failure::err_msg(format!("{} failed (retry: {})", self.name, retry)))
+        }
+
+        fn can_retry(&self) -> bool {
+            self.can_retry
+        }
+    }
+
+    fn sigstruct(enclavehash: u8) -> Sigstruct {
+        Sigstruct { enclavehash: [enclavehash; 32], ..Default::default() }
+    }
+
+    #[test]
+    fn cache_hit() {
+        let inner = CountingProvider::default();
+        let calls = inner.calls.clone();
+        let provider = CachedTokenProvider::new(inner);
+
+        let token = provider.token(&sigstruct(1), Attributes::default(), false).unwrap();
+        assert_eq!(provider.token(&sigstruct(1), Attributes::default(), false).unwrap(), token);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Different enclaves and attributes have their own tokens.
+        provider.token(&sigstruct(2), Attributes::default(), false).unwrap();
+        let attributes = Attributes { xfrm: 3, ..Default::default() };
+        provider.token(&sigstruct(1), attributes, false).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn cache_signer() {
+        let inner = CountingProvider::default();
+        let calls = inner.calls.clone();
+        let provider = CachedTokenProvider::new(inner);
+
+        // The same enclave signed by another key gets its own token, which
+        // holds that key's MRSIGNER.
+        let token = provider.token(&sigstruct(1), Attributes::default(), false).unwrap();
+        let resigned = Sigstruct { modulus: [1; 384], ..sigstruct(1) };
+        assert_ne!(provider.token(&resigned, Attributes::default(), false).unwrap(), token);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn retry() {
+        let inner = CountingProvider::default();
+        let calls = inner.calls.clone();
+        let mut provider = CachedTokenProvider::new(inner);
+        assert!(EinittokenProvider::can_retry(&provider));
+
+        let first = EinittokenProvider::token(&mut provider, &sigstruct(1), Attributes::default(), false).unwrap();
+        let second = EinittokenProvider::token(&mut provider, &sigstruct(1), Attributes::default(), true).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        // The new token replaces the cached one.
+        assert_eq!(provider.token(&sigstruct(1), Attributes::default(), false).unwrap(), second);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn shared() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<CachedTokenProvider<CountingProvider>>();
+
+        let inner = CountingProvider::default();
+        let calls = inner.calls.clone();
+        let provider = Arc::new(CachedTokenProvider::new(inner));
+        provider.token(&sigstruct(1), Attributes::default(), false).unwrap();
+
+        let threads = (0..4).map(|_| {
+            let provider = provider.clone();
+            thread::spawn(move || provider.token(&sigstruct(1), Attributes::default(), false).unwrap())
+        }).collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn chain_first_succeeds() {
+        let first = CountingProvider::default();
+        let second = CountingProvider::default();
+        let (first_calls, second_calls) = (first.calls.clone(), second.calls.clone());
+        let mut chain = ChainedTokenProvider::new(vec![Box::new(first), Box::new(second)]);
+        assert!(!chain.can_retry());
+
+        chain.token(&sigstruct(1), Attributes::default(), false).unwrap();
+        assert_eq!(first_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(second_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn chain_fallback() {
+        let fallback = CountingProvider::default();
+        let calls = fallback.calls.clone();
+        let mut chain = ChainedTokenProvider::new(vec![
+            Box::new(FailingProvider { name: "flc", can_retry: false }),
+            Box::new(fallback),
+        ]);
+
+        chain.token(&sigstruct(1), Attributes::default(), false).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn chain_all_fail() {
+        let mut chain = ChainedTokenProvider::new(vec![
+            Box::new(FailingProvider { name: "flc", can_retry: false }),
+            Box::new(FailingProvider { name: "aesm", can_retry: true }),
+        ]);
+        assert!(chain.can_retry());
+
+        let err = chain.token(&sigstruct(1), Attributes::default(), false).unwrap_err();
+        let messages = err.downcast_ref::<ChainedTokenError>().unwrap()
+            .errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        // The provider that can retry was retried.
+        assert_eq!(messages, [
+            "flc failed (retry: false)",
+            "aesm failed (retry: false)",
+            "aesm failed (retry: true)",
+        ]);
+        assert_eq!(
+            err.to_string(),
+            "all token providers failed: flc failed (retry: false); aesm failed (retry: false); aesm failed (retry: true)"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Whether the platform needs updates, as returned by
+/// `AesmClient::check_update_status` and
+/// `AesmClient::report_attestation_status`.
+///
+/// The update flags decode `sgx_update_info_bit_t` of the Intel SDK. AESM
+/// only determines them from a platform info blob, so without one they are
+/// `false`, even if `update_available` is set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UpdateStatus {
+    /// AESM reports that an update of the platform is available.
+    pub update_available: bool,
+    /// The CPU microcode needs to be updated.
+    pub ucode_update: bool,
+    /// The firmware of the Intel Converged Security and Management Engine
+    /// needs to be updated.
+    pub csme_fw_update: bool,
+    /// The platform software needs to be updated.
+    pub psw_update: bool,
+    /// The attestation status that AESM returned, if any, as is.
+    pub status: Option<u32>,
+}
+
+impl UpdateStatus {
+    /// Decode the `sgx_update_info_bit_t` in `update_info`, three
+    /// little-endian ints. Missing fields are taken to be zero.
+    pub(crate) fn new(update_available: bool, update_info: &[u8], status: Option<u32>) -> UpdateStatus {
+        let flag = |i: usize| match update_info.get(i * 4..i * 4 + 4) {
+            Some(b) => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) != 0,
+            None => false,
+        };
+        UpdateStatus {
+            update_available,
+            ucode_update: flag(0),
+            csme_fw_update: flag(1),
+            psw_update: flag(2),
+            status,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::MockAesm;
+    use crate::{AesmClient, AesmError, Error, Response_CheckUpdateStatusResponse, Response_ReportAttestationErrorResponse};
+
+    #[test]
+    fn check_update_status() {
+        let mock = MockAesm::start(|req| {
+            let req = req.get_checkUpdateStatusReq();
+            assert_eq!(req.get_update_info_size(), 12);
+            if req.has_platform_info() {
+                assert_eq!(req.get_platform_info(), &[0x15, 0x02, 0x00, 0x65][..]);
+                // The microcode and the platform software need updates.
+                let mut body = Response_CheckUpdateStatusResponse::new();
+                body.set_platform_update_info(vec![1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+                body.set_status(2);
+                MockAesm::response(20, body) // UpdateAvailable
+            } else {
+                MockAesm::error_code::<Response_CheckUpdateStatusResponse>(0)
+            }
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        assert_eq!(client.check_update_status(None).unwrap(), UpdateStatus::default());
+        assert_eq!(client.check_update_status(Some(&[0x15, 0x02, 0x00, 0x65])).unwrap(), UpdateStatus {
+            update_available: true,
+            ucode_update: true,
+            csme_fw_update: false,
+            psw_update: true,
+            status: Some(2),
+        });
+    }
+
+    #[test]
+    fn check_update_status_error() {
+        let mock = MockAesm::start(|_| {
+            MockAesm::error_code::<Response_CheckUpdateStatusResponse>(26) // PlatformInfoBlobInvalidSig
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.check_update_status(Some(&[0; 4])) {
+            Err(Error::AesmCode(AesmError::PlatformInfoBlobInvalidSig_26)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn report_attestation_status() {
+        let mock = MockAesm::start(|req| {
+            let req = req.get_reportErrReq();
+            assert_eq!(req.get_platform_info(), &[0x15, 0x02, 0x00, 0x65][..]);
+            assert_eq!(req.get_update_info_size(), 12);
+            match req.get_attestation_error_code() {
+                0 => MockAesm::error_code::<Response_ReportAttestationErrorResponse>(0),
+                1 => {
+                    // The CSME firmware needs an update.
+                    let mut body = Response_ReportAttestationErrorResponse::new();
+                    body.set_platform_update_info(vec![0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+                    MockAesm::response(20, body) // UpdateAvailable
+                }
+                _ => MockAesm::error_code::<Response_ReportAttestationErrorResponse>(26), // PlatformInfoBlobInvalidSig
+            }
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let platform_info = [0x15, 0x02, 0x00, 0x65];
+        assert_eq!(client.report_attestation_status(&platform_info, 0).unwrap(), UpdateStatus::default());
+        assert_eq!(client.report_attestation_status(&platform_info, 1).unwrap(), UpdateStatus {
+            update_available: true,
+            ucode_update: false,
+            csme_fw_update: true,
+            psw_update: false,
+            status: None,
+        });
+        match client.report_attestation_status(&platform_info, 2) {
+            Err(Error::AesmCode(AesmError::PlatformInfoBlobInvalidSig_26)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // Without AESM, the failure is an error rather than a status.
+        let client = AesmClient::builder().socket_path("/nonexistent").build().unwrap();
+        match client.report_attestation_status(&platform_info, 1) {
+            Err(Error::AesmCommunication(_)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use sgx_isa::Report;
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::quote::REPORT_DATA_OFFSET;
+use crate::ct::ct_eq;
+use crate::{check_size, QuoteInfoEx, QuoteResult, NONCE_SIZE};
+
+/// How `QuoteInfoEx::report_data` binds the attestation public key to
+/// REPORTDATA: the first 32 bytes are the SHA-256 hash of the public key, and
+/// the rest are zero. Verifiers that agree on a scheme out of band can
+/// compare this identifier.
+pub const PUB_KEY_BINDING_SCHEME: &str = "sha256-zero-padded";
+
+fn pub_key_report_data(pub_key: &[u8]) -> [u8; 64] {
+    let mut report_data = [0; 64];
+    report_data[..32].copy_from_slice(&Sha256::digest(pub_key));
+    report_data
+}
+
+impl QuoteInfoEx {
+    /// The REPORTDATA for reports passed to `get_quote_ex` that binds the
+    /// attestation public key, `pub_key`, see `PUB_KEY_BINDING_SCHEME`.
+    pub fn report_data(&self) -> [u8; 64] {
+        pub_key_report_data(self.pub_key())
+    }
+}
+
+/// An EPID quote with its verified QE report, as returned by
+/// `AesmClient::attest_epid`.
+#[derive(Clone, Debug)]
+pub struct EpidAttestation {
+    pub(crate) quote: QuoteResult,
+    pub(crate) qe_report: Report,
+    pub(crate) nonce: [u8; NONCE_SIZE],
+}
+
+impl EpidAttestation {
+    /// The quote, and the QE report as returned by AESM.
+    pub fn quote(&self) -> &QuoteResult {
+        &self.quote
+    }
+
+    /// The QE report, which is bound to the nonce and the quote. Its MAC
+    /// hasn't been checked, see `QuoteResult::verify_qe_report`.
+    pub fn qe_report(&self) -> &Report {
+        &self.qe_report
+    }
+
+    /// The nonce the quote was requested with.
+    pub fn nonce(&self) -> &[u8; NONCE_SIZE] {
+        &self.nonce
+    }
+
+    pub fn into_quote(self) -> QuoteResult {
+        self.quote
+    }
+}
+
+impl QuoteResult {
+    /// Check that the QE report is bound to `nonce` and the quote.
+    ///
+    /// The quoting enclave sets the first 32 bytes of its report's REPORTDATA
+    /// to SHA-256(`nonce` || quote), and the rest to zero. On success, the
+    /// parsed QE report is returned. Its MAC isn't checked: only the enclave
+    /// the report targets can do that, using `EGETKEY`.
+    pub fn verify_qe_report(&self, nonce: &[u8; NONCE_SIZE]) -> Result<Report> {
+        check_size("qe_report", Report::UNPADDED_SIZE, self.qe_report().len())?;
+        let report = Report::try_copy_from(self.qe_report()).unwrap();
+
+        let mut expected = [0; 64];
+        let hash = Sha256::new().chain(nonce).chain(self.quote()).result();
+        expected[..32].copy_from_slice(&hash);
+        if !ct_eq(&report.reportdata, &expected) {
+            return Err(Error::QeReportMismatch);
+        }
+        Ok(report)
+    }
+
+    /// Check that the REPORTDATA in the quote's report body binds `pub_key`,
+    /// as computed by `QuoteInfoEx::report_data`.
+    ///
+    /// Returns `Error::InvalidQuoteSize` if the quote is too short to contain
+    /// a report body, and `Error::ReportDataMismatch` if the REPORTDATA is
+    /// different.
+    pub fn verify_report_data(&self, pub_key: &[u8]) -> Result<()> {
+        let report_data = self.quote().get(REPORT_DATA_OFFSET..REPORT_DATA_OFFSET + 64).ok_or(Error::InvalidQuoteSize)?;
+        if !ct_eq(report_data, &pub_key_report_data(pub_key)) {
+            return Err(Error::ReportDataMismatch);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sgx_isa::Targetinfo;
+
+    use super::*;
+
+    const NONCE: [u8; 16] = *b"0123456789abcdef";
+
+    // A quote and a QE report bound to it with `NONCE`.
+    fn fixture() -> (Vec<u8>, Report) {
+        let quote = (0..=255).cycle().take(1116).collect::<Vec<u8>>();
+        let mut report = Report { isvsvn: 7, ..Default::default() };
+        let hash = Sha256::new().chain(NONCE).chain(&quote).result();
+        report.reportdata[..32].copy_from_slice(&hash);
+        (quote, report)
+    }
+
+    fn quote_result(quote: Vec<u8>, report: &Report) -> QuoteResult {
+        QuoteResult::new(quote, AsRef::<[u8]>::as_ref(report))
+    }
+
+    #[test]
+    fn matching() {
+        let (quote, report) = fixture();
+        let qe_report = quote_result(quote, &report).verify_qe_report(&NONCE).unwrap();
+        assert_eq!(qe_report, report);
+    }
+
+    #[test]
+    fn tampered() {
+        fn assert_mismatch(res: Result<Report>) {
+            match res {
+                Err(Error::QeReportMismatch) => {}
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
+
+        let (quote, report) = fixture();
+
+        let mut other_nonce = NONCE;
+        other_nonce[15] ^= 1;
+        assert_mismatch(quote_result(quote.clone(), &report).verify_qe_report(&other_nonce));
+
+        let mut tampered_quote = quote.clone();
+        tampered_quote[500] ^= 1;
+        assert_mismatch(quote_result(tampered_quote, &report).verify_qe_report(&NONCE));
+
+        let mut truncated_quote = quote.clone();
+        truncated_quote.pop();
+        assert_mismatch(quote_result(truncated_quote, &report).verify_qe_report(&NONCE));
+
+        let mut tampered_report = report.clone();
+        tampered_report.reportdata[63] = 1;
+        assert_mismatch(quote_result(quote.clone(), &tampered_report).verify_qe_report(&NONCE));
+
+        match QuoteResult::new(quote, vec![0; 100]).verify_qe_report(&NONCE) {
+            Err(Error::InvalidArgument { field: "qe_report", .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    fn quote_info_ex(pub_key: &[u8]) -> QuoteInfoEx {
+        QuoteInfoEx::new(vec![], vec![0; Targetinfo::UNPADDED_SIZE], pub_key.to_vec(), 0).unwrap()
+    }
+
+    #[test]
+    fn report_data_known_answers() {
+        let table: [(&[u8], &str); 3] = [
+            (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (&(0..64).collect::<Vec<u8>>(), "fdeab9acf3710362bd2658cdc9a29e8f9c757fcf9811603a8c447cd1d9151108"),
+        ];
+        for &(pub_key, hash) in &table {
+            let report_data = quote_info_ex(pub_key).report_data();
+            let hex = report_data[..32].iter().map(|b| format!("{:02x}", b)).collect::<String>();
+            assert_eq!(hex, hash);
+            assert_eq!(report_data[32..], [0; 32]);
+        }
+    }
+
+    #[test]
+    fn verify_report_data() {
+        let pub_key = [7; 64];
+        let report = Report { reportdata: quote_info_ex(&pub_key).report_data(), ..Default::default() };
+        // A quote header followed by the report body, without the KEYID and
+        // MAC of the report.
+        let mut quote = vec![0; 48];
+        quote.extend_from_slice(&report.as_ref()[..384]);
+        let quote = QuoteResult::new(quote, vec![]);
+
+        quote.verify_report_data(&pub_key).unwrap();
+        match quote.verify_report_data(&[8; 64]) {
+            Err(Error::ReportDataMismatch) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match QuoteResult::new(vec![0; 431], vec![]).verify_report_data(&pub_key) {
+            Err(Error::InvalidQuoteSize) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+
+use crate::error::{AesmError, Error, Result, WhiteListRejection};
+use crate::hex::HexBytes;
+use crate::imp;
+
+// The launch enclave white list is a `wl_cert_chain_t`: a provider
+// certificate (`wl_provider_cert_t`, 136 bytes) followed by the white list
+// certificate (`wl_cert_t`), whose version is a big-endian word at offset 8.
+// See sgx_wl_cert.h in the Intel SDK.
+const WHITE_LIST_VERSION_OFFSET: usize = 136 + 8;
+
+/// The launch enclave white list, as returned by `AesmClient::get_white_list`.
+///
+/// The certificate chain is kept as AESM returned it. Its signature isn't
+/// verified.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WhiteList {
+    cert_chain: Vec<u8>,
+    version: u32,
+}
+
+impl WhiteList {
+    /// Parse the white list certificate chain `cert_chain`, a
+    /// `wl_cert_chain_t`. Returns `Error::MalformedResponse` if it's too
+    /// short to contain a version.
+    pub fn parse(cert_chain: Vec<u8>) -> Result<WhiteList> {
+        match version(&cert_chain) {
+            Some(version) => Ok(WhiteList { cert_chain, version }),
+            None => Err(Error::MalformedResponse { field: "white_list", reason: "too short for a version", raw: cert_chain }),
+        }
+    }
+
+    /// The version of the white list, which increases with every update.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The certificate chain, a `wl_cert_chain_t`.
+    pub fn cert_chain(&self) -> &[u8] {
+        &self.cert_chain
+    }
+
+    pub fn into_cert_chain(self) -> Vec<u8> {
+        self.cert_chain
+    }
+}
+
+impl fmt::Debug for WhiteList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WhiteList")
+            .field("version", &self.version)
+            .field("cert_chain", &HexBytes(&self.cert_chain))
+            .finish()
+    }
+}
+
+/// The version of the launch enclave white list `white_list`, if it's long
+/// enough to contain one.
+pub(crate) fn version(white_list: &[u8]) -> Option<u32> {
+    let version = white_list.get(WHITE_LIST_VERSION_OFFSET..WHITE_LIST_VERSION_OFFSET + 4)?;
+    Some(u32::from_be_bytes([version[0], version[1], version[2], version[3]]))
+}
+
+/// Maps the error codes AESM returns for white list requests when it has no
+/// launch service, e.g. on platforms with flexible launch control, to
+/// `Error::WhiteListNotApplicable`.
+pub(crate) fn not_applicable<T>(res: Result<T>) -> Result<T> {
+    match res {
+        Err(Error::AesmCode(AesmError::ServiceNotAvailable_27)) | Err(Error::AesmCode(AesmError::ServiceUnavailable_30)) => {
+            Err(Error::WhiteListNotApplicable)
+        }
+        res => res,
+    }
+}
+
+/// Register `cert_chain` with AESM, unless it's older than the installed
+/// white list. AESM itself accepts older versions without installing them.
+pub(crate) fn register(client: &imp::AesmClient, cert_chain: &[u8]) -> Result<()> {
+    let requested = version(cert_chain).ok_or(Error::WhiteListRejected(WhiteListRejection::Malformed))?;
+    let installed = match not_applicable(client.get_white_list_size())? {
+        // No white list is installed yet.
+        0 => None,
+        size => version(&client.get_white_list(size)?),
+    };
+    if let Some(installed) = installed.filter(|&installed| installed > requested) {
+        return Err(Error::WhiteListRejected(WhiteListRejection::Downgrade { installed, requested }));
+    }
+    match not_applicable(client.register_white_list(cert_chain.to_vec())) {
+        Err(Error::AesmCode(AesmError::ParameterError_3)) => Err(Error::WhiteListRejected(WhiteListRejection::InvalidSignature)),
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::time::Duration;
+
+    use super::*;
+    #[cfg(unix)]
+    use crate::test_support::MockAesm;
+    #[cfg(unix)]
+    use crate::{AesmClient, Response_GetWhiteListResponse, Response_GetWhiteListSizeResponse, Response_SGXRegisterResponse};
+
+    fn cert_chain(version: u32) -> Vec<u8> {
+        let mut cert_chain = vec![0x5a; 200];
+        cert_chain[144..148].copy_from_slice(&version.to_be_bytes());
+        cert_chain
+    }
+
+    #[test]
+    fn parse() {
+        let white_list = WhiteList::parse(cert_chain(0x12c)).unwrap();
+        assert_eq!(white_list.version(), 300);
+        assert_eq!(white_list.cert_chain(), &cert_chain(0x12c)[..]);
+        assert_eq!(format!("{:?}", white_list), "WhiteList { version: 300, cert_chain: 200 bytes [5a 5a 5a 5a … 5a 5a] }");
+        assert_eq!(white_list.into_cert_chain(), cert_chain(0x12c));
+
+        match WhiteList::parse(vec![0; 147]) {
+            Err(Error::MalformedResponse { field: "white_list", raw, .. }) => assert_eq!(raw, vec![0; 147]),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn not_applicable() {
+        for &code in &[27, 30] {
+            assert!(matches!(super::not_applicable::<()>(Err(Error::aesm_code(code))), Err(Error::WhiteListNotApplicable)));
+        }
+        assert!(matches!(super::not_applicable::<()>(Err(Error::aesm_code(1))), Err(Error::AesmCode(AesmError::UnexpectedError_1))));
+        assert_eq!(super::not_applicable(Ok(1)).unwrap(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn get_white_list() {
+        let mock = MockAesm::start(|req| {
+            if req.has_getWhiteListSizeReq() {
+                let mut body = Response_GetWhiteListSizeResponse::new();
+                body.set_white_list_size(200);
+                MockAesm::response(0, body)
+            } else {
+                assert_eq!(req.get_getWhiteListReq().get_white_list_size(), 200);
+                let mut white_list = vec![0x5a; 200];
+                white_list[144..148].copy_from_slice(&[0, 0, 1, 0x2c]);
+                let mut body = Response_GetWhiteListResponse::new();
+                body.set_white_list(white_list);
+                MockAesm::response(0, body)
+            }
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        let white_list = client.get_white_list().unwrap();
+        assert_eq!(white_list.version(), 300);
+        assert_eq!(white_list.cert_chain().len(), 200);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn get_white_list_not_applicable() {
+        // Like an AESM without launch service.
+        let mock = MockAesm::start(|_| {
+            MockAesm::error_code::<Response_GetWhiteListSizeResponse>(27) // ServiceNotAvailable
+        });
+
+        let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+        match client.get_white_list() {
+            Err(Error::WhiteListNotApplicable) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[cfg(unix)]
+    // Serves white list requests with the installed version `installed`, and
+    // responds to registrations with `register_code`.
+    fn start_white_list_mock(installed: Option<u32>, register_code: u32) -> MockAesm {
+        MockAesm::start(move |req| {
+            if req.has_getWhiteListSizeReq() {
+                let mut body = Response_GetWhiteListSizeResponse::new();
+                body.set_white_list_size(if installed.is_some() { 200 } else { 0 });
+                MockAesm::response(0, body)
+            } else if req.has_getWhiteListReq() {
+                let mut white_list = vec![0; 200];
+                white_list[144..148].copy_from_slice(&installed.unwrap().to_be_bytes());
+                let mut body = Response_GetWhiteListResponse::new();
+                body.set_white_list(white_list);
+                MockAesm::response(0, body)
+            } else {
+                let req = req.get_sgxRegisterReq();
+                assert_eq!(req.get_data_type(), 0);
+                assert_eq!(req.get_buf().len(), 300);
+                assert_eq!(&req.get_buf()[144..148], &[0, 0, 1, 0x2c]);
+                assert_eq!(req.get_timeout(), 60_000_000);
+                MockAesm::error_code::<Response_SGXRegisterResponse>(register_code)
+            }
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn register_white_list() {
+        let mut cert_chain = vec![0x5a; 300];
+        cert_chain[144..148].copy_from_slice(&300u32.to_be_bytes());
+        let register = |installed, register_code| {
+            let mock = start_white_list_mock(installed, register_code);
+            let client = AesmClient::builder().socket_path(mock.path()).build().unwrap();
+            client.register_white_list_with_timeout(&cert_chain, Duration::from_secs(60))
+        };
+
+        register(None, 0).unwrap();
+        register(Some(299), 0).unwrap();
+        register(Some(300), 0).unwrap();
+        match register(Some(301), 0) {
+            Err(Error::WhiteListRejected(WhiteListRejection::Downgrade { installed: 301, requested: 300 })) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match register(Some(299), 3) { // ParameterError
+            Err(Error::WhiteListRejected(WhiteListRejection::InvalidSignature)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match register(Some(299), 1) { // UnexpectedError
+            Err(Error::AesmCode(AesmError::UnexpectedError_1)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // Malformed chains aren't sent.
+        let client = AesmClient::builder().socket_path("/nonexistent").build().unwrap();
+        match client.register_white_list(&[0; 100]) {
+            Err(Error::WhiteListRejected(WhiteListRejection::Malformed)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
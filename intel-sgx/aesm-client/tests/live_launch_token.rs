@@ -0,0 +1,49 @@
+/* Copyright (c) Fortanix, Inc.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// Launch tokens are only needed on platforms without flexible launch control,
+// which Windows supports through the PSW.
+#![cfg(windows)]
+
+extern crate aesm_client;
+extern crate report_test;
+extern crate sgx_isa;
+extern crate sgxs;
+extern crate sgxs_loaders;
+
+use aesm_client::AesmClient;
+use sgx_isa::{Attributes, AttributesFlags, Sigstruct, Targetinfo};
+use sgxs::einittoken::EinittokenProvider;
+use sgxs_loaders::enclaveapi::Sgx;
+
+#[test]
+fn live_launch_token() {
+    // The launch enclave issues tokens for debug enclaves of any signer.
+    let attributes = Attributes { flags: AttributesFlags::INIT | AttributesFlags::DEBUG | AttributesFlags::MODE64BIT, xfrm: 0x3 };
+    let sigstruct = Sigstruct {
+        attributes,
+        attributemask: [(AttributesFlags::DEBUG | AttributesFlags::MODE64BIT).bits(), 0x3],
+        enclavehash: [1; 32],
+        modulus: [2; 384],
+        ..Default::default()
+    };
+
+    let token = AesmClient::new().token(&sigstruct, attributes, false).unwrap();
+
+    assert_eq!(token.valid & 1, 1);
+    assert_eq!(token.attributes, attributes);
+    assert_eq!(token.mrenclave, sigstruct.enclavehash);
+}
+
+#[test]
+fn live_load_with_launch_token() {
+    let mut device = Sgx::new()
+        .unwrap()
+        .einittoken_provider(AesmClient::new())
+        .build();
+
+    report_test::report(&Targetinfo::default(), &mut device).unwrap();
+}
@@ -8,7 +8,7 @@
 
 use std::fmt;
 
-use aesm_client::AesmClient;
+use aesm_client::{AesmClient, AttestationAlgorithm};
 use dcap_ql::quote::{Qe3CertDataPpid, Quote, Quote3SignatureEcdsaP256, QuoteHeader};
 use sgx_isa::Targetinfo;
 #[cfg(windows)]
@@ -16,14 +16,6 @@ use sgxs_loaders::enclaveapi::Sgx as IsgxDevice;
 #[cfg(unix)]
 use sgxs_loaders::isgx::Device as IsgxDevice;
 
-fn get_algorithm_id(key_id: &Vec<u8>) -> u32 {
-    const ALGORITHM_OFFSET: usize = 154;
-
-    let mut bytes: [u8; 4] = Default::default();
-    bytes.copy_from_slice(&key_id[ALGORITHM_OFFSET..ALGORITHM_OFFSET + 4]);
-    u32::from_le_bytes(bytes)
-}
-
 struct PrintHex<'a>(&'a [u8]);
 
 impl<'a> fmt::Display for PrintHex<'a> {
@@ -36,8 +28,6 @@ impl<'a> fmt::Display for PrintHex<'a> {
 }
 
 fn go() -> Result<(), &'static str> {
-    const SGX_QL_ALG_ECDSA_P256: u32 = 2;
-
     let mut device = IsgxDevice::new()
         .map_err(|_| "Error opening SGX device")?
         .einittoken_provider(AesmClient::new())
@@ -50,19 +40,19 @@ fn go() -> Result<(), &'static str> {
         .map_err(|_| "AESM communication error getting attestation key ID")?;
 
     let ecdsa_key_id = key_ids
-        .into_iter()
-        .find(|id| SGX_QL_ALG_ECDSA_P256 == get_algorithm_id(id))
+        .iter()
+        .find(|id| id.algorithm() == AttestationAlgorithm::EcdsaP256)
         .ok_or("No appropriate attestation key ID")?;
 
     let quote_info = client
-        .init_quote_ex(ecdsa_key_id.clone())
+        .init_quote_ex(ecdsa_key_id.key().to_vec())
         .map_err(|_| "Error during quote initialization")?;
 
     let ti = Targetinfo::try_copy_from(quote_info.target_info()).unwrap();
     let report = report_test::report(&ti, &mut device).unwrap();
 
     let res = client
-        .get_quote_ex(ecdsa_key_id, report.as_ref().to_owned(), None, vec![0; 16])
+        .get_quote_ex(&quote_info, report.as_ref().to_owned(), None, vec![0; 16])
         .map_err(|_| "Error obtaining quote")?;
 
     let quote = Quote::parse(res.quote()).map_err(|_| "Error parsing quote")?;